@@ -26,7 +26,7 @@ extern crate serde_crate as serde;
 
 use std::process::ExitCode;
 
-use bpwallet::cli::{Args, BpCommand, Config, DescrStdOpts, Exec, ExecError, LogLevel};
+use bpwallet::cli::{Args, BpCommand, Config, DescrMultiOpts, Exec, ExecError, LogLevel};
 use clap::Parser;
 
 fn main() -> ExitCode {
@@ -39,7 +39,7 @@ fn main() -> ExitCode {
 }
 
 fn run() -> Result<(), ExecError> {
-    let mut args = Args::<BpCommand, DescrStdOpts>::parse();
+    let mut args = Args::<BpCommand, DescrMultiOpts>::parse();
     args.process();
     LogLevel::from_verbosity_flag_count(args.verbose).apply();
     trace!("Command-line arguments: {:#?}", &args);
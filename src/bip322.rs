@@ -0,0 +1,244 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! BIP-322 generic message signing: construction of the virtual transactions and verification
+//! of the signatures for P2WPKH and P2TR key path spending addresses.
+
+use bpstd::secp256k1::SECP256K1;
+use bpstd::{
+    Address, AddressPayload, AddressType, Bip340Sig, CompressedPk, ConsensusDecode,
+    ConsensusEncode, Descriptor, LegacySig, LockTime, OpCode, Outpoint, Sats, ScriptCode,
+    ScriptPubkey, SeqNo, SigScript, SighashCache, Terminal, Tx, TxIn, TxOut, TxVer, Txid,
+    VarIntArray, Vout, WPubkeyHash, Witness,
+};
+use psbt::{Prevout, Psbt, PsbtVer};
+use sha2::{Digest, Sha256};
+
+/// Tag of the BIP-340 tagged hash used for the signed message.
+pub const MESSAGE_TAG: &[u8] = b"BIP0322-signed-message";
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum Bip322Error {
+    /// BIP-322 signatures are supported only for P2WPKH and P2TR addresses, while the address
+    /// has {0} type.
+    UnsupportedAddress(AddressType),
+
+    /// signature is neither a valid witness nor a valid transaction.
+    InvalidEncoding,
+
+    /// signature transaction doesn't spend the message commitment.
+    InvalidToSign,
+
+    /// signature witness doesn't match the address type.
+    InvalidWitness,
+
+    /// the public key provided in the signature doesn't match the address.
+    KeyMismatch,
+
+    /// invalid signature.
+    InvalidSignature,
+}
+
+/// Computes BIP-340 tagged hash of the message.
+pub fn message_hash(message: &[u8]) -> [u8; 32] {
+    let tag = Sha256::digest(MESSAGE_TAG);
+    let mut engine = Sha256::new();
+    engine.update(tag);
+    engine.update(tag);
+    engine.update(message);
+    engine.finalize().into()
+}
+
+/// Bare `OP_RETURN` script without the data push, used as the `to_sign` transaction output.
+fn op_return() -> ScriptPubkey { ScriptPubkey::from_unsafe(vec![OpCode::Return as u8]) }
+
+/// Constructs the virtual `to_spend` transaction committing to the message and paying to the
+/// script pubkey of the signing address.
+pub fn to_spend(script_pubkey: &ScriptPubkey, message: &[u8]) -> Tx {
+    // OP_0 PUSH32 <message hash>
+    let mut sig_script = vec![0x00, 0x20];
+    sig_script.extend(message_hash(message));
+    Tx {
+        version: TxVer::from_consensus_i32(0),
+        inputs: VarIntArray::from_checked(vec![TxIn {
+            prev_output: Outpoint::new(Txid::coinbase(), Vout::from_u32(0xFFFF_FFFF)),
+            sig_script: SigScript::from_unsafe(sig_script),
+            sequence: SeqNo::ZERO,
+            witness: Witness::new(),
+        }]),
+        outputs: VarIntArray::from_checked(vec![TxOut::new(script_pubkey.clone(), Sats::ZERO)]),
+        lock_time: LockTime::ZERO,
+    }
+}
+
+/// Constructs the virtual `to_sign` transaction spending the `to_spend` transaction output, with
+/// an empty witness.
+pub fn to_sign(to_spend: Txid) -> Tx {
+    Tx {
+        version: TxVer::from_consensus_i32(0),
+        inputs: VarIntArray::from_checked(vec![TxIn {
+            prev_output: Outpoint::new(to_spend, Vout::from_u32(0)),
+            sig_script: SigScript::empty(),
+            sequence: SeqNo::ZERO,
+            witness: Witness::new(),
+        }]),
+        outputs: VarIntArray::from_checked(vec![TxOut::new(op_return(), Sats::ZERO)]),
+        lock_time: LockTime::ZERO,
+    }
+}
+
+/// Constructs PSBT for the `to_sign` transaction, which, once signed and finalized with the
+/// descriptor, provides the signature with [`simple_signature`].
+pub fn signing_psbt<K, D: Descriptor<K>>(
+    descriptor: &D,
+    terminal: Terminal,
+    script_pubkey: &ScriptPubkey,
+    message: &[u8],
+) -> Psbt {
+    let to_spend = to_spend(script_pubkey, message);
+    let mut psbt = Psbt::create(PsbtVer::V0);
+    psbt.tx_version = TxVer::from_consensus_i32(0);
+    psbt.fallback_locktime = Some(LockTime::ZERO);
+    let prevout = Prevout::new(Outpoint::new(to_spend.txid(), Vout::from_u32(0)), Sats::ZERO);
+    psbt.construct_input_expect(prevout, descriptor, terminal, SeqNo::ZERO);
+    psbt.construct_output_expect(op_return(), Sats::ZERO);
+    psbt
+}
+
+/// Returns BIP-322 simple signature, which is the consensus-encoded witness of the finalized
+/// PSBT produced by [`signing_psbt`], or `None` if the PSBT is not finalized.
+pub fn simple_signature(psbt: &Psbt) -> Option<Vec<u8>> {
+    let witness = psbt.inputs().next()?.final_witness.as_ref()?;
+    Some(witness.consensus_serialize())
+}
+
+/// Verifies BIP-322 signature of the message for the address. The signature may be given either
+/// in the simple format (the consensus-encoded witness) or in the full format (the
+/// consensus-encoded `to_sign` transaction).
+pub fn verify(address: &Address, message: &[u8], signature: &[u8]) -> Result<(), Bip322Error> {
+    let script_pubkey = address.script_pubkey();
+    let to_spend = to_spend(&script_pubkey, message);
+    let to_sign = match Witness::consensus_deserialize(signature) {
+        Ok(witness) => {
+            let mut tx = to_sign(to_spend.txid());
+            tx.inputs[0].witness = witness;
+            tx
+        }
+        Err(_) => {
+            let tx =
+                Tx::consensus_deserialize(signature).map_err(|_| Bip322Error::InvalidEncoding)?;
+            if tx.inputs.len() != 1
+                || tx.inputs[0].prev_output != Outpoint::new(to_spend.txid(), Vout::from_u32(0))
+            {
+                return Err(Bip322Error::InvalidToSign);
+            }
+            tx
+        }
+    };
+
+    let witness = to_sign.inputs[0].witness.elements().map(<[u8]>::to_vec).collect::<Vec<_>>();
+    let prevout = TxOut::new(script_pubkey.clone(), Sats::ZERO);
+    let mut cache = SighashCache::new(to_sign, vec![prevout]).expect("single input");
+    match address.payload {
+        AddressPayload::Wpkh(_) => {
+            let [sig, pk] = witness.as_slice() else {
+                return Err(Bip322Error::InvalidWitness);
+            };
+            let sig = LegacySig::from_bytes(sig).map_err(|_| Bip322Error::InvalidSignature)?;
+            let pk = CompressedPk::from_bytes(pk).map_err(|_| Bip322Error::InvalidWitness)?;
+            if ScriptPubkey::p2wpkh(WPubkeyHash::from(pk)) != script_pubkey {
+                return Err(Bip322Error::KeyMismatch);
+            }
+            let script_code = ScriptCode::with_p2wpkh(&script_pubkey);
+            let sighash = cache
+                .segwit_sighash(0, &script_code, Sats::ZERO, sig.sighash_type)
+                .map_err(|_| Bip322Error::InvalidSignature)?;
+            SECP256K1
+                .verify_ecdsa(&sighash.into(), &sig.sig, &pk)
+                .map_err(|_| Bip322Error::InvalidSignature)
+        }
+        AddressPayload::Tr(output_key) => {
+            let [sig] = witness.as_slice() else {
+                return Err(Bip322Error::InvalidWitness);
+            };
+            let sig = Bip340Sig::from_bytes(sig).map_err(|_| Bip322Error::InvalidSignature)?;
+            let sighash = cache
+                .tap_sighash_key(0, sig.sighash_type)
+                .map_err(|_| Bip322Error::InvalidSignature)?;
+            SECP256K1
+                .verify_schnorr(&sig.sig, &<[u8; 32]>::from(sighash), &output_key)
+                .map_err(|_| Bip322Error::InvalidSignature)
+        }
+        _ => Err(Bip322Error::UnsupportedAddress(address.address_type())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use amplify::hex::{FromHex, ToHex};
+
+    use super::*;
+
+    #[test]
+    fn message_hashes() {
+        assert_eq!(
+            message_hash(b"").to_hex(),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            message_hash(b"Hello World").to_hex(),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+    }
+
+    #[test]
+    fn verify_p2wpkh() {
+        let addr = Address::from_str("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l").unwrap();
+        let empty = Vec::<u8>::from_hex(
+            "024730440220336801010aaf657d79662cac98a990a43ac6f376af2c84f8f76401ccb9d0231602201693a4e683db4a91944ca5cb11527840366daf583a2c695fccf8e93483b52e34012102c7f12003196442943d8588e01aee840423cc54fc1521526a3b85c2b0cbd58872",
+        )
+        .unwrap();
+        let hello = Vec::<u8>::from_hex(
+            "0247304402206517c8637a7bfc3a154edcba6196d64bbd5b73955cb7da7d1626bcdde466c364022022bf10d19fc0bb69b4596e306b362acaa835293cf693bb176f7324b531f5afec012102c7f12003196442943d8588e01aee840423cc54fc1521526a3b85c2b0cbd58872",
+        )
+        .unwrap();
+        assert_eq!(verify(&addr, b"", &empty), Ok(()));
+        assert_eq!(verify(&addr, b"Hello World", &hello), Ok(()));
+        assert_eq!(verify(&addr, b"Hello World", &empty), Err(Bip322Error::InvalidSignature));
+    }
+
+    #[test]
+    fn verify_p2tr() {
+        let addr =
+            Address::from_str("bc1ppv609nr0vr25u07u95waq5lucwfm6tde4nydujnu8npg4q75mr5sxq8lt3")
+                .unwrap();
+        let sig = Vec::<u8>::from_hex(
+            "0141ddebd3eb25012ffa82937d9f25f9644e047bb2f472ab6c5089bbb53588ada2884cb5bcc53911f32d8dcf9548733b694d120db6a4e485194559e8d8fe668d269f01",
+        )
+        .unwrap();
+        assert_eq!(verify(&addr, b"Hello World", &sig), Ok(()));
+        assert_eq!(verify(&addr, b"", &sig), Err(Bip322Error::InvalidSignature));
+    }
+}
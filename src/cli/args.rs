@@ -20,21 +20,26 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::path::PathBuf;
 use std::process::exit;
+use std::thread;
+use std::time::Duration;
 
-use bpstd::XpubDerivable;
+use bpstd::{Tx, Txid, XpubDerivable};
 use clap::Subcommand;
 use descriptors::Descriptor;
 use strict_encoding::Ident;
 
 use crate::cli::{
-    Config, DescrStdOpts, DescriptorOpts, ExecError, GeneralOpts, ResolverOpt, WalletOpts,
+    is_sane_fee_rate, Config, DescrStdOpts, DescriptorOpts, ExecError, FeeSource, GeneralOpts,
+    PublishOpts, ResolverOpt, ScanOpts, WalletOpts, DEFAULT_FEE_FLOOR, DEFAULT_MEMPOOL,
 };
 use crate::fs::FsTextStore;
-use crate::indexers::esplora;
-use crate::{AnyIndexer, Wallet};
+use crate::indexers::pool::{ClientPool, WeightedUrl};
+use crate::indexers::{bitcoind, electrum, esplora, ScanProgress};
+use crate::{AnyIndexer, AnyIndexerError, BroadcastError, Indexer, TxStatus, Wallet};
 
 /// Command-line arguments
 #[derive(Parser)]
@@ -57,6 +62,12 @@ pub struct Args<C: Clone + Eq + Debug + Subcommand, O: DescriptorOpts = DescrStd
     #[clap(long, global = true)]
     pub sync: bool,
 
+    #[command(flatten)]
+    pub scan: ScanOpts,
+
+    #[command(flatten)]
+    pub publish: PublishOpts,
+
     #[command(flatten)]
     pub general: GeneralOpts,
 
@@ -72,6 +83,8 @@ impl<C: Clone + Eq + Debug + Subcommand, O: DescriptorOpts> Args<C, O> {
             wallet: self.wallet.clone(),
             resolver: self.resolver.clone(),
             sync: self.sync,
+            scan: self.scan.clone(),
+            publish: self.publish.clone(),
             general: self.general.clone(),
             command: cmd.clone(),
         }
@@ -97,34 +110,298 @@ impl<C: Clone + Eq + Debug + Subcommand, O: DescriptorOpts> Args<C, O> {
 
     pub fn indexer(&self) -> Result<AnyIndexer, ExecError> {
         let network = self.general.network.to_string();
+        let configure = |mut client: esplora::Client| -> Result<_, ExecError> {
+            client = client.with_concurrency(self.resolver.concurrency);
+            if !self.resolver.pool.is_empty() {
+                let urls = self.resolver.pool.iter().map(|endpoint| WeightedUrl {
+                    weight: endpoint.weight,
+                    url: endpoint.url.replace("{network}", &network),
+                });
+                client = client.with_pool(ClientPool::with_proxy(
+                    urls,
+                    self.resolver.esplora_proxy.as_deref(),
+                )?);
+            }
+            if !self.resolver.privacy {
+                return Ok(Box::new(client));
+            }
+            let servers = self
+                .resolver
+                .privacy_servers
+                .iter()
+                .map(|url| url.replace("{network}", &network))
+                .collect::<Vec<_>>();
+            let jitter = Duration::from_millis(self.resolver.jitter);
+            Ok(Box::new(client.with_privacy(servers.iter().map(String::as_str), jitter)?))
+        };
+        if let Some(url) = &self.resolver.bitcoind {
+            if self.resolver.privacy || !self.resolver.pool.is_empty() {
+                eprintln!(
+                    "Error: privacy mode and server pool are supported only with --esplora or \
+                     --mempool"
+                );
+                exit(1);
+            }
+            let mut client = bitcoind::Client::new(url, &self.resolver.rpc_wallet);
+            if let Some(cookie) = &self.resolver.rpc_cookie {
+                client = client.with_cookie(cookie)?;
+            }
+            if let Some(birthday) = self.resolver.rpc_birthday {
+                client = client.with_birthday(birthday);
+            }
+            client = client.with_estimate_mode(self.resolver.estimate_mode);
+            return Ok(AnyIndexer::Bitcoind(Box::new(client)));
+        }
         Ok(match (&self.resolver.esplora, &self.resolver.electrum, &self.resolver.mempool) {
-            (None, Some(url), None) => AnyIndexer::Electrum(Box::new(electrum::Client::new(url)?)),
-            (Some(url), None, None) => AnyIndexer::Esplora(Box::new(esplora::Client::new_esplora(
-                &url.replace("{network}", &network),
-            )?)),
-            (None, None, Some(url)) => AnyIndexer::Mempool(Box::new(esplora::Client::new_mempool(
-                &url.replace("{network}", &network),
-            )?)),
+            (None, Some(_), None) if self.resolver.privacy => {
+                eprintln!("Error: privacy mode is supported only with --esplora or --mempool");
+                exit(1);
+            }
+            (None, Some(_), None) if !self.resolver.pool.is_empty() => {
+                eprintln!("Error: server pool is supported only with --esplora or --mempool");
+                exit(1);
+            }
+            (None, Some(url), None) => {
+                let opts = electrum::ConnectionOpts {
+                    socks5: self.resolver.tor_proxy.clone(),
+                };
+                match self.resolver.electrum_pin {
+                    Some(pin) => AnyIndexer::ElectrumPinned(Box::new(electrum::connect_pinned(
+                        url,
+                        pin.to_byte_array(),
+                        &opts,
+                    )?)),
+                    None => AnyIndexer::Electrum(Box::new(electrum::connect(url, &opts)?)),
+                }
+            }
+            (Some(url), None, None) => {
+                let url = url.replace("{network}", &network);
+                AnyIndexer::Esplora(configure(match &self.resolver.esplora_proxy {
+                    Some(proxy) => esplora::Client::new_esplora_with_proxy(&url, proxy)?,
+                    None => esplora::Client::new_esplora(&url)?,
+                })?)
+            }
+            (None, None, Some(url)) => AnyIndexer::Mempool(configure(
+                self.mempool_client(&url.replace("{network}", &network))?,
+            )?),
             _ => {
                 eprintln!(
-                    "Error: no blockchain indexer specified; use either --esplora --mempool or \
-                     --electrum argument"
+                    "Error: no blockchain indexer specified; use either --esplora, --mempool, \
+                     --electrum or --bitcoind argument"
                 );
                 exit(1);
             }
         })
     }
 
+    #[allow(clippy::result_large_err)]
+    fn mempool_client(&self, url: &str) -> Result<esplora::Client, ::esplora::Error> {
+        match &self.resolver.esplora_proxy {
+            Some(proxy) => esplora::Client::new_mempool_with_proxy(url, proxy),
+            None => esplora::Client::new_mempool(url),
+        }
+    }
+
+    /// Estimates fee rate (in sats per vbyte) for the transaction to be mined within `target`
+    /// blocks, querying the fee sources from the config (or the default chain) in order and
+    /// returning the first sane value together with the source which has provided it.
+    pub fn fee_rate(&self, conf: &Config, target: u16) -> (f64, FeeSource) {
+        self.fee_rates(conf, &[target])
+            .remove(&target)
+            .expect("fee rate is provided for each target")
+    }
+
+    /// Estimates fee rates for multiple confirmation targets. Each fee source is queried once
+    /// for all targets not yet covered by the preceding sources; targets which are left
+    /// uncovered by all sources use the default fee floor.
+    pub fn fee_rates(&self, conf: &Config, targets: &[u16]) -> BTreeMap<u16, (f64, FeeSource)> {
+        let sources = if conf.fee_sources.is_empty() {
+            FeeSource::default_chain()
+        } else {
+            conf.fee_sources.clone()
+        };
+        let mut rates = BTreeMap::new();
+        for source in sources {
+            let pending =
+                targets.iter().copied().filter(|t| !rates.contains_key(t)).collect::<Vec<_>>();
+            if pending.is_empty() {
+                break;
+            }
+            let estimates = match source {
+                FeeSource::Indexer => self
+                    .indexer()
+                    .and_then(|indexer| Ok(indexer.fee_estimates(&pending)?))
+                    .map_err(|err| err.to_string()),
+                FeeSource::Mempool => {
+                    let url = self.resolver.mempool.as_deref().unwrap_or(DEFAULT_MEMPOOL);
+                    self.mempool_client(
+                        &url.replace("{network}", &self.general.network.to_string()),
+                    )
+                    .and_then(|client| client.recommended_fees())
+                    .map(|fees| pending.iter().map(|t| (*t, fees.for_target(*t))).collect())
+                    .map_err(|err| err.to_string())
+                }
+                FeeSource::Floor(rate) => Ok(pending.iter().map(|t| (*t, rate)).collect()),
+            };
+            let estimates = match estimates {
+                Ok(estimates) => estimates,
+                Err(err) => {
+                    eprintln!("Warning: fee source {source} has failed: {err}");
+                    continue;
+                }
+            };
+            for target in pending {
+                match estimates.get(&target) {
+                    Some(rate) if is_sane_fee_rate(*rate) => {
+                        rates.insert(target, (*rate, source));
+                    }
+                    Some(rate) => eprintln!(
+                        "Warning: fee source {source} returned insane fee rate {rate} for target \
+                         {target}"
+                    ),
+                    None => {
+                        eprintln!(
+                            "Warning: fee source {source} has no estimate for target {target}"
+                        )
+                    }
+                }
+            }
+        }
+        for target in targets {
+            rates
+                .entry(*target)
+                .or_insert((DEFAULT_FEE_FLOOR, FeeSource::Floor(DEFAULT_FEE_FLOOR)));
+        }
+        rates
+    }
+
+    /// Describes the fee source for the user, adding the fee estimation mode when the estimate
+    /// is provided by Bitcoin Core.
+    pub fn fee_source_info(&self, source: FeeSource) -> String {
+        match source {
+            FeeSource::Indexer if self.resolver.bitcoind.is_some() => {
+                format!("{source} (bitcoind, {} mode)", self.resolver.estimate_mode)
+            }
+            source => source.to_string(),
+        }
+    }
+
+    /// Publishes transaction and, unless disabled, polls the indexer until the transaction is
+    /// seen by it. Transactions already known to the network are treated as published.
+    pub fn publish(&self, tx: &Tx) -> Result<Txid, ExecError> {
+        let indexer = self.indexer()?;
+        eprint!("Publishing transaction via {} ... ", indexer.name());
+        let txid = match indexer.publish(tx) {
+            Ok(txid) => {
+                eprintln!("success");
+                txid
+            }
+            Err(AnyIndexerError::Broadcast(BroadcastError::AlreadyKnown)) => {
+                eprintln!("already known");
+                tx.txid()
+            }
+            Err(err) => {
+                eprintln!("failed");
+                return Err(err.into());
+            }
+        };
+        println!("broadcast {txid}");
+
+        if self.publish.no_poll {
+            return Ok(txid);
+        }
+        eprint!("Waiting for the transaction to appear at the indexer ");
+        for _ in 0..self.publish.poll_attempts {
+            thread::sleep(Duration::from_secs(self.publish.poll_interval));
+            match indexer.status(txid) {
+                Ok(TxStatus::Unknown) => eprint!("."),
+                Ok(TxStatus::Mined(info)) => {
+                    eprintln!(" mined at height {}", info.height);
+                    return Ok(txid);
+                }
+                Ok(_) => {
+                    eprintln!(" seen in the mempool");
+                    return Ok(txid);
+                }
+                Err(err) => {
+                    eprint!("!");
+                    warn!("Unable to check transaction {txid} status: {err}");
+                }
+            }
+        }
+        eprintln!(" not seen");
+        eprintln!(
+            "Warning: transaction {txid} was accepted for broadcast but hasn't appeared at the \
+             indexer; it may have been dropped"
+        );
+        Ok(txid)
+    }
+
+    /// Checks that the lock time `height` is not below the blockchain tip, if an indexer is
+    /// specified and reachable. Exits if the height is in the past.
+    pub fn check_lock_height(&self, height: u32) {
+        if !self.resolver.has_indexer() {
+            return;
+        }
+        let tip = self.indexer().and_then(|indexer| Ok(indexer.tip_height()?));
+        match tip {
+            Ok(Some(tip)) if height < tip => {
+                eprintln!(
+                    "Error: lock time height {height} is below the current blockchain tip {tip}"
+                );
+                exit(1);
+            }
+            Ok(_) => {}
+            Err(err) => {
+                eprintln!(
+                    "Warning: unable to check the lock time against the blockchain tip: {err}"
+                )
+            }
+        }
+    }
+
+    fn wallet_name(&self, conf: &Config) -> String {
+        self.wallet.name.as_ref().map(Ident::to_string).unwrap_or(conf.default_wallet.clone())
+    }
+
+    /// Returns directory of the wallet used by the command, or `None` if the wallet descriptor
+    /// is given as a command-line argument.
+    pub fn wallet_path(&self, conf: &Config) -> Option<PathBuf> {
+        if self.wallet.descriptor_opts.is_some() {
+            return None;
+        }
+        Some(
+            self.wallet
+                .wallet_path
+                .clone()
+                .unwrap_or_else(|| self.general.wallet_dir(self.wallet_name(conf))),
+        )
+    }
+
     #[allow(clippy::multiple_bound_locations)]
     pub fn bp_wallet<D: Descriptor>(
         &self,
         conf: &Config,
     ) -> Result<Wallet<XpubDerivable, D>, ExecError>
+    where
+        for<'de> D: From<O::Descr> + serde::Serialize + serde::Deserialize<'de>,
+    {
+        self.bp_wallet_with_sync(conf, self.sync)
+    }
+
+    /// Loads the wallet, syncing it with the indexer if `sync` is set or the wallet descriptor is
+    /// given in the command line, ignoring the `--sync` option.
+    #[allow(clippy::multiple_bound_locations)]
+    pub fn bp_wallet_with_sync<D: Descriptor>(
+        &self,
+        conf: &Config,
+        sync: bool,
+    ) -> Result<Wallet<XpubDerivable, D>, ExecError>
     where
         for<'de> D: From<O::Descr> + serde::Serialize + serde::Deserialize<'de>,
     {
         eprint!("Loading descriptor");
-        let sync = self.sync || self.wallet.descriptor_opts.is_some();
+        let sync = sync || self.wallet.descriptor_opts.is_some();
 
         let mut wallet: Wallet<XpubDerivable, D> =
             if let Some(d) = self.wallet.descriptor_opts.descriptor() {
@@ -132,19 +409,12 @@ impl<C: Clone + Eq + Debug + Subcommand, O: DescriptorOpts> Args<C, O> {
                 eprint!("Syncing");
                 Wallet::new_layer1(d.into(), self.general.network)
             } else {
-                let path = if let Some(wallet_path) = self.wallet.wallet_path.clone() {
+                if self.wallet.wallet_path.is_some() {
                     eprint!(" from specified wallet directory ... ");
-                    wallet_path
                 } else {
-                    let wallet_name = self
-                        .wallet
-                        .name
-                        .as_ref()
-                        .map(Ident::to_string)
-                        .unwrap_or(conf.default_wallet.clone());
-                    eprint!(" from wallet {wallet_name} ... ");
-                    self.general.wallet_dir(wallet_name)
-                };
+                    eprint!(" from wallet {} ... ", self.wallet_name(conf));
+                }
+                let path = self.wallet_path(conf).expect("no descriptor is given");
                 let provider = FsTextStore::new(path)?;
                 let wallet = Wallet::load(provider, true)?;
                 eprintln!("success");
@@ -152,9 +422,20 @@ impl<C: Clone + Eq + Debug + Subcommand, O: DescriptorOpts> Args<C, O> {
             };
 
         if sync {
+            wallet.set_flush_interval(self.scan.flush_interval());
+            wallet.set_gap_limits(self.scan.gap_limits(conf));
+            let mut current = None;
+            wallet.set_scan_progress(ScanProgress::new(move |keychain, _, _| {
+                if current != Some(keychain) {
+                    eprint!(" keychain {keychain} ");
+                    current = Some(keychain);
+                }
+                eprint!(".");
+            }));
             let indexer = self.indexer()?;
             eprint!("Syncing");
-            if let Some(errors) = wallet.update(&indexer).into_err() {
+            let (reorged, errors) = wallet.update(&indexer).split();
+            if let Some(errors) = errors {
                 eprintln!(" partial, some requests has failed:");
                 for err in errors {
                     eprintln!("- {err}");
@@ -162,6 +443,14 @@ impl<C: Clone + Eq + Debug + Subcommand, O: DescriptorOpts> Args<C, O> {
             } else {
                 eprintln!(" success");
             }
+            if let AnyIndexer::Esplora(client) | AnyIndexer::Mempool(client) = &indexer {
+                for stats in client.pool().map(ClientPool::stats).unwrap_or_default() {
+                    eprintln!("Endpoint {stats}");
+                }
+            }
+            if reorged > 0 {
+                eprintln!("Warning: {reorged} previously mined transaction(s) were reorged out");
+            }
         }
 
         Ok(wallet)
@@ -0,0 +1,214 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Combining of PSBTs carrying different subsets of signatures and other input data for the same
+//! transaction, as performed by the BIP174 combiner role.
+
+use std::hash::Hash;
+
+use bpstd::Txid;
+use indexmap::IndexMap;
+use psbt::{Input, Output, Psbt};
+
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum CombineError {
+    /// no PSBTs provided for combining.
+    NoPsbts,
+
+    /// PSBT #{0} is for transaction {1}, while the first PSBT is for transaction {2}.
+    TxMismatch(usize, Txid, Txid),
+}
+
+/// Combines multiple PSBTs for the same unsigned transaction into a single PSBT, merging
+/// signatures, key derivations, scripts and all other per-input and per-output data.
+///
+/// The resulting PSBT uses the highest version among the combined PSBTs.
+pub fn combine_psbts(psbts: impl IntoIterator<Item = Psbt>) -> Result<Psbt, CombineError> {
+    let mut psbts = psbts.into_iter();
+    let mut combined = psbts.next().ok_or(CombineError::NoPsbts)?;
+    let txid = combined.txid();
+    for (no, psbt) in psbts.enumerate() {
+        let other_txid = psbt.txid();
+        if other_txid != txid {
+            return Err(CombineError::TxMismatch(no + 1, other_txid, txid));
+        }
+        combined.version = combined.version.max(psbt.version);
+        merge_map(&mut combined.xpubs, &psbt.xpubs);
+        merge_map(&mut combined.proprietary, &psbt.proprietary);
+        for (key, map) in &psbt.unknown {
+            merge_map(combined.unknown.entry(*key).or_default(), map);
+        }
+        for (input, other) in combined.inputs_mut().zip(psbt.inputs()) {
+            merge_input(input, other);
+        }
+        for (output, other) in combined.outputs_mut().zip(psbt.outputs()) {
+            merge_output(output, other);
+        }
+    }
+    Ok(combined)
+}
+
+fn merge_input(input: &mut Input, other: &Input) {
+    merge_opt(&mut input.non_witness_tx, &other.non_witness_tx);
+    merge_opt(&mut input.witness_utxo, &other.witness_utxo);
+    merge_map(&mut input.partial_sigs, &other.partial_sigs);
+    merge_opt(&mut input.sighash_type, &other.sighash_type);
+    merge_opt(&mut input.redeem_script, &other.redeem_script);
+    merge_opt(&mut input.witness_script, &other.witness_script);
+    merge_map(&mut input.bip32_derivation, &other.bip32_derivation);
+    merge_opt(&mut input.final_script_sig, &other.final_script_sig);
+    merge_opt(&mut input.final_witness, &other.final_witness);
+    merge_opt(&mut input.proof_of_reserves, &other.proof_of_reserves);
+    merge_map(&mut input.ripemd160, &other.ripemd160);
+    merge_map(&mut input.sha256, &other.sha256);
+    merge_map(&mut input.hash160, &other.hash160);
+    merge_map(&mut input.hash256, &other.hash256);
+    merge_opt(&mut input.tap_key_sig, &other.tap_key_sig);
+    merge_map(&mut input.tap_script_sig, &other.tap_script_sig);
+    merge_map(&mut input.tap_leaf_script, &other.tap_leaf_script);
+    merge_map(&mut input.tap_bip32_derivation, &other.tap_bip32_derivation);
+    merge_opt(&mut input.tap_internal_key, &other.tap_internal_key);
+    merge_opt(&mut input.tap_merkle_root, &other.tap_merkle_root);
+    merge_map(&mut input.proprietary, &other.proprietary);
+    for (key, map) in &other.unknown {
+        merge_map(input.unknown.entry(*key).or_default(), map);
+    }
+}
+
+fn merge_output(output: &mut Output, other: &Output) {
+    merge_opt(&mut output.redeem_script, &other.redeem_script);
+    merge_opt(&mut output.witness_script, &other.witness_script);
+    merge_map(&mut output.bip32_derivation, &other.bip32_derivation);
+    merge_opt(&mut output.tap_internal_key, &other.tap_internal_key);
+    merge_opt(&mut output.tap_tree, &other.tap_tree);
+    merge_map(&mut output.tap_bip32_derivation, &other.tap_bip32_derivation);
+    merge_map(&mut output.proprietary, &other.proprietary);
+    for (key, map) in &other.unknown {
+        merge_map(output.unknown.entry(*key).or_default(), map);
+    }
+}
+
+fn merge_opt<T: Clone>(dst: &mut Option<T>, src: &Option<T>) {
+    if dst.is_none() {
+        dst.clone_from(src);
+    }
+}
+
+fn merge_map<K: Clone + Eq + Hash, V: Clone>(dst: &mut IndexMap<K, V>, src: &IndexMap<K, V>) {
+    for (key, value) in src {
+        dst.entry(key.clone()).or_insert_with(|| value.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bpstd::TxVer;
+    use psbt::PsbtVer;
+
+    use super::*;
+
+    #[test]
+    fn combine_version() {
+        let psbt = Psbt::create(PsbtVer::V0);
+        let combined = combine_psbts([psbt.clone(), Psbt::create(PsbtVer::V2), psbt]).unwrap();
+        assert_eq!(combined.version, PsbtVer::V2);
+    }
+
+    #[test]
+    fn combine_mismatch() {
+        let psbt = Psbt::create(PsbtVer::V0);
+        let mut other = Psbt::create(PsbtVer::V0);
+        other.tx_version = TxVer::V1;
+        assert_eq!(
+            combine_psbts([psbt.clone(), other.clone()]),
+            Err(CombineError::TxMismatch(1, other.txid(), psbt.txid()))
+        );
+        assert_eq!(combine_psbts([]), Err(CombineError::NoPsbts));
+    }
+
+    #[test]
+    #[cfg(feature = "signers")]
+    fn combine_partial_sigs() {
+        use std::str::FromStr;
+
+        use bpstd::signers::TestnetRefSigner;
+        use bpstd::{
+            HardenedIndex, Idx, Keychain, NormalIndex, Outpoint, Sats, ScriptPubkey, SeqNo,
+            SighashType, Terminal, Vout, XprivAccount, XpubDerivable,
+        };
+        use descriptors::{StdDescr, Wpkh};
+        use psbt::Prevout;
+
+        let derivation = [HardenedIndex::hardened(84), HardenedIndex::ONE, HardenedIndex::ZERO];
+        let cosigner = |seed: u8| {
+            let account = XprivAccount::with_seed(true, &[seed; 32]).derive(derivation);
+            let xpub = format!("{}/<0;1>/*", account.to_xpub_account());
+            let xpub = XpubDerivable::from_str(&xpub).unwrap();
+            (account, StdDescr::<XpubDerivable>::from(Wpkh::from(xpub)))
+        };
+        let (account_a, descr_a) = cosigner(7);
+        let (account_b, descr_b) = cosigner(8);
+
+        let mut psbt = Psbt::create(PsbtVer::V2);
+        let terminal = Terminal::new(Keychain::OUTER, NormalIndex::ZERO);
+        for (no, descr) in [&descr_a, &descr_b].into_iter().enumerate() {
+            let outpoint = Outpoint::new(Txid::from([1u8; 32]), Vout::from_u32(no as u32));
+            let prevout = Prevout::new(outpoint, Sats::from(10_000u64));
+            psbt.construct_input_expect(prevout, descr, terminal, SeqNo::ZERO);
+        }
+        psbt.construct_output_expect(ScriptPubkey::op_return(b"test"), Sats::ZERO);
+
+        let mut signed_a = psbt.clone();
+        assert_eq!(signed_a.sign(&TestnetRefSigner::new(&account_a)).unwrap(), 1);
+        let mut signed_b = psbt.clone();
+        assert_eq!(signed_b.sign(&TestnetRefSigner::new(&account_b)).unwrap(), 1);
+        let mut signed = signed_a.clone();
+        assert_eq!(signed.sign(&TestnetRefSigner::new(&account_b)).unwrap(), 1);
+
+        let combined = combine_psbts([signed_a.clone(), signed_b.clone()]).unwrap();
+        assert_eq!(combined, signed);
+        assert_eq!(combine_psbts([signed_b.clone(), signed_a.clone()]).unwrap(), signed);
+        assert_eq!(combine_psbts([signed_a.clone(), signed_a.clone()]).unwrap(), signed_a);
+
+        // On conflicting values the ones from the earlier PSBT are kept
+        let sig_a = *signed_a.inputs().next().unwrap().partial_sigs.values().next().unwrap();
+        let sig_b = *signed_b.inputs().nth(1).unwrap().partial_sigs.values().next().unwrap();
+        let mut conflicting = signed_b.clone();
+        let input = conflicting.input_mut(0).unwrap();
+        let pk_a = *signed_a.inputs().next().unwrap().partial_sigs.keys().next().unwrap();
+        input.partial_sigs.insert(pk_a, sig_b);
+        input.sighash_type = Some(SighashType::none());
+        signed_a.input_mut(0).unwrap().sighash_type = Some(SighashType::all());
+
+        let combined = combine_psbts([signed_a.clone(), conflicting.clone()]).unwrap();
+        let input = combined.inputs().next().unwrap();
+        assert_eq!(input.partial_sigs.get(&pk_a), Some(&sig_a));
+        assert_eq!(input.sighash_type, Some(SighashType::all()));
+        assert_eq!(combined.inputs().nth(1).unwrap(), signed.inputs().nth(1).unwrap());
+
+        let combined = combine_psbts([conflicting, signed_a]).unwrap();
+        let input = combined.inputs().next().unwrap();
+        assert_eq!(input.partial_sigs.get(&pk_a), Some(&sig_b));
+        assert_eq!(input.sighash_type, Some(SighashType::none()));
+    }
+}
@@ -20,23 +20,283 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display};
 use std::fs::File;
+use std::hash::BuildHasher;
+use std::io::Write;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 use std::process::exit;
-use std::{fs, io};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use std::{fs, io, thread};
 
+use amplify::hex::{FromHex, ToHex};
 use amplify::IoError;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use bpstd::psbt::{Beneficiary, TxParams};
-use bpstd::{ConsensusEncode, Derive, IdxBase, Keychain, NormalIndex, Sats, Tx, XpubDerivable};
+use bpstd::{
+    Address, AddressNetwork, AddressType, BlockHash, ConsensusDecode, ConsensusDecodeError,
+    ConsensusEncode, Derive, DerivedAddr, DerivedScript, HardenedIndex, Idx, IdxBase, Keychain,
+    LockTime, Network, NormalIndex, Outpoint, Sats, ScriptPubkey, SeqNo, SighashFlag, Terminal, Tx,
+    TxVer, Txid, Weight, Witness, XpubAccount, XpubDerivable, XpubFp,
+};
 use colored::Colorize;
-use descriptors::Descriptor;
+use descriptors::{Descriptor, SpkClass, StdDescr, TrKey, Wpkh};
 use nonasync::persistence::PersistenceError;
-use psbt::{ConstructionError, Payment, Psbt, PsbtConstructor, PsbtVer, UnfinalizedInputs};
+use psbt::{
+    ConstructionError, Payment, Psbt, PsbtConstructor, PsbtMeta, PsbtVer, UnfinalizedInputs,
+};
 use strict_encoding::Ident;
 
-use crate::cli::{Args, Config, DescriptorOpts, Exec};
+use crate::bip322::{self, Bip322Error};
+use crate::cli::{
+    apply_max_floors, apply_sighash_types, apply_sigs, checksummed, combine_psbts, disassemble,
+    export_labels, import_labels, multisig_threshold, privacy_check, Args, Bip329Error,
+    CoinStrategy, CombineError, Config, CoreExport, CoreImportError, DescriptorExport,
+    DescriptorOpts, DetachedSig, DetachedSigError, Exec, FeeRate, InputSighash, InputSighashError,
+    Payee, PayeeError, SpendFee, SpendPlan, SpendRequest, SIGNER_PROFILES,
+};
 use crate::fs::FsTextStore;
-use crate::{coinselect, AnyIndexerError, Indexer, OpType, Wallet, WalletAddr, WalletUtxo};
+use crate::{
+    coinselect, finalize_psbt, AnyIndexer, AnyIndexerError, Bip43, BlockHeight, CoinRow,
+    Counterparty, Indexer, LabelTarget, Layer2Empty, Layer2Tx, OpType, Party, RawTxError,
+    ScanProgress, TxRow, TxStatus, Wallet, WalletAddr, WalletTx, WalletUtxo, DEFAULT_GAP_LIMIT,
+};
+
+/// Transaction given either in hex-encoded form or by its id
+#[derive(Clone, PartialEq, Eq, Debug, Display)]
+pub enum TxRef {
+    #[display(inner)]
+    Txid(Txid),
+    #[display("{0:x}")]
+    Tx(Tx),
+}
+
+impl FromStr for TxRef {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Transaction id is 32 bytes long, while the smallest possible transaction is 60 bytes
+        if s.len() == 64 {
+            return Txid::from_str(s).map(TxRef::Txid).map_err(|e| e.to_string());
+        }
+        Tx::from_str(s).map(TxRef::Tx).map_err(|e| e.to_string())
+    }
+}
+
+/// Source of the private key material for signing PSBTs
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SignerSource {
+    /// Encrypted seed file, created with `bp-hot seed`
+    Seed(String),
+
+    /// Encrypted signing account file with extended private key, created with `bp-hot derive`
+    Account(String),
+
+    /// Environment variable holding either a BIP-39 seed mnemonic or an extended private key with
+    /// its origin
+    Env(String),
+
+    /// Hardware wallet connected via HWI, optionally selected by its master key fingerprint
+    Hwi(Option<XpubFp>),
+}
+
+impl Display for SignerSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignerSource::Seed(path) => write!(f, "seed:{path}"),
+            SignerSource::Account(path) => write!(f, "account:{path}"),
+            SignerSource::Env(var) => write!(f, "env:{var}"),
+            SignerSource::Hwi(None) => f.write_str("hwi"),
+            SignerSource::Hwi(Some(fp)) => write!(f, "hwi:{fp}"),
+        }
+    }
+}
+
+impl FromStr for SignerSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("seed", path)) => Ok(SignerSource::Seed(path.to_owned())),
+            Some(("account", path)) => Ok(SignerSource::Account(path.to_owned())),
+            Some(("env", var)) => Ok(SignerSource::Env(var.to_owned())),
+            Some(("hwi", fp)) => XpubFp::from_str(fp)
+                .map(|fp| SignerSource::Hwi(Some(fp)))
+                .map_err(|e| format!("invalid hardware wallet fingerprint '{fp}': {e}")),
+            None if s == "hwi" => Ok(SignerSource::Hwi(None)),
+            _ => Err(format!(
+                "'{s}' is not a signer source; use `seed:<file>`, `account:<file>`, `env:<var>` \
+                 or `hwi[:<fingerprint>]`"
+            )),
+        }
+    }
+}
+
+/// Half-open range of derivation indexes given as `<start>..<end>`
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Display)]
+#[display("{start}..{end}")]
+pub struct IndexRange {
+    pub start: NormalIndex,
+    pub end: NormalIndex,
+}
+
+impl FromStr for IndexRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) =
+            s.split_once("..").ok_or_else(|| format!("'{s}' is not a range `<start>..<end>`"))?;
+        let start = NormalIndex::from_str(start).map_err(|e| e.to_string())?;
+        let end = NormalIndex::from_str(end).map_err(|e| e.to_string())?;
+        if start > end {
+            return Err(format!("range start {start} exceeds its end {end}"));
+        }
+        Ok(IndexRange { start, end })
+    }
+}
+
+/// Output format for streamed addresses
+#[derive(ValueEnum, Copy, Clone, PartialEq, Eq, Debug, Default, Display)]
+pub enum AddressFormat {
+    /// Tab-separated terminal, address and scriptPubkey
+    #[default]
+    #[display("text")]
+    Text,
+
+    /// JSON object per line
+    #[display("json")]
+    Json,
+}
+
+/// Output format for the wallet history
+#[derive(ValueEnum, Copy, Clone, PartialEq, Eq, Debug, Default, Display)]
+pub enum HistoryFormat {
+    /// Human-readable table
+    #[default]
+    #[display("text")]
+    Text,
+
+    /// Comma-separated values with a header line
+    #[display("csv")]
+    Csv,
+}
+
+/// Output format for the inspected PSBT
+#[derive(ValueEnum, Copy, Clone, PartialEq, Eq, Debug, Default, Display)]
+pub enum InspectFormat {
+    /// YAML representation of the PSBT structure
+    #[default]
+    #[display("yaml")]
+    Yaml,
+
+    /// JSON representation of the PSBT structure
+    #[display("json")]
+    Json,
+
+    /// Hex-encoded consensus-serialized PSBT
+    #[display("hex")]
+    Hex,
+}
+
+/// Format of the exported wallet descriptor
+#[derive(ValueEnum, Copy, Clone, PartialEq, Eq, Debug, Default, Display)]
+pub enum ExportFormat {
+    /// Output descriptor with BIP-380 checksum
+    #[default]
+    #[display("descriptor")]
+    Descriptor,
+
+    /// BIP-388 wallet policy: descriptor template followed by the list of keys
+    #[display("policy")]
+    Policy,
+
+    /// JSON bundle with network, descriptor, wallet policy and per-keychain descriptors
+    #[display("json")]
+    Json,
+}
+
+/// Script type of the wallet descriptors scanned during the recovery
+#[derive(ValueEnum, Copy, Clone, PartialEq, Eq, Debug, Display)]
+pub enum ScriptType {
+    /// Native segwit v0 P2WPKH outputs, derived according to BIP-84
+    #[display("wpkh")]
+    Wpkh,
+
+    /// Single-key taproot P2TR outputs, derived according to BIP-86
+    #[display("tr")]
+    Tr,
+}
+
+impl ScriptType {
+    /// Derivation standard used for the accounts of this script type.
+    pub fn bip43(self) -> Bip43 {
+        match self {
+            ScriptType::Wpkh => Bip43::Bip84,
+            ScriptType::Tr => Bip43::Bip86,
+        }
+    }
+
+    /// Constructs wallet descriptor of this script type for the account.
+    pub fn descriptor(self, account: XpubAccount) -> StdDescr {
+        let xpub = XpubDerivable::from(account);
+        match self {
+            ScriptType::Wpkh => Wpkh::from(xpub).into(),
+            ScriptType::Tr => TrKey::from(xpub).into(),
+        }
+    }
+}
+
+/// Strategy for selecting coins spent by a transaction
+#[derive(ValueEnum, Copy, Clone, PartialEq, Eq, Debug, Display)]
+pub enum CoinSelection {
+    /// Branch-and-bound search for coins matching the payments and the fee up to the dust
+    /// limit, avoiding change output; falls back to the largest coins first
+    #[display("bnb")]
+    Bnb,
+
+    /// Largest coins first, minimizing number of inputs
+    #[display("largest")]
+    Largest,
+
+    /// Smallest coins first, consolidating small coins
+    #[display("smallest")]
+    Smallest,
+
+    /// Coins with the earliest confirmation first
+    #[display("oldest")]
+    Oldest,
+}
+
+/// Commands managing labels of wallet transactions, transaction outputs and addresses
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum LabelCommand {
+    /// Assign label to a transaction, transaction output (`<txid>:<vout>`) or address, replacing
+    /// its existing label
+    #[display("set")]
+    Set {
+        /// Transaction id, transaction output or address to label
+        target: LabelTarget,
+
+        /// Label text
+        text: String,
+    },
+
+    /// Remove label of a transaction, transaction output or address
+    #[display("remove")]
+    Remove {
+        /// Transaction id, transaction output or address to remove the label from
+        target: LabelTarget,
+    },
+
+    /// List all wallet labels
+    #[display("list")]
+    List,
+}
 
 #[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
 pub enum Command {
@@ -79,7 +339,109 @@ pub enum Command {
 
         /// Number of addresses to generate
         #[clap(short = 'C', long, default_value = "1")]
-        count: u8,
+        count: u32,
+
+        /// Generate all addresses in the half-open index range `<start>..<end>` without shifting
+        /// the last used index
+        #[clap(long, conflicts_with_all = ["count", "index", "dry_run", "stream"])]
+        range: Option<IndexRange>,
+
+        /// Stream addresses to STDOUT line by line, starting from index zero (or the index given
+        /// by `--index`) without shifting the last used index. Suitable for exporting large
+        /// address ranges to external indexers
+        #[clap(long, conflicts_with = "dry_run")]
+        stream: bool,
+
+        /// Format of the streamed addresses
+        #[clap(long, requires = "stream", default_value = "text")]
+        format: AddressFormat,
+
+        /// Print each address as a QR code. Requires `qr` feature
+        #[clap(long, conflicts_with = "stream")]
+        qr: bool,
+
+        /// Cross-check each address against the scriptPubkey re-derived from an independently
+        /// re-parsed copy of the wallet descriptor before printing it
+        #[clap(long, conflicts_with = "stream")]
+        verify: bool,
+    },
+
+    /// Show scripts derived from the wallet descriptor for a specific terminal, disassembled into
+    /// opcodes
+    #[display("script-at")]
+    ScriptAt {
+        /// Keychain to derive the scripts for
+        #[clap(short, long, default_value = "0")]
+        keychain: Keychain,
+
+        /// Address index to derive the scripts for
+        index: NormalIndex,
+    },
+
+    /// Validate an address against the wallet network, showing its script type and whether it
+    /// belongs to the wallet
+    #[display("validate-address")]
+    ValidateAddress {
+        /// Address to validate
+        address: String,
+    },
+
+    /// Import a wallet from Bitcoin Core descriptor export produced by `listdescriptors` RPC
+    #[display("import-core")]
+    ImportCore {
+        /// Import taproot descriptors instead of segwit v0 ones
+        #[clap(long)]
+        taproot: bool,
+
+        /// The name for the imported wallet
+        name: Ident,
+
+        /// JSON file with `listdescriptors` output
+        file: PathBuf,
+    },
+
+    /// Export the wallet descriptor for use in other wallet software. Exports never contain
+    /// private keys
+    #[display("export")]
+    Export {
+        /// Format of the export
+        #[clap(long, default_value = "descriptor")]
+        format: ExportFormat,
+    },
+
+    /// Recover funds of a key used with an unknown script type, scanning wallet descriptors of
+    /// each of the given script types and reporting balances found for each of them
+    #[display("recover")]
+    Recover {
+        /// Account-level extended public key with its origin, `[fingerprint/path]xpub`, used with
+        /// each of the script types
+        #[clap(long, required_unless_present = "mnemonic_env")]
+        xpub: Option<XpubAccount>,
+
+        /// Name of an environment variable containing BIP-39 mnemonic of a seed, from which
+        /// standard accounts for each of the script types are derived. Requires `hot` feature
+        #[clap(long, conflicts_with = "xpub")]
+        mnemonic_env: Option<String>,
+
+        /// Name of an environment variable containing BIP-39 passphrase protecting the mnemonic
+        #[clap(long, requires = "mnemonic_env")]
+        passphrase_env: Option<String>,
+
+        /// Account derivation number used with the mnemonic (should be hardened, i.e. with `h`
+        /// suffix)
+        #[clap(long, default_value = "0h", requires = "mnemonic_env")]
+        account: HardenedIndex,
+
+        /// Script types to scan
+        #[clap(short, long = "type", value_delimiter = ',', default_value = "wpkh,tr")]
+        types: Vec<ScriptType>,
+    },
+
+    /// Check whether common hardware signers are able to handle the wallet descriptor
+    #[display("signer-check")]
+    SignerCheck {
+        /// Name of the wallet to check. If not given, checks the current wallet
+        name: Option<Ident>,
     },
 
     /// Finalize a PSBT, optionally extracting and publishing the signed transaction
@@ -89,6 +451,10 @@ pub enum Command {
         #[clap(short, long)]
         publish: bool,
 
+        /// Overwrite the transaction file if it already exists.
+        #[clap(short, long)]
+        force: bool,
+
         /// Name of PSBT file to finalize.
         psbt: PathBuf,
 
@@ -103,6 +469,10 @@ pub enum Command {
         #[clap(short, long)]
         publish: bool,
 
+        /// Overwrite the transaction file if it already exists.
+        #[clap(short, long)]
+        force: bool,
+
         /// Name of PSBT file to take the transaction from
         psbt: PathBuf,
 
@@ -118,6 +488,15 @@ pub enum BpCommand {
     #[display(inner)]
     General(Command),
 
+    /// Display lifetime wallet statistics: total received, sent, paid in fees and the current
+    /// balance
+    #[display("summary")]
+    Summary {
+        /// Print the summary in JSON format
+        #[clap(long)]
+        json: bool,
+    },
+
     /// List wallet balance and UTXOs
     #[display("balance")]
     Balance {
@@ -128,6 +507,20 @@ pub enum BpCommand {
         /// Print information about individual UTXOs
         #[clap(short, long)]
         utxo: bool,
+
+        /// Print the balance in JSON format, nesting UTXOs into the addresses if both `--addr`
+        /// and `--utxo` are given
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Display balance and UTXOs of a single wallet address. With `--sync` option and
+    /// `--esplora` or `--mempool` indexer only the history of this address is retrieved from the
+    /// indexer; other indexers sync the whole wallet
+    #[display("address-balance")]
+    AddressBalance {
+        /// Wallet address to display the balance for
+        address: Address,
     },
 
     /// Display history of wallet operations
@@ -140,81 +533,759 @@ pub enum BpCommand {
         /// Print operation details
         #[clap(long)]
         details: bool,
+
+        /// Print hex-encoded raw transactions, retrieving them from the indexer if they were not
+        /// retrieved before
+        #[clap(long)]
+        raw: bool,
+
+        /// Classify scripts of the counterparties which can't be represented as an address (bare
+        /// multisig, future witness versions, non-standard scripts) in the operation details
+        #[clap(long, requires = "details")]
+        classify: bool,
+
+        /// Output format. CSV contains full transaction ids and block timestamps; with
+        /// `--details` it has a row per each of the operation details
+        #[clap(long, default_value = "text", conflicts_with_all = ["txid", "raw", "classify"])]
+        format: HistoryFormat,
+
+        /// Write the CSV history to a file instead of STDOUT
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+
+        /// Include only transactions mined at or after the given block height
+        #[clap(long)]
+        since: Option<u32>,
+
+        /// Include only transactions mined at or before the given block height. Unconfirmed
+        /// transactions are excluded if this option is given
+        #[clap(long)]
+        until: Option<u32>,
+
+        /// Include only the given number of the most recent transactions
+        #[clap(long)]
+        limit: Option<usize>,
+    },
+
+    /// Display unconfirmed wallet transactions, indicating whether they signal RBF and thus may
+    /// still be replaced
+    #[display("pending")]
+    Pending {
+        /// Print the pending transactions in JSON format
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Manage labels of wallet transactions, transaction outputs and addresses, which are shown
+    /// in the balance and history outputs
+    #[display("label")]
+    Label {
+        #[clap(subcommand)]
+        command: LabelCommand,
+    },
+
+    /// Export wallet labels in BIP-329 format, compatible with other wallets
+    #[display("label-export")]
+    LabelExport {
+        /// File to write the labels to. If not given, the labels are printed to STDOUT
+        path: Option<PathBuf>,
+    },
+
+    /// Import labels in BIP-329 format, replacing existing labels of the same objects. Labels of
+    /// objects not known to the wallet, as well as labels of public keys, transaction inputs and
+    /// xpubs are skipped
+    #[display("label-import")]
+    LabelImport {
+        /// File with the labels
+        path: PathBuf,
+    },
+
+    /// Freeze wallet coin, excluding it from automatic coin selection. Frozen coins can still be
+    /// spent by giving them explicitly with `--utxo`
+    #[display("freeze")]
+    Freeze {
+        /// Wallet coin to freeze, in form of `<txid>:<vout>`
+        outpoint: Outpoint,
     },
 
-    /// Inspect transaction
-    Tx { tx: Tx },
+    /// Unfreeze wallet coin, returning it to automatic coin selection
+    #[display("unfreeze")]
+    Unfreeze {
+        /// Wallet coin to unfreeze, in form of `<txid>:<vout>`
+        outpoint: Outpoint,
+    },
+
+    /// Display history of an arbitrary address using Esplora or Mempool indexer
+    #[display("address-history")]
+    AddressHistory {
+        /// Print the history in JSON format
+        #[clap(long)]
+        json: bool,
+
+        /// Address to retrieve history for
+        address: Address,
+    },
+
+    /// Check consistency of the wallet cache. Exits with non-zero code if inconsistencies are
+    /// found
+    #[display("check")]
+    Check {
+        /// Verify cached address balances and UTXO set against their recomputation from the
+        /// cached transactions
+        #[clap(long)]
+        balances: bool,
+    },
+
+    /// Verify cached transactions against the indexer, reporting transactions which were
+    /// replaced, reorged out or diverge from the indexer data. Exits with non-zero code if
+    /// divergences are found
+    #[display("verify")]
+    Verify {
+        /// Re-fetch each transaction and compare its inputs and outputs with the cached ones,
+        /// instead of checking only the transaction status. This is slow on large wallets
+        #[clap(long)]
+        deep: bool,
+
+        /// Percentage of randomly selected cached transactions to verify
+        #[clap(long, default_value = "100", value_parser = clap::value_parser!(u8).range(1..=100))]
+        sample: u8,
+    },
+
+    /// Discard the cached wallet data and rebuild them with a full indexer scan. Useful when the
+    /// cache became inconsistent, for instance after a reorg or an interrupted sync
+    #[display("rescan")]
+    Rescan,
+
+    /// Wait until an address receives the expected amount with the required number of
+    /// confirmations, using Esplora or Mempool indexer. Exits with non-zero code on timeout
+    #[display("await-payment")]
+    AwaitPayment {
+        /// Number of confirmations required for the payment to be accepted. Zero accepts
+        /// unconfirmed payments
+        #[clap(short, long, default_value = "1")]
+        confirmations: u32,
+
+        /// Maximal time to wait for the payment, in seconds
+        #[clap(short, long, default_value = "3600")]
+        timeout: u64,
+
+        /// Interval between indexer requests, in seconds. The interval is doubled each time the
+        /// indexer fails to respond, which happens when it rate limits requests
+        #[clap(long, default_value = "30")]
+        interval: u64,
+
+        /// Invoice address to watch
+        address: Address,
+
+        /// Expected amount
+        amount: Sats,
+    },
+
+    /// Estimate fee rate using the fee estimate sources from the config
+    #[display("fee-estimate")]
+    FeeEstimate {
+        /// Number of blocks within which the transaction should be mined. May be repeated or
+        /// given as a comma-separated list; defaults to 1, 3, 6 and 144 blocks
+        #[clap(short, long = "target", value_delimiter = ',')]
+        targets: Vec<u16>,
+    },
+
+    /// Print mempool fee-rate histogram reported by the indexer, with the cumulative size of
+    /// transactions paying at least each fee rate and the number of blocks required to mine them
+    #[display("mempool")]
+    Mempool,
+
+    /// Scan multiple independent descriptors using a single indexer connection and output their
+    /// balances and coins as JSON map keyed by the descriptor
+    #[display("scan-batch")]
+    ScanBatch {
+        /// Maximal number of descriptors scanned in parallel
+        #[clap(short, long, default_value = "4")]
+        jobs: NonZeroUsize,
+
+        /// YAML file containing a list of descriptors to scan
+        descriptors_file: PathBuf,
+    },
+
+    /// Inspect transaction, given either hex-encoded or by the id of a wallet transaction
+    Tx {
+        /// Print hex-encoded raw transaction instead of its YAML representation
+        #[clap(long)]
+        raw: bool,
+
+        /// Hex-encoded transaction or transaction id
+        tx: TxRef,
+    },
 
     /// Inspect PSBT file
     Inspect {
+        /// Print the PSBT as a base64-encoded string instead of its YAML representation
+        #[clap(long)]
+        base64: bool,
+
+        /// Print the PSBT as a QR code instead of its YAML representation, splitting it into
+        /// animated BBQr fragments if it does not fit into a single code. Requires `qr` feature
+        #[clap(long, conflicts_with = "base64")]
+        qr: bool,
+
+        /// Output format
+        #[clap(long, default_value = "yaml", conflicts_with_all = ["base64", "qr"])]
+        format: InspectFormat,
+
+        /// Print a summary of the input signing status instead of the full PSBT: amounts, number
+        /// of required and provided signatures, fingerprints of the signers and finalization
+        #[clap(long, conflicts_with_all = ["base64", "qr", "format"])]
+        summary: bool,
+
         /// Name of a PSBT file to inspect
         psbt: PathBuf,
     },
 
-    /// Compose a new PSBT for bitcoin payment
-    #[display("construct")]
-    Construct {
+    /// Decode a raw transaction file, labelling outputs belonging to the wallet
+    #[display("decode-tx")]
+    DecodeTx {
+        /// Print the decoded transaction in JSON format instead of YAML
+        #[clap(long)]
+        json: bool,
+
+        /// File containing consensus-encoded transaction, either hex-encoded or binary
+        tx: PathBuf,
+    },
+
+    /// Publish a signed raw transaction file, for instance produced by an offline signer
+    #[display("broadcast")]
+    Broadcast {
+        /// File containing consensus-encoded transaction, either hex-encoded or binary
+        tx: PathBuf,
+    },
+
+    /// Report which BIP174 roles were already completed for a PSBT
+    #[display("status")]
+    Status {
+        /// Print the status in JSON format
+        #[clap(long)]
+        json: bool,
+
+        /// Name of a PSBT file to analyze
+        psbt: PathBuf,
+    },
+
+    /// Report how far the wallet cache is behind the indexer chain tip
+    #[display("sync-status")]
+    SyncStatus {
+        /// Print the status in JSON format
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Show how wallet balances change once a PSBT transaction gets mined
+    #[display("simulate")]
+    Simulate {
+        /// Name of a PSBT file to simulate
+        psbt: PathBuf,
+    },
+
+    /// Add signatures produced by a detached signer to a PSBT
+    ///
+    /// Each line of the signature file contains zero-based input number, public key, signature
+    /// and, for tapscript signatures, the leaf hash, all separated by whitespaces. Each signature
+    /// is verified against the input sighash before being added.
+    #[display("apply-sigs")]
+    ApplySigs {
+        /// Name of a PSBT file to add signatures to
+        psbt: PathBuf,
+
+        /// File containing detached signatures
+        sigs: PathBuf,
+    },
+
+    /// Combine multiple PSBTs for the same transaction, each carrying a subset of signatures, into
+    /// a single PSBT
+    #[display("combine")]
+    Combine {
+        /// Overwrite the output PSBT file if it already exists
+        #[clap(short, long)]
+        force: bool,
+
+        /// Names of PSBT files to combine
+        #[clap(required = true, num_args = 2..)]
+        inputs: Vec<PathBuf>,
+
+        /// Name of the PSBT file to save the combined PSBT to
+        output: PathBuf,
+    },
+
+    /// Compose a PSBT splitting the whole wallet balance into multiple outputs of approximately
+    /// equal value sent to fresh change addresses of the wallet
+    #[display("split")]
+    Split {
         /// Encode PSBT as V2
         #[clap(short = '2')]
         v2: bool,
 
-        /// Bitcoin invoice in form of `<sats>@<address>`. To spend full wallet balance use
-        /// `MAX` for the amount.
-        ///
-        /// If multiple `MAX` addresses provided the wallet balance is split between them in equal
-        /// proportions.
+        /// Number of outputs to create
+        count: NonZeroUsize,
+
+        /// Fee rate for the transaction, in sats per vbyte
+        fee_rate: FeeRate,
+
+        /// Name of a PSBT file to save. If not given, prints PSBT to STDOUT
+        psbt: Option<PathBuf>,
+    },
+
+    /// Compose a PSBT spending all wallet coins to a single address. The fee is computed from the
+    /// estimated size of the signed transaction
+    #[display("sweep")]
+    Sweep {
+        /// Encode PSBT as V2
+        #[clap(short = '2')]
+        v2: bool,
+
+        /// Address receiving the whole wallet balance net of the fee
         #[clap(long)]
-        to: Vec<Beneficiary>,
+        to: Address,
 
-        /// Fee
-        fee: Sats,
+        /// Print the constructed PSBT in base64 together with its fee and size metadata in JSON
+        /// format
+        #[clap(long)]
+        json: bool,
+
+        /// Output the PSBT as a base64-encoded string. If a PSBT file name is given, the file
+        /// will contain the base64 text instead of the binary encoding
+        #[clap(long, conflicts_with = "json")]
+        base64: bool,
+
+        /// Overwrite the PSBT file if it already exists
+        #[clap(short, long)]
+        force: bool,
+
+        /// Fee rate for the transaction, in sats per vbyte
+        fee_rate: FeeRate,
 
         /// Name of a PSBT file to save. If not given, prints PSBT to STDOUT
         psbt: Option<PathBuf>,
     },
-}
 
-#[derive(Debug, Display, Error, From)]
-#[non_exhaustive]
-#[display(inner)]
-pub enum ExecError {
-    #[from]
-    #[from(io::Error)]
-    Io(IoError),
+    /// Compose a PSBT unsticking a pending transaction: a fee-bumped replacement for transactions
+    /// signalling RBF, or a CPFP child spending one of its outputs otherwise. If the signing
+    /// account is given, the new transaction is signed and published right away
+    #[display("unstick")]
+    Unstick {
+        /// Encode PSBT as V2
+        #[clap(short = '2')]
+        v2: bool,
 
-    #[from]
-    Store(PersistenceError),
+        /// Signing account file, created with `bp-hot derive`, holding keys to sign the new
+        /// transaction
+        #[clap(long, value_name = "FILE")]
+        signing_account: Option<PathBuf>,
 
-    #[from]
-    ConstructPsbt(ConstructionError),
+        /// Pending transaction to unstick
+        txid: Txid,
 
-    #[from]
-    DecodePsbt(psbt::DecodeError),
+        /// Target fee rate for the replacement transaction or for the package of the pending
+        /// transaction and its child, in sats per vbyte
+        fee_rate: FeeRate,
 
-    #[from]
-    Unfinalized(UnfinalizedInputs),
+        /// Name of a PSBT file to save. If not given, prints PSBT to STDOUT
+        psbt: Option<PathBuf>,
+    },
 
-    /// indexer failed with {0}
-    #[from]
-    #[cfg_attr(feature = "electrum", from(electrum::Error))]
-    #[cfg_attr(feature = "electrum", from(crate::indexers::electrum::ElectrumError))]
-    #[cfg_attr(feature = "esplora", from(esplora::Error))]
-    #[display(doc_comments)]
-    Indexer(AnyIndexerError),
-}
+    /// Compose a PSBT replacing a pending wallet transaction with the one paying a higher fee
+    /// (RBF fee bump)
+    ///
+    /// The replacement spends the same inputs and pays the same beneficiaries, reducing the change
+    /// to cover the higher fee. If the change is not sufficient, more wallet coins are added.
+    #[display("bump-fee")]
+    BumpFee {
+        /// Encode PSBT as V2
+        #[clap(short = '2')]
+        v2: bool,
 
-impl<O: DescriptorOpts> Exec for Args<Command, O> {
-    type Error = ExecError;
-    const CONF_FILE_NAME: &'static str = "bp.toml";
+        /// Pending transaction to replace
+        txid: Txid,
 
-    fn exec(self, mut config: Config, conf_filename: &'static str) -> Result<(), Self::Error> {
-        match &self.command {
-            Command::List => {
-                let dir = self.general.base_dir();
-                let Ok(dir) = fs::read_dir(dir).inspect_err(|err| {
-                    error!("Error reading wallet directory: {err:?}");
-                    eprintln!("System directory is not initialized");
-                    println!("no wallets found");
+        /// Fee rate for the replacement transaction, in sats per vbyte
+        fee_rate: FeeRate,
+
+        /// Name of a PSBT file to save. If not given, prints PSBT to STDOUT
+        psbt: Option<PathBuf>,
+    },
+
+    /// Compose a PSBT spending an unconfirmed wallet output by a child transaction paying for its
+    /// parent (CPFP fee bump)
+    ///
+    /// The child sends the output back to the wallet with a fee sized so the package of the parent
+    /// and the child pays the requested effective fee rate.
+    #[display("bump-fee-cpfp")]
+    BumpFeeCpfp {
+        /// Encode PSBT as V2
+        #[clap(short = '2')]
+        v2: bool,
+
+        /// Unconfirmed wallet output to spend
+        outpoint: Outpoint,
+
+        /// Effective fee rate for the package of the parent and child transactions, in sats per
+        /// vbyte
+        fee_rate: FeeRate,
+
+        /// Name of a PSBT file to save. If not given, prints PSBT to STDOUT
+        psbt: Option<PathBuf>,
+    },
+
+    /// Compose a new PSBT for bitcoin payment
+    #[display("construct")]
+    Construct {
+        /// Encode PSBT as V2
+        #[clap(short = '2')]
+        v2: bool,
+
+        /// Bitcoin invoice in form of `<sats>@<address>`. To spend full wallet balance use
+        /// `MAX` for the amount.
+        ///
+        /// If multiple `MAX` addresses provided the wallet balance is split between them in equal
+        /// proportions. Use `MAX(min=<sats>)` to guarantee a recipient a minimal amount; the
+        /// balance above the floors is split equally.
+        #[clap(long)]
+        to: Vec<Payee>,
+
+        /// Spend only coins bearing the provided label (assigned either to the transaction
+        /// output or to the address holding it).
+        #[clap(long)]
+        from_label: Option<String>,
+
+        /// Spend exactly the provided wallet coins in form of `<txid>:<vout>` instead of
+        /// selecting them automatically. The coins must cover the payments and the fee; the
+        /// remaining value goes to change.
+        #[clap(long, value_name = "OUTPOINT", conflicts_with = "from_label")]
+        utxo: Vec<Outpoint>,
+
+        /// Coin selection strategy. If not given, coins are selected in the order they are
+        /// stored in the wallet
+        #[clap(long, conflicts_with = "utxo")]
+        coin_strategy: Option<CoinSelection>,
+
+        /// Custom sighash type for an input in form of `<txid>:<vout>:<type>`, where type is
+        /// `ALL`, `NONE` or `SINGLE`, optionally followed by `|ANYONECANPAY`. Inputs without
+        /// explicit sighash type are signed with `SIGHASH_ALL`.
+        #[clap(long)]
+        sighash: Vec<InputSighash>,
+
+        /// Print the constructed PSBT in base64 together with its fee and size metadata in JSON
+        /// format
+        #[clap(long)]
+        json: bool,
+
+        /// Output the PSBT as a base64-encoded string. If a PSBT file name is given, the file
+        /// will contain the base64 text instead of the binary encoding
+        #[clap(long, conflicts_with_all = ["json", "send"])]
+        base64: bool,
+
+        /// Display the PSBT as a QR code, splitting it into animated BBQr fragments if it does
+        /// not fit into a single code. Requires `qr` feature
+        #[clap(long, conflicts_with_all = ["json", "base64", "send"])]
+        qr: bool,
+
+        /// Send change to the provided address instead of deriving a new change address from
+        /// the wallet descriptor. Can't be combined with `MAX` payments.
+        #[clap(long)]
+        change_address: Option<Address>,
+
+        /// Add an `OP_RETURN` output carrying the provided hex-encoded data
+        #[clap(long, value_name = "HEX")]
+        op_return: Vec<String>,
+
+        /// Allow construction of non-standard transactions with multiple `OP_RETURN` outputs or
+        /// with `OP_RETURN` outputs exceeding the relay size limit
+        #[clap(long)]
+        allow_nonstandard: bool,
+
+        /// Lock time of the transaction: a block height if below 500000000, or a UNIX timestamp
+        /// otherwise. A non-zero height must not be below the blockchain tip reported by the
+        /// indexer
+        #[clap(long, value_name = "HEIGHT|TIME")]
+        locktime: Option<u32>,
+
+        /// Sequence number of the transaction inputs in consensus encoding, enabling relative
+        /// time locks. Takes precedence over `--rbf` and `--no-rbf`
+        #[clap(long, value_name = "SEQ_NO")]
+        sequence: Option<u32>,
+
+        /// Signal BIP-125 replaceability of the transaction (default)
+        #[clap(long, overrides_with = "no_rbf")]
+        rbf: bool,
+
+        /// Do not signal BIP-125 replaceability of the transaction
+        #[clap(long, overrides_with = "rbf")]
+        no_rbf: bool,
+
+        /// Change not exceeding this amount is added to the fee instead of creating a change
+        /// output. Defaults to the dust limit of the wallet descriptor output type, or of the
+        /// `--change-address` type if given
+        #[clap(long, value_name = "SATS")]
+        dust_limit: Option<Sats>,
+
+        /// Overwrite the PSBT file if it already exists
+        #[clap(short, long)]
+        force: bool,
+
+        /// Save PSBT to a file in the current directory named after the unsigned transaction id
+        #[clap(long, conflicts_with = "psbt")]
+        auto_name: bool,
+
+        /// Sign the transaction with the keys from `--signing-account`, finalize and broadcast
+        /// it right away after a confirmation. Intended only for hot wallets holding low value;
+        /// by default the PSBT is produced for signing by a separate (cold) signer
+        #[clap(long, conflicts_with_all = ["json", "auto_name"])]
+        send: bool,
+
+        /// Signing account file, created with `bp-hot derive`, holding keys to sign the
+        /// transaction with `--send`
+        #[clap(long, requires = "send", value_name = "FILE")]
+        signing_account: Option<PathBuf>,
+
+        /// Fee
+        fee: Sats,
+
+        /// Name of a PSBT file to save. If not given, prints PSBT to STDOUT
+        psbt: Option<PathBuf>,
+    },
+
+    /// Compose, finalize and publish a transaction in one step. Inputs are signed with the keys
+    /// from `--signing-account`, if provided; the transaction is not published if any of its
+    /// inputs can't be finalized
+    #[display("send")]
+    Send {
+        /// Bitcoin invoice in form of `<sats>@<address>`, with the same syntax as used by
+        /// `construct`
+        #[clap(long)]
+        to: Vec<Payee>,
+
+        /// Signing account file, created with `bp-hot derive`, holding keys to sign the
+        /// transaction
+        #[clap(long, value_name = "FILE")]
+        signing_account: Option<PathBuf>,
+
+        /// Do not publish the transaction, printing its hex encoding instead
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Fee
+        fee: Sats,
+    },
+
+    /// Sign PSBT with private keys derived from a seed or an extended private key, according to
+    /// the key origins present in the PSBT, or with a hardware wallet. The secrets are asked for
+    /// a password if encrypted and are never stored or printed
+    #[display("sign")]
+    Sign {
+        /// Source of the private key: `seed:<file>` or `account:<file>` for the files created
+        /// with `bp-hot`, `env:<var>` for an environment variable containing a BIP-39 mnemonic or
+        /// an extended private key with its origin, or `hwi[:<fingerprint>]` for a connected
+        /// hardware wallet matching the wallet descriptor keys. Hardware wallets require `hwi`
+        /// feature and HWI tool installed
+        #[clap(short, long)]
+        key: SignerSource,
+
+        /// PSBT file to sign. The signed PSBT is written back to the same file
+        psbt: PathBuf,
+    },
+
+    /// Sign a message proving control over a wallet address, producing BIP-322 simple
+    /// signature in base64 encoding. Supports P2WPKH and P2TR addresses
+    #[display("sign-message")]
+    SignMessage {
+        /// Signing account file, created with `bp-hot derive`, holding the key of the address
+        #[clap(long, value_name = "FILE")]
+        signing_account: PathBuf,
+
+        /// Wallet address to sign the message with
+        address: Address,
+
+        /// Message to sign
+        message: String,
+    },
+
+    /// Verify BIP-322 signature of a message for an address. The signature may be given either
+    /// in the simple or in the full format, encoded in base64. No wallet is required
+    #[display("verify-message")]
+    VerifyMessage {
+        /// Address which has signed the message
+        address: Address,
+
+        /// Signed message
+        message: String,
+
+        /// Base64-encoded signature
+        signature: String,
+    },
+
+    /// Compose a new PSBT from a JSON spend request file describing the payments, coin
+    /// selection, fee, lock time, RBF and change policy. The file schema is documented in
+    /// `SpendRequest` type of the library
+    #[display("construct-from")]
+    ConstructFrom {
+        /// Overwrite the PSBT file if it already exists
+        #[clap(short, long)]
+        force: bool,
+
+        /// JSON file with the spend request
+        request_file: PathBuf,
+
+        /// Name of a PSBT file to save. If not given, prints PSBT to STDOUT
+        psbt: Option<PathBuf>,
+    },
+
+    /// Check a candidate payment against common chain-analysis heuristics before constructing
+    /// it. Doesn't create a PSBT and doesn't modify the wallet
+    #[display("privacy-check")]
+    PrivacyCheck {
+        /// Bitcoin invoice in form of `<sats>@<address>`, with the same syntax as used by
+        /// `construct`
+        #[clap(long)]
+        to: Vec<Payee>,
+
+        /// Spend only coins bearing the provided label (assigned either to the transaction
+        /// output or to the address holding it).
+        #[clap(long)]
+        from_label: Option<String>,
+
+        /// Fee
+        fee: Sats,
+    },
+}
+
+#[derive(Debug, Display, Error, From)]
+#[non_exhaustive]
+#[display(inner)]
+pub enum ExecError {
+    #[from]
+    #[from(io::Error)]
+    Io(IoError),
+
+    #[from]
+    Store(PersistenceError),
+
+    #[from]
+    ConstructPsbt(ConstructionError),
+
+    #[from]
+    DecodePsbt(psbt::DecodeError),
+
+    #[from]
+    ParsePsbt(psbt::PsbtParseError),
+
+    #[from]
+    DecodeTx(ConsensusDecodeError),
+
+    /// PSBT is too large to be encoded as a sequence of QR codes.
+    #[display(doc_comments)]
+    QrTooLarge,
+
+    #[from]
+    Unfinalized(UnfinalizedInputs),
+
+    /// beneficiary addresses {0} belong to a network different from the wallet network {1}.
+    #[display(doc_comments)]
+    BeneficiaryNetwork(String, Network),
+
+    /// coins labelled '{0}' contain {1} sats, which is insufficient to cover {2} sats.
+    #[display(doc_comments)]
+    InsufficientLabelledCoins(String, Sats, Sats),
+
+    /// {0} is not an unspent output of the wallet.
+    #[display(doc_comments)]
+    UnknownUtxo(Outpoint),
+
+    /// {0} is an output of a coinbase transaction which is not mature yet and can't be spent.
+    #[display(doc_comments)]
+    ImmatureCoinbase(Outpoint),
+
+    /// selected coins contain {0} sats, which is insufficient to cover {1} sats.
+    #[display(doc_comments)]
+    InsufficientSelectedCoins(Sats, Sats),
+
+    #[from]
+    DetachedSig(DetachedSigError),
+
+    #[from]
+    Combine(CombineError),
+
+    #[from]
+    Bip329(Bip329Error),
+
+    #[from]
+    Bip322(Bip322Error),
+
+    /// signature is not a valid base64 string.
+    #[display(doc_comments)]
+    SignatureEncoding,
+
+    #[from]
+    InputSighash(InputSighashError),
+
+    #[from]
+    Payee(PayeeError),
+
+    #[from]
+    CoreImport(CoreImportError),
+
+    #[cfg(feature = "hot")]
+    #[from]
+    Signing(crate::hot::DataError),
+
+    #[cfg(feature = "hwi")]
+    #[from]
+    Hwi(crate::cli::HwiError),
+
+    /// outputs #{0} and #{1} both carry OP_RETURN data, while standard transactions may contain
+    /// only a single OP_RETURN output; use --allow-nonstandard to override.
+    #[display(doc_comments)]
+    MultipleOpReturns(usize, usize),
+
+    /// OP_RETURN output #{0} is {1} bytes long, exceeding the relay limit of 83 bytes; use
+    /// --allow-nonstandard to override.
+    #[display(doc_comments)]
+    OpReturnTooLarge(usize, usize),
+
+    /// invalid hex-encoded OP_RETURN data '{0}'.
+    #[display(doc_comments)]
+    InvalidOpReturn(String),
+
+    /// file {0} already exists; use --force to overwrite it.
+    #[display(doc_comments)]
+    FileExists(String),
+
+    /// ADDRESS VERIFICATION FAILED: address {1} displayed for terminal {0} does not match the
+    /// script re-derived from the wallet descriptor. Do not use this address and check the
+    /// integrity of the software and the machine it runs on.
+    #[display(doc_comments)]
+    AddressMismatch(Terminal, Address),
+
+    /// indexer failed with {0}
+    #[from]
+    #[cfg_attr(feature = "electrum", from(electrum::Error))]
+    #[cfg_attr(feature = "electrum", from(crate::indexers::electrum::ElectrumError))]
+    #[cfg_attr(feature = "esplora", from(esplora::Error))]
+    #[cfg_attr(feature = "esplora", from(crate::indexers::esplora::EsploraError))]
+    #[cfg_attr(feature = "bitcoind", from(crate::indexers::bitcoind::BitcoindError))]
+    #[display(doc_comments)]
+    Indexer(AnyIndexerError),
+}
+
+impl<O: DescriptorOpts> Exec for Args<Command, O> {
+    type Error = ExecError;
+    const CONF_FILE_NAME: &'static str = "bp.toml";
+
+    fn exec(self, mut config: Config, conf_filename: &'static str) -> Result<(), Self::Error> {
+        match &self.command {
+            Command::List => {
+                let dir = self.general.base_dir();
+                let Ok(dir) = fs::read_dir(dir).inspect_err(|err| {
+                    error!("Error reading wallet directory: {err:?}");
+                    eprintln!("System directory is not initialized");
+                    println!("no wallets found");
                 }) else {
                     return Ok(());
                 };
@@ -276,13 +1347,245 @@ impl<O: DescriptorOpts> Exec for Args<Command, O> {
                     println!("success");
                 }
             }
+            Command::ImportCore {
+                taproot,
+                name,
+                file,
+            } => {
+                let export = fs::read_to_string(file)?;
+                let export: CoreExport = match serde_json::from_str(&export) {
+                    Ok(export) => export,
+                    Err(err) => {
+                        eprintln!("Error: invalid Bitcoin Core descriptor export: {err}");
+                        exit(1);
+                    }
+                };
+                let import = export.import(*taproot)?;
+                for desc in &import.skipped {
+                    eprintln!("Skipping unsupported descriptor {desc}");
+                }
+                let mut wallet: Wallet<XpubDerivable, StdDescr> =
+                    Wallet::new_layer1(import.descriptor.clone(), self.general.network);
+                for (keychain, next) in &import.next_index {
+                    wallet.advance_derivation_index(*keychain, *next);
+                }
+                println!("Imported descriptor {}", import.descriptor);
+                print!("Saving the wallet as '{name}' ... ");
+                let name = name.to_string();
+                let provider = FsTextStore::new(self.general.wallet_dir(&name))?;
+                wallet.make_persistent(provider, true)?;
+                wallet.set_name(name);
+                if let Err(err) = wallet.store() {
+                    println!("error: {err}");
+                } else {
+                    println!("success");
+                }
+                let gap =
+                    import.next_index.values().map(|idx| idx.index()).max().unwrap_or_default();
+                if gap as usize > DEFAULT_GAP_LIMIT {
+                    eprintln!(
+                        "Bitcoin Core has already issued addresses up to index {gap}; use \
+                         `--gap-limit {gap}` during the first sync to discover all the wallet \
+                         transactions"
+                    );
+                }
+            }
+            Command::Export { format } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let export = DescriptorExport::with(wallet.descriptor(), wallet.network());
+                match format {
+                    ExportFormat::Descriptor => println!("{}", export.descriptor),
+                    ExportFormat::Policy => {
+                        println!("{}", export.policy.template);
+                        for (no, key) in export.policy.keys.iter().enumerate() {
+                            println!("@{no}\t{key}");
+                        }
+                    }
+                    ExportFormat::Json => println!(
+                        "{}",
+                        serde_json::to_string_pretty(&export)
+                            .expect("unable to generate JSON representation")
+                    ),
+                }
+            }
+            Command::Recover {
+                xpub,
+                mnemonic_env,
+                passphrase_env,
+                account,
+                types,
+            } => {
+                let network = self.general.network;
+                let accounts = match (xpub, mnemonic_env) {
+                    (Some(xpub), _) => recovery_descriptors(types, |_| xpub.clone()),
+                    #[cfg(feature = "hot")]
+                    (None, Some(var)) => {
+                        let seed = recovery_seed(var, passphrase_env.as_deref())?;
+                        recovery_descriptors(types, |script| {
+                            seed.derive(script.bip43(), network.is_testnet(), *account)
+                                .to_xpub_account()
+                        })
+                    }
+                    #[cfg(not(feature = "hot"))]
+                    (None, Some(_)) => {
+                        let _ = passphrase_env;
+                        eprintln!(
+                            "Error: recovery of account {account} from a mnemonic requires `hot` \
+                             feature to be enabled"
+                        );
+                        exit(1);
+                    }
+                    (None, None) => unreachable!("clap requires either xpub or mnemonic"),
+                };
+
+                let indexer = self.indexer()?;
+                eprintln!("Scanning {} descriptors using {}", accounts.len(), indexer.name());
+                println!("{:<6}\t{:>16}\t{:>6}\tDescriptor", "Type", "Balance, ṩ", "Txes");
+                for (script, descr) in accounts {
+                    let mut wallet = Wallet::<XpubDerivable, StdDescr>::new_layer1(descr, network);
+                    wallet.set_flush_interval(self.scan.flush_interval());
+                    wallet.set_gap_limits(self.scan.gap_limits(&config));
+                    let errors = wallet.update(&indexer).into_err().unwrap_or_default();
+                    println!(
+                        "{script:<6}\t{:>16}\t{:>6}\t{}{}",
+                        wallet.balance(),
+                        wallet.transactions().len(),
+                        checksummed(&wallet.descriptor().to_string()),
+                        if errors.is_empty() { "" } else { "\t(partial)" }
+                    );
+                    for err in errors {
+                        eprintln!("Error scanning {script} descriptor: {err}");
+                    }
+                }
+            }
+            Command::SignerCheck { name } => {
+                let wallet: Wallet<XpubDerivable, O::Descr> = match name {
+                    Some(name) => {
+                        eprint!("Loading descriptor from wallet {name} ... ");
+                        let provider = FsTextStore::new(self.general.wallet_dir(name.to_string()))?;
+                        let wallet = Wallet::load(provider, true)?;
+                        eprintln!("success");
+                        wallet
+                    }
+                    None => self.bp_wallet::<O::Descr>(&config)?,
+                };
+                let descriptor = wallet.descriptor();
+                let class = descriptor.class();
+                let purposes = descriptor
+                    .xpubs()
+                    .map(|xpub| xpub.derivation().first().map(HardenedIndex::child_number))
+                    .collect::<Vec<_>>();
+                println!("\nDescriptor:  {descriptor}");
+                println!("Script type: {class:?}");
+                println!("Keys:        {}", purposes.len());
+                println!();
+                for profile in SIGNER_PROFILES {
+                    let verdict = profile.check(class, purposes.len(), &purposes);
+                    if verdict.is_supported() {
+                        println!("{:<12}{}", verdict.signer, "likely supported".bright_green());
+                    } else {
+                        println!("{:<12}{}", verdict.signer, "unsupported".bright_red());
+                    }
+                    for error in &verdict.errors {
+                        println!("\t- {error}");
+                    }
+                    for warning in &verdict.warnings {
+                        println!("\t? {warning}");
+                    }
+                }
+            }
+            Command::ScriptAt { keychain, index } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                if !wallet.keychains().contains(keychain) {
+                    eprintln!(
+                        "Error: the specified keychain {keychain} is not a part of the descriptor"
+                    );
+                    exit(1);
+                }
+                let network = wallet.network();
+                let derived = wallet.descriptor().derive(*keychain, *index);
+                let script_pubkey = derived.to_script_pubkey();
+                println!("\nTerminal:      /{keychain}/{index}");
+                match Address::with(&script_pubkey, network) {
+                    Ok(addr) => println!("Address:       {addr}"),
+                    Err(_) => println!("Address:       ~"),
+                }
+                println!("scriptPubkey:  {script_pubkey:x}");
+                println!("               {}", disassemble(script_pubkey.as_slice()));
+                match derived {
+                    DerivedScript::Bip13(redeem_script) => {
+                        println!("redeemScript:  {redeem_script:x}");
+                        println!("               {}", disassemble(redeem_script.as_slice()));
+                    }
+                    DerivedScript::Segwit(witness_script) => {
+                        println!("witnessScript: {witness_script:x}");
+                        println!("               {}", disassemble(witness_script.as_slice()));
+                    }
+                    DerivedScript::Nested(witness_script) => {
+                        let redeem_script = witness_script.to_redeem_script();
+                        println!("redeemScript:  {redeem_script:x}");
+                        println!("               {}", disassemble(redeem_script.as_slice()));
+                        println!("witnessScript: {witness_script:x}");
+                        println!("               {}", disassemble(witness_script.as_slice()));
+                    }
+                    DerivedScript::TaprootKeyOnly(internal_pk) => {
+                        println!("Internal key:  {internal_pk}");
+                    }
+                    DerivedScript::TaprootScript(internal_pk, tap_tree) => {
+                        println!("Internal key:  {internal_pk}");
+                        for leaf in tap_tree.iter() {
+                            println!(
+                                "Tap leaf:      depth {}, version {:#04x}",
+                                leaf.depth,
+                                leaf.script.version.to_consensus_u8()
+                            );
+                            println!("               {:x}", leaf.script.script);
+                            println!("               {}", disassemble(&leaf.script.script));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Command::ValidateAddress { address } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let network = wallet.network();
+                let address = match Address::from_str(address) {
+                    Ok(address) => address,
+                    Err(err) => {
+                        eprintln!("Error: '{address}' is not a valid address ({err})");
+                        exit(1);
+                    }
+                };
+                if address.network != AddressNetwork::from(network) {
+                    eprintln!("Error: address {address} does not belong to {network} network");
+                    exit(1);
+                }
+                let script_pubkey = address.script_pubkey();
+                println!("\nAddress:       {address}");
+                println!("Network:       {network}");
+                println!("Type:          {}", address.address_type());
+                println!("scriptPubkey:  {script_pubkey:x}");
+                match wallet.terminal_for(&script_pubkey) {
+                    Some(terminal) => println!("Wallet:        yes, derived at {terminal}"),
+                    None => println!("Wallet:        no"),
+                }
+            }
             Command::Address {
                 change,
                 keychain,
                 index,
                 dry_run: no_shift,
                 count: no,
+                range,
+                stream,
+                format,
+                qr,
+                verify,
             } => {
+                if *qr && !cfg!(feature = "qr") {
+                    eprintln!("Error: QR code output requires `qr` feature to be enabled");
+                    exit(1);
+                }
                 let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
                 let keychain = match (change, keychain) {
                     (false, None) => wallet.default_keychain(),
@@ -296,20 +1599,82 @@ impl<O: DescriptorOpts> Exec for Args<Command, O> {
                     );
                     exit(1);
                 }
-                let index =
-                    index.unwrap_or_else(|| wallet.next_derivation_index(keychain, !*no_shift));
+                if *stream {
+                    const FLUSH_EVERY: u32 = 1000;
+                    let index = index.unwrap_or_default();
+                    let mut out = io::BufWriter::new(io::stdout().lock());
+                    for (no, derived_addr) in wallet
+                        .addresses(keychain)
+                        .skip(index.index() as usize)
+                        .take(*no as usize)
+                        .enumerate()
+                    {
+                        let script_pubkey = derived_addr.addr.script_pubkey();
+                        match format {
+                            AddressFormat::Text => writeln!(
+                                out,
+                                "{}\t{}\t{script_pubkey:x}",
+                                derived_addr.terminal, derived_addr.addr
+                            )?,
+                            AddressFormat::Json => {
+                                let row = AddressRow {
+                                    terminal: derived_addr.terminal.to_string(),
+                                    address: derived_addr.addr.to_string(),
+                                    script_pubkey: format!("{script_pubkey:x}"),
+                                };
+                                let line = serde_json::to_string(&row)
+                                    .expect("unable to generate JSON representation");
+                                writeln!(out, "{line}")?;
+                            }
+                        }
+                        if (no as u32 + 1) % FLUSH_EVERY == 0 {
+                            out.flush()?;
+                        }
+                    }
+                    out.flush()?;
+                    return Ok(());
+                }
+                let mut lock = None;
+                let (index, no) = match (range, index) {
+                    (Some(range), _) => (range.start, range.end.index() - range.start.index()),
+                    (None, Some(index)) => (*index, *no),
+                    (None, None) if *no_shift => {
+                        (wallet.next_derivation_index(keychain, false), *no)
+                    }
+                    (None, None) => {
+                        // Prevents concurrent invocations from handing out the same index. The
+                        // lock is held until the wallet is saved on drop.
+                        if let Some(path) = self.wallet_path(&config) {
+                            lock = Some(FsTextStore::new(path)?.lock()?);
+                        }
+                        (wallet.reserve_next_index(keychain)?, *no)
+                    }
+                };
                 println!("\nTerm.\tAddress");
                 for derived_addr in
-                    wallet.addresses(keychain).skip(index.index() as usize).take(*no as usize)
+                    wallet.addresses(keychain).skip(index.index() as usize).take(no as usize)
                 {
+                    if *verify {
+                        verify_address(wallet.descriptor(), &derived_addr)?;
+                    }
                     println!("{}\t{}", derived_addr.terminal, derived_addr.addr);
+                    #[cfg(feature = "qr")]
+                    if *qr {
+                        crate::cli::qr_print(&[derived_addr.addr.to_string()])?;
+                    }
                 }
+                drop(wallet);
+                drop(lock);
             }
             Command::Finalize {
                 publish,
+                force,
                 psbt: psbt_path,
                 tx,
             } => {
+                if let Some(tx) = tx {
+                    ensure_no_clobber(tx, *force)?;
+                }
                 let mut psbt = psbt_read(psbt_path)?;
                 if psbt.is_finalized() {
                     eprintln!("The PSBT is already finalized");
@@ -321,18 +1686,19 @@ impl<O: DescriptorOpts> Exec for Args<Command, O> {
                 psbt_write(&psbt, psbt_path)?;
                 if let Ok(tx) = psbt_extract(&psbt, *publish, tx.as_deref()) {
                     if *publish {
-                        let indexer = self.indexer()?;
-                        eprint!("Publishing transaction via {} ... ", indexer.name());
-                        indexer.publish(&tx)?;
-                        eprintln!("success");
+                        self.publish(&tx)?;
                     }
                 }
             }
             Command::Extract {
                 publish,
+                force,
                 psbt: psbt_path,
                 tx,
             } => {
+                if let Some(tx) = tx {
+                    ensure_no_clobber(tx, *force)?;
+                }
                 let mut psbt = psbt_read(psbt_path)?;
                 if !psbt.is_finalized() {
                     let wallet = self.bp_wallet::<O::Descr>(&config)?;
@@ -341,10 +1707,7 @@ impl<O: DescriptorOpts> Exec for Args<Command, O> {
 
                 if let Ok(tx) = psbt_extract(&psbt, *publish, tx.as_deref()) {
                     if *publish {
-                        let indexer = self.indexer()?;
-                        eprint!("Publishing transaction via {} ... ", indexer.name());
-                        indexer.publish(&tx)?;
-                        eprintln!("success");
+                        self.publish(&tx)?;
                     }
                 }
             }
@@ -361,19 +1724,72 @@ impl<O: DescriptorOpts> Exec for Args<BpCommand, O> {
     fn exec(mut self, config: Config, conf_filename: &'static str) -> Result<(), Self::Error> {
         match &self.command {
             BpCommand::General(cmd) => self.translate(cmd).exec(config, conf_filename)?,
+            BpCommand::Balance {
+                addr,
+                utxo,
+                json: true,
+            } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let mut address_coins =
+                    if *addr && *utxo { wallet.address_coins() } else { none!() };
+                let coin_info = |row: CoinRow<_>, address: bool| CoinInfo {
+                    height: row.height,
+                    outpoint: row.outpoint,
+                    amount: row.amount,
+                    address: address.then_some(row.address),
+                    frozen: wallet.is_frozen(row.outpoint),
+                };
+                let info = BalanceInfo {
+                    balance: wallet.balance(),
+                    spendable: wallet.spendable_balance(true),
+                    addresses: addr.then(|| {
+                        wallet
+                            .address_balance()
+                            .map(|info| {
+                                let derived = DerivedAddr {
+                                    addr: info.addr,
+                                    terminal: info.terminal,
+                                };
+                                let coins = utxo.then(|| {
+                                    address_coins
+                                        .remove(&derived)
+                                        .unwrap_or_default()
+                                        .into_iter()
+                                        .map(|row| coin_info(row, false))
+                                        .collect()
+                                });
+                                AddrBalance { info, coins }
+                            })
+                            .collect()
+                    }),
+                    coins: (*utxo && !*addr)
+                        .then(|| wallet.coins().map(|row| coin_info(row, true)).collect()),
+                    pending: utxo
+                        .then(|| wallet.pending_coins().map(|row| coin_info(row, true)).collect())
+                        .filter(|pending: &Vec<_>| !pending.is_empty()),
+                };
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&info)
+                        .expect("unable to generate JSON representation")
+                );
+            }
             BpCommand::Balance {
                 addr: false,
                 utxo: false,
+                json: false,
             } => {
                 let runtime = self.bp_wallet::<O::Descr>(&config)?;
-                println!("\nWallet total balance: {} ṩ", runtime.balance());
+                println!("\nWallet total balance:     {} ṩ", runtime.balance());
+                println!("Wallet spendable balance: {} ṩ", runtime.spendable_balance(true));
             }
             BpCommand::Balance {
                 addr: true,
                 utxo: false,
+                json: false,
             } => {
                 let wallet = self.bp_wallet::<O::Descr>(&config)?;
-                println!("\nTerm.\t{:62}\t# used\tVol., ṩ\tBalance, ṩ", "Address");
+                println!("\nTerm.\t{:62}\t# used\tVol., ṩ\tBalance, ṩ\tLabel", "Address");
                 for info in wallet.address_balance() {
                     let WalletAddr {
                         addr,
@@ -382,11 +1798,16 @@ impl<O: DescriptorOpts> Exec for Args<BpCommand, O> {
                         volume,
                         balance,
                     } = info;
-                    println!("{terminal}\t{:62}\t{used}\t{volume}\t{balance}", addr.to_string());
+                    println!(
+                        "{terminal}\t{:62}\t{used}\t{volume}\t{balance}\t{}",
+                        addr.to_string(),
+                        wallet.address_label(&addr).unwrap_or_default()
+                    );
                 }
                 self.command = BpCommand::Balance {
                     addr: false,
                     utxo: false,
+                    json: false,
                 };
                 self.sync = false;
                 self.exec(config, conf_filename)?;
@@ -394,19 +1815,27 @@ impl<O: DescriptorOpts> Exec for Args<BpCommand, O> {
             BpCommand::Balance {
                 addr: false,
                 utxo: true,
+                json: false,
             } => {
                 let wallet = self.bp_wallet::<O::Descr>(&config)?;
                 println!("Balance of {}", wallet.descriptor());
-                println!("\nHeight\t{:>12}\t{:68}\tAddress", "Amount, ṩ", "Outpoint");
+                println!("\nHeight\t{:>12}\t{:68}\tAddress\tLabel", "Amount, ṩ", "Outpoint");
                 for row in wallet.coins() {
                     println!(
-                        "{}\t{: >12}\t{:68}\t{}",
-                        row.height, row.amount, row.outpoint, row.address
+                        "{}\t{: >12}\t{:68}\t{}\t{}{}",
+                        row.height,
+                        row.amount,
+                        row.outpoint,
+                        row.address,
+                        wallet.coin_label(row.outpoint).unwrap_or_default(),
+                        coin_markers(&wallet, row.outpoint)
                     );
                 }
+                print_pending_coins(&wallet);
                 self.command = BpCommand::Balance {
                     addr: false,
                     utxo: false,
+                    json: false,
                 };
                 self.sync = false;
                 self.exec(config, conf_filename)?;
@@ -414,50 +1843,244 @@ impl<O: DescriptorOpts> Exec for Args<BpCommand, O> {
             BpCommand::Balance {
                 addr: true,
                 utxo: true,
+                json: false,
             } => {
                 let wallet = self.bp_wallet::<O::Descr>(&config)?;
                 println!("Balance of {}", wallet.descriptor());
-                println!("\nHeight\t{:>12}\t{:68}", "Amount, ṩ", "Outpoint");
+                println!("\nHeight\t{:>12}\t{:68}\tLabel", "Amount, ṩ", "Outpoint");
                 for (derived_addr, utxos) in wallet.address_coins() {
-                    println!("{}\t{}", derived_addr.addr, derived_addr.terminal);
+                    println!(
+                        "{}\t{}\t{}",
+                        derived_addr.addr,
+                        derived_addr.terminal,
+                        wallet.address_label(&derived_addr.addr).unwrap_or_default()
+                    );
                     for row in utxos {
-                        println!("{}\t{: >12}\t{:68}", row.height, row.amount, row.outpoint);
+                        let label =
+                            wallet.label(LabelTarget::Output(row.outpoint)).unwrap_or_default();
+                        println!(
+                            "{}\t{: >12}\t{:68}\t{label}{}",
+                            row.height,
+                            row.amount,
+                            row.outpoint,
+                            coin_markers(&wallet, row.outpoint)
+                        );
                     }
                     println!()
                 }
+                print_pending_coins(&wallet);
                 self.command = BpCommand::Balance {
                     addr: false,
                     utxo: false,
+                    json: false,
                 };
                 self.sync = false;
                 self.exec(config, conf_filename)?;
             }
-            BpCommand::History { txid, details } => {
+            BpCommand::AddressBalance { address } => {
+                let client = match self.sync.then(|| self.indexer()).transpose()? {
+                    #[cfg(feature = "esplora")]
+                    Some(AnyIndexer::Esplora(client)) => Some(client),
+                    #[cfg(feature = "mempool")]
+                    Some(AnyIndexer::Mempool(client)) => Some(client),
+                    _ => None,
+                };
+                let wallet =
+                    self.bp_wallet_with_sync::<O::Descr>(&config, self.sync && client.is_none())?;
+                let script = address.script_pubkey();
+                let Some(terminal) = wallet.terminal_for(&script) else {
+                    eprintln!(
+                        "Error: address {address} is not known to the wallet; it may be not \
+                         derived by the wallet descriptor or lie beyond the scanned gap limit"
+                    );
+                    exit(1);
+                };
+                let (info, coins) = match client {
+                    Some(client) => {
+                        eprint!("Syncing address {address} ... ");
+                        let history = client.address_history(address)?;
+                        eprintln!("success");
+                        address_state(&history, terminal, *address)
+                    }
+                    None => {
+                        let info =
+                            wallet.address_balance().find(|info| info.addr == *address).unwrap_or(
+                                WalletAddr::new(*address, terminal.keychain, terminal.index),
+                            );
+                        let coins = wallet
+                            .coins()
+                            .filter(|row| row.address.addr == *address)
+                            .map(|row| (row.height, row.outpoint, row.amount))
+                            .collect();
+                        (info, coins)
+                    }
+                };
+                println!("\nAddress:    {}", info.addr);
+                println!("Terminal:   {}", info.terminal);
+                println!("Balance:    {} ṩ", info.balance);
+                println!("Used:       {} time(s)", info.used);
+                println!("Volume:     {} ṩ", info.volume);
+                if coins.is_empty() {
+                    println!("\nNo unspent outputs");
+                } else {
+                    println!("\nHeight\t{:>12}\t{:68}", "Amount, ṩ", "Outpoint");
+                    for (height, outpoint, amount) in coins {
+                        println!("{height}\t{amount: >12}\t{outpoint:68}");
+                    }
+                }
+            }
+            BpCommand::Summary { json } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let summary = wallet.summary();
+                if *json {
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&summary)
+                            .expect("unable to generate JSON representation")
+                    );
+                } else {
+                    println!("Summary of {}", wallet.descriptor());
+                    println!("\nTransactions:\t{}", summary.tx_count);
+                    for (title, sats) in [
+                        ("Received:", summary.received),
+                        ("Sent:", summary.sent),
+                        ("Fees paid:", summary.fees),
+                        ("Balance:", summary.balance),
+                    ] {
+                        println!(
+                            "{title:<12}\t{sats: >16} ṩ\t{: >16.8} BTC",
+                            sats.sats() as f64 / 1e8
+                        );
+                    }
+                }
+            }
+            BpCommand::History {
+                details,
+                format: HistoryFormat::Csv,
+                output,
+                since,
+                until,
+                limit,
+                ..
+            } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let mut rows = wallet.history().collect::<Vec<_>>();
+                rows.sort_by_key(|row| row.height);
+                filter_history(&mut rows, *since, *until, *limit);
+                match output {
+                    Some(path) => {
+                        let mut file = io::BufWriter::new(File::create(path)?);
+                        write_history_csv(&mut file, &rows, wallet.transactions(), *details)?;
+                        file.flush()?;
+                        eprintln!("History is written to {}", path.display());
+                    }
+                    None => write_history_csv(
+                        &mut io::stdout().lock(),
+                        &rows,
+                        wallet.transactions(),
+                        *details,
+                    )?,
+                }
+            }
+            BpCommand::Pending { json } => {
                 let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let rows = pending_history(wallet.history());
+                if *json {
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&rows)
+                            .expect("unable to generate JSON representation")
+                    );
+                } else {
+                    println!("Pending transactions of {}", wallet.descriptor());
+                    println!("\n{:<64}\t    Amount, ṩ\tFee rate, ṩ/vbyte\tRBF", "Txid");
+                    for row in rows {
+                        println!(
+                            "{}\t{}{: >12}\t{: >8.2}\t{}",
+                            row.txid,
+                            row.operation,
+                            row.amount,
+                            row.fee_rate,
+                            if row.rbf { "RBF" } else { "" }
+                        );
+                    }
+                }
+            }
+            BpCommand::History {
+                output: Some(_), ..
+            } => {
+                eprintln!("Error: --output can be used only with --format csv");
+                exit(1);
+            }
+            BpCommand::History {
+                txid,
+                details,
+                raw,
+                classify,
+                since,
+                until,
+                limit,
+                ..
+            } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let indexer = if *raw { Some(self.indexer()?) } else { None };
                 println!("History of {}", wallet.descriptor());
                 println!(
-                    "\nHeight\t{:<1$}\t    Amount, ṩ\tFee rate, ṩ/vbyte",
+                    "\nHeight\tConfs\t{:<1$}\t    Amount, ṩ\tFee rate, ṩ/vbyte\tRBF\tLabel",
                     "Txid",
                     if *txid { 64 } else { 18 }
                 );
                 let mut rows = wallet.history().collect::<Vec<_>>();
                 rows.sort_by_key(|row| row.height);
+                filter_history(&mut rows, *since, *until, *limit);
+                // Incoming unconfirmed payments paying less than required for the next block are
+                // at risk of being replaced or dropped
+                let next_block_rate =
+                    rows.iter().any(TxRow::is_pending_credit).then(|| self.fee_rate(&config, 1).0);
+                let mut low_fee = 0usize;
                 for row in rows {
+                    let is_low_fee = row.is_pending_credit()
+                        && next_block_rate.is_some_and(|rate| row.fee_rate() < rate);
+                    low_fee += is_low_fee as usize;
+                    let confs = match wallet.tip_height() {
+                        _ if !row.height.is_mined() => s!("mempool"),
+                        Some(tip) => wallet
+                            .transactions()
+                            .get(&row.txid)
+                            .and_then(|tx| tx.confirmations(tip))
+                            .map(|confs| confs.to_string())
+                            .unwrap_or_else(|| s!("~")),
+                        None => s!("~"),
+                    };
                     println!(
-                        "{}\t{}\t{}{: >12}\t{: >8.2}",
+                        "{}\t{confs}\t{}\t{}{: >12}\t{: >8.2}\t{}\t{}{}",
                         row.height,
                         if *txid { row.txid.to_string() } else { format!("{:#}", row.txid) },
                         row.operation,
                         row.amount,
-                        row.fee.sats() as f64 * 4.0 / row.weight as f64
+                        row.fee_rate(),
+                        if row.rbf { "RBF" } else { "" },
+                        wallet.label(LabelTarget::Tx(row.txid)).unwrap_or_default(),
+                        if is_low_fee {
+                            "\tlow-fee, replacement risk".bright_red()
+                        } else {
+                            "".normal()
+                        }
                     );
+                    if let Some(indexer) = &indexer {
+                        match wallet.raw_tx(row.txid, indexer) {
+                            Ok(Some(tx)) => println!("\t{tx:x}"),
+                            Ok(None) => println!("\t~ (not available from the indexer)"),
+                            Err(err) => println!("\t~ (unable to retrieve: {err})"),
+                        }
+                    }
                     if *details {
                         for (cp, value) in &row.own {
                             println!(
                                 "\t* {value: >-12}ṩ\t{}\t{cp}",
                                 if *value < 0 {
                                     "taken from"
-                                } else if row.operation == OpType::Credit {
+                                } else if row.operation != OpType::Debit {
                                     "moved to  "
                                 } else {
                                     "change    "
@@ -465,8 +2088,25 @@ impl<O: DescriptorOpts> Exec for Args<BpCommand, O> {
                             );
                         }
                         for (cp, value) in &row.counterparties {
+                            if let Some(data) = cp.data() {
+                                match String::from_utf8(data.clone()) {
+                                    Ok(text) => println!(
+                                        "\t* {value: >-12}ṩ\tdata      \t{}\t{text:?}",
+                                        data.to_hex()
+                                    ),
+                                    Err(_) => println!(
+                                        "\t* {value: >-12}ṩ\tdata      \t{}",
+                                        data.to_hex()
+                                    ),
+                                }
+                                continue;
+                            }
+                            let class = match cp.script_class() {
+                                Some(class) if *classify => format!("\t[{class}]"),
+                                _ => s!(""),
+                            };
                             println!(
-                                "\t* {value: >-12}ṩ\t{}\t{cp}",
+                                "\t* {value: >-12}ṩ\t{}\t{cp}{class}",
                                 if *value > 0 {
                                     "received  "
                                 } else if row.operation == OpType::Credit {
@@ -480,69 +2120,2568 @@ impl<O: DescriptorOpts> Exec for Args<BpCommand, O> {
                         println!();
                     }
                 }
+                if let (Some(rate), 1..) = (next_block_rate, low_fee) {
+                    eprintln!(
+                        "\nWarning: {low_fee} unconfirmed incoming transaction(s) pay less than \
+                         the next-block fee rate of {rate:.2} ṩ/vbyte and may get replaced or \
+                         dropped; wait for their confirmation before accepting the payment"
+                    );
+                }
+            }
+            BpCommand::Label { command } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                match command {
+                    LabelCommand::Set { target, text } => {
+                        wallet.set_label(*target, Some(text.clone()));
+                        eprintln!("Label of {target} is set to '{text}'");
+                    }
+                    LabelCommand::Remove { target } => {
+                        if wallet.label(*target).is_none() {
+                            eprintln!("Error: {target} has no label");
+                            exit(1);
+                        }
+                        wallet.set_label(*target, None);
+                        eprintln!("Label of {target} is removed");
+                    }
+                    LabelCommand::List => {
+                        for (target, label) in wallet.labels() {
+                            println!("{target}\t{label}");
+                        }
+                    }
+                }
+            }
+            BpCommand::LabelExport { path } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let labels = export_labels(wallet.labels());
+                match path {
+                    Some(path) => {
+                        fs::write(path, labels)?;
+                        eprintln!("Labels are exported to {}", path.display());
+                    }
+                    None => print!("{labels}"),
+                }
+            }
+            BpCommand::LabelImport { path } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let records = import_labels(&fs::read_to_string(path)?)?;
+                let mut imported = 0usize;
+                let mut unsupported = 0usize;
+                let mut unlabelled = 0usize;
+                for record in records {
+                    let Some(label) = record.label.clone() else {
+                        unlabelled += 1;
+                        continue;
+                    };
+                    match record.target() {
+                        Ok(Some(target)) if wallet.contains(target) => {
+                            wallet.set_label(target, Some(label));
+                            imported += 1;
+                        }
+                        Ok(Some(target)) => eprintln!(
+                            "Warning: skipping label of {} {target}, which is not known to the \
+                             wallet",
+                            record.ty
+                        ),
+                        Ok(None) => unsupported += 1,
+                        Err(reference) => eprintln!(
+                            "Warning: skipping label with invalid {} reference '{reference}'",
+                            record.ty
+                        ),
+                    }
+                }
+                eprintln!("Imported {imported} label(s)");
+                if unsupported > 0 {
+                    eprintln!(
+                        "Warning: skipped {unsupported} label(s) of public keys, transaction \
+                         inputs or xpubs, which are not supported by the wallet"
+                    );
+                }
+                if unlabelled > 0 {
+                    eprintln!("Warning: skipped {unlabelled} record(s) without a label");
+                }
+            }
+            BpCommand::Freeze { outpoint } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                if !wallet.is_unspent(*outpoint) {
+                    eprintln!("Error: {outpoint} is not an unspent output of the wallet");
+                    exit(1);
+                }
+                if wallet.freeze(*outpoint) {
+                    eprintln!("Coin {outpoint} is frozen");
+                } else {
+                    eprintln!("Coin {outpoint} is already frozen");
+                }
+            }
+            BpCommand::Unfreeze { outpoint } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                if !wallet.unfreeze(*outpoint) {
+                    eprintln!("Error: {outpoint} is not frozen");
+                    exit(1);
+                }
+                eprintln!("Coin {outpoint} is unfrozen");
+            }
+            BpCommand::Sign {
+                key: SignerSource::Hwi(fp),
+                psbt: psbt_path,
+            } => {
+                #[cfg(not(feature = "hwi"))]
+                {
+                    let _ = fp;
+                    eprintln!(
+                        "Error: signing {} with a hardware wallet requires `hwi` feature to be \
+                         enabled",
+                        psbt_path.display()
+                    );
+                    exit(1);
+                }
+                #[cfg(feature = "hwi")]
+                {
+                    let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                    let psbt = psbt_read(psbt_path)?;
+                    let fingerprints = wallet
+                        .descriptor()
+                        .xpubs()
+                        .map(|xpub| xpub.origin().master_fp())
+                        .collect::<Vec<_>>();
+                    eprint!("Looking for hardware wallets ... ");
+                    let devices = crate::cli::hwi_enumerate()?;
+                    let device = crate::cli::hwi_select(&devices, &fingerprints, *fp)?;
+                    eprintln!(
+                        "found {} {}",
+                        device.model.as_deref().unwrap_or(&device.ty),
+                        device.fingerprint.as_deref().unwrap_or_default()
+                    );
+                    eprintln!("Confirm the transaction on the device");
+                    let signed = crate::cli::hwi_sign(device, wallet.network(), &psbt)?;
+                    let combined = combine_psbts([psbt.clone(), signed])?;
+                    let count = signed_inputs(&psbt, &combined);
+                    eprintln!(
+                        "Signed {} of {} input(s)",
+                        count.to_string().bright_green(),
+                        psbt.inputs().count()
+                    );
+                    if count > 0 {
+                        psbt_write(&combined, psbt_path)?;
+                    }
+                }
+            }
+            BpCommand::Sign {
+                key,
+                psbt: psbt_path,
+            } => {
+                #[cfg(not(feature = "hot"))]
+                {
+                    eprintln!(
+                        "Error: signing {} with {key} requires `hot` feature to be enabled",
+                        psbt_path.display()
+                    );
+                    exit(1);
+                }
+                #[cfg(feature = "hot")]
+                {
+                    let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                    let mut psbt = psbt_read(psbt_path)?;
+                    let account = signer_account(key, wallet.network().is_testnet())?;
+                    if !wallet
+                        .descriptor()
+                        .xpubs()
+                        .any(|xpub| xpub.origin().master_fp() == account.origin().master_fp())
+                    {
+                        eprintln!(
+                            "Warning: the signing key doesn't match any of the wallet descriptor \
+                             keys"
+                        );
+                    }
+                    let unsigned = psbt.clone();
+                    let sigs = crate::hot::sign_psbt_with(&mut psbt, &account)?;
+                    let signed = signed_inputs(&unsigned, &psbt);
+                    eprintln!(
+                        "Created {sigs} signature(s), signing {} of {} input(s)",
+                        signed.to_string().bright_green(),
+                        psbt.inputs().count()
+                    );
+                    if sigs > 0 {
+                        psbt_write(&psbt, psbt_path)?;
+                    }
+                }
+            }
+            BpCommand::SignMessage {
+                signing_account,
+                address,
+                message,
+            } => {
+                if !cfg!(feature = "hot") {
+                    eprintln!(
+                        "Error: signing with {} requires `hot` feature to be enabled",
+                        signing_account.display()
+                    );
+                    exit(1);
+                }
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                if !matches!(address.address_type(), AddressType::P2wpkh | AddressType::P2tr) {
+                    return Err(Bip322Error::UnsupportedAddress(address.address_type()).into());
+                }
+                let script_pubkey = address.script_pubkey();
+                // Addresses which were never used are known to the wallet only by derivation
+                let Some(terminal) = wallet.terminal_for(&script_pubkey) else {
+                    eprintln!("Error: address {address} doesn't belong to the wallet");
+                    exit(1);
+                };
+                let mut psbt = bip322::signing_psbt(
+                    wallet.descriptor(),
+                    terminal,
+                    &script_pubkey,
+                    message.as_bytes(),
+                );
+                #[cfg(feature = "hot")]
+                crate::hot::sign_psbt(&mut psbt, signing_account, false)?;
+                finalize_psbt(&mut psbt, wallet.descriptor());
+                let Some(sig) = bip322::simple_signature(&psbt) else {
+                    eprintln!("Error: signing account doesn't hold the key for address {address}");
+                    exit(1);
+                };
+                println!("{}", BASE64.encode(sig));
+            }
+            BpCommand::VerifyMessage {
+                address,
+                message,
+                signature,
+            } => {
+                let sig = BASE64.decode(signature).map_err(|_| ExecError::SignatureEncoding)?;
+                bip322::verify(address, message.as_bytes(), &sig)?;
+                eprintln!("Signature is valid");
+            }
+            BpCommand::Check { balances } => {
+                if !*balances {
+                    eprintln!("Error: no checks requested; use --balances");
+                    exit(1);
+                }
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let mismatches = wallet.audit_balances();
+                if mismatches.is_empty() {
+                    println!("Cached balances are consistent with the wallet transactions");
+                } else {
+                    println!("Found {} balance inconsistencies:", mismatches.len());
+                    for mismatch in mismatches {
+                        println!("- {mismatch}");
+                    }
+                    exit(1);
+                }
+            }
+            BpCommand::Verify { deep, sample } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let indexer = self.indexer()?;
+                let random = RandomState::new();
+                let txids = wallet
+                    .transactions()
+                    .keys()
+                    .filter(|txid| random.hash_one(txid) % 100 < *sample as u64)
+                    .copied()
+                    .collect::<Vec<_>>();
+                eprint!(
+                    "Verifying {} of {} cached transactions ",
+                    txids.len(),
+                    wallet.transactions().len()
+                );
+                let mut divergences = vec![];
+                for txid in txids {
+                    divergences.extend(wallet.verify_tx(txid, &indexer, *deep)?);
+                    eprint!(".");
+                }
+                eprintln!();
+                if divergences.is_empty() {
+                    println!("Cached transactions match the indexer data");
+                } else {
+                    println!("Found {} divergences from the indexer data:", divergences.len());
+                    for divergence in divergences {
+                        println!("- {divergence}");
+                    }
+                    exit(1);
+                }
+            }
+            BpCommand::Rescan => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                wallet.set_flush_interval(self.scan.flush_interval());
+                wallet.set_gap_limits(self.scan.gap_limits(&config));
+                wallet.set_scan_progress(ScanProgress::new(|_, _, _| eprint!(".")));
+                let indexer = self.indexer()?;
+                eprint!("Rescanning ");
+                if let Some(errors) = wallet.sync_from_scratch(&indexer).into_err() {
+                    eprintln!(" partial, some requests has failed:");
+                    for err in errors {
+                        eprintln!("- {err}");
+                    }
+                } else {
+                    eprintln!(" success");
+                }
+                wallet.store()?;
+                println!(
+                    "Discovered {} transactions and {} unspent outputs",
+                    wallet.transactions().len(),
+                    wallet.utxos().count() + wallet.pending_coins().count()
+                );
+            }
+            BpCommand::AwaitPayment {
+                confirmations,
+                timeout,
+                interval,
+                address,
+                amount,
+            } => {
+                let client = match self.indexer()? {
+                    #[cfg(feature = "esplora")]
+                    AnyIndexer::Esplora(client) => client,
+                    #[cfg(feature = "mempool")]
+                    AnyIndexer::Mempool(client) => client,
+                    _ => {
+                        eprintln!(
+                            "Error: awaiting payments is supported only by --esplora and \
+                             --mempool indexers"
+                        );
+                        exit(1);
+                    }
+                };
+                const MAX_INTERVAL: u64 = 600;
+                let script = address.script_pubkey();
+                let deadline = Instant::now() + Duration::from_secs(*timeout);
+                let mut delay = (*interval).max(1);
+                eprintln!(
+                    "Awaiting {amount} sats at {address} with {confirmations} confirmation(s)"
+                );
+                loop {
+                    match client
+                        .height()
+                        .and_then(|tip| client.address_history(address).map(|txes| (tip, txes)))
+                    {
+                        Ok((tip, txes)) => {
+                            delay = (*interval).max(1);
+                            let (confirmed, pending) =
+                                received_payment(&txes, &script, tip, *confirmations);
+                            eprintln!(
+                                "Received {confirmed} sats confirmed and {pending} sats pending"
+                            );
+                            if confirmed >= *amount {
+                                println!("Payment of {amount} sats to {address} is received");
+                                break;
+                            }
+                        }
+                        Err(err) => {
+                            delay = (delay * 2).min(MAX_INTERVAL);
+                            eprintln!(
+                                "Warning: indexer request failed ({err}), retrying in {delay}s"
+                            );
+                        }
+                    }
+                    let now = Instant::now();
+                    if now >= deadline {
+                        eprintln!("Error: timed out waiting for the payment");
+                        exit(1);
+                    }
+                    thread::sleep(Duration::from_secs(delay).min(deadline - now));
+                }
+            }
+            BpCommand::AddressHistory { json, address } => {
+                let client = match self.indexer()? {
+                    #[cfg(feature = "esplora")]
+                    AnyIndexer::Esplora(client) => client,
+                    #[cfg(feature = "mempool")]
+                    AnyIndexer::Mempool(client) => client,
+                    _ => {
+                        eprintln!(
+                            "Error: address history is supported only by --esplora and --mempool \
+                             indexers"
+                        );
+                        exit(1);
+                    }
+                };
+                eprint!("Retrieving history of {address} ... ");
+                let history = client.address_history(address)?;
+                eprintln!("success");
+                let script = address.script_pubkey();
+                let mut rows = history
+                    .iter()
+                    .map(|tx| AddrTxRow {
+                        txid: tx.txid,
+                        height: tx.status.map(|info| info.height),
+                        received: tx
+                            .outputs
+                            .iter()
+                            .filter(|out| out.beneficiary.script_pubkey().as_ref() == Some(&script))
+                            .map(|out| out.value)
+                            .sum(),
+                        sent: tx
+                            .inputs
+                            .iter()
+                            .filter(|inp| inp.payer.script_pubkey().as_ref() == Some(&script))
+                            .map(|inp| inp.value)
+                            .sum(),
+                        fee: tx.fee,
+                        rbf: tx.signals_rbf(),
+                    })
+                    .collect::<Vec<_>>();
+                rows.sort_by_key(|row| row.height);
+                if *json {
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&rows)
+                            .expect("unable to generate JSON representation")
+                    );
+                } else {
+                    println!(
+                        "\nHeight\t{:<64}\t{:>12}\t{:>12}\t{:>8}\tRBF",
+                        "Txid", "Received, ṩ", "Sent, ṩ", "Fee, ṩ"
+                    );
+                    for row in rows {
+                        println!(
+                            "{}\t{}\t{: >12}\t{: >12}\t{: >8}\t{}",
+                            row.height,
+                            row.txid,
+                            row.received,
+                            row.sent,
+                            row.fee,
+                            if row.rbf { "RBF" } else { "" }
+                        );
+                    }
+                }
+            }
+            BpCommand::FeeEstimate { targets } => {
+                let targets =
+                    if targets.is_empty() { DEFAULT_FEE_TARGETS.to_vec() } else { targets.clone() };
+                println!("{:>8}\t{:>12}\tSource", "Blocks", "ṩ/vbyte");
+                for (target, (rate, source)) in self.fee_rates(&config, &targets) {
+                    println!("{target:>8}\t{rate:>12.2}\t{}", self.fee_source_info(source));
+                }
+            }
+            BpCommand::Mempool => {
+                let indexer = self.indexer()?;
+                let Some(histogram) = indexer.mempool_histogram()? else {
+                    eprintln!(
+                        "Error: {} indexer doesn't provide mempool fee histogram",
+                        indexer.name()
+                    );
+                    exit(1);
+                };
+                println!("{:>12}\t{:>12}\t{:>14}\tBlocks", "ṩ/vbyte", "vbytes", "Cumulative");
+                let mut cumulative = 0u64;
+                for (rate, vsize) in histogram {
+                    cumulative += vsize;
+                    println!(
+                        "{rate:>12.2}\t{vsize:>12}\t{cumulative:>14}\t{:.2}",
+                        cumulative as f64 / BLOCK_VSIZE as f64
+                    );
+                }
+            }
+            BpCommand::ScanBatch {
+                jobs,
+                descriptors_file,
+            } => {
+                let file = File::open(descriptors_file)?;
+                let descriptors: Vec<O::Descr> = serde_yaml::from_reader(file)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+                let indexer = self.indexer()?;
+                let network = self.general.network;
+                let flush_interval = self.scan.flush_interval();
+                let gap_limits = self.scan.gap_limits(&config);
+                eprintln!(
+                    "Scanning {} descriptors using {} with up to {jobs} parallel jobs",
+                    descriptors.len(),
+                    indexer.name()
+                );
+                let mut results = BTreeMap::new();
+                for chunk in descriptors.chunks(jobs.get()) {
+                    let chunk_results = thread::scope(|scope| {
+                        let handles = chunk
+                            .iter()
+                            .map(|descr| {
+                                let indexer = &indexer;
+                                let gap_limits = &gap_limits;
+                                scope.spawn(move || {
+                                    let mut wallet = Wallet::<XpubDerivable, O::Descr>::new_layer1(
+                                        descr.clone(),
+                                        network,
+                                    );
+                                    wallet.set_flush_interval(flush_interval);
+                                    wallet.set_gap_limits(gap_limits.clone());
+                                    let errors = wallet
+                                        .update(indexer)
+                                        .into_err()
+                                        .unwrap_or_default()
+                                        .iter()
+                                        .map(ToString::to_string)
+                                        .collect::<Vec<_>>();
+                                    (descr.to_string(), ScanResult {
+                                        balance: wallet.balance(),
+                                        coins: wallet.coins().collect(),
+                                        errors,
+                                    })
+                                })
+                            })
+                            .collect::<Vec<_>>();
+                        handles
+                            .into_iter()
+                            .map(|handle| handle.join().expect("scanning thread has panicked"))
+                            .collect::<Vec<_>>()
+                    });
+                    for (descr, result) in chunk_results {
+                        eprintln!(
+                            "- {descr}: {} ṩ{}",
+                            result.balance,
+                            if result.errors.is_empty() { "" } else { " (partial)" }
+                        );
+                        results.insert(descr, result);
+                    }
+                }
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&results)
+                        .expect("unable to generate JSON representation")
+                );
+            }
+            BpCommand::Tx { raw, tx } => {
+                let tx = match tx {
+                    TxRef::Tx(tx) => tx.clone(),
+                    TxRef::Txid(txid) => {
+                        let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                        let indexer = self.indexer()?;
+                        match wallet.raw_tx(*txid, &indexer) {
+                            Ok(Some(tx)) => tx,
+                            Ok(None) => {
+                                eprintln!(
+                                    "Error: transaction {txid} is not available from the indexer; \
+                                     it may have been pruned or dropped from the mempool"
+                                );
+                                exit(1);
+                            }
+                            Err(RawTxError::Indexer(err)) => return Err(err.into()),
+                            Err(err) => {
+                                eprintln!("Error: {err}");
+                                exit(1);
+                            }
+                        }
+                    }
+                };
+                if *raw {
+                    println!("{tx:x}");
+                } else {
+                    println!(
+                        "{}",
+                        serde_yaml::to_string(&tx).expect("unable to generate YAML representation")
+                    );
+                }
+            }
+            BpCommand::Inspect {
+                base64,
+                qr,
+                format,
+                summary,
+                psbt,
+            } => {
+                if *qr && !cfg!(feature = "qr") {
+                    eprintln!("Error: QR code output requires `qr` feature to be enabled");
+                    exit(1);
+                }
+                let psbt = psbt_read(psbt)?;
+                if *summary {
+                    println!("Signing status of PSBT for transaction {}", psbt.txid());
+                    println!(
+                        "\nInput #\t{:68}\t   Amount, ṩ\tSigs\tSigned by\tFinalized",
+                        "Outpoint"
+                    );
+                    for input in psbt.inputs().map(PsbtInputSigning::with) {
+                        input.print();
+                    }
+                    return Ok(());
+                }
+                if *base64 {
+                    println!("{}", psbt_base64(&psbt));
+                    return Ok(());
+                }
+                if *qr {
+                    #[cfg(feature = "qr")]
+                    psbt_print_qr(&psbt)?;
+                    return Ok(());
+                }
+                match format {
+                    InspectFormat::Yaml => println!(
+                        "{}",
+                        serde_yaml::to_string(&psbt)
+                            .expect("unable to generate YAML representation")
+                    ),
+                    InspectFormat::Json => println!(
+                        "{}",
+                        serde_json::to_string_pretty(&psbt)
+                            .expect("unable to generate JSON representation")
+                    ),
+                    InspectFormat::Hex => println!("{}", psbt.serialize(psbt.version).to_hex()),
+                }
+            }
+            BpCommand::DecodeTx { json, tx } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let tx = tx_read(tx)?;
+                let decoded = DecodedTx::with(&tx, &wallet);
+                if *json {
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&decoded)
+                            .expect("unable to generate JSON representation")
+                    );
+                } else {
+                    println!(
+                        "{}",
+                        serde_yaml::to_string(&decoded)
+                            .expect("unable to generate YAML representation")
+                    );
+                }
+            }
+            BpCommand::Broadcast { tx } => {
+                let tx = tx_read(tx)?;
+                self.publish(&tx)?;
+            }
+            BpCommand::Status { json, psbt } => {
+                let psbt = psbt_read(psbt)?;
+                let status = PsbtStatus::with(&psbt);
+                if *json {
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&status)
+                            .expect("unable to generate JSON representation")
+                    );
+                } else {
+                    status.print();
+                }
+            }
+            BpCommand::SyncStatus { json } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let indexer = self.indexer()?;
+                let (tip_height, tip_hash) = match indexer.chain_tip()? {
+                    Some((height, hash)) => (Some(height), Some(hash)),
+                    None => (indexer.tip_height()?, None),
+                };
+                let status = WalletStatus {
+                    tip_height,
+                    tip_hash,
+                    cache_height: wallet.tip_height(),
+                    behind: wallet
+                        .tip_height()
+                        .zip(tip_height)
+                        .map(|(h, tip)| tip.saturating_sub(h)),
+                    transactions: wallet.transactions().len(),
+                    utxos: wallet.utxos().count(),
+                    last_used: wallet.last_used_indexes(),
+                };
+                if *json {
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&status)
+                            .expect("unable to generate JSON representation")
+                    );
+                } else {
+                    status.print();
+                }
+            }
+            BpCommand::Simulate { psbt } => {
+                let psbt = psbt_read(psbt)?;
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let change = wallet.simulate(&psbt);
+                println!("Simulating transaction {} for {}", psbt.txid(), wallet.descriptor());
+                println!(
+                    "\nKeychain\t{:>16}\t{:>16}\t{:>16}",
+                    "Current, ṩ", "Change, ṩ", "Resulting, ṩ"
+                );
+                let current = wallet.keychain_balance();
+                for keychain in wallet.keychains() {
+                    let balance = current.get(&keychain).copied().unwrap_or_default();
+                    let delta = change.keychains.get(&keychain).copied().unwrap_or_default();
+                    print_balance_change(keychain, balance, delta);
+                }
+                print_balance_change("total", wallet.balance(), change.total);
+                if !change.labels.is_empty() {
+                    println!(
+                        "\n{:<16}\t{:>16}\t{:>16}\t{:>16}",
+                        "Label", "Current, ṩ", "Change, ṩ", "Resulting, ṩ"
+                    );
+                    let current = wallet.label_balance();
+                    for (label, delta) in &change.labels {
+                        let balance = current.get(label).copied().unwrap_or_default();
+                        print_balance_change(format!("{label:<16}"), balance, *delta);
+                    }
+                }
+            }
+            BpCommand::ApplySigs {
+                psbt: psbt_path,
+                sigs,
+            } => {
+                let mut psbt = psbt_read(psbt_path)?;
+                eprint!("Reading signatures from file {} ... ", sigs.display());
+                let sigs = DetachedSig::parse_list(&fs::read_to_string(sigs)?)?;
+                eprintln!("success");
+                eprint!("Verifying and adding {} signatures ... ", sigs.len());
+                let count = apply_sigs(&mut psbt, &sigs)?;
+                eprintln!("success, {} signatures added", count.to_string().bright_green());
+                psbt_write(&psbt, psbt_path)?;
+            }
+            BpCommand::Combine {
+                force,
+                inputs,
+                output,
+            } => {
+                ensure_no_clobber(output, *force)?;
+                let psbts =
+                    inputs.iter().map(|path| psbt_read(path)).collect::<Result<Vec<_>, _>>()?;
+                eprint!("Combining {} PSBTs ... ", psbts.len());
+                let psbt = combine_psbts(psbts)?;
+                eprintln!("success");
+                psbt_write(&psbt, output)?;
+            }
+            BpCommand::Split {
+                v2,
+                count,
+                fee_rate,
+                psbt: psbt_file,
+            } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let keychain = if wallet.keychains().contains(&Keychain::INNER) {
+                    Keychain::INNER
+                } else {
+                    wallet.default_keychain()
+                };
+                let class = wallet.keychain_class(keychain);
+                let count = count.get();
+                let coins =
+                    wallet.spendable_utxos().map(WalletUtxo::into_outpoint).collect::<Vec<_>>();
+
+                // Construct a draft transaction with the same number of outputs to estimate its
+                // size
+                let mut params = TxParams::with(Sats::ZERO);
+                params.change_keychain = keychain;
+                let (draft, meta, vsize) =
+                    draft_vsize(&mut wallet, &coins, &[], params, |draft, meta| {
+                        let change =
+                            meta.change_vout.and_then(|vout| draft.output(vout.into_usize()));
+                        let Some(script) = change.map(|output| output.script.clone()) else {
+                            return;
+                        };
+                        for _ in 1..count {
+                            draft.construct_output_expect(script.clone(), Sats::ZERO);
+                        }
+                    })?;
+                let Some(vout) = meta.change_vout else {
+                    eprintln!("Error: wallet balance is too low to be split");
+                    exit(1);
+                };
+                let total = draft.outputs().nth(vout.into_usize()).expect("change output").amount;
+                let fee = Sats::from((fee_rate.to_f64() * vsize as f64).ceil() as u64);
+                let portion = total.checked_sub(fee).unwrap_or_default() / count;
+                if portion <= class.dust_limit() {
+                    eprintln!(
+                        "Error: splitting {total} ṩ into {count} outputs after paying {fee} ṩ fee \
+                         leaves {portion} ṩ per output, which is below the dust limit of {} ṩ",
+                        class.dust_limit()
+                    );
+                    exit(1);
+                }
+
+                let mut params = TxParams::with(fee);
+                params.change_keychain = keychain;
+                let (mut psbt, meta) = wallet.construct_psbt(coins, &[], params)?;
+                let vout = meta.change_vout.expect("change output").into_usize();
+                let terminals = (1..count)
+                    .map(|_| Terminal::new(keychain, wallet.next_derivation_index(keychain, true)))
+                    .collect::<Vec<_>>();
+                let output = psbt.outputs_mut().nth(vout).expect("change output");
+                output.amount = portion + output.amount - Sats::from(portion.sats() * count as u64);
+                eprintln!("Splitting into {count} outputs:");
+                eprintln!("- {} ṩ to {}", output.amount, meta.change_terminal.expect("change"));
+                for terminal in terminals {
+                    psbt.construct_change_expect(wallet.descriptor(), terminal, portion);
+                    eprintln!("- {portion} ṩ to {terminal}");
+                }
+                eprintln!("Transaction pays {fee} ṩ fee");
+                psbt.version = if *v2 { PsbtVer::V2 } else { PsbtVer::V0 };
+                psbt_write_or_print(&psbt, psbt_file.as_deref())?;
+            }
+            BpCommand::Sweep {
+                v2,
+                to,
+                json,
+                base64,
+                force,
+                fee_rate,
+                psbt: psbt_file,
+            } => {
+                if let Some(file_name) = psbt_file {
+                    ensure_no_clobber(file_name, *force)?;
+                }
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let network = wallet.network();
+                if to.network != AddressNetwork::from(network) {
+                    return Err(ExecError::BeneficiaryNetwork(to.to_string(), network));
+                }
+                let class = wallet.descriptor().class();
+                let coins =
+                    wallet.spendable_utxos().map(WalletUtxo::into_outpoint).collect::<Vec<_>>();
+                let beneficiaries = [Beneficiary::with_max(*to)];
+
+                // Construct a draft transaction to estimate its size
+                let params = TxParams::with(Sats::ZERO);
+                let (draft, _, vsize) =
+                    draft_vsize(&mut wallet, &coins, &beneficiaries, params, |_, _| ())?;
+                let fee = Sats::from((fee_rate.to_f64() * vsize as f64).ceil() as u64);
+                let total = draft.input_sum();
+                let amount = total.checked_sub(fee).unwrap_or_default();
+                let dust_limit = address_dust_limit(to);
+                if amount <= dust_limit {
+                    eprintln!(
+                        "Error: sweeping {total} ṩ after paying {fee} ṩ fee leaves {amount} ṩ, \
+                         which is below the dust limit of {dust_limit} ṩ"
+                    );
+                    exit(1);
+                }
+
+                let (mut psbt, meta) =
+                    wallet.construct_psbt(coins, &beneficiaries, TxParams::with(fee))?;
+                eprintln!(
+                    "Sweeping {total} ṩ from {} coins: {amount} ṩ to {to}, {fee} ṩ fee",
+                    psbt.inputs().count()
+                );
+                psbt.version = if *v2 { PsbtVer::V2 } else { PsbtVer::V0 };
+                if *json {
+                    if let Some(file_name) = psbt_file {
+                        psbt_write(&psbt, file_name)?;
+                    }
+                    let info = ConstructInfo::with(&psbt, &meta, class, self.general.network);
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&info)
+                            .expect("unable to generate JSON representation")
+                    );
+                } else if *base64 {
+                    match psbt_file {
+                        Some(file_name) => psbt_write_base64(&psbt, file_name)?,
+                        None => println!("{}", psbt_base64(&psbt)),
+                    }
+                } else {
+                    psbt_write_or_print(&psbt, psbt_file.as_deref())?;
+                }
+            }
+            BpCommand::Unstick {
+                v2,
+                signing_account,
+                txid,
+                fee_rate,
+                psbt: psbt_file,
+            } => {
+                if signing_account.is_some() && !cfg!(feature = "hot") {
+                    eprintln!("Error: signing requires `hot` feature to be enabled");
+                    exit(1);
+                }
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let Some(tx) = wallet.transactions().get(txid).cloned() else {
+                    eprintln!("Error: transaction {txid} is not known to the wallet");
+                    exit(1);
+                };
+                if tx.status != TxStatus::Mempool {
+                    eprintln!("Error: transaction {txid} is not pending in the mempool");
+                    exit(1);
+                }
+                let (coins, beneficiaries, parent_vsize, replaced_fee) = if tx.signals_rbf() {
+                    let (coins, beneficiaries) = replacement_spending(&tx);
+                    eprintln!("Transaction {txid} signals RBF; composing a replacement");
+                    (coins, beneficiaries, 0, tx.fee)
+                } else {
+                    let Some((vout, _)) = tx
+                        .outputs
+                        .iter()
+                        .enumerate()
+                        .filter(|(vout, out)| {
+                            out.is_ourself()
+                                && wallet.is_unspent(Outpoint::new(*txid, *vout as u32))
+                        })
+                        .max_by_key(|(_, out)| out.value)
+                    else {
+                        eprintln!(
+                            "Error: transaction {txid} doesn't signal RBF and has no unspent \
+                             outputs belonging to the wallet to be spent by a child"
+                        );
+                        exit(1);
+                    };
+                    eprintln!("Transaction {txid} doesn't signal RBF; composing a CPFP child");
+                    let parent_vsize = tx.weight.div_ceil(4);
+                    let coins = vec![Outpoint::new(*txid, vout as u32)];
+                    (coins, vec![], parent_vsize, Sats::ZERO)
+                };
+
+                // Construct a draft transaction to estimate its size
+                let params = TxParams::with(Sats::ZERO);
+                let (_, _, vsize) =
+                    draft_vsize(&mut wallet, &coins, &beneficiaries, params, |_, _| ())?;
+                let parent_fee = if parent_vsize > 0 { tx.fee } else { Sats::ZERO };
+                // BIP-125 requires replacement to pay for its own bandwidth at the minimal relay
+                // fee rate on top of the fee of the replaced transaction; CPFP child must pay at
+                // least the minimal relay fee rate.
+                let package_vsize = (vsize + parent_vsize) as f64;
+                let fee = Sats::from((fee_rate.to_f64() * package_vsize).ceil() as u64)
+                    .checked_sub(parent_fee)
+                    .unwrap_or_default()
+                    .max(replaced_fee + Sats::from(vsize));
+
+                // When publishing, the change index is shifted only once the transaction gets
+                // published
+                let mut params = TxParams::with(fee);
+                params.change_shift = signing_account.is_none();
+                let (mut psbt, meta) = wallet.construct_psbt(coins, &beneficiaries, params)?;
+                psbt.version = if *v2 { PsbtVer::V2 } else { PsbtVer::V0 };
+                eprintln!(
+                    "New transaction {} pays {fee} ṩ; effective fee rate is {:.2} ṩ/vbyte",
+                    psbt.txid(),
+                    (fee + parent_fee).sats() as f64 / (vsize + parent_vsize) as f64
+                );
+                let Some(account) = signing_account else {
+                    eprintln!(
+                        "Sign the PSBT and publish it using `finalize --publish` to unstick the \
+                         transaction"
+                    );
+                    psbt_write_or_print(&psbt, psbt_file.as_deref())?;
+                    return Ok(());
+                };
+                let new_tx = psbt_sign_extract(&mut psbt, wallet.descriptor(), Some(account))?;
+                if let Some(file_name) = psbt_file {
+                    psbt_write(&psbt, file_name)?;
+                }
+                self.publish(&new_tx)?;
+                if let Some(terminal) = meta.change_terminal {
+                    wallet.advance_derivation_index(
+                        terminal.keychain,
+                        terminal.index.saturating_inc(),
+                    );
+                }
+                println!("{}", new_tx.txid());
+            }
+            BpCommand::BumpFee {
+                v2,
+                txid,
+                fee_rate,
+                psbt: psbt_file,
+            } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let Some(tx) = wallet.transactions().get(txid).cloned() else {
+                    eprintln!("Error: transaction {txid} is not known to the wallet");
+                    exit(1);
+                };
+                match tx.status {
+                    TxStatus::Mempool => {}
+                    TxStatus::Mined(_) => {
+                        eprintln!(
+                            "Error: transaction {txid} is already mined and can't be replaced"
+                        );
+                        exit(1);
+                    }
+                    _ => {
+                        eprintln!("Error: transaction {txid} is not pending in the mempool");
+                        exit(1);
+                    }
+                }
+                if !tx.signals_rbf() {
+                    eprintln!(
+                        "Warning: none of the inputs of transaction {txid} signals \
+                         replaceability; the replacement will be relayed only by nodes with full \
+                         RBF enabled"
+                    );
+                }
+                let (mut coins, beneficiaries) = replacement_spending(&tx);
+                let payments = tx.debits().map(|out| out.value).sum::<Sats>();
+                let mut available = tx.inputs.iter().map(|inp| inp.value).sum::<Sats>();
+
+                // Add wallet coins until they cover the fee for the size of the replacement
+                // spending them
+                let mut added = 0usize;
+                let fee = loop {
+                    let fee = replacement_fee(
+                        &mut wallet,
+                        &coins,
+                        &beneficiaries,
+                        fee_rate.to_f64(),
+                        tx.fee,
+                    )?;
+                    if available >= payments + fee {
+                        break fee;
+                    }
+                    // Outputs of the replaced transaction can't be spent by its replacement
+                    let extra = wallet
+                        .coinselect(payments + fee - available, |utxo| {
+                            utxo.outpoint.txid != *txid && !coins.contains(&utxo.outpoint)
+                        })
+                        .collect::<Vec<_>>();
+                    if extra.is_empty() {
+                        eprintln!("Error: no more wallet coins to pay the increased {fee} ṩ fee");
+                        exit(1);
+                    }
+                    available += extra
+                        .iter()
+                        .filter_map(|outpoint| wallet.outpoint_by(*outpoint).ok())
+                        .map(|utxo| utxo.value)
+                        .sum::<Sats>();
+                    added += extra.len();
+                    coins.extend(extra);
+                };
+                if added > 0 {
+                    eprintln!("Adding {added} more inputs to cover the increased fee");
+                }
+
+                let (mut psbt, _) =
+                    wallet.construct_psbt(coins, &beneficiaries, TxParams::with(fee))?;
+                psbt.version = if *v2 { PsbtVer::V2 } else { PsbtVer::V0 };
+                eprintln!(
+                    "Replacement transaction {} pays {fee} ṩ instead of {} ṩ",
+                    psbt.txid(),
+                    tx.fee
+                );
+                psbt_write_or_print(&psbt, psbt_file.as_deref())?;
+            }
+            BpCommand::BumpFeeCpfp {
+                v2,
+                outpoint,
+                fee_rate,
+                psbt: psbt_file,
+            } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                if !wallet.is_unspent(*outpoint) {
+                    eprintln!("Error: {outpoint} is not an unspent output belonging to the wallet");
+                    exit(1);
+                }
+                let Some(parent) = wallet.transactions().get(&outpoint.txid).cloned() else {
+                    eprintln!("Error: transaction {} is not known to the wallet", outpoint.txid);
+                    exit(1);
+                };
+                if parent.status != TxStatus::Mempool {
+                    eprintln!("Error: output {outpoint} is not unconfirmed");
+                    exit(1);
+                }
+                let parent_vsize = parent.weight.div_ceil(4);
+
+                // Construct a draft transaction to estimate its size
+                let params = TxParams::with(Sats::ZERO);
+                let (_, _, vsize) = draft_vsize(&mut wallet, &[*outpoint], &[], params, |_, _| ())?;
+                // CPFP child must pay at least the minimal relay fee rate
+                let package_fee =
+                    Sats::from((fee_rate.to_f64() * (vsize + parent_vsize) as f64).ceil() as u64);
+                let fee =
+                    package_fee.checked_sub(parent.fee).unwrap_or_default().max(Sats::from(vsize));
+                let params = TxParams::with(fee);
+                let value =
+                    wallet.outpoint_by(*outpoint).map(|utxo| utxo.value).unwrap_or_default();
+                let dust_limit = wallet.keychain_class(params.change_keychain).dust_limit();
+                let change = value.checked_sub(fee).unwrap_or_default();
+                if change <= dust_limit {
+                    eprintln!(
+                        "Error: spending {value} ṩ of output {outpoint} with {fee} ṩ fee leaves \
+                         {change} ṩ, which is below the dust limit of {dust_limit} ṩ"
+                    );
+                    exit(1);
+                }
+
+                let (mut psbt, _) = wallet.construct_psbt([*outpoint], &[], params)?;
+                psbt.version = if *v2 { PsbtVer::V2 } else { PsbtVer::V0 };
+                eprintln!(
+                    "Parent transaction {} pays {} ṩ; its fee rate is {:.2} ṩ/vbyte",
+                    outpoint.txid,
+                    parent.fee,
+                    parent.fee.sats() as f64 / parent_vsize as f64
+                );
+                eprintln!(
+                    "Child transaction {} pays {fee} ṩ; its fee rate is {:.2} ṩ/vbyte",
+                    psbt.txid(),
+                    fee.sats() as f64 / vsize as f64
+                );
+                eprintln!(
+                    "Effective fee rate of the package is {:.2} ṩ/vbyte",
+                    (fee + parent.fee).sats() as f64 / (vsize + parent_vsize) as f64
+                );
+                psbt_write_or_print(&psbt, psbt_file.as_deref())?;
+            }
+            BpCommand::Send {
+                to: payees,
+                signing_account,
+                dry_run,
+                fee,
+            } => {
+                if signing_account.is_some() && !cfg!(feature = "hot") {
+                    eprintln!("Error: signing requires `hot` feature to be enabled");
+                    exit(1);
+                }
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let beneficiaries = payees.iter().map(|p| p.beneficiary).collect::<Vec<_>>();
+                let network = wallet.network();
+                let mismatches = beneficiaries
+                    .iter()
+                    .map(|b| b.address)
+                    .filter(|addr| addr.network != AddressNetwork::from(network))
+                    .map(|addr| addr.to_string())
+                    .collect::<Vec<_>>();
+                if !mismatches.is_empty() {
+                    return Err(ExecError::BeneficiaryNetwork(mismatches.join(", "), network));
+                }
+
+                let coins = select_coins(&wallet, &beneficiaries, *fee, None, None)?;
+                // The change index is shifted only once the transaction gets published
+                let mut params = TxParams::with(*fee);
+                params.change_shift = false;
+                let (mut psbt, meta) = wallet.construct_psbt(coins, &beneficiaries, params)?;
+                apply_max_floors(&mut psbt, payees)?;
+                let tx =
+                    psbt_sign_extract(&mut psbt, wallet.descriptor(), signing_account.as_deref())?;
+                if *dry_run {
+                    println!("{tx:x}");
+                } else {
+                    self.publish(&tx)?;
+                    if let Some(terminal) = meta.change_terminal {
+                        let next = terminal.index.saturating_inc();
+                        wallet.advance_derivation_index(terminal.keychain, next);
+                    }
+                    println!("{}", tx.txid());
+                }
+            }
+            BpCommand::ConstructFrom {
+                force,
+                request_file,
+                psbt: psbt_file,
+            } => {
+                let request = fs::read_to_string(request_file)?;
+                let plan = match serde_json::from_str::<SpendRequest>(&request)
+                    .map_err(|err| err.to_string())
+                    .and_then(|request| request.validate().map_err(|err| err.to_string()))
+                {
+                    Ok(plan) => plan,
+                    Err(err) => {
+                        eprintln!("Error: invalid spend request: {err}");
+                        exit(1);
+                    }
+                };
+                if let Some(file_name) = psbt_file {
+                    ensure_no_clobber(file_name, *force)?;
+                }
+
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let payments = plan.payees.iter().map(|p| p.beneficiary).collect::<Vec<_>>();
+                let network = wallet.network();
+                let mismatches = payments
+                    .iter()
+                    .map(|b| b.address)
+                    .chain(plan.change_address)
+                    .filter(|addr| addr.network != AddressNetwork::from(network))
+                    .map(|addr| addr.to_string())
+                    .collect::<Vec<_>>();
+                if !mismatches.is_empty() {
+                    return Err(ExecError::BeneficiaryNetwork(mismatches.join(", "), network));
+                }
+                if let Some(keychain) =
+                    plan.change_keychain.filter(|k| !wallet.keychains().contains(k))
+                {
+                    eprintln!(
+                        "Error: invalid spend request: field `change.keychain`: keychain \
+                         {keychain} is not used by the wallet descriptor"
+                    );
+                    exit(1);
+                }
+
+                // Drafts for the fee estimation always include the output to the change address
+                let mut draft_outputs = payments.clone();
+                draft_outputs.extend(plan.change_address.map(Beneficiary::with_max));
+                let params = |fee: Sats, change_shift: bool| {
+                    let mut params = TxParams::with(fee);
+                    params.lock_time = plan.lock_time;
+                    params.seq_no = plan.seq_no;
+                    params.change_shift = change_shift;
+                    if let Some(keychain) = plan.change_keychain {
+                        params.change_keychain = keychain;
+                    }
+                    params
+                };
+
+                let (fee, rate) = match plan.fee {
+                    SpendFee::Sats(fee) => (fee, None),
+                    SpendFee::Rate(rate) => (Sats::ZERO, Some(rate)),
+                    SpendFee::Target(target) => {
+                        let (rate, source) = self.fee_rate(&config, target);
+                        eprintln!(
+                            "Using fee rate of {rate:.2} ṩ/vbyte provided by {}",
+                            self.fee_source_info(source)
+                        );
+                        (Sats::ZERO, Some(rate))
+                    }
+                };
+                let fee = match rate {
+                    None => fee,
+                    Some(rate) => {
+                        // Repeat coin selection until the selected coins cover the fee for the
+                        // size of the transaction spending them
+                        let mut fee = Sats::ZERO;
+                        loop {
+                            let coins = select_spend_coins(&wallet, &plan, &payments, fee)?;
+                            let (_, _, vsize) = draft_vsize(
+                                &mut wallet,
+                                &coins,
+                                &draft_outputs,
+                                params(fee, false),
+                                |draft, _| {
+                                    for data in &plan.op_return {
+                                        draft.construct_output_expect(
+                                            ScriptPubkey::op_return(data),
+                                            Sats::ZERO,
+                                        );
+                                    }
+                                },
+                            )?;
+                            let required = Sats::from((rate * vsize as f64).ceil() as u64);
+                            if required <= fee {
+                                break fee;
+                            }
+                            fee = required;
+                        }
+                    }
+                };
+
+                let coins = select_spend_coins(&wallet, &plan, &payments, fee)?;
+                let mut outputs = payments.clone();
+                let mut fee = fee;
+                if let Some(address) = plan.change_address {
+                    fee += add_change_address(&wallet, &coins, &mut outputs, fee, address, None);
+                }
+                let (mut psbt, _) = wallet.construct_psbt(coins, &outputs, params(fee, true))?;
+                for data in &plan.op_return {
+                    psbt.construct_output_expect(ScriptPubkey::op_return(data), Sats::ZERO);
+                }
+                check_op_returns(&psbt)?;
+                apply_max_floors(&mut psbt, &plan.payees)?;
+                psbt.version = if plan.v2 { PsbtVer::V2 } else { PsbtVer::V0 };
+                eprintln!("Transaction pays {fee} ṩ fee");
+                psbt_write_or_print(&psbt, psbt_file.as_deref())?;
+            }
+            BpCommand::PrivacyCheck {
+                to: payees,
+                from_label,
+                fee,
+            } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let beneficiaries = payees.iter().map(|p| p.beneficiary).collect::<Vec<_>>();
+                let network = wallet.network();
+                let mismatches = beneficiaries
+                    .iter()
+                    .map(|b| b.address)
+                    .filter(|addr| addr.network != AddressNetwork::from(network))
+                    .map(|addr| addr.to_string())
+                    .collect::<Vec<_>>();
+                if !mismatches.is_empty() {
+                    return Err(ExecError::BeneficiaryNetwork(mismatches.join(", "), network));
+                }
+
+                let coins = select_coins(&wallet, &beneficiaries, *fee, from_label.as_ref(), None)?;
+                // Preview the transaction without shifting the change index
+                let mut params = TxParams::with(*fee);
+                params.change_shift = false;
+                let (mut psbt, meta) = wallet.construct_psbt(coins, &beneficiaries, params)?;
+                apply_max_floors(&mut psbt, payees)?;
+
+                println!(
+                    "Candidate transaction spends {} coin(s) to {} output(s)",
+                    psbt.inputs().count(),
+                    psbt.outputs().count()
+                );
+                let issues = privacy_check(&psbt, meta.change_vout);
+                if issues.is_empty() {
+                    println!("{}", "No privacy issues detected".bright_green());
+                }
+                for issue in issues {
+                    println!("{} {issue}", "Warning:".bright_red());
+                    println!("  Suggestion: {}", issue.suggestion());
+                }
+            }
+            BpCommand::Construct {
+                v2,
+                to: payees,
+                from_label,
+                utxo: manual_coins,
+                coin_strategy,
+                sighash,
+                json,
+                base64,
+                qr,
+                change_address,
+                op_return,
+                allow_nonstandard,
+                locktime,
+                sequence,
+                rbf: _,
+                no_rbf,
+                dust_limit,
+                force,
+                auto_name,
+                send,
+                signing_account,
+                fee,
+                psbt: psbt_file,
+            } => {
+                if *send && signing_account.is_none() {
+                    eprintln!(
+                        "Error: wallet descriptor is watch-only and can't sign the transaction; \
+                         provide signing keys with --signing-account to use --send"
+                    );
+                    exit(1);
+                }
+                if *send && !cfg!(feature = "hot") {
+                    eprintln!("Error: signing with --send requires `hot` feature to be enabled");
+                    exit(1);
+                }
+                if *qr && !cfg!(feature = "qr") {
+                    eprintln!("Error: QR code output requires `qr` feature to be enabled");
+                    exit(1);
+                }
+                if let Some(file_name) = psbt_file.as_ref().filter(|_| !*auto_name) {
+                    ensure_no_clobber(file_name, *force)?;
+                }
+                let lock_time = locktime.map(LockTime::from_consensus_u32);
+                // Zero lock time disables the lock and can be always used
+                if let Some(lock_time) =
+                    lock_time.filter(|lt| lt.is_height_based() && *lt != LockTime::ZERO)
+                {
+                    self.check_lock_height(lock_time.to_consensus_u32());
+                }
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let beneficiaries = payees.iter().map(|p| p.beneficiary).collect::<Vec<_>>();
+
+                let network = wallet.network();
+                let mismatches = beneficiaries
+                    .iter()
+                    .map(|b| b.address)
+                    .chain(*change_address)
+                    .filter(|addr| addr.network != AddressNetwork::from(network))
+                    .map(|addr| addr.to_string())
+                    .collect::<Vec<_>>();
+                if !mismatches.is_empty() {
+                    return Err(ExecError::BeneficiaryNetwork(mismatches.join(", "), network));
+                }
+
+                // Do coin selection
+                if change_address.is_some()
+                    && beneficiaries.iter().any(|b| matches!(b.amount, Payment::Max))
+                {
+                    eprintln!("Error: change address can't be used together with `MAX` payments");
+                    exit(1);
+                }
+                let coins = if manual_coins.is_empty() {
+                    select_coins(
+                        &wallet,
+                        &beneficiaries,
+                        *fee,
+                        from_label.as_ref(),
+                        *coin_strategy,
+                    )?
+                } else {
+                    check_coins(&wallet, manual_coins, &beneficiaries, *fee)?
+                };
+
+                let mut params = TxParams::with(*fee);
+                params.lock_time = lock_time;
+                params.seq_no = match sequence {
+                    Some(seq_no) => SeqNo::from_consensus_u32(*seq_no),
+                    None if *no_rbf => SeqNo::from_consensus_u32(SEQ_NO_NO_RBF),
+                    None => SeqNo::from_consensus_u32(SEQ_NO_RBF),
+                };
+                // The change index is shifted only once the PSBT file is checked not to be
+                // overwritten or, with `--send`, once the transaction gets published
+                params.change_shift = false;
+
+                let mut beneficiaries = beneficiaries;
+                if let Some(address) = change_address {
+                    if wallet.derived_addr_for(&address.script_pubkey(), None).is_none() {
+                        eprintln!(
+                            "{}",
+                            format!(
+                                "Warning: change address {address} does not belong to the wallet; \
+                                 the change sent to it will not be tracked as wallet funds"
+                            )
+                            .bright_red()
+                        );
+                    }
+                    // The remaining value goes to the change address, so the wallet doesn't
+                    // derive own change output and doesn't shift the change index.
+                    params.fee += add_change_address(
+                        &wallet,
+                        &coins,
+                        &mut beneficiaries,
+                        *fee,
+                        *address,
+                        *dust_limit,
+                    );
+                } else if let Some(dust_limit) = dust_limit {
+                    params.fee += dust_change(&wallet, &coins, &beneficiaries, *fee, *dust_limit);
+                }
+                let (mut psbt, meta) = wallet.construct_psbt(coins, &beneficiaries, params)?;
+                if meta.change_vout.is_none() && !beneficiaries.iter().any(Beneficiary::is_max) {
+                    let absorbed = psbt.fee().unwrap_or_default().checked_sub(*fee);
+                    if let Some(absorbed) = absorbed.filter(|sats| *sats > Sats::ZERO) {
+                        eprintln!(
+                            "Note: change of {absorbed} ṩ is below the dust limit and was added \
+                             to the fee"
+                        );
+                    }
+                }
+                for data in op_return {
+                    let data = parse_op_return(data)?;
+                    psbt.construct_output_expect(ScriptPubkey::op_return(&data), Sats::ZERO);
+                }
+                if !*allow_nonstandard {
+                    check_op_returns(&psbt)?;
+                }
+                apply_max_floors(&mut psbt, payees)?;
+                if payees.iter().any(Payee::is_max) {
+                    eprintln!("Resolved payments:");
+                    for (out, payee) in psbt.outputs().zip(payees) {
+                        eprintln!("- {}@{}", out.amount, payee.beneficiary.address);
+                    }
+                }
+                apply_sighash_types(&mut psbt, sighash)?;
+                if sighash.iter().any(|s| s.sighash_type.flag == SighashFlag::None) {
+                    eprintln!(
+                        "Warning: inputs signed with SIGHASH_NONE do not commit to the \
+                         transaction outputs, which may be changed by anybody"
+                    );
+                }
+                psbt.version = if *v2 { PsbtVer::V2 } else { PsbtVer::V0 };
+                let psbt_file = if *auto_name {
+                    Some(PathBuf::from(format!("{}.psbt", psbt.txid())))
+                } else {
+                    psbt_file.clone()
+                };
+                if let Some(file_name) = &psbt_file {
+                    ensure_no_clobber(file_name, *force)?;
+                }
+                if let Some(terminal) = meta.change_terminal.filter(|_| !*send) {
+                    wallet.advance_derivation_index(
+                        terminal.keychain,
+                        terminal.index.saturating_inc(),
+                    );
+                }
+                if *send {
+                    let tx = psbt_sign_extract(
+                        &mut psbt,
+                        wallet.descriptor(),
+                        signing_account.as_deref(),
+                    )?;
+                    if let Some(file_name) = &psbt_file {
+                        psbt_write(&psbt, file_name)?;
+                    }
+                    if confirm_broadcast(&tx, psbt.fee().unwrap_or_default(), network)? {
+                        self.publish(&tx)?;
+                        if let Some(terminal) = meta.change_terminal {
+                            let next = terminal.index.saturating_inc();
+                            wallet.advance_derivation_index(terminal.keychain, next);
+                        }
+                    } else {
+                        eprintln!("Transaction was not broadcast");
+                    }
+                } else if *json {
+                    if let Some(file_name) = &psbt_file {
+                        psbt_write(&psbt, file_name)?;
+                    }
+                    let info = ConstructInfo::with(
+                        &psbt,
+                        &meta,
+                        wallet.descriptor().class(),
+                        self.general.network,
+                    );
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&info)
+                            .expect("unable to generate JSON representation")
+                    );
+                } else if *base64 {
+                    match &psbt_file {
+                        Some(file_name) => psbt_write_base64(&psbt, file_name)?,
+                        None => println!("{}", psbt_base64(&psbt)),
+                    }
+                } else if *qr {
+                    if let Some(file_name) = &psbt_file {
+                        psbt_write(&psbt, file_name)?;
+                    }
+                    #[cfg(feature = "qr")]
+                    psbt_print_qr(&psbt)?;
+                } else {
+                    psbt_write_or_print(&psbt, psbt_file.as_deref())?;
+                }
+            }
+        };
+
+        println!();
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+#[derive(serde::Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+struct AddressRow {
+    terminal: String,
+    address: String,
+    script_pubkey: String,
+}
+
+#[derive(serde::Serialize)]
+#[serde(crate = "serde_crate")]
+struct ConstructInfo {
+    psbt_base64: String,
+    txid_unsigned: Txid,
+    inputs: Vec<Outpoint>,
+    outputs: Vec<OutputInfo>,
+    change: Option<OutputInfo>,
+    fee: Sats,
+    fee_rate: f64,
+    vsize: u32,
+    weight: u32,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(serde::Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+struct OutputInfo {
+    vout: u32,
+    address: Option<String>,
+    sats: Sats,
+}
+
+impl ConstructInfo {
+    /// Collects information about a newly constructed PSBT, estimating the weight of the signed
+    /// transaction with [`psbt_estimated_weight`].
+    fn with(psbt: &Psbt, meta: &PsbtMeta, class: SpkClass, network: Network) -> Self {
+        let outputs = psbt
+            .outputs()
+            .enumerate()
+            .map(|(vout, output)| OutputInfo {
+                vout: vout as u32,
+                address: Address::with(&output.script, network).ok().map(|a| a.to_string()),
+                sats: output.amount,
+            })
+            .collect::<Vec<_>>();
+        let change = meta.change_vout.and_then(|vout| outputs.get(vout.to_u32() as usize)).cloned();
+
+        let weight = psbt_estimated_weight(psbt, class);
+        let vsize = weight.div_ceil(4);
+        let fee = psbt.fee().unwrap_or_default();
+
+        ConstructInfo {
+            psbt_base64: psbt_base64(psbt),
+            txid_unsigned: psbt.txid(),
+            inputs: psbt.inputs().map(|input| input.previous_outpoint).collect(),
+            change: change.clone(),
+            outputs: outputs.into_iter().filter(|o| Some(o) != change.as_ref()).collect(),
+            fee,
+            fee_rate: fee.sats() as f64 / vsize as f64,
+            vsize,
+            weight,
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(serde::Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+struct ScanResult {
+    balance: Sats,
+    coins: Vec<CoinRow<Layer2Empty>>,
+    errors: Vec<String>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(serde::Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+struct BalanceInfo {
+    balance: Sats,
+    /// Balance excluding frozen coins and immature coinbase outputs.
+    spendable: Sats,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    addresses: Option<Vec<AddrBalance>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    coins: Option<Vec<CoinInfo>>,
+    /// Coins spent by unconfirmed transactions, excluded from the balance.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pending: Option<Vec<CoinInfo>>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(serde::Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+struct AddrBalance {
+    #[serde(flatten)]
+    info: WalletAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    coins: Option<Vec<CoinInfo>>,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(serde::Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+struct CoinInfo {
+    height: TxStatus<BlockHeight>,
+    outpoint: Outpoint,
+    amount: Sats,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address: Option<DerivedAddr>,
+    frozen: bool,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(serde::Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+struct AddrTxRow {
+    txid: Txid,
+    height: TxStatus<BlockHeight>,
+    received: Sats,
+    sent: Sats,
+    fee: Sats,
+    rbf: bool,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+#[derive(serde::Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+struct PendingTx {
+    txid: Txid,
+    operation: OpType,
+    amount: Sats,
+    fee: Sats,
+    fee_rate: f64,
+    rbf: bool,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(serde::Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+struct PsbtStatus {
+    txid: Txid,
+    creator: bool,
+    inputs: Vec<PsbtInputStatus>,
+    extractor: bool,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(serde::Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+struct PsbtInputStatus {
+    index: usize,
+    updater: bool,
+    signer: bool,
+    finalizer: bool,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(serde::Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+struct WalletStatus {
+    tip_height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tip_hash: Option<BlockHash>,
+    cache_height: Option<u32>,
+    behind: Option<u32>,
+    transactions: usize,
+    utxos: usize,
+    last_used: BTreeMap<Keychain, NormalIndex>,
+}
+
+impl WalletStatus {
+    fn print(&self) {
+        match (self.tip_height, self.tip_hash) {
+            (Some(height), Some(hash)) => println!("Indexer tip:\t{height} ({hash})"),
+            (Some(height), None) => println!("Indexer tip:\t{height}"),
+            (None, _) => println!("Indexer tip:\t{}", "not provided by the indexer".bright_red()),
+        }
+        match (self.cache_height, self.behind) {
+            (Some(height), Some(0)) => println!("Cache tip:\t{height} (up to date)"),
+            (Some(height), Some(behind)) => {
+                println!("Cache tip:\t{height} ({} blocks behind)", behind.to_string().bright_red())
+            }
+            (Some(height), None) => println!("Cache tip:\t{height}"),
+            (None, _) => {
+                println!("Cache tip:\t{}", "unknown, the wallet was never synced".bright_red())
+            }
+        }
+        println!("Transactions:\t{}", self.transactions);
+        println!("UTXOs:\t\t{}", self.utxos);
+        if self.last_used.is_empty() {
+            println!("Last used:\tnone");
+        }
+        for (keychain, index) in &self.last_used {
+            println!("Last used:\t{index} at keychain {keychain}");
+        }
+    }
+}
+
+impl PsbtStatus {
+    fn with(psbt: &Psbt) -> Self {
+        PsbtStatus {
+            txid: psbt.txid(),
+            creator: true,
+            inputs: psbt.inputs().map(PsbtInputStatus::with).collect(),
+            extractor: psbt.is_finalized(),
+        }
+    }
+
+    fn print(&self) {
+        let done = |flag: bool| if flag { "done".bright_green() } else { "pending".bright_red() };
+        println!("Status of PSBT for transaction {}", self.txid);
+        println!("\nCreator:\t{}", done(self.creator));
+        println!("\nInput #\tUpdater\tSigner\tFinalizer");
+        for input in &self.inputs {
+            println!(
+                "{}\t{}\t{}\t{}",
+                input.index + 1,
+                done(input.updater),
+                done(input.signer),
+                done(input.finalizer)
+            );
+        }
+        println!("\nExtractor:\t{}", if self.extractor { "ready" } else { "not ready" });
+    }
+}
+
+impl PsbtInputStatus {
+    fn with(input: &psbt::Input) -> Self {
+        let finalizer = input.is_finalized();
+        PsbtInputStatus {
+            index: input.index(),
+            updater: input.witness_utxo.is_some() || input.non_witness_tx.is_some(),
+            signer: finalizer
+                || !input.partial_sigs.is_empty()
+                || input.tap_key_sig.is_some()
+                || !input.tap_script_sig.is_empty(),
+            finalizer,
+        }
+    }
+}
+
+/// Signing status of a PSBT input, detected from the PSBT data only.
+#[derive(Clone, Eq, PartialEq, Debug)]
+struct PsbtInputSigning {
+    index: usize,
+    outpoint: Outpoint,
+    amount: Option<Sats>,
+    /// Number of required signatures, detected from the multisig witness, redeem or tapscript
+    /// leaf scripts, defaulting to a single signature for other inputs.
+    required: u8,
+    provided: usize,
+    signers: Vec<XpubFp>,
+    finalized: bool,
+}
+
+impl PsbtInputSigning {
+    fn with(input: &psbt::Input) -> Self {
+        let amount = input.witness_utxo.as_ref().map(|txout| txout.value).or_else(|| {
+            let tx = input.non_witness_tx.as_ref()?;
+            tx.outputs.get(input.previous_outpoint.vout_usize()).map(|txout| txout.value)
+        });
+        let legacy = [
+            input.witness_script.as_ref().map(|script| script.as_slice()),
+            input.redeem_script.as_ref().map(|script| script.as_slice()),
+        ];
+        let required = legacy
+            .into_iter()
+            .flatten()
+            .find_map(multisig_threshold)
+            .or_else(|| {
+                // Key path spending requires a single signature
+                if input.tap_key_sig.is_some() {
+                    return None;
+                }
+                input
+                    .tap_leaf_script
+                    .values()
+                    .filter_map(|leaf| multisig_threshold(leaf.script.as_slice()))
+                    .min()
+            })
+            .unwrap_or(1);
+
+        let mut signers = input
+            .partial_sigs
+            .keys()
+            .filter_map(|pk| input.bip32_derivation.get(pk))
+            .map(|origin| origin.master_fp())
+            .collect::<Vec<_>>();
+        let tap_keys = input
+            .tap_key_sig
+            .and(input.tap_internal_key)
+            .map(|pk| pk.to_xonly_pk())
+            .into_iter()
+            .chain(input.tap_script_sig.keys().map(|(pk, _)| *pk));
+        signers.extend(
+            tap_keys
+                .filter_map(|pk| input.tap_bip32_derivation.get(&pk))
+                .map(|derivation| derivation.origin.master_fp()),
+        );
+        signers.sort();
+        signers.dedup();
+
+        PsbtInputSigning {
+            index: input.index(),
+            outpoint: input.previous_outpoint,
+            amount,
+            required,
+            provided: input.partial_sigs.len()
+                + input.tap_key_sig.is_some() as usize
+                + input.tap_script_sig.len(),
+            signers,
+            finalized: input.is_finalized(),
+        }
+    }
+
+    fn print(&self) {
+        let amount = self.amount.map(|sats| sats.to_string()).unwrap_or_else(|| s!("~"));
+        let sigs =
+            if self.finalized { s!("-") } else { format!("{}/{}", self.provided, self.required) };
+        let signers = self.signers.iter().map(XpubFp::to_string).collect::<Vec<_>>().join(",");
+        println!(
+            "{}\t{:68}\t{amount: >12}\t{sigs}\t{}\t{}",
+            self.index + 1,
+            self.outpoint,
+            if signers.is_empty() { s!("~") } else { signers },
+            if self.finalized { "yes".bright_green() } else { "no".bright_red() }
+        );
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(serde::Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+struct DecodedTx {
+    txid: Txid,
+    version: TxVer,
+    lock_time: LockTime,
+    vsize: u32,
+    weight: u32,
+    inputs: Vec<DecodedInput>,
+    outputs: Vec<DecodedOutput>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(serde::Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+struct DecodedInput {
+    prev_output: Outpoint,
+    sequence: SeqNo,
+    witness: Witness,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(serde::Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+struct DecodedOutput {
+    value: Sats,
+    script_pubkey: ScriptPubkey,
+    beneficiary: Party,
+}
+
+impl DecodedTx {
+    fn with<K, D: Descriptor<K>>(tx: &Tx, wallet: &Wallet<K, D>) -> Self {
+        let network = wallet.network();
+        let weight = tx.weight_units().to_u32();
+        DecodedTx {
+            txid: tx.txid(),
+            version: tx.version,
+            lock_time: tx.lock_time,
+            vsize: weight.div_ceil(4),
+            weight,
+            inputs: tx
+                .inputs()
+                .map(|txin| DecodedInput {
+                    prev_output: txin.prev_output,
+                    sequence: txin.sequence,
+                    witness: txin.witness.clone(),
+                })
+                .collect(),
+            outputs: tx
+                .outputs()
+                .map(|txout| {
+                    let script = &txout.script_pubkey;
+                    let beneficiary =
+                        match (Address::with(script, network), wallet.terminal_for(script)) {
+                            (Ok(addr), Some(terminal)) => Party::Wallet(DerivedAddr::new(
+                                addr,
+                                terminal.keychain,
+                                terminal.index,
+                            )),
+                            (Ok(addr), None) => Party::Counterparty(addr),
+                            (Err(_), _) => Party::Unknown(script.clone()),
+                        };
+                    DecodedOutput {
+                        value: txout.value,
+                        script_pubkey: script.clone(),
+                        beneficiary,
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Selects wallet coins to fund payments to the `beneficiaries` and the `fee`, optionally
+/// restricting them to the coins bearing `from_label`. If there are no fixed-amount payments, all
+/// wallet coins are selected.
+fn select_coins<K, D: Descriptor<K>>(
+    wallet: &Wallet<K, D>,
+    beneficiaries: &[Beneficiary],
+    fee: Sats,
+    from_label: Option<&String>,
+    strategy: Option<CoinSelection>,
+) -> Result<Vec<Outpoint>, ExecError> {
+    let total_amount = beneficiaries.iter().try_fold(Sats::ZERO, |sats, b| match b.amount {
+        Payment::Max => Err(()),
+        Payment::Fixed(s) => sats.checked_add(s).ok_or(()),
+    });
+    let selector = |utxo: &WalletUtxo| match from_label {
+        Some(label) => wallet.coin_label(utxo.outpoint) == Some(label.as_str()),
+        None => coinselect::all(utxo),
+    };
+    let coins: Vec<_> = match total_amount {
+        Ok(sats) if sats > Sats::ZERO => {
+            let up_to = sats + fee;
+            match strategy {
+                None => wallet.coinselect(up_to, selector).collect(),
+                // The fee is already accounted in the target, so the coins are taken with their
+                // full value, and an excess below the dust limit goes to the miners
+                Some(CoinSelection::Bnb) => coinselect::bnb(
+                    wallet.spendable_utxos().filter(selector),
+                    up_to,
+                    0.0,
+                    |_| 0,
+                    wallet.keychain_class(Keychain::INNER).dust_limit(),
+                )
+                .collect(),
+                Some(CoinSelection::Largest) => {
+                    wallet.coinselect_by(up_to, selector, coinselect::largest_first).collect()
+                }
+                Some(CoinSelection::Smallest) => {
+                    wallet.coinselect_by(up_to, selector, coinselect::smallest_first).collect()
+                }
+                Some(CoinSelection::Oldest) => {
+                    wallet.coinselect_by(up_to, selector, coinselect::oldest_first).collect()
+                }
             }
-            BpCommand::Tx { tx } => {
-                println!(
-                    "{}",
-                    serde_yaml::to_string(&tx).expect("unable to generate YAML representation")
-                );
+        }
+        _ => {
+            eprintln!(
+                "Warning: you are not paying to anybody but just aggregating all your balances to \
+                 a single UTXO",
+            );
+            wallet.spendable_utxos().filter(selector).map(WalletUtxo::into_outpoint).collect()
+        }
+    };
+    if let Some(label) = from_label {
+        let required = total_amount.unwrap_or_default() + fee;
+        let available = coins
+            .iter()
+            .filter_map(|outpoint| wallet.outpoint_by(*outpoint).ok())
+            .map(|utxo| utxo.value)
+            .sum::<Sats>();
+        if coins.is_empty() || available < required {
+            return Err(ExecError::InsufficientLabelledCoins(label.clone(), available, required));
+        }
+    }
+    Ok(coins)
+}
+
+/// Computes value left from the coins after the fixed payments and the fee, or `None` if the
+/// coins don't cover them.
+fn spare_value<K, D: Descriptor<K>>(
+    wallet: &Wallet<K, D>,
+    coins: &[Outpoint],
+    beneficiaries: &[Beneficiary],
+    fee: Sats,
+) -> Option<Sats> {
+    let inputs = coins
+        .iter()
+        .filter_map(|outpoint| wallet.outpoint_by(*outpoint).ok())
+        .map(|utxo| utxo.value)
+        .sum::<Sats>();
+    let outputs = beneficiaries
+        .iter()
+        .filter_map(|b| match b.amount {
+            Payment::Max => None,
+            Payment::Fixed(sats) => Some(sats),
+        })
+        .sum::<Sats>();
+    inputs.checked_sub(outputs).and_then(|sats| sats.checked_sub(fee))
+}
+
+/// Returns change of a transaction spending the coins to fixed payments if it doesn't exceed the
+/// dust limit, or zero otherwise.
+fn dust_change<K, D: Descriptor<K>>(
+    wallet: &Wallet<K, D>,
+    coins: &[Outpoint],
+    beneficiaries: &[Beneficiary],
+    fee: Sats,
+    dust_limit: Sats,
+) -> Sats {
+    if beneficiaries.iter().any(Beneficiary::is_max) {
+        return Sats::ZERO;
+    }
+    match spare_value(wallet, coins, beneficiaries, fee) {
+        Some(change) if change <= dust_limit => change,
+        _ => Sats::ZERO,
+    }
+}
+
+/// Directs the change to an external address by adding an output taking the remaining value to
+/// the beneficiaries. If the change doesn't exceed the dust limit of the address (or the
+/// `dust_limit`, if given), no output is added and the change is returned to be added to the
+/// fee.
+fn add_change_address<K, D: Descriptor<K>>(
+    wallet: &Wallet<K, D>,
+    coins: &[Outpoint],
+    beneficiaries: &mut Vec<Beneficiary>,
+    fee: Sats,
+    address: Address,
+    dust_limit: Option<Sats>,
+) -> Sats {
+    let dust_limit = dust_limit.unwrap_or_else(|| {
+        coinselect::spk_class(&address.script_pubkey()).unwrap_or(SpkClass::P2pkh).dust_limit()
+    });
+    match spare_value(wallet, coins, beneficiaries, fee) {
+        Some(change) if change <= dust_limit => change,
+        _ => {
+            beneficiaries.push(Beneficiary::with_max(address));
+            Sats::ZERO
+        }
+    }
+}
+
+/// Checks that manually selected coins are unspent wallet outputs covering fixed payments and the
+/// fee.
+fn check_coins<K, D: Descriptor<K>>(
+    wallet: &Wallet<K, D>,
+    coins: &[Outpoint],
+    beneficiaries: &[Beneficiary],
+    fee: Sats,
+) -> Result<Vec<Outpoint>, ExecError> {
+    let mut selected = Vec::with_capacity(coins.len());
+    let mut available = Sats::ZERO;
+    for outpoint in coins {
+        if selected.contains(outpoint) {
+            continue;
+        }
+        let utxo = wallet
+            .outpoint_by(*outpoint)
+            .ok()
+            .filter(|_| wallet.is_unspent(*outpoint))
+            .ok_or(ExecError::UnknownUtxo(*outpoint))?;
+        if wallet.is_immature(*outpoint) {
+            return Err(ExecError::ImmatureCoinbase(*outpoint));
+        }
+        available += utxo.value;
+        selected.push(*outpoint);
+    }
+    let required = beneficiaries
+        .iter()
+        .filter_map(|b| match b.amount {
+            Payment::Max => None,
+            Payment::Fixed(sats) => Some(sats),
+        })
+        .sum::<Sats>()
+        + fee;
+    if available < required {
+        return Err(ExecError::InsufficientSelectedCoins(available, required));
+    }
+    Ok(selected)
+}
+
+/// Collects inputs and external payments of a wallet transaction for composing its replacement.
+/// Exits if the transaction spends foreign coins or pays to scripts without address form.
+fn replacement_spending(tx: &WalletTx) -> (Vec<Outpoint>, Vec<Beneficiary>) {
+    let txid = tx.txid;
+    if tx.inputs.iter().any(|inp| inp.derived_addr().is_none()) {
+        eprintln!(
+            "Error: transaction {txid} spends coins not belonging to the wallet and can't be \
+             replaced"
+        );
+        exit(1);
+    }
+    let mut beneficiaries = vec![];
+    for out in tx.debits() {
+        let Party::Counterparty(addr) = out.beneficiary else {
+            eprintln!(
+                "Error: transaction {txid} pays to a script which can't be represented as an \
+                 address and can't be replaced"
+            );
+            exit(1);
+        };
+        beneficiaries.push(Beneficiary::new(addr, out.value));
+    }
+    let coins = tx.inputs.iter().map(|inp| inp.outpoint).collect();
+    (coins, beneficiaries)
+}
+
+/// Computes balance of the address and its unspent outputs from the address history retrieved
+/// from an indexer.
+fn address_state(
+    history: &[WalletTx],
+    terminal: Terminal,
+    address: Address,
+) -> (WalletAddr, Vec<(TxStatus<BlockHeight>, Outpoint, Sats)>) {
+    let script = address.script_pubkey();
+    let spent = history
+        .iter()
+        .flat_map(|tx| tx.inputs.iter().map(|inp| inp.outpoint))
+        .collect::<BTreeSet<_>>();
+    let mut info = WalletAddr::new(address, terminal.keychain, terminal.index);
+    let mut coins = vec![];
+    for tx in history {
+        for out in &tx.outputs {
+            if out.beneficiary.script_pubkey().as_ref() != Some(&script) {
+                continue;
             }
-            BpCommand::Inspect { psbt } => {
-                let psbt = psbt_read(psbt)?;
-                println!(
-                    "{}",
-                    serde_yaml::to_string(&psbt).expect("unable to generate YAML representation")
-                );
+            info.used += 1;
+            info.volume += out.value;
+            if !spent.contains(&out.outpoint) {
+                info.balance += out.value;
+                coins.push((tx.status.map(|info| info.height), out.outpoint, out.value));
             }
-            BpCommand::Construct {
-                v2,
-                to: beneficiaries,
-                fee,
-                psbt: psbt_file,
-            } => {
-                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+        }
+    }
+    (info, coins)
+}
 
-                // Do coin selection
-                let total_amount =
-                    beneficiaries.iter().try_fold(Sats::ZERO, |sats, b| match b.amount {
-                        Payment::Max => Err(()),
-                        Payment::Fixed(s) => sats.checked_add(s).ok_or(()),
-                    });
-                let coins: Vec<_> = match total_amount {
-                    Ok(sats) if sats > Sats::ZERO => {
-                        wallet.coinselect(sats + *fee, coinselect::all).collect()
-                    }
-                    _ => {
-                        eprintln!(
-                            "Warning: you are not paying to anybody but just aggregating all your \
-                             balances to a single UTXO",
-                        );
-                        wallet.utxos().map(WalletUtxo::into_outpoint).collect()
-                    }
-                };
+/// Computes fee for a replacement transaction spending the provided coins at the given fee rate.
+///
+/// BIP-125 requires replacement to pay for its own bandwidth at the minimal relay fee rate on top
+/// of the fee of the replaced transaction.
+fn replacement_fee<K, D: Descriptor<K>>(
+    wallet: &mut Wallet<K, D>,
+    coins: &[Outpoint],
+    beneficiaries: &[Beneficiary],
+    fee_rate: f64,
+    replaced_fee: Sats,
+) -> Result<Sats, ExecError> {
+    let params = TxParams::with(Sats::ZERO);
+    let (_, _, vsize) = draft_vsize(wallet, coins, beneficiaries, params, |_, _| ())?;
+    let fee = Sats::from((fee_rate * vsize as f64).ceil() as u64);
+    Ok(fee.max(replaced_fee + Sats::from(vsize)))
+}
 
-                // TODO: Support lock time and RBFs
-                let params = TxParams::with(*fee);
-                let (mut psbt, _) = wallet.construct_psbt(coins, beneficiaries, params)?;
-                psbt.version = if *v2 { PsbtVer::V2 } else { PsbtVer::V0 };
-                psbt_write_or_print(&psbt, psbt_file.as_deref())?;
+/// Constructs a draft transaction spending the coins to the beneficiaries, which pays no fee and
+/// doesn't shift the change index, and estimates its virtual size. The `extend` callback adds to
+/// the draft the outputs which are not created from the beneficiaries before the estimation.
+fn draft_vsize<K, D: Descriptor<K>>(
+    wallet: &mut Wallet<K, D>,
+    coins: &[Outpoint],
+    beneficiaries: &[Beneficiary],
+    mut params: TxParams,
+    extend: impl FnOnce(&mut Psbt, &PsbtMeta),
+) -> Result<(Psbt, PsbtMeta, u32), ExecError> {
+    params.fee = Sats::ZERO;
+    params.change_shift = false;
+    let class = wallet.keychain_class(params.change_keychain);
+    let (mut draft, meta) = wallet.construct_psbt(coins.to_vec(), beneficiaries, params)?;
+    extend(&mut draft, &meta);
+    let vsize = psbt_estimated_weight(&draft, class).div_ceil(4);
+    Ok((draft, meta, vsize))
+}
+
+/// Counts inputs which have more signatures in the `signed` PSBT than in the `unsigned` one.
+#[cfg(any(feature = "hot", feature = "hwi"))]
+fn signed_inputs(unsigned: &Psbt, signed: &Psbt) -> usize {
+    signed
+        .inputs()
+        .map(PsbtInputSigning::with)
+        .zip(unsigned.inputs().map(PsbtInputSigning::with))
+        .filter(|(after, before)| after.provided > before.provided)
+        .count()
+}
+
+/// Constructs descriptors of each of the script types scanned during the recovery, using the
+/// account provided for the script type.
+fn recovery_descriptors(
+    types: &[ScriptType],
+    account: impl Fn(ScriptType) -> XpubAccount,
+) -> Vec<(ScriptType, StdDescr)> {
+    types.iter().map(|script| (*script, script.descriptor(account(*script)))).collect()
+}
+
+/// Reads BIP-39 mnemonic and, optionally, passphrase of the seed used for the wallet recovery from
+/// the environment variables.
+#[cfg(feature = "hot")]
+fn recovery_seed(var: &str, passphrase_var: Option<&str>) -> Result<crate::hot::Seed, ExecError> {
+    use crate::hot::{DataError, Seed};
+
+    let mnemonic = std::env::var(var).map_err(|_| DataError::SignerEnv(var.to_owned()))?;
+    let passphrase = match passphrase_var {
+        Some(var) => std::env::var(var).map_err(|_| DataError::SignerEnv(var.to_owned()))?,
+        None => s!(""),
+    };
+    Ok(Seed::with_mnemonic(mnemonic.trim(), &passphrase)
+        .map_err(|_| DataError::SignerEnv(var.to_owned()))?)
+}
+
+/// Loads the private key from the signer source, asking for the password if it is encrypted. For
+/// seeds, the master key is returned, such that keys for any derivation path can be derived.
+#[cfg(feature = "hot")]
+fn signer_account(source: &SignerSource, testnet: bool) -> Result<bpstd::XprivAccount, ExecError> {
+    use bpstd::XprivAccount;
+
+    use crate::hot::{DataError, SecureIo, Seed};
+
+    let account = match source {
+        SignerSource::Seed(path) => {
+            let password = rpassword::prompt_password("Seed password: ")?;
+            let seed = Seed::read(path, &password)?;
+            XprivAccount::new_master(seed.master_xpriv(testnet))
+        }
+        SignerSource::Account(path) => {
+            let password = rpassword::prompt_password("Account password: ")?;
+            XprivAccount::read(path, &password)?
+        }
+        SignerSource::Env(var) => {
+            let secret = std::env::var(var).map_err(|_| DataError::SignerEnv(var.clone()))?;
+            match Seed::with_mnemonic(secret.trim(), "") {
+                Ok(seed) => XprivAccount::new_master(seed.master_xpriv(testnet)),
+                Err(_) => XprivAccount::from_str(secret.trim())
+                    .map_err(|_| DataError::SignerEnv(var.clone()))?,
             }
-        };
+        }
+        SignerSource::Hwi(_) => unreachable!("hardware wallets don't provide private keys"),
+    };
+    Ok(account)
+}
 
-        println!();
+/// Signs PSBT with the keys from the signing account file, if provided, finalizes it and extracts
+/// the signed transaction. Fails if any of the inputs can't be finalized.
+fn psbt_sign_extract<D: Descriptor<K, V>, K, V>(
+    psbt: &mut Psbt,
+    descriptor: &D,
+    signing_account: Option<&Path>,
+) -> Result<Tx, ExecError> {
+    if let Some(account) = signing_account {
+        #[cfg(feature = "hot")]
+        {
+            let sigs = crate::hot::sign_psbt(psbt, account, false)?;
+            if sigs == 0 {
+                eprintln!(
+                    "Error: signing account doesn't hold keys for any of the transaction inputs"
+                );
+                exit(1);
+            }
+        }
+        #[cfg(not(feature = "hot"))]
+        {
+            eprintln!(
+                "Error: signing with {} requires `hot` feature to be enabled",
+                account.display()
+            );
+            exit(1);
+        }
+    }
+    psbt_finalize(psbt, descriptor)?;
+    psbt_extract(psbt, true, None)
+}
 
-        Ok(())
+/// Prints summary of a signed transaction and asks user to confirm its broadcast.
+fn confirm_broadcast(tx: &Tx, fee: Sats, network: Network) -> Result<bool, ExecError> {
+    eprintln!("Transaction {} is ready to be broadcast:", tx.txid());
+    for txout in tx.outputs() {
+        match Address::with(&txout.script_pubkey, network) {
+            Ok(addr) => eprintln!("- {} ṩ to {addr}", txout.value),
+            Err(_) => eprintln!("- {} ṩ to {:x}", txout.value, txout.script_pubkey),
+        }
+    }
+    eprintln!("- {fee} ṩ miner fee");
+    eprint!("Broadcast the transaction to the network? [y/N] ");
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Selects coins for a spend request according to its coin selection strategy.
+fn select_spend_coins<K, D: Descriptor<K>>(
+    wallet: &Wallet<K, D>,
+    plan: &SpendPlan,
+    payments: &[Beneficiary],
+    fee: Sats,
+) -> Result<Vec<Outpoint>, ExecError> {
+    match plan.strategy {
+        CoinStrategy::Auto => select_coins(wallet, payments, fee, plan.label.as_ref(), None),
+        CoinStrategy::All => Ok(wallet
+            .spendable_utxos()
+            .filter(|utxo| match &plan.label {
+                Some(label) => wallet.coin_label(utxo.outpoint) == Some(label.as_str()),
+                None => true,
+            })
+            .map(WalletUtxo::into_outpoint)
+            .collect()),
+    }
+}
+
+/// Re-derives the scriptPubkey for the address terminal from a copy of the descriptor re-parsed
+/// from its serialized form, using script derivation instead of address derivation, and checks it
+/// against the address.
+fn verify_address<D: Descriptor + serde::Serialize + for<'de> serde::Deserialize<'de>>(
+    descriptor: &D,
+    derived_addr: &DerivedAddr,
+) -> Result<(), ExecError> {
+    let json = serde_json::to_string(descriptor).expect("unable to serialize descriptor");
+    let reparsed: D = serde_json::from_str(&json)
+        .map_err(|_| ExecError::AddressMismatch(derived_addr.terminal, derived_addr.addr))?;
+    let Terminal { keychain, index } = derived_addr.terminal;
+    let script_pubkey = reparsed.derive(keychain, index).to_script_pubkey();
+    if script_pubkey != derived_addr.addr.script_pubkey() {
+        return Err(ExecError::AddressMismatch(derived_addr.terminal, derived_addr.addr));
+    }
+    Ok(())
+}
+
+/// Prints coins spent by unconfirmed transactions, which are not a part of the spendable balance.
+fn print_pending_coins<K, D: Descriptor<K>>(wallet: &Wallet<K, D>) {
+    let mut pending = wallet.pending_coins().peekable();
+    if pending.peek().is_none() {
+        return;
+    }
+    println!("\nPending (spent by unconfirmed transactions):");
+    for row in pending {
+        println!(
+            "{}\t{: >12}\t{:68}\t{}\t{}",
+            row.height,
+            row.amount,
+            row.outpoint,
+            row.address,
+            wallet.coin_label(row.outpoint).unwrap_or_default()
+        );
+    }
+}
+
+/// Returns markers of the frozen and immature coinbase coins for the text balance output.
+fn coin_markers<K, D: Descriptor<K>>(wallet: &Wallet<K, D>, outpoint: Outpoint) -> String {
+    let mut markers = String::new();
+    if wallet.is_frozen(outpoint) {
+        markers.push_str("\t[frozen]");
     }
+    if wallet.is_immature(outpoint) {
+        markers.push_str("\t[immature]");
+    }
+    markers
+}
+
+fn print_balance_change(title: impl Display, balance: Sats, delta: i64) {
+    let resulting = balance.sats_i64() + delta;
+    println!("{title}\t{: >16}\t{: >+16}\t{: >16}", balance, delta, resulting);
+}
+
+/// Reads a consensus-encoded transaction from a file, which may be either hex-encoded or binary.
+fn tx_read(tx_path: &Path) -> Result<Tx, ExecError> {
+    eprint!("Reading transaction from file {} ... ", tx_path.display());
+    let data = fs::read(tx_path)?;
+    let tx = match std::str::from_utf8(&data).ok().and_then(|s| Vec::<u8>::from_hex(s.trim()).ok())
+    {
+        Some(bytes) => Tx::consensus_deserialize(bytes),
+        None => Tx::consensus_deserialize(data),
+    }?;
+    eprintln!("success");
+    Ok(tx)
 }
 
+/// Reads PSBT from a file, which may contain either the binary encoding or a base64 (or hex)
+/// encoded text, as produced by `--base64` option and by other wallets.
 fn psbt_read(psbt_path: &Path) -> Result<Psbt, ExecError> {
     eprint!("Reading PSBT from file {} ... ", psbt_path.display());
-    let mut psbt_file = File::open(psbt_path)?;
-    let psbt = Psbt::decode(&mut psbt_file)?;
+    let data = fs::read(psbt_path)?;
+    let psbt = match std::str::from_utf8(&data) {
+        Ok(text) if !data.starts_with(b"psbt\xff") => Psbt::from_str(text.trim())?,
+        _ => Psbt::decode(&mut data.as_slice())?,
+    };
     eprintln!("success");
     Ok(psbt)
 }
 
+/// Filters wallet history rows sorted by their height, keeping the ones mined within `since` and
+/// `until` heights (inclusive) and then only `limit` most recent of them. Unconfirmed
+/// transactions, which are sorted before the mined ones, are considered the most recent and are
+/// kept only if `until` is not given.
+/// Sums the amounts received by the script in the transactions, splitting them into the ones
+/// having the required number of confirmations and the pending ones. With zero confirmations
+/// unconfirmed transactions are accepted too.
+fn received_payment(
+    txes: &[WalletTx],
+    script: &ScriptPubkey,
+    tip: u32,
+    confirmations: u32,
+) -> (Sats, Sats) {
+    let (mut confirmed, mut pending) = (Sats::ZERO, Sats::ZERO);
+    for tx in txes {
+        let received = tx
+            .outputs
+            .iter()
+            .filter(|out| out.beneficiary.script_pubkey().as_ref() == Some(script))
+            .map(|out| out.value)
+            .sum::<Sats>();
+        match tx.status {
+            _ if confirmations == 0 => confirmed += received,
+            TxStatus::Mined(info) if tip + 1 >= info.height.get() + confirmations => {
+                confirmed += received
+            }
+            _ => pending += received,
+        }
+    }
+    (confirmed, pending)
+}
+
+/// Selects unconfirmed transactions from the wallet history rows.
+fn pending_history<L2: Layer2Tx>(rows: impl IntoIterator<Item = TxRow<L2>>) -> Vec<PendingTx> {
+    rows.into_iter()
+        .filter(|row| !row.height.is_mined())
+        .map(|row| PendingTx {
+            txid: row.txid,
+            operation: row.operation,
+            amount: row.amount,
+            fee: row.fee,
+            fee_rate: row.fee_rate(),
+            rbf: row.rbf,
+        })
+        .collect()
+}
+
+fn filter_history<L2: Layer2Tx>(
+    rows: &mut Vec<TxRow<L2>>,
+    since: Option<u32>,
+    until: Option<u32>,
+    limit: Option<usize>,
+) {
+    rows.retain(|row| match row.height {
+        TxStatus::Mined(height) => {
+            since.map_or(true, |since| height.get() >= since)
+                && until.map_or(true, |until| height.get() <= until)
+        }
+        _ => until.is_none(),
+    });
+    let Some(limit) = limit else {
+        return;
+    };
+    let unconfirmed = rows.iter().take_while(|row| !row.height.is_mined()).count();
+    if limit <= unconfirmed {
+        rows.truncate(limit);
+    } else {
+        let mined = rows.len() - unconfirmed;
+        rows.drain(unconfirmed..unconfirmed + mined.saturating_sub(limit - unconfirmed));
+    }
+}
+
+/// Formats a CSV field, quoting it if it contains separators, quotes or line breaks.
+fn csv_field(value: impl ToString) -> String {
+    let value = value.to_string();
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+/// Writes wallet history rows as CSV. With `details`, each of the operation details (own and
+/// counterparty transaction inputs and outputs, and the miner fee) is written as a separate row.
+fn write_history_csv<L2: Layer2Tx>(
+    out: &mut impl Write,
+    rows: &[TxRow<L2>],
+    txes: &BTreeMap<Txid, WalletTx>,
+    details: bool,
+) -> io::Result<()> {
+    write!(out, "height,time,txid,operation,amount,fee,fee_rate")?;
+    if details {
+        write!(out, ",kind,value,counterparty")?;
+    }
+    writeln!(out)?;
+    for row in rows {
+        let time = match txes.get(&row.txid).map(|tx| tx.status) {
+            Some(TxStatus::Mined(info)) => info.time.to_string(),
+            _ => s!(""),
+        };
+        let operation = match row.operation {
+            OpType::Credit => "credit",
+            OpType::Debit => "debit",
+            OpType::SelfTransfer => "self-transfer",
+        };
+        let line = format!(
+            "{},{time},{},{operation},{},{},{:.2}",
+            csv_field(row.height),
+            row.txid,
+            row.amount,
+            row.fee,
+            row.fee_rate()
+        );
+        if !details {
+            writeln!(out, "{line}")?;
+            continue;
+        }
+        for (addr, value) in &row.own {
+            let kind = if *value < 0 {
+                "debit"
+            } else if row.operation == OpType::Debit {
+                "change"
+            } else {
+                "credit"
+            };
+            writeln!(out, "{line},{kind},{value},{}", csv_field(addr))?;
+        }
+        for (cp, value) in &row.counterparties {
+            let kind = if matches!(cp, Counterparty::Data(_)) {
+                "data"
+            } else if *value > 0 {
+                "credit"
+            } else if row.operation == OpType::Credit {
+                "change"
+            } else {
+                "debit"
+            };
+            writeln!(out, "{line},{kind},{value},{}", csv_field(cp))?;
+        }
+        writeln!(out, "{line},fee,{},", -row.fee.sats_i64())?;
+    }
+    Ok(())
+}
+
+/// Confirmation targets (in blocks) reported by `fee-estimate` when none are given.
+const DEFAULT_FEE_TARGETS: [u16; 4] = [1, 3, 6, 144];
+
+/// Maximal virtual size of a block, in vbytes.
+const BLOCK_VSIZE: u64 = 1_000_000;
+
+/// Maximal size of `OP_RETURN` output script relayed by the nodes under the default policy.
+const MAX_OP_RETURN_RELAY: usize = 83;
+
+/// Input sequence number signalling BIP-125 replaceability while enabling the lock time.
+const SEQ_NO_RBF: u32 = 0xFFFF_FFFD;
+
+/// Maximal input sequence number which doesn't signal replaceability but enables the lock time.
+const SEQ_NO_NO_RBF: u32 = 0xFFFF_FFFE;
+
+fn parse_op_return(data: &str) -> Result<Vec<u8>, ExecError> {
+    Vec::<u8>::from_hex(data).map_err(|_| ExecError::InvalidOpReturn(data.to_owned()))
+}
+
+fn check_op_returns(psbt: &Psbt) -> Result<(), ExecError> {
+    let mut first = None;
+    for output in psbt.outputs().filter(|output| output.script.is_op_return()) {
+        if let Some(first) = first {
+            return Err(ExecError::MultipleOpReturns(first, output.index()));
+        }
+        if output.script.len() > MAX_OP_RETURN_RELAY {
+            return Err(ExecError::OpReturnTooLarge(output.index(), output.script.len()));
+        }
+        first = Some(output.index());
+    }
+    Ok(())
+}
+
+fn ensure_no_clobber(path: &Path, force: bool) -> Result<(), ExecError> {
+    if !force && path.exists() {
+        return Err(ExecError::FileExists(path.display().to_string()));
+    }
+    Ok(())
+}
+
 fn psbt_write(psbt: &Psbt, psbt_path: &Path) -> Result<(), ExecError> {
     eprint!("Saving PSBT to file {} ... ", psbt_path.display());
     let mut psbt_file = File::create(psbt_path)?;
@@ -556,20 +4695,95 @@ fn psbt_write_or_print(psbt: &Psbt, psbt_path: Option<&Path>) -> Result<(), Exec
         Some(file_name) => {
             psbt_write(psbt, file_name)?;
         }
-        None => match psbt.version {
-            PsbtVer::V0 => println!("{psbt}"),
-            PsbtVer::V2 => println!("{psbt:#}"),
-        },
+        None => println!("{}", psbt_base64(psbt)),
+    }
+    Ok(())
+}
+
+/// Saves PSBT to a file as a base64-encoded text instead of the binary encoding.
+fn psbt_write_base64(psbt: &Psbt, psbt_path: &Path) -> Result<(), ExecError> {
+    eprint!("Saving base64-encoded PSBT to file {} ... ", psbt_path.display());
+    fs::write(psbt_path, psbt_base64(psbt))?;
+    eprintln!("success");
+    Ok(())
+}
+
+/// Encodes PSBT as a base64 string, using the version specified in the PSBT itself.
+fn psbt_base64(psbt: &Psbt) -> String {
+    match psbt.version {
+        PsbtVer::V0 => format!("{psbt}"),
+        PsbtVer::V2 => format!("{psbt:#}"),
     }
+}
+
+/// Prints PSBT as a QR code, splitting it into BBQr fragments if its base64 encoding does not
+/// fit into a single code.
+#[cfg(feature = "qr")]
+fn psbt_print_qr(psbt: &Psbt) -> Result<(), ExecError> {
+    use crate::cli::{bbqr_split, qr_print, BbqrType, QR_MAX_CHARS};
+
+    let text = psbt_base64(psbt);
+    let payloads = if text.len() <= QR_MAX_CHARS {
+        vec![text]
+    } else {
+        bbqr_split(&psbt.serialize(psbt.version), BbqrType::Psbt, QR_MAX_CHARS)
+            .ok_or(ExecError::QrTooLarge)?
+    };
+    qr_print(&payloads)?;
     Ok(())
 }
 
+/// Returns dust limit for an output paying to the address.
+fn address_dust_limit(address: &Address) -> Sats {
+    match address.address_type() {
+        AddressType::P2pkh => SpkClass::P2pkh,
+        AddressType::P2sh => SpkClass::P2sh,
+        AddressType::P2wpkh => SpkClass::P2wpkh,
+        AddressType::P2wsh => SpkClass::P2wsh,
+        AddressType::P2tr => SpkClass::P2tr,
+    }
+    .dust_limit()
+}
+
+/// Estimates weight of the signed transaction constructed from the PSBT, assuming all inputs are
+/// spent by the wallet descriptor of the given class.
+fn psbt_estimated_weight(psbt: &Psbt, class: SpkClass) -> u32 {
+    let mut weight = Tx::from(psbt.to_unsigned_tx()).weight_units().to_u32();
+    let mut legacy = 0u32;
+    for input in psbt.inputs() {
+        // Inputs are estimated by the type of the spent output, which may differ between inputs
+        // of the wallets made of multiple descriptors
+        let class = input
+            .witness_utxo
+            .as_ref()
+            .and_then(|prevout| coinselect::spk_class(&prevout.script_pubkey))
+            .unwrap_or(class);
+        match class {
+            // items count + signature + public key
+            SpkClass::P2wpkh => weight += 1 + 73 + 34,
+            // items count + schnorr signature for the key path spending
+            SpkClass::P2tr => weight += 1 + 65,
+            // signature and public key in the script sig
+            _ => {
+                weight += (73 + 34) * 4;
+                legacy += 1;
+            }
+        }
+    }
+    let inputs = psbt.inputs().count() as u32;
+    if legacy < inputs {
+        // marker and flag bytes, and empty witness items count for the legacy inputs
+        weight += 2 + legacy;
+    }
+    weight
+}
+
 fn psbt_finalize<D: Descriptor<K, V>, K, V>(
     psbt: &mut Psbt,
     descriptor: &D,
 ) -> Result<(), ExecError> {
     eprint!("Finalizing PSBT ... ");
-    let inputs = psbt.finalize(descriptor);
+    let inputs = finalize_psbt(psbt, descriptor);
     eprint!(
         "{} of {} inputs were finalized",
         inputs.to_string().bright_green(),
@@ -612,3 +4826,504 @@ fn psbt_extract(psbt: &Psbt, publish: bool, tx: Option<&Path>) -> Result<Tx, Exe
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bpstd::{DeriveScripts, Vout};
+    use psbt::PsbtVer;
+
+    use super::*;
+    use crate::{MiningInfo, TxCredit, TxDebit};
+
+    const XPUB: &str = "[73c5da0a/84h/0h/0h]xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+    #[cfg(feature = "hot")]
+    const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon abandon abandon \
+                            abandon abandon abandon about";
+
+    fn test_wallet() -> Wallet<XpubDerivable, StdDescr> {
+        let xpub = XpubDerivable::from_str(&format!("{XPUB}/<0;1>/*")).unwrap();
+        Wallet::new_layer1(StdDescr::from(Wpkh::from(xpub)), Network::Mainnet)
+    }
+
+    fn fund(wallet: &mut Wallet<XpubDerivable, StdDescr>, no: u8, value: u64) -> Outpoint {
+        let addr = wallet.addresses(Keychain::OUTER).nth(no as usize).unwrap();
+        let txid = Txid::from([no; 32]);
+        let outpoint = Outpoint::new(txid, Vout::from_u32(0));
+        wallet.with_cache(|cache| {
+            cache.tx.insert(txid, WalletTx {
+                txid,
+                status: TxStatus::Mined(MiningInfo::genesis()),
+                inputs: vec![],
+                outputs: vec![TxDebit {
+                    outpoint,
+                    beneficiary: Party::Wallet(addr),
+                    value: Sats::from(value),
+                    spent: None,
+                }],
+                fee: Sats::ZERO,
+                size: 0,
+                weight: 0,
+                version: TxVer::V2,
+                locktime: LockTime::ZERO,
+            });
+            cache.utxo.insert(outpoint);
+        });
+        outpoint
+    }
+
+    fn history_row(no: u8, height: Option<u32>) -> TxRow {
+        TxRow {
+            height: match height {
+                Some(height) => TxStatus::Mined(BlockHeight::new(height).unwrap()),
+                None => TxStatus::Mempool,
+            },
+            operation: OpType::Credit,
+            our_inputs: vec![],
+            counterparties: vec![],
+            own: vec![],
+            txid: Txid::from([no; 32]),
+            fee: Sats::ZERO,
+            weight: 400,
+            size: 100,
+            rbf: false,
+            total: Sats::ZERO,
+            amount: Sats::ZERO,
+            balance: Sats::ZERO,
+            layer2: none!(),
+        }
+    }
+
+    #[test]
+    fn script_type_descriptors() {
+        let xpub = XpubAccount::from_str(XPUB).unwrap();
+        let descr = ScriptType::Wpkh.descriptor(xpub.clone());
+        assert_eq!(descr.class(), SpkClass::P2wpkh);
+        assert_eq!(
+            descr.to_string(),
+            "wpkh([73c5da0a/84h/0h/0h]xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/<0;1>/*)"
+        );
+        assert_eq!(
+            descr.derive_address(AddressNetwork::Mainnet, 0, 0u16).unwrap().to_string(),
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"
+        );
+        assert_eq!(ScriptType::Tr.descriptor(xpub).class(), SpkClass::P2tr);
+    }
+
+    #[test]
+    fn recovery_accounts() {
+        let xpub = XpubAccount::from_str(XPUB).unwrap();
+        let types = [ScriptType::Wpkh, ScriptType::Tr];
+        let accounts = recovery_descriptors(&types, |_| xpub.clone());
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[0], (ScriptType::Wpkh, ScriptType::Wpkh.descriptor(xpub.clone())));
+        assert_eq!(accounts[1], (ScriptType::Tr, ScriptType::Tr.descriptor(xpub)));
+    }
+
+    #[test]
+    #[cfg(feature = "hot")]
+    fn recovery_from_mnemonic() {
+        use crate::hot::Seed;
+
+        // BIP-84 and BIP-86 test vectors
+        let seed = Seed::with_mnemonic(MNEMONIC, "").unwrap();
+        let types = [ScriptType::Wpkh, ScriptType::Tr];
+        let accounts = recovery_descriptors(&types, |script| {
+            seed.derive(script.bip43(), false, HardenedIndex::ZERO).to_xpub_account()
+        });
+        let addresses = accounts
+            .iter()
+            .map(|(_, descr)| descr.derive_address(AddressNetwork::Mainnet, 0, 0u16).unwrap())
+            .map(|addr| addr.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(addresses, [
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu",
+            "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr"
+        ]);
+
+        let protected = Seed::with_mnemonic(MNEMONIC, "TREZOR").unwrap();
+        assert_ne!(protected.master_xpriv(false), seed.master_xpriv(false));
+    }
+
+    #[test]
+    #[cfg(feature = "hot")]
+    fn signer_from_env_mnemonic() {
+        std::env::set_var("BP_TEST_SIGNER_MNEMONIC", MNEMONIC);
+        let source = SignerSource::Env(s!("BP_TEST_SIGNER_MNEMONIC"));
+        let master = signer_account(&source, false).unwrap();
+        let derivation = [HardenedIndex::hardened(84), HardenedIndex::ZERO, HardenedIndex::ZERO];
+        let account = master.derive(derivation).to_xpub_account();
+        let (_, descr) = recovery_descriptors(&[ScriptType::Wpkh], |_| account.clone()).remove(0);
+        let addr = descr.derive_address(AddressNetwork::Mainnet, 0, 0u16).unwrap();
+        assert_eq!(addr.to_string(), "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu");
+    }
+
+    #[test]
+    fn op_return_parsing() {
+        assert_eq!(parse_op_return("cafe").unwrap(), vec![0xca, 0xfe]);
+        assert!(matches!(
+            parse_op_return("xyz"),
+            Err(ExecError::InvalidOpReturn(data)) if data == "xyz"
+        ));
+    }
+
+    #[test]
+    fn op_return_standardness() {
+        let mut psbt = Psbt::create(PsbtVer::V2);
+        psbt.construct_output_expect(ScriptPubkey::op_return(b"hello"), Sats::ZERO);
+        assert!(check_op_returns(&psbt).is_ok());
+
+        psbt.construct_output_expect(ScriptPubkey::op_return(b"world"), Sats::ZERO);
+        assert!(matches!(check_op_returns(&psbt), Err(ExecError::MultipleOpReturns(0, 1))));
+
+        let mut psbt = Psbt::create(PsbtVer::V2);
+        psbt.construct_output_expect(ScriptPubkey::op_return(&[0u8; 80]), Sats::ZERO);
+        assert!(check_op_returns(&psbt).is_ok());
+        psbt = Psbt::create(PsbtVer::V2);
+        psbt.construct_output_expect(ScriptPubkey::op_return(&[0u8; 81]), Sats::ZERO);
+        assert!(matches!(check_op_returns(&psbt), Err(ExecError::OpReturnTooLarge(0, 84))));
+    }
+
+    #[test]
+    fn psbt_read_encodings() {
+        let mut psbt = Psbt::create(PsbtVer::V0);
+        psbt.construct_output_expect(ScriptPubkey::op_return(b"hello"), Sats::ZERO);
+        let path = std::env::temp_dir().join(format!("bp-psbt-{}", std::process::id()));
+
+        psbt_write(&psbt, &path).unwrap();
+        let binary = psbt_read(&path).unwrap();
+        assert_eq!(binary.to_base64(), psbt.to_base64());
+        psbt_write_base64(&psbt, &path).unwrap();
+        assert_eq!(psbt_read(&path).unwrap(), binary);
+        fs::write(&path, format!("{}\n", psbt.to_base16())).unwrap();
+        assert_eq!(psbt_read(&path).unwrap(), binary);
+        fs::write(&path, "not a psbt").unwrap();
+        assert!(matches!(psbt_read(&path), Err(ExecError::ParsePsbt(_))));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn change_address_dust() {
+        let mut wallet = test_wallet();
+        let coins = [fund(&mut wallet, 1, 10_000)];
+        let payee = wallet.addresses(Keychain::OUTER).nth(10).unwrap().addr;
+        let change = wallet.addresses(Keychain::OUTER).nth(11).unwrap().addr;
+        let fee = Sats::from(1_000u64);
+
+        let mut beneficiaries = vec![Beneficiary::new(payee, Sats::from(5_000u64))];
+        let absorbed = add_change_address(&wallet, &coins, &mut beneficiaries, fee, change, None);
+        assert_eq!(absorbed, Sats::ZERO);
+        assert_eq!(beneficiaries[1], Beneficiary::with_max(change));
+
+        // Change below the P2WPKH dust limit is added to the fee
+        let mut beneficiaries = vec![Beneficiary::new(payee, Sats::from(8_800u64))];
+        let absorbed = add_change_address(&wallet, &coins, &mut beneficiaries, fee, change, None);
+        assert_eq!(absorbed, Sats::from(200u64));
+        assert_eq!(beneficiaries.len(), 1);
+        let (psbt, _) =
+            wallet.construct_psbt(coins, &beneficiaries, TxParams::with(fee + absorbed)).unwrap();
+        assert_eq!(psbt.outputs().count(), 1);
+        assert_eq!(psbt.fee(), Some(Sats::from(1_200u64)));
+
+        // No zero-value output is created if nothing is left
+        let mut beneficiaries = vec![Beneficiary::new(payee, Sats::from(9_000u64))];
+        let absorbed = add_change_address(&wallet, &coins, &mut beneficiaries, fee, change, None);
+        assert_eq!(absorbed, Sats::ZERO);
+        assert_eq!(beneficiaries.len(), 1);
+
+        // Custom dust limit overrides the one of the address type
+        let mut beneficiaries = vec![Beneficiary::new(payee, Sats::from(8_800u64))];
+        let dust_limit = Some(Sats::from(100u64));
+        let absorbed =
+            add_change_address(&wallet, &coins, &mut beneficiaries, fee, change, dust_limit);
+        assert_eq!(absorbed, Sats::ZERO);
+        assert_eq!(beneficiaries.len(), 2);
+        let mut beneficiaries = vec![Beneficiary::new(payee, Sats::from(8_000u64))];
+        let dust_limit = Some(Sats::from(1_000u64));
+        let absorbed =
+            add_change_address(&wallet, &coins, &mut beneficiaries, fee, change, dust_limit);
+        assert_eq!(absorbed, Sats::from(1_000u64));
+        assert_eq!(beneficiaries.len(), 1);
+    }
+
+    #[test]
+    fn construct_json_info() {
+        let mut wallet = test_wallet();
+        let coin = fund(&mut wallet, 1, 10_000);
+        let payee = wallet.addresses(Keychain::OUTER).nth(10).unwrap().addr;
+        let beneficiaries = [Beneficiary::new(payee, Sats::from(5_000u64))];
+        let (psbt, meta) = wallet
+            .construct_psbt([coin], &beneficiaries, TxParams::with(Sats::from(1_000u64)))
+            .unwrap();
+
+        let info = ConstructInfo::with(&psbt, &meta, SpkClass::P2wpkh, Network::Mainnet);
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["psbt_base64"], psbt_base64(&psbt));
+        assert_eq!(json["txid_unsigned"], psbt.txid().to_string());
+        assert_eq!(json["inputs"], serde_json::json!([coin.to_string()]));
+        assert_eq!(json["outputs"][0]["address"], payee.to_string());
+        assert_eq!(json["outputs"][0]["sats"], 5_000);
+        assert_eq!(json["change"]["sats"], 4_000);
+        assert_eq!(json["fee"], 1_000);
+        // 1 P2WPKH input and 2 P2WPKH outputs
+        assert_eq!(json["vsize"], 141);
+        assert_eq!(json["weight"], info.weight);
+        assert_eq!(json["fee_rate"], 1_000.0 / 141.0);
+    }
+
+    #[test]
+    fn await_payment_confirmations() {
+        let wallet = test_wallet();
+        let addr = wallet.addresses(Keychain::OUTER).nth(3).unwrap().addr;
+        let other = wallet.addresses(Keychain::OUTER).nth(4).unwrap().addr;
+        let tx = |no: u8, status: TxStatus, value: u64| {
+            let txid = Txid::from([no; 32]);
+            WalletTx {
+                txid,
+                status,
+                inputs: vec![],
+                outputs: vec![
+                    TxDebit {
+                        outpoint: Outpoint::new(txid, 0u32),
+                        beneficiary: Party::Counterparty(addr),
+                        value: Sats::from(value),
+                        spent: None,
+                    },
+                    TxDebit {
+                        outpoint: Outpoint::new(txid, 1u32),
+                        beneficiary: Party::Counterparty(other),
+                        value: Sats::from(100_000u64),
+                        spent: None,
+                    },
+                ],
+                fee: Sats::ZERO,
+                size: 0,
+                weight: 0,
+                version: TxVer::V2,
+                locktime: LockTime::ZERO,
+            }
+        };
+        let txes =
+            [tx(1, TxStatus::Mined(MiningInfo::genesis()), 5_000), tx(2, TxStatus::Mempool, 3_000)];
+        let script = addr.script_pubkey();
+        let sats = |value: u64| Sats::from(value);
+
+        assert_eq!(received_payment(&txes, &script, 1, 1), (sats(5_000), sats(3_000)));
+        assert_eq!(received_payment(&txes, &script, 1, 2), (sats(0), sats(8_000)));
+        assert_eq!(received_payment(&txes, &script, 2, 2), (sats(5_000), sats(3_000)));
+        assert_eq!(received_payment(&txes, &script, 1, 0), (sats(8_000), sats(0)));
+    }
+
+    #[test]
+    fn history_filter() {
+        let heights = [None, None, Some(10), Some(20), Some(30), Some(40)];
+        let mut rows =
+            (1..).zip(heights).map(|(no, height)| history_row(no, height)).collect::<Vec<_>>();
+        rows.sort_by_key(|row| row.height);
+        let filter = |since, until, limit| {
+            let mut rows = rows.clone();
+            filter_history(&mut rows, since, until, limit);
+            rows.into_iter().map(|row| row.txid[0]).collect::<Vec<_>>()
+        };
+
+        assert_eq!(filter(None, None, None), [1, 2, 3, 4, 5, 6]);
+        // Limit below or at the number of unconfirmed transactions keeps only them
+        assert_eq!(filter(None, None, Some(1)), [1]);
+        assert_eq!(filter(None, None, Some(2)), [1, 2]);
+        // Limit above it adds the most recent mined transactions
+        assert_eq!(filter(None, None, Some(4)), [1, 2, 5, 6]);
+        assert_eq!(filter(None, None, Some(10)), [1, 2, 3, 4, 5, 6]);
+        assert_eq!(filter(Some(20), None, None), [1, 2, 4, 5, 6]);
+        assert_eq!(filter(Some(20), None, Some(3)), [1, 2, 6]);
+        // Upper bound excludes mempool transactions
+        assert_eq!(filter(None, Some(30), None), [3, 4, 5]);
+        assert_eq!(filter(None, Some(30), Some(2)), [4, 5]);
+        assert_eq!(filter(Some(20), Some(30), Some(1)), [5]);
+    }
+
+    #[test]
+    fn history_csv() {
+        let wallet = test_wallet();
+        let input = wallet.addresses(Keychain::OUTER).next().unwrap();
+        let change = wallet.addresses(Keychain::INNER).next().unwrap();
+        let payee = wallet.addresses(Keychain::OUTER).nth(10).unwrap().addr;
+        let data = ScriptPubkey::op_return(b"test");
+
+        let mut debit = history_row(1, Some(100));
+        debit.operation = OpType::Debit;
+        debit.own = vec![(input, -10_000), (change, 4_000)];
+        debit.counterparties =
+            vec![(Counterparty::Address(payee), -5_000), (Counterparty::Data(data.clone()), 0)];
+        debit.fee = Sats::from(1_000u64);
+        debit.amount = Sats::from(6_000u64);
+        let mut credit = history_row(2, None);
+        credit.own = vec![(input, 2_000)];
+        credit.counterparties = vec![(Counterparty::Miner, 2_000)];
+        credit.amount = Sats::from(2_000u64);
+        let rows = [debit, credit];
+
+        let mut txes = BTreeMap::new();
+        let mut mined = MiningInfo::genesis();
+        mined.height = BlockHeight::new(100).unwrap();
+        txes.insert(Txid::from([1u8; 32]), WalletTx {
+            txid: Txid::from([1u8; 32]),
+            status: TxStatus::Mined(mined),
+            inputs: vec![],
+            outputs: vec![],
+            fee: Sats::from(1_000u64),
+            size: 100,
+            weight: 400,
+            version: TxVer::V2,
+            locktime: LockTime::ZERO,
+        });
+
+        let (txid1, txid2) = (Txid::from([1u8; 32]), Txid::from([2u8; 32]));
+        let debit = format!("100,1231006505,{txid1},debit,6000,1000,10.00");
+        let credit = format!("mempool,,{txid2},credit,2000,0,0.00");
+        let mut out = Vec::new();
+        write_history_csv(&mut out, &rows, &txes, false).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        assert_eq!(csv.lines().collect::<Vec<_>>(), [
+            s!("height,time,txid,operation,amount,fee,fee_rate"),
+            debit.clone(),
+            credit.clone()
+        ]);
+
+        let mut out = Vec::new();
+        write_history_csv(&mut out, &rows, &txes, true).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        assert_eq!(csv.lines().collect::<Vec<_>>(), [
+            s!("height,time,txid,operation,amount,fee,fee_rate,kind,value,counterparty"),
+            format!("{debit},debit,-10000,{input}"),
+            format!("{debit},change,4000,{change}"),
+            format!("{debit},debit,-5000,{payee}"),
+            format!("{debit},data,0,data:{data:x}"),
+            format!("{debit},fee,-1000,"),
+            format!("{credit},credit,2000,{input}"),
+            format!("{credit},credit,2000,miner"),
+            format!("{credit},fee,0,"),
+        ]);
+
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn pending_rbf_signaling() {
+        let mut wallet = test_wallet();
+        let outpoint = fund(&mut wallet, 0, 10_000);
+        let addr = wallet.addresses(Keychain::OUTER).nth(1).unwrap();
+        let other = wallet.addresses(Keychain::OUTER).nth(2).unwrap().addr;
+        let pending = |no: u8, sequence: u32| {
+            let txid = Txid::from([no; 32]);
+            WalletTx {
+                txid,
+                status: TxStatus::Mempool,
+                inputs: vec![TxCredit {
+                    outpoint: Outpoint::new(Txid::from([0xFF; 32]), no as u32),
+                    payer: Party::Counterparty(other),
+                    sequence: SeqNo::from_consensus_u32(sequence),
+                    coinbase: false,
+                    script_sig: none!(),
+                    witness: none!(),
+                    value: Sats::from(2_100u64),
+                }],
+                outputs: vec![TxDebit {
+                    outpoint: Outpoint::new(txid, 0u32),
+                    beneficiary: Party::Wallet(addr),
+                    value: Sats::from(2_000u64),
+                    spent: None,
+                }],
+                fee: Sats::from(100u64),
+                size: 100,
+                weight: 400,
+                version: TxVer::V2,
+                locktime: LockTime::ZERO,
+            }
+        };
+        wallet.with_cache(|cache| {
+            cache.tx.insert(Txid::from([1u8; 32]), pending(1, 0xFFFF_FFFD));
+            cache.tx.insert(Txid::from([2u8; 32]), pending(2, 0xFFFF_FFFE));
+        });
+
+        let rows = pending_history(wallet.history());
+        assert_eq!(rows.len(), 2);
+        assert!(rows.iter().all(|row| row.txid != outpoint.txid));
+        let rbf = rows.iter().find(|row| row.txid == Txid::from([1u8; 32])).unwrap();
+        assert!(rbf.rbf);
+        assert_eq!(rbf.operation, OpType::Credit);
+        assert_eq!(rbf.fee_rate, 1.0);
+        let fin = rows.iter().find(|row| row.txid == Txid::from([2u8; 32])).unwrap();
+        assert!(!fin.rbf);
+
+        let json = serde_json::to_value(&rows).unwrap();
+        assert_eq!(json[0]["rbf"], true);
+        assert_eq!(json[1]["rbf"], false);
+    }
+
+    #[test]
+    fn address_history_state() {
+        let wallet = test_wallet();
+        let addr = wallet.addresses(Keychain::OUTER).nth(3).unwrap();
+        let other = wallet.addresses(Keychain::OUTER).nth(4).unwrap().addr;
+        let tx = |no: u8, inputs: Vec<Outpoint>, outputs: Vec<(Address, u64)>| {
+            let txid = Txid::from([no; 32]);
+            WalletTx {
+                txid,
+                status: if no == 1 {
+                    TxStatus::Mined(MiningInfo::genesis())
+                } else {
+                    TxStatus::Mempool
+                },
+                inputs: inputs
+                    .into_iter()
+                    .map(|outpoint| TxCredit {
+                        outpoint,
+                        payer: Party::Counterparty(addr.addr),
+                        sequence: SeqNo::from_consensus_u32(0),
+                        coinbase: false,
+                        script_sig: none!(),
+                        witness: none!(),
+                        value: Sats::ZERO,
+                    })
+                    .collect(),
+                outputs: outputs
+                    .into_iter()
+                    .enumerate()
+                    .map(|(vout, (addr, value))| TxDebit {
+                        outpoint: Outpoint::new(txid, vout as u32),
+                        beneficiary: Party::Counterparty(addr),
+                        value: Sats::from(value),
+                        spent: None,
+                    })
+                    .collect(),
+                fee: Sats::ZERO,
+                size: 0,
+                weight: 0,
+                version: TxVer::V2,
+                locktime: LockTime::ZERO,
+            }
+        };
+        let spent = Outpoint::new(Txid::from([1u8; 32]), 0u32);
+        let history = [
+            tx(1, vec![], vec![(addr.addr, 5_000), (addr.addr, 3_000), (other, 1_000)]),
+            tx(2, vec![spent], vec![(other, 4_000), (addr.addr, 700)]),
+        ];
+
+        let (info, coins) = address_state(&history, addr.terminal, addr.addr);
+        assert_eq!(info.terminal, addr.terminal);
+        assert_eq!(info.used, 3);
+        assert_eq!(info.volume, Sats::from(8_700u64));
+        assert_eq!(info.balance, Sats::from(3_700u64));
+        assert_eq!(coins, vec![
+            (
+                TxStatus::Mined(BlockHeight::MIN),
+                Outpoint::new(Txid::from([1u8; 32]), 1u32),
+                Sats::from(3_000u64)
+            ),
+            (TxStatus::Mempool, Outpoint::new(Txid::from([2u8; 32]), 1u32), Sats::from(700u64)),
+        ]);
+    }
+}
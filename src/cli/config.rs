@@ -23,17 +23,33 @@
 use std::fs;
 use std::path::Path;
 
-#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+use crate::cli::FeeSource;
+use crate::GapLimit;
+
+#[derive(Clone, PartialEq, Debug)]
 #[derive(serde::Serialize, serde::Deserialize)]
 #[serde(crate = "serde_crate", rename_all = "camelCase")]
 pub struct Config {
     pub default_wallet: String,
+
+    /// Gap limits used during wallet scans, either shared (`"<n>"`) or keychain-specific
+    /// (`"<keychain>=<n>"`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gap_limits: Vec<GapLimit>,
+
+    /// Ordered list of fee estimate sources (`"indexer"`, `"mempool"`, `"floor:<sats/vbyte>"`).
+    /// The first source providing a sane value is used; if none does, the default floor of
+    /// 1 sat/vbyte applies.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fee_sources: Vec<FeeSource>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
             default_wallet: s!("default"),
+            gap_limits: none!(),
+            fee_sources: none!(),
         }
     }
 }
@@ -0,0 +1,263 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Import of wallet descriptors exported from Bitcoin Core with `listdescriptors` RPC command.
+//!
+//! Bitcoin Core keeps separate descriptors for receive and change keychains of each script type.
+//! The active receive and change descriptors of the selected script type are combined into a
+//! single multipath descriptor supported by the wallet.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use bpstd::{Idx, Keychain, NormalIndex, XkeyParseError, XpubDerivable};
+use descriptors::{StdDescr, TrKey, Wpkh};
+
+const INPUT_CHARSET: &str = "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!\
+                             ^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum CoreImportError {
+    /// descriptor '{0}' contains characters not allowed in descriptors.
+    InvalidCharacter(String),
+
+    /// descriptor '{0}' has invalid checksum; expected checksum is {1}.
+    InvalidChecksum(String, String),
+
+    /// descriptor '{0}' has unrecognized structure.
+    Malformed(String),
+
+    /// invalid extended key in descriptor '{0}'. Details: {1}
+    Key(String, XkeyParseError),
+
+    /// active {0} descriptors use different keys; only descriptors of a single account can be
+    /// imported.
+    MultipleAccounts(&'static str),
+
+    /// export doesn't contain active {0} descriptors.
+    NoDescriptors(&'static str),
+}
+
+/// Descriptor export produced by `listdescriptors` Bitcoin Core RPC command.
+#[derive(Clone, Eq, PartialEq, Debug, serde::Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct CoreExport {
+    #[serde(default)]
+    pub wallet_name: Option<String>,
+    pub descriptors: Vec<CoreDescriptor>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, serde::Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct CoreDescriptor {
+    pub desc: String,
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    #[serde(default)]
+    pub active: bool,
+    #[serde(default)]
+    pub internal: Option<bool>,
+    #[serde(default)]
+    pub range: Option<[u32; 2]>,
+    #[serde(default)]
+    pub next: Option<u32>,
+}
+
+/// Wallet descriptor reconstructed from Bitcoin Core export.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct CoreImport {
+    pub descriptor: StdDescr,
+    /// Next unused derivation index for each of the keychains.
+    pub next_index: BTreeMap<Keychain, NormalIndex>,
+    /// Descriptors which were skipped since their script types are not supported.
+    pub skipped: Vec<String>,
+}
+
+/// Computes descriptor checksum according to BIP-380. Returns `None` if the descriptor contains
+/// invalid characters.
+pub fn descriptor_checksum(desc: &str) -> Option<String> {
+    fn polymod(c: u64, val: u64) -> u64 {
+        let c0 = c >> 35;
+        let mut c = ((c & 0x7ffffffff) << 5) ^ val;
+        for (bit, gen) in [0xf5dee51989, 0xa9fdca3312, 0x1bab10e32d, 0x3706b1677a, 0x644d626ffd]
+            .into_iter()
+            .enumerate()
+        {
+            if c0 & (1 << bit) != 0 {
+                c ^= gen;
+            }
+        }
+        c
+    }
+
+    let mut c = 1u64;
+    let mut cls = 0u64;
+    let mut cls_count = 0;
+    for ch in desc.chars() {
+        let pos = INPUT_CHARSET.find(ch)? as u64;
+        c = polymod(c, pos & 31);
+        cls = cls * 3 + (pos >> 5);
+        cls_count += 1;
+        if cls_count == 3 {
+            c = polymod(c, cls);
+            cls = 0;
+            cls_count = 0;
+        }
+    }
+    if cls_count > 0 {
+        c = polymod(c, cls);
+    }
+    for _ in 0..8 {
+        c = polymod(c, 0);
+    }
+    c ^= 1;
+    Some((0..8).map(|j| CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char).collect())
+}
+
+impl CoreDescriptor {
+    /// Verifies the descriptor checksum, returning the descriptor without it.
+    fn checked(&self) -> Result<&str, CoreImportError> {
+        let (desc, checksum) = self.desc.split_once('#').unwrap_or((&self.desc, ""));
+        let expected = descriptor_checksum(desc)
+            .ok_or_else(|| CoreImportError::InvalidCharacter(self.desc.clone()))?;
+        if !checksum.is_empty() && checksum != expected {
+            return Err(CoreImportError::InvalidChecksum(self.desc.clone(), expected));
+        }
+        Ok(desc)
+    }
+}
+
+impl CoreExport {
+    /// Reconstructs wallet descriptor from the active Bitcoin Core descriptors of the `wpkh`
+    /// (or `combo`, from which only segwit outputs are used) or, if `taproot` is set, `tr`
+    /// script type.
+    pub fn import(&self, taproot: bool) -> Result<CoreImport, CoreImportError> {
+        let script = if taproot { "tr" } else { "wpkh" };
+        let mut account = None;
+        let mut keychains = BTreeMap::<u8, Option<u32>>::new();
+        let mut skipped = vec![];
+        for descr in self.descriptors.iter().filter(|d| d.active) {
+            let desc = descr.checked()?;
+            let malformed = || CoreImportError::Malformed(descr.desc.clone());
+            let (func, inner) = desc.split_once('(').ok_or_else(malformed)?;
+            let key = inner.strip_suffix(')').ok_or_else(malformed)?;
+            let matches = match func {
+                "wpkh" | "combo" => !taproot,
+                "tr" => taproot && !key.contains(','),
+                _ => false,
+            };
+            if !matches {
+                skipped.push(descr.desc.clone());
+                continue;
+            }
+            let (acc, terminal) = match key.split_once("/<") {
+                Some((acc, multipath)) => (acc, format!("<{multipath}")),
+                None => {
+                    let (rest, wildcard) = key.rsplit_once('/').ok_or_else(malformed)?;
+                    let (acc, keychain) = rest.rsplit_once('/').ok_or_else(malformed)?;
+                    (acc, format!("{keychain}/{wildcard}"))
+                }
+            };
+            if *account.get_or_insert(acc) != acc {
+                return Err(CoreImportError::MultipleAccounts(script));
+            }
+            let terminal = terminal.strip_suffix("/*").ok_or_else(malformed)?;
+            let terminal = terminal.trim_start_matches('<').trim_end_matches('>');
+            for keychain in terminal.split(';') {
+                let keychain = u8::from_str(keychain).map_err(|_| malformed())?;
+                keychains.insert(keychain, descr.next);
+            }
+        }
+        let account = account.ok_or(CoreImportError::NoDescriptors(script))?;
+
+        let terminal = match keychains.len() {
+            1 => keychains.keys().map(u8::to_string).collect::<String>(),
+            _ => format!("<{}>", keychains.keys().map(u8::to_string).collect::<Vec<_>>().join(";")),
+        };
+        let key = format!("{account}/{terminal}/*");
+        let xpub = XpubDerivable::from_str(&key).map_err(|e| CoreImportError::Key(key, e))?;
+        let descriptor = if taproot { TrKey::from(xpub).into() } else { Wpkh::from(xpub).into() };
+        let next_index = keychains
+            .into_iter()
+            .filter_map(|(keychain, next)| Some((Keychain::from(keychain), next?)))
+            .filter_map(|(keychain, next)| {
+                Some((keychain, NormalIndex::try_from_index(next).ok()?))
+            })
+            .collect();
+        Ok(CoreImport {
+            descriptor,
+            next_index,
+            skipped,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use descriptors::Descriptor;
+
+    use super::*;
+
+    const ACCOUNT: &str = "[643a7adc/84h/1h/0h]tpubDCNiWHaiSkgnQjuhsg9kjwaUzaxQjUcmhagvYzqQ3TYJTgFGJstVaqnu4yhtFktBhCVFmBNLQ5sN53qKzZbMksm3XEyGJsEhQPfVZdWmTE2";
+
+    fn core_descr(desc: String, internal: bool, next: u32) -> CoreDescriptor {
+        let checksum = descriptor_checksum(&desc).unwrap();
+        CoreDescriptor {
+            desc: format!("{desc}#{checksum}"),
+            timestamp: Some(1700000000),
+            active: true,
+            internal: Some(internal),
+            range: Some([0, 999]),
+            next: Some(next),
+        }
+    }
+
+    #[test]
+    fn checksum() {
+        assert_eq!(descriptor_checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+        assert_eq!(descriptor_checksum("raw(deadbeef)é"), None);
+    }
+
+    #[test]
+    fn import() {
+        let export = CoreExport {
+            wallet_name: Some(s!("test")),
+            descriptors: vec![
+                core_descr(format!("pkh({ACCOUNT}/0/*)"), false, 0),
+                core_descr(format!("wpkh({ACCOUNT}/0/*)"), false, 5),
+                core_descr(format!("wpkh({ACCOUNT}/1/*)"), true, 2),
+            ],
+        };
+        let import = export.import(false).unwrap();
+        assert_eq!(import.skipped.len(), 1);
+        assert_eq!(import.descriptor.class(), descriptors::SpkClass::P2wpkh);
+        assert_eq!(import.next_index.get(&Keychain::INNER), Some(&NormalIndex::from(2u8)));
+        assert_eq!(export.import(true).unwrap_err(), CoreImportError::NoDescriptors("tr"));
+
+        let mut export = export;
+        export.descriptors[1].desc.pop();
+        export.descriptors[1].desc.push('q');
+        assert!(matches!(export.import(false), Err(CoreImportError::InvalidChecksum(..))));
+    }
+}
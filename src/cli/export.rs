@@ -0,0 +1,157 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Export of wallet descriptors in the forms understood by other wallet software.
+//!
+//! Wallet descriptors are built from extended public keys only, thus exports never contain
+//! private key material.
+
+use bpstd::{Keychain, Network, XpubDerivable};
+use descriptors::Descriptor;
+
+use crate::cli::descriptor_checksum;
+
+/// Appends BIP-380 checksum to the descriptor. Descriptors containing characters not allowed by
+/// BIP-380 are returned unchanged.
+pub fn checksummed(desc: &str) -> String {
+    match descriptor_checksum(desc) {
+        Some(checksum) => format!("{desc}#{checksum}"),
+        None => desc.to_owned(),
+    }
+}
+
+/// Splits the key into the `[fingerprint/path]xpub` account part and the derivation suffix
+/// following it, like `/<0;1>/*`.
+fn key_parts(key: &XpubDerivable) -> (String, String) {
+    let account = format!("[{}]{}", key.origin(), key.xpub());
+    let suffix = key.to_string()[account.len()..].to_owned();
+    (account, suffix)
+}
+
+/// Wallet policy according to BIP-388, consisting of the descriptor template with `@<n>`
+/// placeholders and the list of keys the placeholders refer to.
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(serde::Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct WalletPolicy {
+    pub template: String,
+    pub keys: Vec<String>,
+}
+
+impl WalletPolicy {
+    pub fn with<D: Descriptor>(descr: &D) -> Self {
+        let mut template = descr.to_string();
+        let mut keys = Vec::<String>::new();
+        for key in descr.keys() {
+            let (account, suffix) = key_parts(key);
+            let pos = match keys.iter().position(|k| *k == account) {
+                Some(pos) => pos,
+                None => {
+                    keys.push(account);
+                    keys.len() - 1
+                }
+            };
+            let suffix = if suffix == "/<0;1>/*" { s!("/**") } else { suffix };
+            template = template.replace(&key.to_string(), &format!("@{pos}{suffix}"));
+        }
+        WalletPolicy { template, keys }
+    }
+}
+
+/// Descriptor of a single keychain of the wallet, as used by software not supporting multipath
+/// descriptors.
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(serde::Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct KeychainExport {
+    pub keychain: Keychain,
+    pub descriptor: String,
+}
+
+/// Bundle with the wallet descriptor in all supported forms.
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(serde::Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct DescriptorExport {
+    pub network: String,
+    pub descriptor: String,
+    pub policy: WalletPolicy,
+    pub keychains: Vec<KeychainExport>,
+}
+
+impl DescriptorExport {
+    pub fn with<D: Descriptor>(descr: &D, network: Network) -> Self {
+        let desc = descr.to_string();
+        let keychains = descr
+            .keychains()
+            .into_iter()
+            .map(|keychain| {
+                let single = descr.keys().fold(desc.clone(), |single, key| {
+                    let (account, suffix) = key_parts(key);
+                    let variant = suffix
+                        .strip_suffix("/*")
+                        .and_then(|s| s.rsplit_once('/'))
+                        .map(|(variant, _)| variant)
+                        .unwrap_or_default();
+                    single.replace(&key.to_string(), &format!("{account}{variant}/{keychain}/*"))
+                });
+                KeychainExport {
+                    keychain,
+                    descriptor: checksummed(&single),
+                }
+            })
+            .collect();
+        DescriptorExport {
+            network: network.to_string(),
+            descriptor: checksummed(&desc),
+            policy: WalletPolicy::with(descr),
+            keychains,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use descriptors::{StdDescr, Wpkh};
+
+    use super::*;
+
+    const ACCOUNT: &str = "[643a7adc/84h/1h/0h]tpubDCNiWHaiSkgnQjuhsg9kjwaUzaxQjUcmhagvYzqQ3TYJTgFGJstVaqnu4yhtFktBhCVFmBNLQ5sN53qKzZbMksm3XEyGJsEhQPfVZdWmTE2";
+
+    #[test]
+    fn export() {
+        let xpub = XpubDerivable::from_str(&format!("{ACCOUNT}/<0;1>/*")).unwrap();
+        let descr = StdDescr::from(Wpkh::from(xpub));
+        let export = DescriptorExport::with(&descr, Network::Testnet3);
+
+        let desc = format!("wpkh({ACCOUNT}/<0;1>/*)");
+        assert_eq!(export.descriptor, format!("{desc}#{}", descriptor_checksum(&desc).unwrap()));
+        assert_eq!(export.policy.template, "wpkh(@0/**)");
+        assert_eq!(export.policy.keys, vec![ACCOUNT.to_owned()]);
+        assert_eq!(export.keychains.len(), 2);
+        assert_eq!(export.keychains[1].keychain, Keychain::INNER);
+        assert!(export.keychains[1].descriptor.starts_with(&format!("wpkh({ACCOUNT}/1/*)#")));
+        assert!(!serde_json::to_string(&export).unwrap().contains("prv"));
+    }
+}
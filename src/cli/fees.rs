@@ -0,0 +1,165 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::{self, Display, Formatter};
+use std::num::ParseFloatError;
+use std::str::FromStr;
+
+/// Fee rates (in sats per vbyte) outside of this range are considered not sane and are skipped
+/// by the fee estimation.
+pub const SANE_FEE_RATES: (f64, f64) = (1.0, 10_000.0);
+
+/// Default fee rate used when none of the fee estimate sources has provided a sane value.
+pub const DEFAULT_FEE_FLOOR: f64 = 1.0;
+
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum FeeSourceError {
+    /// unknown fee estimate source '{0}'; expected 'indexer', 'mempool' or 'floor:<sats/vbyte>'.
+    Unknown(String),
+
+    /// invalid static fee rate. Details: {0}
+    #[from]
+    Rate(ParseFloatError),
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum FeeRateError {
+    /// invalid fee rate. Details: {0}
+    #[from]
+    Invalid(ParseFloatError),
+
+    /// fee rate {0} sats/vbyte is outside of the sane range.
+    NotSane(String),
+}
+
+/// Fee rate in sats per vbyte, which is guaranteed to be within [`SANE_FEE_RATES`].
+#[derive(Copy, Clone, PartialEq, PartialOrd, Debug, Display)]
+#[display(inner)]
+pub struct FeeRate(f64);
+
+// Sane fee rates are always finite, so the equality is total.
+impl Eq for FeeRate {}
+
+impl FeeRate {
+    /// Constructs fee rate, returning `None` if the value is not sane.
+    pub fn new(rate: f64) -> Option<Self> { is_sane_fee_rate(rate).then_some(FeeRate(rate)) }
+
+    pub fn to_f64(self) -> f64 { self.0 }
+}
+
+impl FromStr for FeeRate {
+    type Err = FeeRateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rate = f64::from_str(s.trim())?;
+        FeeRate::new(rate).ok_or_else(|| FeeRateError::NotSane(s.trim().to_owned()))
+    }
+}
+
+/// Source of a fee rate estimate.
+///
+/// Fee estimation queries an ordered list of sources, using the first one which returns a sane
+/// value. String representation is `indexer`, `mempool` or `floor:<sats/vbyte>`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(crate = "serde_crate", try_from = "String", into = "String")]
+pub enum FeeSource {
+    /// Fee estimate provided by the indexer used by the wallet.
+    Indexer,
+    /// Fee rates recommended by the mempool server (mempool.space or the one provided via
+    /// `--mempool`).
+    Mempool,
+    /// Static fee rate, which is always available.
+    Floor(f64),
+}
+
+impl Display for FeeSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FeeSource::Indexer => f.write_str("indexer"),
+            FeeSource::Mempool => f.write_str("mempool"),
+            FeeSource::Floor(rate) => write!(f, "floor:{rate}"),
+        }
+    }
+}
+
+impl FromStr for FeeSource {
+    type Err = FeeSourceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "indexer" => Ok(FeeSource::Indexer),
+            "mempool" => Ok(FeeSource::Mempool),
+            s => match s.strip_prefix("floor:") {
+                Some(rate) => Ok(FeeSource::Floor(f64::from_str(rate.trim())?)),
+                None => Err(FeeSourceError::Unknown(s.to_owned())),
+            },
+        }
+    }
+}
+
+impl TryFrom<String> for FeeSource {
+    type Error = FeeSourceError;
+    fn try_from(s: String) -> Result<Self, Self::Error> { FeeSource::from_str(&s) }
+}
+
+impl From<FeeSource> for String {
+    fn from(source: FeeSource) -> Self { source.to_string() }
+}
+
+impl FeeSource {
+    /// Default fee estimation chain.
+    pub fn default_chain() -> Vec<FeeSource> {
+        vec![FeeSource::Indexer, FeeSource::Mempool, FeeSource::Floor(DEFAULT_FEE_FLOOR)]
+    }
+}
+
+/// Checks whether the fee rate (in sats per vbyte) is within [`SANE_FEE_RATES`].
+pub fn is_sane_fee_rate(rate: f64) -> bool {
+    rate.is_finite() && rate >= SANE_FEE_RATES.0 && rate <= SANE_FEE_RATES.1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fee_source_roundtrip() {
+        for s in ["indexer", "mempool", "floor:2.5"] {
+            assert_eq!(FeeSource::from_str(s).unwrap().to_string(), s);
+        }
+        assert_eq!(FeeSource::from_str("Floor: 3").unwrap(), FeeSource::Floor(3.0));
+        assert!(FeeSource::from_str("bitcoind").is_err());
+        assert!(!is_sane_fee_rate(0.5));
+        assert!(!is_sane_fee_rate(f64::NAN));
+    }
+
+    #[test]
+    fn fee_rate() {
+        assert_eq!(FeeRate::from_str("2.5").unwrap().to_f64(), 2.5);
+        assert!(matches!(FeeRate::from_str("0.5"), Err(FeeRateError::NotSane(_))));
+        assert!(matches!(FeeRate::from_str("NaN"), Err(FeeRateError::NotSane(_))));
+        assert!(matches!(FeeRate::from_str("fast"), Err(FeeRateError::Invalid(_))));
+    }
+}
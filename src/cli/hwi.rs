@@ -0,0 +1,199 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Signing of PSBTs with hardware wallets (Coldcard, Ledger, Trezor and others) through the
+//! [HWI] command-line tool, which must be installed and present in `PATH`.
+//!
+//! [HWI]: https://github.com/bitcoin-core/HWI
+
+use std::process::Command;
+use std::str::FromStr;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bpstd::{Network, XpubFp};
+use psbt::{Psbt, PsbtVer};
+
+/// Name of the HWI executable.
+pub const HWI_BIN: &str = "hwi";
+
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum HwiError {
+    /// unable to run `hwi` tool ({0}); make sure HWI is installed and present in PATH.
+    Exec(String),
+
+    /// `hwi` tool has failed: {0}
+    Failed(String),
+
+    /// `hwi` tool has returned unrecognized output: {0}
+    Output(String),
+
+    /// none of the connected hardware wallets matches the keys of the wallet descriptor.
+    NoDevice,
+
+    /// hardware wallet with fingerprint {0} is not connected or doesn't match the keys of the
+    /// wallet descriptor.
+    DeviceNotFound(XpubFp),
+
+    /// several connected hardware wallets match the wallet descriptor ({0}); select one of them
+    /// with `hwi:<fingerprint>`.
+    Ambiguous(String),
+}
+
+/// Hardware wallet device as reported by `hwi enumerate`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(serde::Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct HwiDevice {
+    #[serde(rename = "type")]
+    pub ty: String,
+    #[serde(default)]
+    pub model: Option<String>,
+    pub path: String,
+    #[serde(default)]
+    pub fingerprint: Option<String>,
+    /// Error preventing use of the device, like a locked device or a missing PIN.
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl HwiDevice {
+    /// Returns master key fingerprint of the device, if it is known.
+    pub fn master_fp(&self) -> Option<XpubFp> {
+        self.fingerprint.as_deref().and_then(|fp| XpubFp::from_str(fp).ok())
+    }
+}
+
+/// Returns name of the chain as used by HWI for the network.
+pub fn hwi_chain(network: Network) -> &'static str {
+    match network {
+        Network::Mainnet => "main",
+        Network::Testnet3 | Network::Testnet4 => "test",
+        Network::Signet => "signet",
+        Network::Regtest => "regtest",
+    }
+}
+
+/// Runs HWI with the arguments, returning its JSON output. Errors reported by HWI are returned
+/// as [`HwiError::Failed`].
+fn hwi_run(args: &[&str]) -> Result<serde_json::Value, HwiError> {
+    let output =
+        Command::new(HWI_BIN).args(args).output().map_err(|e| HwiError::Exec(e.to_string()))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let value = match serde_json::from_str::<serde_json::Value>(&stdout) {
+        Ok(value) => value,
+        Err(_) if !output.status.success() => {
+            return Err(HwiError::Failed(String::from_utf8_lossy(&output.stderr).trim().to_owned()))
+        }
+        Err(_) => return Err(HwiError::Output(stdout.trim().to_owned())),
+    };
+    if let Some(err) = value.get("error") {
+        return Err(HwiError::Failed(err.as_str().map(str::to_owned).unwrap_or(err.to_string())));
+    }
+    Ok(value)
+}
+
+/// Lists connected hardware wallets.
+pub fn hwi_enumerate() -> Result<Vec<HwiDevice>, HwiError> {
+    let value = hwi_run(&["enumerate"])?;
+    serde_json::from_value(value).map_err(|e| HwiError::Output(e.to_string()))
+}
+
+/// Selects the device which master key fingerprint matches one of the `fingerprints` of the
+/// wallet descriptor keys and, if given, the `requested` fingerprint. Devices reporting errors
+/// are skipped.
+pub fn hwi_select<'a>(
+    devices: &'a [HwiDevice],
+    fingerprints: &[XpubFp],
+    requested: Option<XpubFp>,
+) -> Result<&'a HwiDevice, HwiError> {
+    let mut matching = devices.iter().filter(|device| {
+        device.error.is_none()
+            && device.master_fp().is_some_and(|fp| {
+                fingerprints.contains(&fp) && requested.map_or(true, |req| req == fp)
+            })
+    });
+    let Some(device) = matching.next() else {
+        return Err(requested.map(HwiError::DeviceNotFound).unwrap_or(HwiError::NoDevice));
+    };
+    let others = matching.filter_map(|device| device.fingerprint.clone()).collect::<Vec<_>>();
+    if !others.is_empty() {
+        let all = device.fingerprint.iter().chain(&others).cloned().collect::<Vec<_>>();
+        return Err(HwiError::Ambiguous(all.join(", ")));
+    }
+    Ok(device)
+}
+
+/// Asks the device to sign the PSBT, returning the PSBT with the device signatures. The PSBT is
+/// passed to the device in version 0, which is supported by all devices.
+pub fn hwi_sign(device: &HwiDevice, network: Network, psbt: &Psbt) -> Result<Psbt, HwiError> {
+    let encoded = BASE64.encode(psbt.serialize(PsbtVer::V0));
+    let value = hwi_run(&[
+        "--device-type",
+        &device.ty,
+        "--device-path",
+        &device.path,
+        "--chain",
+        hwi_chain(network),
+        "signtx",
+        &encoded,
+    ])?;
+    let signed = value
+        .get("psbt")
+        .and_then(serde_json::Value::as_str)
+        .ok_or_else(|| HwiError::Output(value.to_string()))?;
+    let data = BASE64.decode(signed).map_err(|e| HwiError::Output(e.to_string()))?;
+    Psbt::deserialize(&data).map_err(|e| HwiError::Output(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_device() {
+        let devices: Vec<HwiDevice> = serde_json::from_str(
+            r#"[
+                {"type": "coldcard", "model": "coldcard", "path": "0001", "fingerprint": "643a7adc"},
+                {"type": "trezor", "path": "webusb:001", "error": "Device is locked"},
+                {"type": "ledger", "path": "0002", "fingerprint": "d34db33f"}
+            ]"#,
+        )
+        .unwrap();
+        let coldcard = XpubFp::from_str("643a7adc").unwrap();
+        let ledger = XpubFp::from_str("d34db33f").unwrap();
+        let other = XpubFp::from_str("00000001").unwrap();
+
+        assert_eq!(hwi_select(&devices, &[coldcard], None).unwrap().ty, "coldcard");
+        assert_eq!(hwi_select(&devices, &[coldcard, ledger], Some(ledger)).unwrap().ty, "ledger");
+        assert_eq!(hwi_select(&devices, &[other], None), Err(HwiError::NoDevice));
+        assert_eq!(
+            hwi_select(&devices, &[coldcard], Some(ledger)),
+            Err(HwiError::DeviceNotFound(ledger))
+        );
+        assert!(matches!(
+            hwi_select(&devices, &[coldcard, ledger], None),
+            Err(HwiError::Ambiguous(_))
+        ));
+    }
+}
@@ -0,0 +1,142 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Import and export of wallet labels in the BIP-329 format: JSON lines, each describing the
+//! label of a single wallet object.
+
+use std::str::FromStr;
+
+use bpstd::{Address, Outpoint, Txid};
+
+use crate::LabelTarget;
+
+/// Single label record of the BIP-329 export.
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct Bip329Label {
+    /// Type of the labelled object: `tx`, `addr`, `pubkey`, `input`, `output` or `xpub`.
+    #[serde(rename = "type")]
+    pub ty: String,
+    /// Reference to the labelled object; for outputs has form of `<txid>:<vout>`.
+    #[serde(rename = "ref")]
+    pub reference: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+/// line {0} is not a valid BIP-329 label record. Details: {1}
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub struct Bip329Error(usize, String);
+
+impl Bip329Label {
+    pub fn new(target: LabelTarget, label: &str) -> Self {
+        let ty = match target {
+            LabelTarget::Tx(_) => "tx",
+            LabelTarget::Output(_) => "output",
+            LabelTarget::Address(_) => "addr",
+        };
+        Bip329Label {
+            ty: ty.to_owned(),
+            reference: target.to_string(),
+            label: Some(label.to_owned()),
+        }
+    }
+
+    /// Returns the wallet object referenced by the label, or `None` if the label type is not
+    /// supported by the wallet (`pubkey`, `input` and `xpub`). Errors with the reference if it is
+    /// invalid for the label type.
+    pub fn target(&self) -> Result<Option<LabelTarget>, String> {
+        let target = match self.ty.as_str() {
+            "tx" => Txid::from_str(&self.reference).map(LabelTarget::from).map_err(|_| ()),
+            "output" => Outpoint::from_str(&self.reference).map(LabelTarget::from).map_err(|_| ()),
+            "addr" => Address::from_str(&self.reference).map(LabelTarget::from).map_err(|_| ()),
+            _ => return Ok(None),
+        };
+        target.map(Some).map_err(|_| self.reference.clone())
+    }
+}
+
+/// Serializes labels into BIP-329 JSON lines.
+pub fn export_labels<'a>(labels: impl IntoIterator<Item = (LabelTarget, &'a str)>) -> String {
+    labels
+        .into_iter()
+        .map(|(target, label)| {
+            let line = serde_json::to_string(&Bip329Label::new(target, label))
+                .expect("unable to generate JSON representation");
+            line + "\n"
+        })
+        .collect()
+}
+
+/// Parses BIP-329 JSON lines, skipping empty lines. References of the records are not
+/// validated; see [`Bip329Label::target`].
+pub fn import_labels(s: &str) -> Result<Vec<Bip329Label>, Bip329Error> {
+    s.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(no, line)| {
+            serde_json::from_str::<Bip329Label>(line)
+                .map_err(|err| Bip329Error(no + 1, err.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let txid = "cca7507897abc89628f450e8b1e0c6fca4ec3f7b34cccf55f3f531c659ff4d79";
+        let labels = [
+            (LabelTarget::from_str(txid).unwrap(), "salary"),
+            (LabelTarget::from_str(&format!("{txid}:1")).unwrap(), "cold storage"),
+        ];
+        let export = export_labels(labels.iter().map(|(t, l)| (*t, *l)));
+        let mut lines = export.lines();
+        assert_eq!(
+            lines.next(),
+            Some(format!(r#"{{"type":"tx","ref":"{txid}","label":"salary"}}"#).as_str())
+        );
+        assert_eq!(
+            lines.next(),
+            Some(
+                format!(r#"{{"type":"output","ref":"{txid}:1","label":"cold storage"}}"#).as_str()
+            )
+        );
+        let records = import_labels(&export).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].target().unwrap(), Some(labels[1].0));
+        assert_eq!(records[1].label.as_deref(), Some("cold storage"));
+    }
+
+    #[test]
+    fn unsupported_and_invalid() {
+        let s = "{\"type\":\"xpub\",\"ref\":\"xpub...\",\"label\":\"x\"}\n\n";
+        assert_eq!(import_labels(s).unwrap()[0].target(), Ok(None));
+        let records = import_labels("{\"type\":\"tx\",\"ref\":\"nope\"}").unwrap();
+        assert_eq!(records[0].target(), Err(s!("nope")));
+        assert!(matches!(import_labels("\nnope"), Err(Bip329Error(2, _))));
+    }
+}
@@ -25,12 +25,55 @@ mod opts;
 mod args;
 mod config;
 mod command;
+mod sigs;
+mod script;
+mod fees;
+mod sighash;
+mod core;
+mod export;
+mod payee;
+mod signers;
+mod privacy;
+mod spend;
+mod combine;
+mod labels;
+#[cfg(feature = "qr")]
+mod qr;
+#[cfg(feature = "hwi")]
+mod hwi;
+
+pub use core::{descriptor_checksum, CoreDescriptor, CoreExport, CoreImport, CoreImportError};
 
 pub use args::{Args, Exec};
-pub use command::{BpCommand, Command, ExecError};
+pub use combine::{combine_psbts, CombineError};
+pub use command::{
+    AddressFormat, BpCommand, Command, ExecError, ExportFormat, IndexRange, ScriptType,
+    SignerSource, TxRef,
+};
 pub use config::Config;
+pub use export::{checksummed, DescriptorExport, KeychainExport, WalletPolicy};
+pub use fees::{
+    is_sane_fee_rate, FeeRate, FeeRateError, FeeSource, FeeSourceError, DEFAULT_FEE_FLOOR,
+    SANE_FEE_RATES,
+};
+#[cfg(feature = "hwi")]
+pub use hwi::{hwi_chain, hwi_enumerate, hwi_select, hwi_sign, HwiDevice, HwiError, HWI_BIN};
+pub use labels::{export_labels, import_labels, Bip329Error, Bip329Label};
 pub use loglevel::LogLevel;
 pub use opts::{
-    DescrStdOpts, DescriptorOpts, GeneralOpts, ResolverOpt, WalletOpts, DATA_DIR, DATA_DIR_ENV,
-    DEFAULT_ELECTRUM, DEFAULT_ESPLORA,
+    DescrMultiOpts, DescrStdOpts, DescriptorOpts, GeneralOpts, PublishOpts, ResolverOpt, ScanOpts,
+    WalletOpts, DATA_DIR, DATA_DIR_ENV, DEFAULT_BITCOIND, DEFAULT_ELECTRUM, DEFAULT_ESPLORA,
+    DEFAULT_MEMPOOL, DEFAULT_RPC_WALLET,
+};
+pub use payee::{apply_max_floors, Payee, PayeeError};
+pub use privacy::{privacy_check, PrivacyIssue, ROUND_AMOUNT};
+#[cfg(feature = "qr")]
+pub use qr::{bbqr_split, qr_print, qr_render, BbqrType, QR_FRAME_DELAY, QR_MAX_CHARS};
+pub use script::{disassemble, multisig_threshold};
+pub use sighash::{apply_sighash_types, InputSighash, InputSighashError};
+pub use signers::{SignerProfile, SignerVerdict, SIGNER_PROFILES};
+pub use sigs::{apply_sigs, DetachedSig, DetachedSigError};
+pub use spend::{
+    ChangePolicy, CoinPolicy, CoinStrategy, FeePolicy, SpendFee, SpendPlan, SpendRequest,
+    SpendRequestError, SPEND_REQUEST_VERSION,
 };
@@ -21,13 +21,21 @@
 // limitations under the License.
 
 use std::fmt::Debug;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
+use std::process::exit;
 
+use amplify::Bytes32;
 use bpstd::{Network, XpubDerivable};
 use clap::ValueHint;
 use descriptors::{Descriptor, StdDescr, TrKey, Wpkh};
 use strict_encoding::Ident;
 
+use crate::cli::Config;
+use crate::indexers::bitcoind::EstimateMode;
+use crate::indexers::pool::WeightedUrl;
+use crate::{FlushInterval, GapLimit, GapLimits, MultiDescr};
+
 pub const DATA_DIR_ENV: &str = "LNPBP_DATA_DIR";
 #[cfg(target_os = "linux")]
 pub const DATA_DIR: &str = "~/.lnp-bp";
@@ -45,11 +53,13 @@ pub const DATA_DIR: &str = ".";
 pub const DEFAULT_ELECTRUM: &str = "example.com:50001";
 pub const DEFAULT_ESPLORA: &str = "https://blockstream.info/{network}/api";
 pub const DEFAULT_MEMPOOL: &str = "https://mempool.space/{network}/api";
+pub const DEFAULT_BITCOIND: &str = "http://127.0.0.1:8332";
+pub const DEFAULT_RPC_WALLET: &str = "bpwallet";
 
 #[derive(Args, Clone, PartialEq, Eq, Debug)]
-#[group(args = ["electrum", "esplora", "mempool"])]
+#[group(args = ["electrum", "esplora", "mempool", "bitcoind"])]
 pub struct ResolverOpt {
-    /// Electrum server to use
+    /// Electrum server to use, given as `[tcp://|ssl://]<host>:<port>`
     #[arg(
         long,
         global = true,
@@ -62,6 +72,16 @@ pub struct ResolverOpt {
     )]
     pub electrum: Option<String>,
 
+    /// SOCKS5 proxy to connect to the electrum server through, like `127.0.0.1:9050` for a Tor
+    /// daemon; required for `.onion` servers
+    #[arg(long, global = true, requires = "electrum", env = "TOR_PROXY", value_name = "HOST:PORT")]
+    pub tor_proxy: Option<String>,
+
+    /// Pin the electrum `ssl://` server TLS certificate to the given hex-encoded SHA-256
+    /// fingerprint of its DER encoding, allowing servers with self-signed certificates
+    #[arg(long, global = true, requires = "electrum", value_name = "SHA256")]
+    pub electrum_pin: Option<Bytes32>,
+
     /// Esplora server to use
     #[arg(
         long,
@@ -87,10 +107,101 @@ pub struct ResolverOpt {
         value_name = "URL"
     )]
     pub mempool: Option<String>,
+
+    /// HTTP proxy to route esplora and mempool requests through, given as
+    /// `[http://][user[:password]@]host[:port]`. SOCKS5 is not supported; to use Tor, enable
+    /// `HTTPTunnelPort` in the Tor daemon and specify its address here
+    #[arg(long, global = true, env = "ESPLORA_PROXY", value_hint = ValueHint::Url, value_name = "URL")]
+    pub esplora_proxy: Option<String>,
+
+    /// Bitcoin Core RPC server to use, given as `http://[<user>:<password>@]<host>:<port>`.
+    /// Wallet addresses are imported into a watch-only wallet of the node
+    #[arg(
+        long,
+        global = true,
+        default_missing_value = DEFAULT_BITCOIND,
+        num_args = 0..=1,
+        require_equals = true,
+        env = "BITCOIND_SERVER",
+        value_hint = ValueHint::Url,
+        value_name = "URL"
+    )]
+    pub bitcoind: Option<String>,
+
+    /// Bitcoin Core RPC cookie file to authenticate with instead of the credentials in the URL
+    #[arg(long, global = true, requires = "bitcoind", value_hint = ValueHint::FilePath, value_name = "FILE")]
+    pub rpc_cookie: Option<PathBuf>,
+
+    /// Name of the Bitcoin Core watch-only wallet holding imported wallet addresses
+    #[arg(long, global = true, default_value = DEFAULT_RPC_WALLET, value_name = "NAME")]
+    pub rpc_wallet: String,
+
+    /// UNIX timestamp of the earliest wallet transaction, limiting the blockchain rescan done by
+    /// Bitcoin Core when the wallet addresses are imported. Rescans from the genesis if not given
+    #[arg(long, global = true, requires = "bitcoind", value_name = "TIMESTAMP")]
+    pub rpc_birthday: Option<u64>,
+
+    /// Fee estimation mode used by Bitcoin Core
+    #[arg(
+        long,
+        global = true,
+        requires = "bitcoind",
+        value_enum,
+        default_value_t = EstimateMode::default(),
+        value_name = "MODE"
+    )]
+    pub estimate_mode: EstimateMode,
+
+    /// Query the indexer in privacy mode, waiting a random delay before each request, rotating
+    /// requests among multiple servers and scanning addresses one by one. Trades scan speed for
+    /// privacy; works with esplora and mempool only
+    #[arg(long, global = true)]
+    pub privacy: bool,
+
+    /// Maximal random delay before each request in privacy mode, in milliseconds
+    #[arg(long, global = true, requires = "privacy", default_value = "3000", value_name = "MS")]
+    pub jitter: u64,
+
+    /// Additional server to rotate through in privacy mode; may be repeated
+    #[arg(
+        long = "privacy-server",
+        global = true,
+        requires = "privacy",
+        value_hint = ValueHint::Url,
+        value_name = "URL"
+    )]
+    pub privacy_servers: Vec<String>,
+
+    /// Distribute address scan requests among a pool of esplora or mempool servers, given as
+    /// `<url>@<weight>` or just `<url>` for the weight of 1. Servers failing consecutive
+    /// requests are removed from the pool
+    #[arg(
+        long,
+        global = true,
+        env = "ESPLORA_POOL",
+        value_delimiter = ',',
+        conflicts_with = "privacy_servers",
+        value_name = "URL@WEIGHT"
+    )]
+    pub pool: Vec<WeightedUrl>,
+
+    /// Number of wallet addresses scanned in parallel; works with esplora and mempool only
+    #[arg(long, global = true, default_value = "4", value_name = "N")]
+    pub concurrency: NonZeroUsize,
+}
+
+impl ResolverOpt {
+    /// Checks whether any blockchain indexer is specified.
+    pub fn has_indexer(&self) -> bool {
+        self.electrum.is_some()
+            || self.esplora.is_some()
+            || self.mempool.is_some()
+            || self.bitcoind.is_some()
+    }
 }
 
 pub trait DescriptorOpts: clap::Args + Clone + Eq + Debug {
-    type Descr: Descriptor + serde::Serialize + for<'de> serde::Deserialize<'de>;
+    type Descr: Descriptor + Send + Sync + serde::Serialize + for<'de> serde::Deserialize<'de>;
     fn is_some(&self) -> bool;
     fn descriptor(&self) -> Option<Self::Descr>;
 }
@@ -120,6 +231,40 @@ impl DescriptorOpts for DescrStdOpts {
     }
 }
 
+/// Descriptor options combining several descriptors, possibly of different script types, into a
+/// single wallet. The `wpkh` descriptors go first in the set, thus the first of them receives the
+/// change by default.
+#[derive(Args, Clone, PartialEq, Eq, Debug)]
+pub struct DescrMultiOpts {
+    /// Use wpkh(WPKH) descriptor as wallet. May be repeated and combined with --tr-key-only
+    #[arg(long, global = true)]
+    pub wpkh: Vec<XpubDerivable>,
+
+    /// Use tr(TR_KEY_ONLY) descriptor as wallet. May be repeated and combined with --wpkh
+    #[arg(long, global = true)]
+    pub tr_key_only: Vec<XpubDerivable>,
+}
+
+impl DescriptorOpts for DescrMultiOpts {
+    type Descr = MultiDescr<StdDescr>;
+
+    fn is_some(&self) -> bool { !self.wpkh.is_empty() || !self.tr_key_only.is_empty() }
+    fn descriptor(&self) -> Option<Self::Descr> {
+        if !self.is_some() {
+            return None;
+        }
+        let wpkh = self.wpkh.iter().map(|x| Wpkh::from(x.clone()).into());
+        let tr = self.tr_key_only.iter().map(|x| TrKey::from(x.clone()).into());
+        match MultiDescr::with(wpkh.chain(tr)) {
+            Ok(descr) => Some(descr),
+            Err(err) => {
+                eprintln!("Error: {err}");
+                exit(1);
+            }
+        }
+    }
+}
+
 #[derive(Args, Clone, PartialEq, Eq, Debug)]
 #[group(multiple = false)]
 pub struct WalletOpts<O: DescriptorOpts = DescrStdOpts> {
@@ -140,6 +285,60 @@ pub struct WalletOpts<O: DescriptorOpts = DescrStdOpts> {
     pub descriptor_opts: O,
 }
 
+#[derive(Args, Clone, PartialEq, Eq, Debug)]
+pub struct ScanOpts {
+    /// Checkpoint wallet cache to disk each time the given number of addresses was scanned
+    #[arg(long, global = true, value_name = "N")]
+    pub flush_addresses: Option<NonZeroUsize>,
+
+    /// Checkpoint wallet cache to disk each time the given number of transactions was
+    /// retrieved
+    #[arg(long, global = true, value_name = "M")]
+    pub flush_txs: Option<NonZeroUsize>,
+
+    /// Stop scanning a keychain after the given number of consecutive unused addresses. Can be
+    /// given as `<keychain>=<n>` to apply to a specific keychain only, and used multiple times.
+    /// Overrides gap limits from the config file.
+    #[arg(long, global = true, value_name = "[KEYCHAIN=]N")]
+    pub gap_limit: Vec<GapLimit>,
+
+    /// Double the gap limit of a keychain each time a run of 200 consecutive used addresses is
+    /// found, up to 1000 addresses, to avoid missing funds of heavily-used wallets
+    #[arg(long, global = true)]
+    pub adaptive_gap: bool,
+}
+
+impl ScanOpts {
+    pub fn flush_interval(&self) -> FlushInterval {
+        FlushInterval {
+            addresses: self.flush_addresses,
+            transactions: self.flush_txs,
+        }
+    }
+
+    pub fn gap_limits(&self, conf: &Config) -> GapLimits {
+        let mut limits: GapLimits =
+            conf.gap_limits.iter().chain(&self.gap_limit).copied().collect();
+        limits.adaptive = self.adaptive_gap;
+        limits
+    }
+}
+
+#[derive(Args, Clone, PartialEq, Eq, Debug)]
+pub struct PublishOpts {
+    /// Do not wait for the published transaction to appear at the indexer
+    #[arg(long, global = true)]
+    pub no_poll: bool,
+
+    /// Number of attempts to check the presence of the published transaction at the indexer
+    #[arg(long, global = true, default_value = "10", value_name = "COUNT")]
+    pub poll_attempts: u16,
+
+    /// Interval in seconds between checks of the published transaction presence
+    #[arg(long, global = true, default_value = "3", value_name = "SECS")]
+    pub poll_interval: u64,
+}
+
 #[derive(Args, Clone, PartialEq, Eq, Debug)]
 pub struct GeneralOpts {
     /// Data directory path
@@ -0,0 +1,134 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Payment beneficiaries with optional floors for `MAX` payments.
+//!
+//! In addition to the `<sats>@<address>` and `MAX@<address>` invoice forms, a `MAX` payment may
+//! guarantee the recipient a minimal amount using `MAX(min=<sats>)@<address>` syntax. Floors are
+//! paid first, and the rest of the balance is split equally between all `MAX` recipients above
+//! their floors.
+
+use std::str::FromStr;
+
+use bpstd::psbt::{Beneficiary, BeneficiaryParseError};
+use bpstd::{Address, Sats};
+use psbt::Psbt;
+
+#[derive(Clone, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum PayeeError {
+    /// {0}
+    #[from]
+    Beneficiary(BeneficiaryParseError),
+
+    /// invalid floor '{0}' for a MAX payment; expected `MAX(min=<sats>)@<address>`.
+    InvalidFloor(String),
+
+    /// MAX payment floors totalling {0} exceed the {1} left after fee and fixed payments.
+    FloorsExceedBalance(Sats, Sats),
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Payee {
+    pub beneficiary: Beneficiary,
+    /// Minimal amount guaranteed to a `MAX` payment recipient.
+    pub floor: Option<Sats>,
+}
+
+impl Payee {
+    pub fn is_max(&self) -> bool { self.beneficiary.is_max() }
+}
+
+impl FromStr for Payee {
+    type Err = PayeeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(rest) = s.strip_prefix("MAX(") else {
+            return Ok(Payee {
+                beneficiary: Beneficiary::from_str(s)?,
+                floor: None,
+            });
+        };
+        let (floor, address) = rest.split_once(")@").ok_or(BeneficiaryParseError::InvalidFormat)?;
+        let floor = floor
+            .strip_prefix("min=")
+            .and_then(|sats| Sats::from_str(sats).ok())
+            .ok_or_else(|| PayeeError::InvalidFloor(floor.to_owned()))?;
+        let address = Address::from_str(address).map_err(BeneficiaryParseError::from)?;
+        Ok(Payee {
+            beneficiary: Beneficiary::with_max(address),
+            floor: Some(floor),
+        })
+    }
+}
+
+/// Re-distributes amounts of the `MAX` outputs of a PSBT constructed for the given payees (in the
+/// same order), such that each of them receives its floor plus an equal share of the rest.
+pub fn apply_max_floors(psbt: &mut Psbt, payees: &[Payee]) -> Result<(), PayeeError> {
+    let floors = payees.iter().filter_map(|payee| payee.floor).sum::<Sats>();
+    if floors == Sats::ZERO {
+        return Ok(());
+    }
+    let max = payees.iter().filter(|payee| payee.is_max()).count();
+    let available = psbt
+        .outputs()
+        .zip(payees)
+        .filter(|(_, payee)| payee.is_max())
+        .map(|(out, _)| out.amount)
+        .sum::<Sats>();
+    if floors > available {
+        return Err(PayeeError::FloorsExceedBalance(floors, available));
+    }
+    let portion = (available - floors) / max;
+    for (out, payee) in psbt.outputs_mut().zip(payees) {
+        if payee.is_max() {
+            out.amount = payee.floor.unwrap_or_default() + portion;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use psbt::Payment;
+
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let addr = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq";
+        let payee = Payee::from_str(&format!("MAX(min=10000)@{addr}")).unwrap();
+        assert!(payee.is_max());
+        assert_eq!(payee.floor, Some(Sats(10000)));
+        assert_eq!(payee.beneficiary.address.to_string(), addr);
+
+        let payee = Payee::from_str(&format!("MAX@{addr}")).unwrap();
+        assert!(payee.is_max());
+        assert_eq!(payee.floor, None);
+
+        let payee = Payee::from_str(&format!("500@{addr}")).unwrap();
+        assert_eq!(payee.beneficiary.amount, Payment::Fixed(Sats(500)));
+
+        assert!(Payee::from_str(&format!("MAX(max=1)@{addr}")).is_err());
+        assert!(Payee::from_str(&format!("MAX(min=1){addr}")).is_err());
+    }
+}
@@ -0,0 +1,247 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeSet;
+
+use bpstd::{AddressPayload, AddressType, Sats, Vout};
+use psbt::Psbt;
+
+/// Amounts which are multiples of this value (0.0001 BTC) are considered round.
+pub const ROUND_AMOUNT: u64 = 10_000;
+
+/// Privacy leak detected in a transaction by one of the common chain-analysis heuristics.
+#[derive(Clone, Eq, PartialEq, Debug, Display)]
+#[display(doc_comments)]
+pub enum PrivacyIssue {
+    /// transaction spends coins from {0} different addresses, revealing that they belong to the
+    /// same owner.
+    AddressMerge(usize),
+
+    /// payment of {0} ṩ is a round amount, which fingerprints it as a payment.
+    RoundPayment(Sats),
+
+    /// change of {0} ṩ is the only non-round output, which reveals it as the change.
+    NonRoundChange(Sats),
+
+    /// change uses {0} address type not used by any of the payments, which reveals it as the
+    /// change.
+    ChangeType(AddressType),
+
+    /// inputs mix different address types ({0}), which fingerprints the wallet.
+    MixedInputTypes(String),
+}
+
+impl PrivacyIssue {
+    /// Suggests how the issue may be avoided.
+    pub fn suggestion(&self) -> &'static str {
+        match self {
+            PrivacyIssue::AddressMerge(_) => {
+                "spend coins from a single address, selecting them with `--from-label`"
+            }
+            PrivacyIssue::RoundPayment(_) => {
+                "if the recipient allows, add a few random satoshis to the amount"
+            }
+            PrivacyIssue::NonRoundChange(_) => {
+                "make the payment amount non-round or spend coins matching the payment to avoid \
+                 change"
+            }
+            PrivacyIssue::ChangeType(_) => {
+                "ask the recipient for an address of the same type as used by the wallet"
+            }
+            PrivacyIssue::MixedInputTypes(_) => {
+                "avoid spending coins of different address types in the same transaction"
+            }
+        }
+    }
+}
+
+fn is_round(sats: Sats) -> bool { sats > Sats::ZERO && sats.sats() % ROUND_AMOUNT == 0 }
+
+fn address_type(script: &bpstd::ScriptPubkey) -> Option<AddressType> {
+    AddressPayload::from_script(script).ok().map(AddressPayload::address_type)
+}
+
+/// Runs privacy heuristics against a transaction, returning the list of detected issues.
+///
+/// The output with `change_vout` number is treated as change, and all other outputs - except
+/// `OP_RETURN`s - as payments.
+pub fn privacy_check(psbt: &Psbt, change_vout: Option<Vout>) -> Vec<PrivacyIssue> {
+    let mut issues = vec![];
+
+    let addresses =
+        psbt.inputs().map(|input| &input.prev_txout().script_pubkey).collect::<BTreeSet<_>>();
+    if addresses.len() > 1 {
+        issues.push(PrivacyIssue::AddressMerge(addresses.len()));
+    }
+
+    let input_types = addresses.iter().filter_map(|spk| address_type(spk)).collect::<BTreeSet<_>>();
+    if input_types.len() > 1 {
+        let types = input_types.iter().map(AddressType::to_string).collect::<Vec<_>>();
+        issues.push(PrivacyIssue::MixedInputTypes(types.join(", ")));
+    }
+
+    let change = change_vout.and_then(|vout| psbt.outputs().nth(vout.into_usize()));
+    let payments = psbt
+        .outputs()
+        .filter(|output| Some(output.index()) != change_vout.map(Vout::into_usize))
+        .filter(|output| !output.script.is_op_return())
+        .collect::<Vec<_>>();
+
+    for payment in &payments {
+        if is_round(payment.amount) {
+            issues.push(PrivacyIssue::RoundPayment(payment.amount));
+        }
+    }
+
+    let Some(change) = change else {
+        return issues;
+    };
+    if !payments.is_empty()
+        && payments.iter().all(|payment| is_round(payment.amount))
+        && !is_round(change.amount)
+    {
+        issues.push(PrivacyIssue::NonRoundChange(change.amount));
+    }
+    if let Some(change_type) = address_type(&change.script) {
+        let payment_types =
+            payments.iter().filter_map(|payment| address_type(&payment.script)).collect::<Vec<_>>();
+        if !payment_types.is_empty() && !payment_types.contains(&change_type) {
+            issues.push(PrivacyIssue::ChangeType(change_type));
+        }
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bpstd::{
+        AddressNetwork, DeriveScripts, Keychain, NormalIndex, Outpoint, ScriptPubkey, SeqNo,
+        Terminal, Txid, XpubDerivable,
+    };
+    use descriptors::{StdDescr, TrKey, Wpkh};
+    use psbt::{Prevout, PsbtVer};
+
+    use super::*;
+
+    const XPUB: &str = "[73c5da0a/84h/0h/0h]xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/<0;1>/*";
+
+    fn wpkh() -> StdDescr<XpubDerivable> {
+        StdDescr::from(Wpkh::from(XpubDerivable::from_str(XPUB).unwrap()))
+    }
+
+    fn tr() -> StdDescr<XpubDerivable> {
+        StdDescr::from(TrKey::from(XpubDerivable::from_str(XPUB).unwrap()))
+    }
+
+    fn script(descr: &StdDescr<XpubDerivable>, index: u16) -> ScriptPubkey {
+        descr.derive_address(AddressNetwork::Mainnet, 0, index).unwrap().script_pubkey()
+    }
+
+    /// Constructs PSBT spending coins from the given descriptor addresses to the outputs.
+    fn construct(
+        inputs: &[(&StdDescr<XpubDerivable>, u16)],
+        outputs: &[(ScriptPubkey, u64)],
+    ) -> Psbt {
+        let mut psbt = Psbt::create(PsbtVer::V2);
+        for (no, (descr, index)) in inputs.iter().enumerate() {
+            let outpoint = Outpoint::new(Txid::from([1u8; 32]), no as u32);
+            let prevout = Prevout::new(outpoint, Sats::from(100_000u64));
+            let terminal = Terminal::new(Keychain::OUTER, NormalIndex::from(*index));
+            psbt.construct_input_expect(prevout, *descr, terminal, SeqNo::ZERO);
+        }
+        for (script, value) in outputs {
+            psbt.construct_output_expect(script.clone(), Sats::from(*value));
+        }
+        psbt
+    }
+
+    #[test]
+    fn round() {
+        assert!(is_round(Sats::from(100_000u64)));
+        assert!(is_round(Sats::from(10_000u64)));
+        assert!(!is_round(Sats::from(10_001u64)));
+        assert!(!is_round(Sats::ZERO));
+    }
+
+    #[test]
+    fn no_issues() {
+        let wpkh = wpkh();
+        let psbt =
+            construct(&[(&wpkh, 0)], &[(script(&wpkh, 10), 12_345), (script(&wpkh, 11), 7_000)]);
+        assert_eq!(privacy_check(&psbt, Some(Vout::from_u32(1))), vec![]);
+        assert_eq!(privacy_check(&psbt, None), vec![]);
+    }
+
+    #[test]
+    fn input_issues() {
+        let (wpkh, tr) = (wpkh(), tr());
+        let outputs = [(script(&wpkh, 10), 12_345)];
+
+        let psbt = construct(&[(&wpkh, 0), (&wpkh, 1), (&wpkh, 1)], &outputs);
+        assert_eq!(privacy_check(&psbt, None), vec![PrivacyIssue::AddressMerge(2)]);
+
+        let psbt = construct(&[(&wpkh, 0), (&tr, 0)], &outputs);
+        assert_eq!(privacy_check(&psbt, None), vec![
+            PrivacyIssue::AddressMerge(2),
+            PrivacyIssue::MixedInputTypes(format!(
+                "{}, {}",
+                AddressType::P2wpkh,
+                AddressType::P2tr
+            ))
+        ]);
+    }
+
+    #[test]
+    fn output_issues() {
+        let (wpkh, tr) = (wpkh(), tr());
+        let change = Some(Vout::from_u32(1));
+
+        let psbt =
+            construct(&[(&wpkh, 0)], &[(script(&wpkh, 10), 50_000), (script(&wpkh, 11), 12_345)]);
+        assert_eq!(privacy_check(&psbt, change), vec![
+            PrivacyIssue::RoundPayment(Sats::from(50_000u64)),
+            PrivacyIssue::NonRoundChange(Sats::from(12_345u64)),
+        ]);
+        // Without change only the payments are checked
+        assert_eq!(privacy_check(&psbt, None), vec![PrivacyIssue::RoundPayment(Sats::from(
+            50_000u64
+        ))]);
+
+        // Round change doesn't stand out among the round payments
+        let psbt =
+            construct(&[(&wpkh, 0)], &[(script(&wpkh, 10), 50_000), (script(&wpkh, 11), 20_000)]);
+        assert_eq!(privacy_check(&psbt, change), vec![PrivacyIssue::RoundPayment(Sats::from(
+            50_000u64
+        ))]);
+
+        // `OP_RETURN` outputs are not payments
+        let psbt = construct(&[(&wpkh, 0)], &[
+            (ScriptPubkey::op_return(b"test"), 0),
+            (script(&tr, 11), 12_345),
+            (script(&wpkh, 10), 12_345),
+        ]);
+        assert_eq!(privacy_check(&psbt, change), vec![PrivacyIssue::ChangeType(AddressType::P2tr)]);
+    }
+}
@@ -0,0 +1,157 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rendering of QR codes in the terminal. Payloads not fitting into a single QR code are split
+//! into [BBQr] fragments, which can be scanned in any order.
+//!
+//! [BBQr]: https://bbqr.org
+
+use std::io::{self, IsTerminal, Write};
+use std::thread;
+use std::time::Duration;
+
+use qrcode::render::unicode::Dense1x2;
+use qrcode::{EcLevel, QrCode};
+
+/// Maximum number of characters put into a single QR code, keeping it readable when rendered in
+/// the terminal.
+pub const QR_MAX_CHARS: usize = 600;
+
+/// Delay between the frames of an animated QR code.
+pub const QR_FRAME_DELAY: Duration = Duration::from_millis(600);
+
+/// Length of the BBQr fragment header.
+const BBQR_HEADER_LEN: usize = 8;
+
+/// Maximum number of fragments representable in the BBQr header.
+const BBQR_MAX_PARTS: usize = 36 * 36 - 1;
+
+/// Type of the file encoded into BBQr fragments.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Display)]
+pub enum BbqrType {
+    /// Partially signed bitcoin transaction.
+    #[display("P")]
+    Psbt,
+
+    /// Signed bitcoin transaction.
+    #[display("T")]
+    Tx,
+}
+
+/// Renders the data as a QR code made of unicode block characters. Light and dark modules are
+/// inverted, so the code reads well on dark terminal backgrounds.
+///
+/// # Panics
+///
+/// If the data does not fit into a single QR code, which never happens for data not exceeding
+/// [`QR_MAX_CHARS`].
+pub fn qr_render(data: &str) -> String {
+    QrCode::with_error_correction_level(data, EcLevel::L)
+        .expect("data too long for a single QR code")
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build()
+}
+
+/// Splits binary data into hex-encoded BBQr fragments of at most `max_chars` characters, with
+/// all fragments except the last one having the same length. Returns `None` if the data requires
+/// more fragments than BBQr supports.
+pub fn bbqr_split(data: &[u8], file_type: BbqrType, max_chars: usize) -> Option<Vec<String>> {
+    let hex = data.iter().map(|byte| format!("{byte:02X}")).collect::<String>();
+    let capacity = (max_chars.saturating_sub(BBQR_HEADER_LEN) & !1).max(2);
+    let count = hex.len().div_ceil(capacity).max(1);
+    if count > BBQR_MAX_PARTS {
+        return None;
+    }
+    let part_len = (hex.len().div_ceil(count) + 1) & !1;
+    let parts = (0..count)
+        .map(|no| {
+            let start = (no * part_len).min(hex.len());
+            let end = (start + part_len).min(hex.len());
+            format!("B$H{file_type}{}{}{}", base36(count), base36(no), &hex[start..end])
+        })
+        .collect();
+    Some(parts)
+}
+
+/// Prints a sequence of QR codes to STDOUT. A single code is printed as is; multiple codes are
+/// animated in a loop until the process is interrupted if STDOUT is a terminal, and printed one
+/// after another otherwise.
+pub fn qr_print(payloads: &[String]) -> io::Result<()> {
+    let frames = payloads.iter().map(|payload| qr_render(payload)).collect::<Vec<_>>();
+    let mut out = io::stdout().lock();
+    if frames.len() == 1 {
+        return writeln!(out, "\n{}", frames[0]);
+    }
+    if !out.is_terminal() {
+        for (no, frame) in frames.iter().enumerate() {
+            writeln!(out, "\nPart {} of {}:\n{frame}", no + 1, frames.len())?;
+        }
+        return Ok(());
+    }
+    eprintln!("Showing {} animated QR code frames; press Ctrl+C to stop", frames.len());
+    loop {
+        for (no, frame) in frames.iter().enumerate() {
+            // Clear the screen and move the cursor to the top left corner
+            write!(out, "\x1B[2J\x1B[H{frame}\nPart {} of {}", no + 1, frames.len())?;
+            out.flush()?;
+            thread::sleep(QR_FRAME_DELAY);
+        }
+    }
+}
+
+fn base36(n: usize) -> String {
+    const DIGITS: &[u8; 36] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    format!("{}{}", DIGITS[n / 36] as char, DIGITS[n % 36] as char)
+}
+
+#[cfg(test)]
+mod test {
+    use amplify::hex::FromHex;
+
+    use super::*;
+
+    #[test]
+    fn bbqr_single() {
+        let parts = bbqr_split(&[0xde, 0xad, 0xbe, 0xef], BbqrType::Psbt, QR_MAX_CHARS).unwrap();
+        assert_eq!(parts, vec![s!("B$HP0100DEADBEEF")]);
+    }
+
+    #[test]
+    fn bbqr_fragments() {
+        let data = (0u8..=255).collect::<Vec<_>>();
+        let parts = bbqr_split(&data, BbqrType::Tx, 108).unwrap();
+        assert_eq!(parts.len(), 6);
+        assert!(parts.iter().all(|part| part.len() <= 108));
+        assert!(parts[..5].iter().all(|part| part.len() == parts[0].len()));
+        assert_eq!(&parts[1][..8], "B$HT0601");
+        assert_eq!(&parts[5][..8], "B$HT0605");
+        let hex = parts.iter().map(|part| &part[8..]).collect::<String>();
+        assert_eq!(Vec::<u8>::from_hex(&hex.to_lowercase()).unwrap(), data);
+    }
+
+    #[test]
+    fn bbqr_limit() {
+        assert!(bbqr_split(&[0u8; 2000], BbqrType::Psbt, 10).is_none());
+    }
+}
@@ -0,0 +1,208 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Disassembly of bitcoin scripts into human-readable opcodes.
+
+use amplify::hex::ToHex;
+
+use crate::{Instruction, Instructions};
+
+/// Disassembles script into a space-separated list of opcodes, where data pushes are shown as
+/// hex-encoded data. Truncated data pushes are reported as `<unexpected end>`.
+pub fn disassemble(script: &[u8]) -> String {
+    let mut asm = Vec::new();
+    for instruction in Instructions::new(script) {
+        match instruction {
+            Ok(Instruction::Push(op @ 0x4c..=0x4e, data)) => {
+                asm.push(opcode_name(op).to_owned());
+                asm.push(data.to_hex());
+            }
+            Ok(Instruction::Push(_, data)) => asm.push(data.to_hex()),
+            Ok(Instruction::Op(op)) => asm.push(opcode_name(op).to_owned()),
+            Err(_) => {
+                asm.push(s!("<unexpected end>"));
+                break;
+            }
+        }
+    }
+    asm.join(" ")
+}
+
+/// Detects number of signatures required by a multisig script, which may be either a legacy
+/// `OP_CHECKMULTISIG` script or a tapscript `OP_CHECKSIGADD`-based script with the threshold
+/// checked by `OP_NUMEQUAL`. Returns `None` for other scripts.
+pub fn multisig_threshold(script: &[u8]) -> Option<u8> {
+    let small_int = |op: u8| (0x51..=0x60).contains(&op).then(|| op - 0x50);
+    match script {
+        [m, .., n, 0xae] => {
+            let (m, n) = (small_int(*m)?, small_int(*n)?);
+            (m <= n).then_some(m)
+        }
+        [.., 0xba, m, 0x9c] => small_int(*m),
+        _ => None,
+    }
+}
+
+fn opcode_name(op: u8) -> &'static str {
+    const NUMBERS: [&str; 16] = [
+        "OP_1", "OP_2", "OP_3", "OP_4", "OP_5", "OP_6", "OP_7", "OP_8", "OP_9", "OP_10", "OP_11",
+        "OP_12", "OP_13", "OP_14", "OP_15", "OP_16",
+    ];
+    match op {
+        0x00 => "OP_0",
+        0x4c => "OP_PUSHDATA1",
+        0x4d => "OP_PUSHDATA2",
+        0x4e => "OP_PUSHDATA4",
+        0x4f => "OP_1NEGATE",
+        0x50 => "OP_RESERVED",
+        0x51..=0x60 => NUMBERS[(op - 0x51) as usize],
+        0x61 => "OP_NOP",
+        0x62 => "OP_VER",
+        0x63 => "OP_IF",
+        0x64 => "OP_NOTIF",
+        0x65 => "OP_VERIF",
+        0x66 => "OP_VERNOTIF",
+        0x67 => "OP_ELSE",
+        0x68 => "OP_ENDIF",
+        0x69 => "OP_VERIFY",
+        0x6a => "OP_RETURN",
+        0x6b => "OP_TOALTSTACK",
+        0x6c => "OP_FROMALTSTACK",
+        0x6d => "OP_2DROP",
+        0x6e => "OP_2DUP",
+        0x6f => "OP_3DUP",
+        0x70 => "OP_2OVER",
+        0x71 => "OP_2ROT",
+        0x72 => "OP_2SWAP",
+        0x73 => "OP_IFDUP",
+        0x74 => "OP_DEPTH",
+        0x75 => "OP_DROP",
+        0x76 => "OP_DUP",
+        0x77 => "OP_NIP",
+        0x78 => "OP_OVER",
+        0x79 => "OP_PICK",
+        0x7a => "OP_ROLL",
+        0x7b => "OP_ROT",
+        0x7c => "OP_SWAP",
+        0x7d => "OP_TUCK",
+        0x7e => "OP_CAT",
+        0x7f => "OP_SUBSTR",
+        0x80 => "OP_LEFT",
+        0x81 => "OP_RIGHT",
+        0x82 => "OP_SIZE",
+        0x83 => "OP_INVERT",
+        0x84 => "OP_AND",
+        0x85 => "OP_OR",
+        0x86 => "OP_XOR",
+        0x87 => "OP_EQUAL",
+        0x88 => "OP_EQUALVERIFY",
+        0x89 => "OP_RESERVED1",
+        0x8a => "OP_RESERVED2",
+        0x8b => "OP_1ADD",
+        0x8c => "OP_1SUB",
+        0x8d => "OP_2MUL",
+        0x8e => "OP_2DIV",
+        0x8f => "OP_NEGATE",
+        0x90 => "OP_ABS",
+        0x91 => "OP_NOT",
+        0x92 => "OP_0NOTEQUAL",
+        0x93 => "OP_ADD",
+        0x94 => "OP_SUB",
+        0x95 => "OP_MUL",
+        0x96 => "OP_DIV",
+        0x97 => "OP_MOD",
+        0x98 => "OP_LSHIFT",
+        0x99 => "OP_RSHIFT",
+        0x9a => "OP_BOOLAND",
+        0x9b => "OP_BOOLOR",
+        0x9c => "OP_NUMEQUAL",
+        0x9d => "OP_NUMEQUALVERIFY",
+        0x9e => "OP_NUMNOTEQUAL",
+        0x9f => "OP_LESSTHAN",
+        0xa0 => "OP_GREATERTHAN",
+        0xa1 => "OP_LESSTHANOREQUAL",
+        0xa2 => "OP_GREATERTHANOREQUAL",
+        0xa3 => "OP_MIN",
+        0xa4 => "OP_MAX",
+        0xa5 => "OP_WITHIN",
+        0xa6 => "OP_RIPEMD160",
+        0xa7 => "OP_SHA1",
+        0xa8 => "OP_SHA256",
+        0xa9 => "OP_HASH160",
+        0xaa => "OP_HASH256",
+        0xab => "OP_CODESEPARATOR",
+        0xac => "OP_CHECKSIG",
+        0xad => "OP_CHECKSIGVERIFY",
+        0xae => "OP_CHECKMULTISIG",
+        0xaf => "OP_CHECKMULTISIGVERIFY",
+        0xb0 => "OP_NOP1",
+        0xb1 => "OP_CHECKLOCKTIMEVERIFY",
+        0xb2 => "OP_CHECKSEQUENCEVERIFY",
+        0xb3 => "OP_NOP4",
+        0xb4 => "OP_NOP5",
+        0xb5 => "OP_NOP6",
+        0xb6 => "OP_NOP7",
+        0xb7 => "OP_NOP8",
+        0xb8 => "OP_NOP9",
+        0xb9 => "OP_NOP10",
+        0xba => "OP_CHECKSIGADD",
+        0xff => "OP_INVALIDOPCODE",
+        _ => "OP_UNKNOWN",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amplify::hex::FromHex;
+
+    use super::*;
+
+    #[test]
+    fn disassemble_scripts() {
+        let p2pkh =
+            Vec::<u8>::from_hex("76a914000102030405060708090a0b0c0d0e0f1011121388ac").unwrap();
+        assert_eq!(
+            disassemble(&p2pkh),
+            "OP_DUP OP_HASH160 000102030405060708090a0b0c0d0e0f10111213 OP_EQUALVERIFY OP_CHECKSIG"
+        );
+        let multisig = Vec::<u8>::from_hex("52ae").unwrap();
+        assert_eq!(disassemble(&multisig), "OP_2 OP_CHECKMULTISIG");
+        let pushdata = Vec::<u8>::from_hex("6a4c02cafe").unwrap();
+        assert_eq!(disassemble(&pushdata), "OP_RETURN OP_PUSHDATA1 cafe");
+        assert_eq!(disassemble(&[0x05, 0x01]), "<unexpected end>");
+    }
+
+    #[test]
+    fn multisig_thresholds() {
+        let key = "21020000000000000000000000000000000000000000000000000000000000000001";
+        let multi = Vec::<u8>::from_hex(&format!("52{key}{key}{key}53ae")).unwrap();
+        assert_eq!(multisig_threshold(&multi), Some(2));
+        let xonly = "200000000000000000000000000000000000000000000000000000000000000001";
+        let multi_a = Vec::<u8>::from_hex(&format!("{xonly}ac{xonly}ba529c")).unwrap();
+        assert_eq!(multisig_threshold(&multi_a), Some(2));
+        let invalid = Vec::<u8>::from_hex(&format!("53{key}{key}52ae")).unwrap();
+        assert_eq!(multisig_threshold(&invalid), None);
+        let p2pkh =
+            Vec::<u8>::from_hex("76a914000102030405060708090a0b0c0d0e0f1011121388ac").unwrap();
+        assert_eq!(multisig_threshold(&p2pkh), None);
+    }
+}
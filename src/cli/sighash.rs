@@ -0,0 +1,156 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Custom sighash types for the inputs of a constructed PSBT.
+//!
+//! Sighash types are specified in the form of `<txid>:<vout>:<type>`, where `<type>` is one of
+//! `ALL`, `NONE` or `SINGLE`, optionally followed by `|ANYONECANPAY` (case-insensitive).
+
+use std::str::FromStr;
+
+use bpstd::{Outpoint, OutpointParseError, SighashFlag, SighashType};
+use psbt::Psbt;
+
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum InputSighashError {
+    /// input sighash '{0}' must have form of `<txid>:<vout>:<type>`.
+    Malformed(String),
+
+    /// invalid outpoint in input sighash. Details: {0}
+    #[from]
+    Outpoint(OutpointParseError),
+
+    /// unknown sighash type '{0}'; expected ALL, NONE or SINGLE, optionally followed by
+    /// `|ANYONECANPAY`.
+    UnknownType(String),
+
+    /// sighash type is specified for {0}, which is not spent by the transaction.
+    UnknownOutpoint(Outpoint),
+
+    /// sighash type is specified multiple times for {0}.
+    Repeated(Outpoint),
+
+    /// SIGHASH_SINGLE is specified for input #{0}, which has no corresponding output; the
+    /// signature would commit to a constant value and could be reused for any transaction.
+    SingleWithoutOutput(usize),
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Display)]
+#[display("{outpoint}:{sighash_type}")]
+pub struct InputSighash {
+    pub outpoint: Outpoint,
+    pub sighash_type: SighashType,
+}
+
+impl FromStr for InputSighash {
+    type Err = InputSighashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (outpoint, ty) =
+            s.rsplit_once(':').ok_or_else(|| InputSighashError::Malformed(s.to_owned()))?;
+        let outpoint = Outpoint::from_str(outpoint)?;
+        let ty = ty.to_uppercase();
+        let (flag, anyone_can_pay) = match ty.split_once('|') {
+            None => (ty.trim(), false),
+            Some((flag, "ANYONECANPAY")) => (flag.trim(), true),
+            Some(_) => return Err(InputSighashError::UnknownType(ty)),
+        };
+        let flag = match flag {
+            "ALL" => SighashFlag::All,
+            "NONE" => SighashFlag::None,
+            "SINGLE" => SighashFlag::Single,
+            _ => return Err(InputSighashError::UnknownType(ty)),
+        };
+        Ok(InputSighash {
+            outpoint,
+            sighash_type: SighashType {
+                flag,
+                anyone_can_pay,
+            },
+        })
+    }
+}
+
+/// Assigns custom sighash types to the PSBT inputs spending the given outpoints.
+///
+/// For taproot inputs `SIGHASH_ALL` is stored as `SIGHASH_DEFAULT` (i.e. no sighash type is set),
+/// which commits to the same data but saves a byte in the witness. If any of the sighash types is
+/// invalid the PSBT is left unmodified.
+pub fn apply_sighash_types(
+    psbt: &mut Psbt,
+    sighashes: &[InputSighash],
+) -> Result<(), InputSighashError> {
+    let outputs = psbt.outputs().count();
+    let mut assignments = Vec::with_capacity(sighashes.len());
+    for InputSighash {
+        outpoint,
+        sighash_type,
+    } in sighashes
+    {
+        if assignments.iter().any(|(_, o, _)| o == outpoint) {
+            return Err(InputSighashError::Repeated(*outpoint));
+        }
+        let input = psbt
+            .inputs()
+            .find(|input| input.previous_outpoint == *outpoint)
+            .ok_or(InputSighashError::UnknownOutpoint(*outpoint))?;
+        let index = input.index();
+        if sighash_type.flag == SighashFlag::Single && index >= outputs {
+            return Err(InputSighashError::SingleWithoutOutput(index));
+        }
+        let sighash_type =
+            if input.prev_txout().script_pubkey.is_p2tr() && *sighash_type == SighashType::all() {
+                None
+            } else {
+                Some(*sighash_type)
+            };
+        assignments.push((index, *outpoint, sighash_type));
+    }
+
+    for (index, _, sighash_type) in assignments {
+        psbt.input_mut(index).expect("checked above").sighash_type = sighash_type;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_input_sighash() {
+        let outpoint = "a1c2e5bd0dcdb5d6f2d31a0b0f3bbaf9e1a4c7e4e2d16ba4d6b1e2f4c8a5d3e1:2";
+        let sighash = InputSighash::from_str(&format!("{outpoint}:single|anyonecanpay")).unwrap();
+        assert_eq!(sighash.outpoint, Outpoint::from_str(outpoint).unwrap());
+        assert_eq!(sighash.sighash_type, SighashType::single_anyone_can_pay());
+        assert_eq!(
+            InputSighash::from_str(&format!("{outpoint}:NONE")).unwrap().sighash_type,
+            SighashType::none()
+        );
+        assert!(matches!(
+            InputSighash::from_str(&format!("{outpoint}:DEFAULT")),
+            Err(InputSighashError::UnknownType(_))
+        ));
+        assert!(InputSighash::from_str(outpoint).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use descriptors::SpkClass;
+
+/// Known capabilities of a hardware signer, used for static compatibility analysis of wallet
+/// descriptors.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct SignerProfile {
+    pub name: &'static str,
+    /// Script types supported for single-key wallets.
+    pub single_sig: &'static [SpkClass],
+    /// Script types supported for multisig wallets.
+    pub multisig: &'static [SpkClass],
+    /// Maximal number of cosigners in a multisig wallet.
+    pub max_cosigners: usize,
+    /// BIP-43 purpose values the signer derives keys for without custom path settings.
+    pub purposes: &'static [u32],
+}
+
+/// Compatibility matrix for common hardware signers.
+pub const SIGNER_PROFILES: [SignerProfile; 4] = [
+    SignerProfile {
+        name: "Coldcard",
+        single_sig: &[SpkClass::P2pkh, SpkClass::P2sh, SpkClass::P2wpkh, SpkClass::P2tr],
+        multisig: &[SpkClass::P2sh, SpkClass::P2wsh],
+        max_cosigners: 15,
+        purposes: &[44, 45, 48, 49, 84, 86],
+    },
+    SignerProfile {
+        name: "Trezor",
+        single_sig: &[SpkClass::P2pkh, SpkClass::P2sh, SpkClass::P2wpkh, SpkClass::P2tr],
+        multisig: &[SpkClass::P2sh, SpkClass::P2wsh],
+        max_cosigners: 15,
+        purposes: &[44, 48, 49, 84, 86],
+    },
+    SignerProfile {
+        name: "Ledger",
+        single_sig: &[SpkClass::P2pkh, SpkClass::P2sh, SpkClass::P2wpkh, SpkClass::P2tr],
+        multisig: &[SpkClass::P2sh, SpkClass::P2wsh, SpkClass::P2tr],
+        max_cosigners: 16,
+        purposes: &[44, 48, 49, 84, 86],
+    },
+    SignerProfile {
+        name: "BitBox02",
+        single_sig: &[SpkClass::P2sh, SpkClass::P2wpkh, SpkClass::P2tr],
+        multisig: &[SpkClass::P2sh, SpkClass::P2wsh],
+        max_cosigners: 5,
+        purposes: &[48, 49, 84, 86],
+    },
+];
+
+/// Result of checking a descriptor against a signer profile.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SignerVerdict {
+    pub signer: &'static str,
+    /// Reasons why the signer can't handle the descriptor.
+    pub errors: Vec<String>,
+    /// Issues which may require additional signer configuration.
+    pub warnings: Vec<String>,
+}
+
+impl SignerVerdict {
+    pub fn is_supported(&self) -> bool { self.errors.is_empty() }
+}
+
+impl SignerProfile {
+    /// Checks descriptor with the given script type, number of keys and BIP-43 purpose of each of
+    /// the key derivation paths (`None` for keys without origin information).
+    pub fn check(
+        &self,
+        class: SpkClass,
+        key_count: usize,
+        purposes: &[Option<u32>],
+    ) -> SignerVerdict {
+        let mut errors = vec![];
+        let mut warnings = vec![];
+        let multisig = key_count > 1;
+        if !multisig && !self.single_sig.contains(&class) {
+            errors.push(format!("single-key {class:?} wallets are not supported"));
+        }
+        if multisig && !self.multisig.contains(&class) {
+            errors.push(format!("multisig {class:?} wallets are not supported"));
+        }
+        if multisig && key_count > self.max_cosigners {
+            errors.push(format!(
+                "{key_count} keys exceed maximum of {} multisig cosigners",
+                self.max_cosigners
+            ));
+        }
+        for purpose in purposes {
+            match purpose {
+                None => warnings.push(s!("key without origin information can't be verified")),
+                Some(purpose) if !self.purposes.contains(purpose) => warnings.push(format!(
+                    "non-standard derivation path purpose {purpose}h may require custom path \
+                     settings"
+                )),
+                Some(_) => {}
+            }
+        }
+        warnings.dedup();
+        SignerVerdict {
+            signer: self.name,
+            errors,
+            warnings,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_sig() {
+        for profile in SIGNER_PROFILES {
+            let verdict = profile.check(SpkClass::P2wpkh, 1, &[Some(84)]);
+            assert!(verdict.is_supported());
+            assert!(verdict.warnings.is_empty());
+        }
+        let bitbox = SIGNER_PROFILES[3].check(SpkClass::P2pkh, 1, &[Some(44)]);
+        assert!(!bitbox.is_supported());
+    }
+
+    #[test]
+    fn multisig() {
+        let verdicts = SIGNER_PROFILES
+            .iter()
+            .map(|profile| profile.check(SpkClass::P2tr, 7, &[Some(48); 7]))
+            .filter(SignerVerdict::is_supported)
+            .map(|verdict| verdict.signer)
+            .collect::<Vec<_>>();
+        assert_eq!(verdicts, vec!["Ledger"]);
+        let verdict = SIGNER_PROFILES[0].check(SpkClass::P2wsh, 2, &[Some(48), Some(1017)]);
+        assert!(verdict.is_supported());
+        assert_eq!(verdict.warnings.len(), 1);
+    }
+}
@@ -0,0 +1,339 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Detached signatures produced by signers which do not round-trip the whole PSBT.
+//!
+//! The signatures are provided in a text format, one signature per line:
+//!
+//! ```text
+//! <input no> <pubkey> <signature> [<tap leaf hash>]
+//! ```
+//!
+//! where `<input no>` is a zero-based index of the PSBT input, and the public key, signature and
+//! optional tapscript leaf hash are hex-encoded. Compressed or uncompressed public keys are used
+//! for ECDSA signatures; x-only public keys are used for BIP340 signatures, which are treated as
+//! key-path spending signatures unless the leaf hash is given. Empty lines and lines starting
+//! with `#` are ignored.
+
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+use amplify::hex::{self, FromHex};
+use bpstd::secp256k1::{Message, SECP256K1};
+use bpstd::{
+    Bip340Sig, LegacyPk, LegacySig, SigError, SighashCache, SighashError, SighashType, TapLeafHash,
+    Tx, XOnlyPk,
+};
+use psbt::Psbt;
+
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum DetachedSigError {
+    /// detached signature line '{0}' must contain input number, public key and signature.
+    Malformed(String),
+
+    /// invalid input number in detached signature. Details: {0}
+    #[from]
+    InputNo(ParseIntError),
+
+    /// invalid hex encoding in detached signature. Details: {0}
+    #[from]
+    Hex(hex::Error),
+
+    /// invalid public key length {0} in detached signature.
+    PubkeyLen(usize),
+
+    /// invalid public key in detached signature.
+    Pubkey,
+
+    /// invalid signature encoding. Details: {0}
+    #[from]
+    Sig(SigError),
+
+    /// PSBT doesn't have input #{0}.
+    NoInput(usize),
+
+    /// PSBT input #{0} is not a taproot input and can't take BIP340 signature.
+    NonTaproot(usize),
+
+    /// PSBT input #{0} misses witness script required to compute the sighash.
+    NoScriptCode(usize),
+
+    /// PSBT input #{0} misses witness UTXO required to compute the sighash; detached signatures
+    /// are supported only for segwit inputs.
+    NoWitnessUtxo(usize),
+
+    /// public key provided for input #{0} doesn't match the taproot key of the input.
+    TapKeyMismatch(usize),
+
+    /// unable to compute sighash for input #{0}. Details: {1}
+    Sighash(usize, SighashError),
+
+    /// signature for input #{0} doesn't match its sighash and public key.
+    InvalidSig(usize),
+
+    /// signature for input #{0} uses sighash type {1}, while the PSBT requires {2}.
+    SighashMismatch(usize, SighashType, SighashType),
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum DetachedSig {
+    Ecdsa {
+        input: usize,
+        pk: LegacyPk,
+        sig: LegacySig,
+    },
+    TapKey {
+        input: usize,
+        pk: XOnlyPk,
+        sig: Bip340Sig,
+    },
+    TapScript {
+        input: usize,
+        pk: XOnlyPk,
+        leaf: TapLeafHash,
+        sig: Bip340Sig,
+    },
+}
+
+impl FromStr for DetachedSig {
+    type Err = DetachedSigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split_whitespace();
+        let (Some(input), Some(pk), Some(sig)) = (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(DetachedSigError::Malformed(s.to_owned()));
+        };
+        let leaf = fields.next().map(TapLeafHash::from_str).transpose()?;
+        if fields.next().is_some() {
+            return Err(DetachedSigError::Malformed(s.to_owned()));
+        }
+
+        let input = usize::from_str(input)?;
+        let pk = Vec::<u8>::from_hex(pk)?;
+        let sig = Vec::<u8>::from_hex(sig)?;
+        Ok(match (pk.len(), leaf) {
+            (33 | 65, None) => DetachedSig::Ecdsa {
+                input,
+                pk: LegacyPk::from_bytes(&pk).map_err(|_| DetachedSigError::Pubkey)?,
+                sig: LegacySig::from_bytes(&sig)?,
+            },
+            (32, leaf) => {
+                let mut bytes = [0u8; 32];
+                bytes.copy_from_slice(&pk);
+                let pk = XOnlyPk::from_byte_array(bytes).map_err(|_| DetachedSigError::Pubkey)?;
+                let sig = Bip340Sig::from_bytes(&sig)?;
+                match leaf {
+                    None => DetachedSig::TapKey { input, pk, sig },
+                    Some(leaf) => DetachedSig::TapScript {
+                        input,
+                        pk,
+                        leaf,
+                        sig,
+                    },
+                }
+            }
+            (len, _) => return Err(DetachedSigError::PubkeyLen(len)),
+        })
+    }
+}
+
+impl DetachedSig {
+    /// Parses detached signatures from their text representation.
+    pub fn parse_list(s: &str) -> Result<Vec<Self>, DetachedSigError> {
+        s.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(DetachedSig::from_str)
+            .collect()
+    }
+
+    pub fn input(&self) -> usize {
+        match self {
+            DetachedSig::Ecdsa { input, .. }
+            | DetachedSig::TapKey { input, .. }
+            | DetachedSig::TapScript { input, .. } => *input,
+        }
+    }
+}
+
+/// Validates detached signatures against the sighashes of the PSBT inputs and adds them to the
+/// PSBT. Returns number of the added signatures. All PSBT inputs must provide their witness UTXO.
+///
+/// If any of the signatures is invalid the PSBT is left unmodified.
+pub fn apply_sigs(psbt: &mut Psbt, sigs: &[DetachedSig]) -> Result<usize, DetachedSigError> {
+    let tx = Tx::from(psbt.to_unsigned_tx());
+    let prevouts = psbt
+        .inputs()
+        .map(|input| {
+            input.witness_utxo.clone().ok_or(DetachedSigError::NoWitnessUtxo(input.index()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut sig_hasher =
+        SighashCache::new(tx, prevouts).expect("inputs and prevouts match algorithmically");
+
+    for detached in sigs {
+        let no = detached.input();
+        let input = psbt.inputs().nth(no).ok_or(DetachedSigError::NoInput(no))?;
+        let sighash_err = |err| DetachedSigError::Sighash(no, err);
+        let sig_sighash_type = match detached {
+            DetachedSig::Ecdsa { sig, .. } => sig.sighash_type,
+            DetachedSig::TapKey { sig, .. } | DetachedSig::TapScript { sig, .. } => {
+                sig.sighash_type.unwrap_or_default()
+            }
+        };
+        if let Some(required) = input.sighash_type {
+            if sig_sighash_type != required {
+                return Err(DetachedSigError::SighashMismatch(no, sig_sighash_type, required));
+            }
+        }
+        let valid = match detached {
+            DetachedSig::Ecdsa { pk, sig, .. } => {
+                let script_code = input.script_code().ok_or(DetachedSigError::NoScriptCode(no))?;
+                let sighash = sig_hasher
+                    .segwit_sighash(no, &script_code, input.value(), sig.sighash_type)
+                    .map_err(sighash_err)?;
+                SECP256K1.verify_ecdsa(&Message::from(sighash), &sig.sig, &pk.pubkey).is_ok()
+            }
+            DetachedSig::TapKey { pk, sig, .. } => {
+                let internal_pk = input.tap_internal_key.ok_or(DetachedSigError::NonTaproot(no))?;
+                let output_pk = internal_pk.to_output_pk(input.tap_merkle_root).0.to_xonly_pk();
+                if *pk != output_pk && *pk != internal_pk.to_xonly_pk() {
+                    return Err(DetachedSigError::TapKeyMismatch(no));
+                }
+                let sighash =
+                    sig_hasher.tap_sighash_key(no, sig.sighash_type).map_err(sighash_err)?;
+                let msg = <[u8; 32]>::from(sighash);
+                SECP256K1.verify_schnorr(&sig.sig, &msg, &output_pk).is_ok()
+            }
+            DetachedSig::TapScript { pk, leaf, sig, .. } => {
+                if input.tap_internal_key.is_none() {
+                    return Err(DetachedSigError::NonTaproot(no));
+                }
+                let sighash = sig_hasher
+                    .tap_sighash_script(no, *leaf, sig.sighash_type)
+                    .map_err(sighash_err)?;
+                let msg = <[u8; 32]>::from(sighash);
+                SECP256K1.verify_schnorr(&sig.sig, &msg, pk).is_ok()
+            }
+        };
+        if !valid {
+            return Err(DetachedSigError::InvalidSig(no));
+        }
+    }
+
+    for detached in sigs {
+        let input = psbt.input_mut(detached.input()).expect("checked above");
+        match detached {
+            DetachedSig::Ecdsa { pk, sig, .. } => {
+                input.partial_sigs.insert(*pk, *sig);
+            }
+            DetachedSig::TapKey { sig, .. } => input.tap_key_sig = Some(*sig),
+            DetachedSig::TapScript { pk, leaf, sig, .. } => {
+                input.tap_script_sig.insert((*pk, *leaf), *sig);
+            }
+        }
+    }
+
+    Ok(sigs.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use amplify::hex::ToHex;
+    use bpstd::secp256k1::{PublicKey, SecretKey};
+
+    use super::*;
+
+    #[test]
+    fn parse_ecdsa_sig() {
+        let sk = SecretKey::from_slice(&[1u8; 32]).unwrap();
+        let pk = LegacyPk::from(PublicKey::from_secret_key(SECP256K1, &sk));
+        let sig =
+            LegacySig::sighash_all(SECP256K1.sign_ecdsa(&Message::from_digest([2u8; 32]), &sk));
+        let line = format!("1 {} {}", pk.to_vec().to_hex(), sig.to_vec().to_hex());
+
+        let list = DetachedSig::parse_list(&format!("# comment\n\n{line}\n")).unwrap();
+        assert_eq!(list, vec![DetachedSig::Ecdsa { input: 1, pk, sig }]);
+    }
+
+    #[test]
+    fn parse_malformed() {
+        assert_eq!(DetachedSig::from_str("0 00"), Err(DetachedSigError::Malformed(s!("0 00"))));
+        assert_eq!(DetachedSig::from_str("0 0000 00"), Err(DetachedSigError::PubkeyLen(2)));
+    }
+
+    #[test]
+    #[cfg(feature = "signers")]
+    fn apply_ecdsa_sig() {
+        use bpstd::signers::TestnetRefSigner;
+        use bpstd::{
+            HardenedIndex, Idx, Keychain, NormalIndex, Outpoint, Sats, ScriptPubkey, SeqNo,
+            Terminal, Txid, Vout, XprivAccount, XpubDerivable,
+        };
+        use descriptors::{StdDescr, Wpkh};
+        use psbt::{Prevout, PsbtVer};
+
+        let derivation = [HardenedIndex::hardened(84), HardenedIndex::ONE, HardenedIndex::ZERO];
+        let account = XprivAccount::with_seed(true, &[7u8; 32]).derive(derivation);
+        let xpub = format!("{}/<0;1>/*", account.to_xpub_account());
+        let descr =
+            StdDescr::<XpubDerivable>::from(Wpkh::from(XpubDerivable::from_str(&xpub).unwrap()));
+
+        let mut psbt = Psbt::create(PsbtVer::V2);
+        let outpoint = Outpoint::new(Txid::from([1u8; 32]), Vout::from_u32(0));
+        let prevout = Prevout::new(outpoint, Sats::from(10_000u64));
+        let terminal = Terminal::new(Keychain::OUTER, NormalIndex::ZERO);
+        psbt.construct_input_expect(prevout, &descr, terminal, SeqNo::ZERO);
+        psbt.construct_output_expect(ScriptPubkey::op_return(b"test"), Sats::ZERO);
+
+        let mut signed = psbt.clone();
+        assert_eq!(signed.sign(&TestnetRefSigner::new(&account)).unwrap(), 1);
+        let (pk, sig) = signed.inputs().next().unwrap().partial_sigs.iter().next().unwrap();
+        let detached = DetachedSig::Ecdsa {
+            input: 0,
+            pk: *pk,
+            sig: *sig,
+        };
+
+        // Signature of another key doesn't validate and the PSBT is left unmodified
+        let sk = SecretKey::from_slice(&[1u8; 32]).unwrap();
+        let other = LegacyPk::from(PublicKey::from_secret_key(SECP256K1, &sk));
+        let forged = DetachedSig::Ecdsa {
+            input: 0,
+            pk: other,
+            sig: *sig,
+        };
+        assert_eq!(
+            apply_sigs(&mut psbt, &[detached.clone(), forged]),
+            Err(DetachedSigError::InvalidSig(0))
+        );
+        assert!(psbt.inputs().next().unwrap().partial_sigs.is_empty());
+
+        assert_eq!(apply_sigs(&mut psbt, std::slice::from_ref(&detached)), Ok(1));
+        assert_eq!(psbt, signed);
+
+        psbt.input_mut(0).unwrap().witness_utxo = None;
+        assert_eq!(apply_sigs(&mut psbt, &[detached]), Err(DetachedSigError::NoWitnessUtxo(0)));
+    }
+}
@@ -0,0 +1,289 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Declarative spend requests for constructing transactions from JSON files.
+
+use std::str::FromStr;
+
+use amplify::hex::FromHex;
+use bpstd::{Address, Keychain, LockTime, Sats, SeqNo};
+
+use crate::cli::{is_sane_fee_rate, Payee, PayeeError};
+
+/// Version of the spend request schema supported by the tool.
+pub const SPEND_REQUEST_VERSION: u8 = 1;
+
+#[derive(Clone, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum SpendRequestError {
+    /// unsupported spend request version {0}; the only supported version is 1.
+    UnsupportedVersion(u8),
+
+    /// field `beneficiaries[{0}]`: {1}
+    Beneficiary(usize, PayeeError),
+
+    /// field `fee` must contain exactly one of `sats`, `rate` or `target`.
+    FeeAmbiguous,
+
+    /// field `fee.rate`: fee rate {0} sats/vbyte is outside of the sane range.
+    FeeRate(f64),
+
+    /// field `fee.target`: confirmation target must be at least one block.
+    FeeTarget,
+
+    /// field `change.address`: invalid address '{0}'.
+    ChangeAddress(String),
+
+    /// field `change.address` can't be used together with `MAX` payments.
+    ChangeWithMax,
+
+    /// field `opReturn[{0}]`: invalid hex-encoded data '{1}'.
+    OpReturn(usize, String),
+
+    /// field `psbtVersion`: unsupported PSBT version {0}; only versions 0 and 2 are supported.
+    PsbtVersion(u8),
+}
+
+/// Spend request describing the transaction to construct, replacing the `construct` command
+/// options. The schema (version 1) is the following; all fields except `version`, `beneficiaries`
+/// and `fee` are optional:
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "beneficiaries": ["10000@<address>", "MAX(min=5000)@<address>"],
+///   "coins": { "strategy": "auto", "label": "<label>" },
+///   "fee": { "rate": 2.5 },
+///   "lockTime": 850000,
+///   "rbf": true,
+///   "change": { "address": "<address>", "keychain": 1 },
+///   "opReturn": ["<hex>"],
+///   "psbtVersion": 2
+/// }
+/// ```
+///
+/// - `beneficiaries` use the same syntax as `construct --to`;
+/// - `coins.strategy` is either `auto`, selecting just enough coins to fund the payments, or `all`,
+///   spending all coins; `coins.label` restricts selection to the coins bearing the label;
+/// - `fee` must contain exactly one of `sats` (absolute fee), `rate` (sats per vbyte) or `target`
+///   (number of blocks for the fee rate estimation);
+/// - `rbf` signals replaceability of the transaction (defaults to `false`);
+/// - `change.address` sends change to an external address instead of the wallet keychain given by
+///   `change.keychain` (defaults to the internal keychain);
+/// - `psbtVersion` is either `0` (default) or `2`.
+///
+/// Unknown fields are rejected to catch typos.
+#[derive(Clone, PartialEq, Debug, serde::Deserialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase", deny_unknown_fields)]
+pub struct SpendRequest {
+    pub version: u8,
+    pub beneficiaries: Vec<String>,
+    #[serde(default)]
+    pub coins: CoinPolicy,
+    pub fee: FeePolicy,
+    #[serde(default)]
+    pub lock_time: Option<u32>,
+    #[serde(default)]
+    pub rbf: bool,
+    #[serde(default)]
+    pub change: ChangePolicy,
+    #[serde(default)]
+    pub op_return: Vec<String>,
+    #[serde(default)]
+    pub psbt_version: u8,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Default, serde::Deserialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase", deny_unknown_fields)]
+pub struct CoinPolicy {
+    #[serde(default)]
+    pub strategy: CoinStrategy,
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, serde::Deserialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub enum CoinStrategy {
+    /// Select just enough coins to fund the payments and the fee.
+    #[default]
+    Auto,
+    /// Spend all (matching) wallet coins.
+    All,
+}
+
+#[derive(Clone, PartialEq, Debug, serde::Deserialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase", deny_unknown_fields)]
+pub struct FeePolicy {
+    #[serde(default)]
+    pub sats: Option<u64>,
+    #[serde(default)]
+    pub rate: Option<f64>,
+    #[serde(default)]
+    pub target: Option<u16>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Default, serde::Deserialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase", deny_unknown_fields)]
+pub struct ChangePolicy {
+    #[serde(default)]
+    pub address: Option<String>,
+    #[serde(default)]
+    pub keychain: Option<u8>,
+}
+
+/// Fee for the transaction.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SpendFee {
+    /// Absolute fee amount.
+    Sats(Sats),
+    /// Fee rate in sats per vbyte.
+    Rate(f64),
+    /// Confirmation target in blocks, used to estimate the fee rate.
+    Target(u16),
+}
+
+/// Validated spend request, mapping onto the `construct` command parameters.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SpendPlan {
+    pub payees: Vec<Payee>,
+    pub strategy: CoinStrategy,
+    pub label: Option<String>,
+    pub fee: SpendFee,
+    pub lock_time: Option<LockTime>,
+    pub seq_no: SeqNo,
+    pub change_address: Option<Address>,
+    pub change_keychain: Option<Keychain>,
+    pub op_return: Vec<Vec<u8>>,
+    pub v2: bool,
+}
+
+impl SpendRequest {
+    /// Validates the request, reporting the first invalid field.
+    pub fn validate(&self) -> Result<SpendPlan, SpendRequestError> {
+        if self.version != SPEND_REQUEST_VERSION {
+            return Err(SpendRequestError::UnsupportedVersion(self.version));
+        }
+        let payees = self
+            .beneficiaries
+            .iter()
+            .enumerate()
+            .map(|(no, s)| {
+                Payee::from_str(s).map_err(|err| SpendRequestError::Beneficiary(no, err))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let fee = match (self.fee.sats, self.fee.rate, self.fee.target) {
+            (Some(sats), None, None) => SpendFee::Sats(Sats::from(sats)),
+            (None, Some(rate), None) if is_sane_fee_rate(rate) => SpendFee::Rate(rate),
+            (None, Some(rate), None) => return Err(SpendRequestError::FeeRate(rate)),
+            (None, None, Some(0)) => return Err(SpendRequestError::FeeTarget),
+            (None, None, Some(target)) => SpendFee::Target(target),
+            _ => return Err(SpendRequestError::FeeAmbiguous),
+        };
+
+        let change_address = self
+            .change
+            .address
+            .as_deref()
+            .map(|s| {
+                Address::from_str(s).map_err(|_| SpendRequestError::ChangeAddress(s.to_owned()))
+            })
+            .transpose()?;
+        if change_address.is_some() && payees.iter().any(Payee::is_max) {
+            return Err(SpendRequestError::ChangeWithMax);
+        }
+
+        let op_return = self
+            .op_return
+            .iter()
+            .enumerate()
+            .map(|(no, s)| {
+                Vec::<u8>::from_hex(s).map_err(|_| SpendRequestError::OpReturn(no, s.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let v2 = match self.psbt_version {
+            0 => false,
+            2 => true,
+            ver => return Err(SpendRequestError::PsbtVersion(ver)),
+        };
+
+        Ok(SpendPlan {
+            payees,
+            strategy: self.coins.strategy,
+            label: self.coins.label.clone(),
+            fee,
+            lock_time: self.lock_time.map(LockTime::from_consensus_u32),
+            // Sequence numbers below 0xFFFFFFFE signal replaceability (BIP-125); otherwise the
+            // highest number which still enables lock time is used.
+            seq_no: SeqNo::from_consensus_u32(if self.rbf { 0xFFFFFFFD } else { 0xFFFFFFFE }),
+            change_address,
+            change_keychain: self.change.keychain.map(Keychain::from),
+            op_return,
+            v2,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request(json: &str) -> Result<SpendPlan, SpendRequestError> {
+        serde_json::from_str::<SpendRequest>(json).unwrap().validate()
+    }
+
+    #[test]
+    fn validate() {
+        let plan = request(
+            r#"{
+                "version": 1,
+                "beneficiaries": ["1000@bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq"],
+                "coins": { "strategy": "all", "label": "savings" },
+                "fee": { "rate": 2.5 },
+                "rbf": true,
+                "opReturn": ["68656c6c6f"],
+                "psbtVersion": 2
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(plan.payees.len(), 1);
+        assert_eq!(plan.strategy, CoinStrategy::All);
+        assert_eq!(plan.fee, SpendFee::Rate(2.5));
+        assert_eq!(plan.op_return, vec![b"hello".to_vec()]);
+        assert!(plan.v2);
+
+        assert!(matches!(
+            request(r#"{ "version": 1, "beneficiaries": ["nope"], "fee": { "sats": 100 } }"#),
+            Err(SpendRequestError::Beneficiary(0, _))
+        ));
+        assert!(matches!(
+            request(r#"{ "version": 1, "beneficiaries": [], "fee": { "sats": 1, "rate": 1.0 } }"#),
+            Err(SpendRequestError::FeeAmbiguous)
+        ));
+        assert!(serde_json::from_str::<SpendRequest>(
+            r#"{ "version": 1, "beneficiaries": [], "fee": { "sats": 1 }, "locktime": 1 }"#
+        )
+        .is_err());
+    }
+}
@@ -20,8 +20,264 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::WalletUtxo;
+use std::cmp::Ordering;
+
+use bpstd::{Outpoint, Sats, ScriptPubkey};
+use descriptors::SpkClass;
+
+use crate::{TxStatus, WalletUtxo};
 
 // TODO: Use traits and structs with internal state
 
+/// Maximal number of branches explored by [`bnb`] before it falls back to the accumulative
+/// selection.
+pub const BNB_MAX_TRIES: usize = 100_000;
+
 pub fn all(_: &WalletUtxo) -> bool { true }
+
+/// Orders coins by descending value, minimizing number of transaction inputs.
+pub fn largest_first(a: &WalletUtxo, b: &WalletUtxo) -> Ordering { b.value.cmp(&a.value) }
+
+/// Orders coins by ascending value, consolidating small coins.
+pub fn smallest_first(a: &WalletUtxo, b: &WalletUtxo) -> Ordering { a.value.cmp(&b.value) }
+
+/// Orders coins by their confirmation height, putting unconfirmed coins last.
+pub fn oldest_first(a: &WalletUtxo, b: &WalletUtxo) -> Ordering {
+    fn height(utxo: &WalletUtxo) -> Option<u32> {
+        match utxo.status {
+            TxStatus::Mined(info) => Some(info.height.get()),
+            _ => None,
+        }
+    }
+    match (height(a), height(b)) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Detects class of a script pubkey. Returns `None` for the scripts not matching any of the
+/// standard templates.
+pub fn spk_class(script: &ScriptPubkey) -> Option<SpkClass> {
+    Some(match script {
+        _ if script.is_p2tr() => SpkClass::P2tr,
+        _ if script.is_p2wpkh() => SpkClass::P2wpkh,
+        _ if script.is_p2wsh() => SpkClass::P2wsh,
+        _ if script.is_p2sh() => SpkClass::P2sh,
+        _ if script.is_p2pkh() => SpkClass::P2pkh,
+        _ => return None,
+    })
+}
+
+/// Estimates weight of a transaction input spending an output of the given class, including the
+/// outpoint, sequence number, script sig and witness.
+pub const fn input_weight(class: SpkClass) -> u32 {
+    // outpoint, sequence and script sig length
+    let base = (32 + 4 + 4 + 1) * 4;
+    match class {
+        // items count + signature + public key
+        SpkClass::P2wpkh => base + 1 + 73 + 34,
+        // items count + schnorr signature for the key path spending
+        SpkClass::P2tr => base + 1 + 65,
+        // signature and public key in the script sig
+        _ => base + (73 + 34) * 4,
+    }
+}
+
+/// Computes weight of a transaction output with a script pubkey of the given class.
+pub const fn output_weight(class: SpkClass) -> u32 {
+    let script_len = match class {
+        SpkClass::Bare => 35,
+        SpkClass::P2pkh => 25,
+        SpkClass::P2sh => 23,
+        SpkClass::P2wpkh => 22,
+        SpkClass::P2wsh | SpkClass::P2tr => 34,
+    };
+    // value, script length and the script
+    (8 + 1 + script_len) * 4
+}
+
+/// Branch-and-bound coin selection, as used by Bitcoin Core.
+///
+/// Searches for a set of coins whose effective value (the coin value minus the fee paid for
+/// spending it with `fee_rate`, in sats per vbyte, and its `input_weight`) falls within
+/// `[target, target + cost_of_change]`, so the transaction needs no change output. Among the
+/// found sets the one with the least excess over the target is used. The `target` must include
+/// the payments and the fee for all parts of the transaction except its inputs.
+///
+/// If no such set is found within [`BNB_MAX_TRIES`] branches, falls back to accumulating coins
+/// from the largest effective value until the target is reached, leaving the excess for the
+/// change. Coins which cost more to spend than their value are never selected.
+pub fn bnb(
+    coins: impl IntoIterator<Item = WalletUtxo>,
+    target: Sats,
+    fee_rate: f64,
+    input_weight: impl Fn(&WalletUtxo) -> u32,
+    cost_of_change: Sats,
+) -> impl Iterator<Item = Outpoint> {
+    let mut pool = coins
+        .into_iter()
+        .filter_map(|utxo| {
+            let input_fee = (fee_rate * input_weight(&utxo) as f64 / 4.0).ceil() as u64;
+            let value = utxo.value.sats().checked_sub(input_fee)?;
+            (value > 0).then_some((utxo.outpoint, value))
+        })
+        .collect::<Vec<_>>();
+    pool.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+    let target = target.sats();
+    let upper = target.saturating_add(cost_of_change.sats());
+    let selection = bnb_search(&pool, target, upper).unwrap_or_else(|| {
+        let mut value = 0u64;
+        pool.iter()
+            .enumerate()
+            .take_while(|(_, (_, amount))| {
+                let take = value < target;
+                value += amount;
+                take
+            })
+            .map(|(index, _)| index)
+            .collect()
+    });
+    selection.into_iter().map(move |index| pool[index].0)
+}
+
+/// Performs depth-first search over inclusion and omission branches of coins sorted by
+/// descending effective value, returning indexes of the selected coins.
+fn bnb_search(pool: &[(Outpoint, u64)], target: u64, upper: u64) -> Option<Vec<usize>> {
+    let mut available = pool.iter().map(|(_, value)| value).sum::<u64>();
+    if available < target {
+        return None;
+    }
+
+    let mut best: Option<(u64, Vec<usize>)> = None;
+    let mut selection = Vec::<usize>::new();
+    let mut value = 0u64;
+    let mut index = 0usize;
+    for _ in 0..BNB_MAX_TRIES {
+        let mut backtrack = false;
+        if value + available < target || value > upper {
+            backtrack = true;
+        } else if value >= target {
+            let excess = value - target;
+            if best.as_ref().map_or(true, |(best_excess, _)| excess < *best_excess) {
+                best = Some((excess, selection.clone()));
+            }
+            if excess == 0 {
+                break;
+            }
+            backtrack = true;
+        }
+
+        if backtrack {
+            let Some(&last) = selection.last() else {
+                // The whole tree is explored
+                break;
+            };
+            // Return omitted coins to the lookahead before exploring the omission branch of the
+            // last included coin
+            index -= 1;
+            while index > last {
+                available += pool[index].1;
+                index -= 1;
+            }
+            value -= pool[last].1;
+            selection.pop();
+        } else {
+            let amount = pool[index].1;
+            available -= amount;
+            // Including a coin of the same value as the previous omitted one produces an
+            // equivalent branch, which was already explored
+            let duplicate =
+                index > 0 && selection.last() != Some(&(index - 1)) && pool[index - 1].1 == amount;
+            if !duplicate {
+                selection.push(index);
+                value += amount;
+            }
+        }
+        index += 1;
+    }
+    best.map(|(_, selection)| selection)
+}
+
+#[cfg(test)]
+mod test {
+    use bpstd::{Idx, Keychain, NormalIndex, Terminal, Txid, Vout};
+
+    use super::*;
+    use crate::{BlockHeight, MiningInfo};
+
+    fn coins(values: &[u64]) -> Vec<WalletUtxo> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(no, value)| WalletUtxo {
+                outpoint: Outpoint::new(Txid::from([no as u8; 32]), Vout::from_u32(0)),
+                value: Sats::from(*value),
+                terminal: Terminal::new(Keychain::OUTER, NormalIndex::ZERO),
+                status: TxStatus::Mempool,
+            })
+            .collect()
+    }
+
+    fn selected(coins: &[WalletUtxo], selection: impl Iterator<Item = Outpoint>) -> Vec<u64> {
+        selection
+            .map(|outpoint| {
+                coins.iter().find(|utxo| utxo.outpoint == outpoint).unwrap().value.sats()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bnb_exact_match() {
+        let coins = coins(&[1_000, 2_000, 5_000, 7_000, 10_000]);
+        let selection = bnb(coins.clone(), Sats::from(8_000u64), 0.0, |_| 0, Sats::ZERO);
+        assert_eq!(selected(&coins, selection), vec![7_000, 1_000]);
+
+        // Effective values are reduced by 100 sats of input fee
+        let weight = input_weight(SpkClass::P2wpkh);
+        let fee_rate = 100.0 * 4.0 / weight as f64;
+        let selection = bnb(coins.clone(), Sats::from(11_800u64), fee_rate, |_| weight, Sats::ZERO);
+        assert_eq!(selected(&coins, selection), vec![10_000, 2_000]);
+    }
+
+    #[test]
+    fn bnb_fallback() {
+        let coins = coins(&[1_000, 2_000, 5_000, 7_000, 10_000]);
+        let selection = bnb(coins.clone(), Sats::from(12_500u64), 0.0, |_| 0, Sats::from(100u64));
+        assert_eq!(selected(&coins, selection), vec![10_000, 7_000]);
+
+        // Change window allows to avoid change
+        let selection = bnb(coins.clone(), Sats::from(12_500u64), 0.0, |_| 0, Sats::from(500u64));
+        assert_eq!(selected(&coins, selection), vec![10_000, 2_000, 1_000]);
+
+        let selection = bnb(coins.clone(), Sats::from(30_000u64), 0.0, |_| 0, Sats::ZERO);
+        assert_eq!(selected(&coins, selection).len(), 5);
+    }
+
+    #[test]
+    fn coin_ordering() {
+        let mut coins = coins(&[5_000, 1_000, 7_000]);
+        let mined = |height| {
+            let mut info = MiningInfo::genesis();
+            info.height = BlockHeight::new(height).unwrap();
+            TxStatus::Mined(info)
+        };
+        coins[0].status = mined(200);
+        coins[2].status = mined(100);
+
+        coins.sort_by(largest_first);
+        assert_eq!(coins.iter().map(|utxo| utxo.value.sats()).collect::<Vec<_>>(), vec![
+            7_000, 5_000, 1_000
+        ]);
+        coins.sort_by(smallest_first);
+        assert_eq!(coins.iter().map(|utxo| utxo.value.sats()).collect::<Vec<_>>(), vec![
+            1_000, 5_000, 7_000
+        ]);
+        coins.sort_by(oldest_first);
+        assert_eq!(coins.iter().map(|utxo| utxo.value.sats()).collect::<Vec<_>>(), vec![
+            7_000, 5_000, 1_000
+        ]);
+    }
+}
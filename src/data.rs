@@ -35,6 +35,9 @@ use psbt::{Prevout, Utxo};
 
 pub type BlockHeight = NonZeroU32;
 
+/// Number of confirmations required for the coinbase transaction outputs to become spendable.
+pub const COINBASE_MATURITY: u32 = 100;
+
 #[cfg_attr(
     feature = "serde",
     derive(serde::Serialize, serde::Deserialize),
@@ -183,6 +186,35 @@ impl FromStr for Inpoint {
     }
 }
 
+/// Wallet object which can be labelled: a transaction, a transaction output or an address.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display, From)]
+#[display(inner)]
+pub enum LabelTarget {
+    #[from]
+    Tx(Txid),
+    #[from]
+    Output(Outpoint),
+    #[from]
+    Address(Address),
+}
+
+/// '{0}' is neither a transaction id, a transaction output (`<txid>:<vout>`) nor an address.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub struct LabelTargetParseError(String);
+
+impl FromStr for LabelTarget {
+    type Err = LabelTargetParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Outpoint::from_str(s)
+            .map(LabelTarget::Output)
+            .or_else(|_| Txid::from_str(s).map(LabelTarget::Tx))
+            .or_else(|_| Address::from_str(s).map(LabelTarget::Address))
+            .map_err(|_| LabelTargetParseError(s.to_owned()))
+    }
+}
+
 #[cfg_attr(
     feature = "serde",
     derive(serde::Serialize, serde::Deserialize),
@@ -210,6 +242,42 @@ impl WalletTx {
         self.outputs.iter().filter(|d| d.is_external())
     }
 
+    /// Detects whether transaction signals opt-in replace-by-fee (BIP-125), i.e. has at least one
+    /// input with sequence number below `0xFFFFFFFE`.
+    pub fn signals_rbf(&self) -> bool {
+        self.inputs.iter().any(|inp| inp.sequence.to_consensus_u32() < 0xFFFF_FFFE)
+    }
+
+    /// Detects whether the transaction just moves funds between wallet addresses, i.e. spends
+    /// only wallet inputs and all of its non-fee value returns to the wallet.
+    pub fn is_self_transfer(&self) -> bool {
+        !self.inputs.is_empty()
+            && self.credits().next().is_none()
+            && self.debits().all(|out| out.value == Sats::ZERO)
+    }
+
+    /// Computes the number of confirmations of the transaction for the blockchain `tip` height.
+    /// Returns `None` if the transaction is not mined.
+    pub fn confirmations(&self, tip: u32) -> Option<u32> {
+        match self.status {
+            TxStatus::Mined(info) => Some(tip.saturating_add(1).saturating_sub(info.height.get())),
+            _ => None,
+        }
+    }
+
+    /// Detects whether the transaction is a coinbase transaction.
+    pub fn is_coinbase(&self) -> bool { self.inputs.iter().any(|inp| inp.coinbase) }
+
+    /// Detects whether the transaction is a coinbase transaction which outputs can't be spent yet
+    /// at the blockchain `tip` height, i.e. having less than [`COINBASE_MATURITY`] confirmations.
+    /// If the tip height is not known, coinbase transactions are considered immature.
+    pub fn is_immature(&self, tip: Option<u32>) -> bool {
+        self.is_coinbase()
+            && !tip
+                .and_then(|tip| self.confirmations(tip))
+                .is_some_and(|confs| confs >= COINBASE_MATURITY)
+    }
+
     pub fn total_moved(&self) -> Sats { self.inputs.iter().map(|vin| vin.value).sum::<Sats>() }
 
     pub fn credit_sum(&self) -> Sats { self.credits().map(|vin| vin.value).sum::<Sats>() }
@@ -424,6 +492,42 @@ impl WalletAddr<i64> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn label_target_from_str() {
+        let txid = "cca7507897abc89628f450e8b1e0c6fca4ec3f7b34cccf55f3f531c659ff4d79";
+        let addr = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+        assert!(matches!(LabelTarget::from_str(txid), Ok(LabelTarget::Tx(_))));
+        assert!(matches!(LabelTarget::from_str(&format!("{txid}:1")), Ok(LabelTarget::Output(_))));
+        assert!(matches!(LabelTarget::from_str(addr), Ok(LabelTarget::Address(_))));
+        assert!(LabelTarget::from_str("salary").is_err());
+        for s in [txid, addr] {
+            assert_eq!(LabelTarget::from_str(s).unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn confirmations() {
+        let mut tx = WalletTx {
+            txid: Txid::from([1u8; 32]),
+            status: TxStatus::Mempool,
+            inputs: vec![],
+            outputs: vec![],
+            fee: Sats::ZERO,
+            size: 0,
+            weight: 0,
+            version: TxVer::V2,
+            locktime: LockTime::ZERO,
+        };
+        assert_eq!(tx.confirmations(100), None);
+        tx.status = TxStatus::Mined(MiningInfo {
+            height: BlockHeight::new(100).unwrap(),
+            ..MiningInfo::genesis()
+        });
+        assert_eq!(tx.confirmations(100), Some(1));
+        assert_eq!(tx.confirmations(105), Some(6));
+        assert_eq!(tx.confirmations(99), Some(0));
+    }
+
     #[test]
     fn test_inpoint_str_round_trip() {
         let s = "cca7507897abc89628f450e8b1e0c6fca4ec3f7b34cccf55f3f531c659ff4d79.1";
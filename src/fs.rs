@@ -20,23 +20,29 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::path::PathBuf;
-use std::{fs, io};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use std::{cmp, fs, io, thread};
 
 use descriptors::Descriptor;
-use nonasync::persistence::{PersistenceError, PersistenceProvider};
+use nonasync::persistence::{CloneNoPersistence, PersistenceError, PersistenceProvider};
 
 use super::*;
 use crate::{
     Layer2Cache, Layer2Data, Layer2Descriptor, NoLayer2, WalletCache, WalletData, WalletDescr,
 };
 
+/// Default [`crate::WalletStore`] backend keeping wallet components in text files inside a
+/// wallet directory.
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct FsTextStore {
     pub descr: PathBuf,
     pub data: PathBuf,
     pub cache: PathBuf,
     pub l2: PathBuf,
+    pub lock: PathBuf,
 }
 
 impl FsTextStore {
@@ -49,16 +55,109 @@ impl FsTextStore {
         data.push("data.toml");
         let mut cache = path.clone();
         cache.push("cache.yaml");
-        let mut l2 = path;
+        let mut l2 = path.clone();
         l2.push("layer2.yaml");
+        let mut lock = path;
+        lock.push("wallet.lock");
 
         Ok(Self {
             descr,
             data,
             cache,
             l2,
+            lock,
         })
     }
+
+    /// Acquires an exclusive lock on the wallet directory, waiting up to [`FsLock::TIMEOUT`] for
+    /// other processes holding it. The lock is released once the returned guard is dropped.
+    ///
+    /// A lock left by a crashed process is detected as stale and removed: either if the process
+    /// which has acquired it is not running anymore (detected on Linux only), or if the lock is
+    /// held for longer than [`FsLock::TIMEOUT`].
+    pub fn lock(&self) -> io::Result<FsLock> {
+        let start = Instant::now();
+        loop {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&self.lock) {
+                Ok(mut file) => {
+                    let guard = FsLock(self.lock.clone());
+                    write!(file, "{}", std::process::id())?;
+                    return Ok(guard);
+                }
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    if self.is_lock_stale() {
+                        #[cfg(feature = "log")]
+                        log::warn!("Removing stale wallet lock '{}'", self.lock.display());
+                        match fs::remove_file(&self.lock) {
+                            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                            _ => continue,
+                        }
+                    }
+                    if start.elapsed() >= FsLock::TIMEOUT {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            format!(
+                                "wallet is locked by another process; if no other process is \
+                                 running, remove '{}' manually",
+                                self.lock.display()
+                            ),
+                        ));
+                    }
+                    thread::sleep(FsLock::RETRY);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn is_lock_stale(&self) -> bool {
+        let Ok(meta) = fs::metadata(&self.lock) else {
+            return false;
+        };
+        let age = meta.modified().ok().and_then(|time| time.elapsed().ok());
+        if age.is_some_and(|age| age >= FsLock::TIMEOUT) {
+            return true;
+        }
+        #[cfg(target_os = "linux")]
+        if let Ok(pid) = fs::read_to_string(&self.lock) {
+            // The file may be just created and not yet contain the process id
+            if let Ok(pid) = pid.trim().parse::<u32>() {
+                return !Path::new("/proc").join(pid.to_string()).exists();
+            }
+        }
+        false
+    }
+}
+
+/// Writes the file through a temporary one, such that concurrent readers never observe it
+/// partially written.
+fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let no = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".{}.{no}.tmp", std::process::id()));
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
+/// Guard holding an exclusive lock on a wallet directory, acquired with [`FsTextStore::lock`].
+#[derive(Debug)]
+pub struct FsLock(PathBuf);
+
+impl FsLock {
+    pub const TIMEOUT: Duration = Duration::from_secs(10);
+    const RETRY: Duration = Duration::from_millis(5);
+}
+
+impl Drop for FsLock {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.0) {
+            #[cfg(feature = "log")]
+            log::error!("Unable to release wallet lock '{}'. Details: {e}", self.0.display());
+            #[cfg(not(feature = "log"))]
+            eprintln!("Unable to release wallet lock '{}'. Details: {e}", self.0.display());
+        }
+    }
 }
 
 impl<K, D: Descriptor<K>, L2: Layer2Descriptor> PersistenceProvider<WalletDescr<K, D, L2>>
@@ -75,7 +174,7 @@ where
 
     fn store(&self, object: &WalletDescr<K, D, L2>) -> Result<(), PersistenceError> {
         let s = toml::to_string_pretty(object).map_err(PersistenceError::with)?;
-        fs::write(&self.descr, s).map_err(PersistenceError::with)?;
+        write_atomic(&self.descr, s).map_err(PersistenceError::with)?;
         Ok(())
     }
 }
@@ -108,8 +207,28 @@ where
     }
 
     fn store(&self, object: &WalletData<L2>) -> Result<(), PersistenceError> {
-        let s = toml::to_string_pretty(object).map_err(PersistenceError::with)?;
-        fs::write(&self.data, s).map_err(PersistenceError::with)?;
+        // Derivation indexes reserved by other processes after the data were loaded must not be
+        // rolled back
+        let stored = fs::read_to_string(&self.data)
+            .ok()
+            .and_then(|data| toml::from_str::<WalletData<L2>>(&data).ok())
+            .map(|stored| stored.last_used.clone())
+            .unwrap_or_default();
+        let stale = stored.iter().any(|(keychain, index)| {
+            object.last_used.get(keychain).map_or(true, |last_used| last_used < index)
+        });
+        let s = if stale {
+            let mut object = object.clone_no_persistence();
+            for (keychain, index) in stored {
+                let last_used = object.last_used.entry(keychain).or_default();
+                *last_used = cmp::max(*last_used, index);
+            }
+            toml::to_string_pretty(&object)
+        } else {
+            toml::to_string_pretty(object)
+        }
+        .map_err(PersistenceError::with)?;
+        write_atomic(&self.data, s).map_err(PersistenceError::with)?;
         Ok(())
     }
 }
@@ -125,3 +244,86 @@ impl PersistenceProvider<NoLayer2> for FsTextStore {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::str::FromStr;
+
+    use bpstd::{Keychain, Network, NormalIndex, StdDescr, Txid, Wpkh, XpubDerivable};
+
+    use super::*;
+    use crate::{LabelTarget, Layer2Empty, Wallet};
+
+    const XPUB: &str = "[643a7adc/84h/1h/0h]tpubDCNiWHaiSkgnQjuhsg9kjwaUzaxQjUcmhagvYzqQ3TYJTgFGJstVaqnu4yhtFktBhCVFmBNLQ5sN53qKzZbMksm3XEyGJsEhQPfVZdWmTE2/<0;1>/*";
+
+    #[test]
+    fn concurrent_index_reservation() {
+        const RESERVATIONS: usize = 20;
+
+        let path = std::env::temp_dir().join(format!("bp-wallet-lock-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        let xpub = XpubDerivable::from_str(XPUB).unwrap();
+        let descr = StdDescr::<XpubDerivable>::from(Wpkh::from(xpub));
+        let mut wallet = Wallet::<_, StdDescr>::new_layer1(descr, Network::Testnet3);
+        wallet.make_persistent(FsTextStore::new(path.clone()).unwrap(), true).unwrap();
+        drop(wallet);
+        // Wallet loaded before the reservations and saved after them
+        let mut stale =
+            Wallet::<XpubDerivable, StdDescr>::load(FsTextStore::new(path.clone()).unwrap(), true)
+                .unwrap();
+
+        let workers = (0..2)
+            .map(|_| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    let store = FsTextStore::new(path).unwrap();
+                    let mut wallet =
+                        Wallet::<XpubDerivable, StdDescr>::load(store.clone(), true).unwrap();
+                    (0..RESERVATIONS)
+                        .map(|_| {
+                            let _lock = store.lock().unwrap();
+                            wallet.reserve_next_index(Keychain::OUTER).unwrap()
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        let indexes =
+            workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect::<BTreeSet<_>>();
+        stale.set_label(LabelTarget::Tx(Txid::from([1u8; 32])), Some(s!("stale")));
+        drop(stale);
+        let store = FsTextStore::new(path.clone()).unwrap();
+        let data: WalletData<Layer2Empty> = store.load().unwrap();
+        let last_used = data.last_used.get(&Keychain::OUTER).copied();
+        drop(data);
+        fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(indexes.len(), RESERVATIONS * 2);
+        assert_eq!(last_used, Some(NormalIndex::from(RESERVATIONS as u16 * 2)));
+    }
+
+    #[test]
+    fn stale_lock_recovery() {
+        let path = std::env::temp_dir().join(format!("bp-wallet-stale-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        let store = FsTextStore::new(path.clone()).unwrap();
+
+        let lock = store.lock().unwrap();
+        assert_eq!(fs::read_to_string(&store.lock).unwrap(), std::process::id().to_string());
+        assert!(!store.is_lock_stale());
+        drop(lock);
+        assert!(!store.lock.exists());
+
+        // Lock left by a crashed process long ago
+        let file = fs::File::create(&store.lock).unwrap();
+        let time = std::time::SystemTime::now() - FsLock::TIMEOUT * 2;
+        file.set_modified(time).unwrap();
+        drop(file);
+        let start = Instant::now();
+        drop(store.lock().unwrap());
+        assert!(start.elapsed() < FsLock::TIMEOUT);
+
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -172,8 +172,7 @@ fn get_password(
             match env::var(varname) {
                 Ok(password) => return Ok(password),
                 Err(VarError::NotUnicode(_)) => {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::Other,
+                    return Err(std::io::Error::other(
                         "password set by environment is not a valid unicode string",
                     ));
                 }
@@ -190,7 +189,7 @@ fn get_password(
         if !accept_weak && (password.is_empty() || entropy < 64.0) {
             eprintln!("Entropy is too low, please try with a different password");
             if password_envvar.is_some() {
-                return Err(std::io::Error::new(std::io::ErrorKind::Other, "low password entropy"));
+                return Err(std::io::Error::other("low password entropy"));
             } else {
                 continue;
             }
@@ -311,22 +310,38 @@ fn derive(
     Ok(())
 }
 
-fn sign(psbt_file: &Path, account_file: &Path, no_password: bool) -> Result<(), DataError> {
-    eprintln!("Signing {} with {}", psbt_file.display(), account_file.display());
+/// Signs PSBT with the keys of the signing account stored in `account_file`, asking for the
+/// account password unless `no_password` is set. Returns the number of added signatures.
+pub fn sign_psbt(
+    psbt: &mut Psbt,
+    account_file: &Path,
+    no_password: bool,
+) -> Result<usize, DataError> {
     let password = if no_password { s!("") } else { rpassword::prompt_password("Password: ")? };
     let account = XprivAccount::read(account_file, &password)?;
+    sign_psbt_with(psbt, &account)
+}
 
+/// Signs all PSBT inputs which keys can be derived from the `account` according to the key origins
+/// present in the PSBT. Returns the number of created signatures.
+pub fn sign_psbt_with(psbt: &mut Psbt, account: &XprivAccount) -> Result<usize, DataError> {
     eprintln!("Signing key: {}", account.to_xpub_account());
     eprintln!("Signing using testnet signer");
 
+    let signer = TestnetRefSigner::new(account);
+    Ok(psbt.sign(&signer)?)
+}
+
+fn sign(psbt_file: &Path, account_file: &Path, no_password: bool) -> Result<(), DataError> {
+    eprintln!("Signing {} with {}", psbt_file.display(), account_file.display());
+
     let data = fs::read(psbt_file)?;
     let mut psbt = Psbt::deserialize(&data)?;
 
     eprintln!("PSBT version: {:#}", psbt.version);
     eprintln!("Transaction id: {}", psbt.txid());
 
-    let signer = TestnetRefSigner::new(&account);
-    let sig_count = psbt.sign(&signer)?;
+    let sig_count = sign_psbt(&mut psbt, account_file, no_password)?;
 
     fs::write(psbt_file, psbt.serialize(psbt.version))?;
     eprintln!(
@@ -28,7 +28,7 @@ pub mod signer;
 mod password;
 
 #[cfg(feature = "cli")]
-pub use command::{HotArgs, HotCommand};
+pub use command::{sign_psbt, sign_psbt_with, HotArgs, HotCommand};
 pub use io::{decrypt, encrypt, DataError, SecureIo};
 pub use password::calculate_entropy;
 pub use seed::{Seed, SeedType};
@@ -78,6 +78,12 @@ mod io {
         #[display("invalid account key password.")]
         AccountPassword,
 
+        #[display(
+            "environment variable {0} is not set or doesn't contain a valid mnemonic or extended \
+             private key."
+        )]
+        SignerEnv(String),
+
         #[from]
         Psbt(PsbtError),
 
@@ -78,6 +78,15 @@ impl Seed {
         Seed(Box::from(entropy))
     }
 
+    /// Constructs seed from BIP-39 mnemonic protected with an optional passphrase (empty if
+    /// not used) in the same way as the other BIP-39 wallets do. Unlike [`Seed::from_str`], which
+    /// reads mnemonics of the seeds created by this crate, keys are derived from the BIP-39 seed
+    /// and not from the mnemonic entropy.
+    pub fn with_mnemonic(mnemonic: &str, passphrase: &str) -> Result<Seed, bip39::Error> {
+        let mnemonic = Mnemonic::from_str(mnemonic)?;
+        Ok(Seed(Box::from(mnemonic.to_seed(passphrase))))
+    }
+
     #[inline]
     pub fn as_entropy(&self) -> &[u8] { &self.0 }
 
@@ -97,14 +106,23 @@ impl Seed {
     }
 }
 
+impl FromStr for Seed {
+    type Err = bip39::Error;
+
+    /// Parses seed from its BIP-39 mnemonic, as written by [`SecureIo::write`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mnemonic = Mnemonic::from_str(s)?;
+        Ok(Seed(Box::from(mnemonic.to_entropy())))
+    }
+}
+
 impl SecureIo for Seed {
     fn read<P>(file: P, password: &str) -> Result<Self, DataError>
     where P: AsRef<Path> {
         let data = fs::read(file)?;
         let data = decrypt(&data, password).map_err(|_| DataError::SeedPassword)?;
         let s = String::from_utf8(data).map_err(|_| DataError::SeedPassword)?;
-        let mnemonic = Mnemonic::from_str(&s).map_err(|_| DataError::SeedPassword)?;
-        Ok(Seed(Box::from(mnemonic.to_entropy())))
+        Seed::from_str(&s).map_err(|_| DataError::SeedPassword)
     }
 
     fn write<P>(&self, file: P, password: &str) -> io::Result<()>
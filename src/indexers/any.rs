@@ -19,10 +19,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use bpstd::Tx;
+use std::collections::BTreeMap;
+
+use bpstd::{BlockHash, Tx, Txid};
 use descriptors::Descriptor;
 
-use crate::{Indexer, Layer2, MayError, WalletCache, WalletDescr};
+use crate::{
+    BroadcastError, Indexer, IndexerError, Layer2, MayError, TxStatus, UnsupportedRequest,
+    WalletCache, WalletDescr,
+};
 
 /// Type that contains any of the client types implementing the Indexer trait
 #[derive(From)]
@@ -32,6 +37,10 @@ pub enum AnyIndexer {
     #[from]
     /// Electrum indexer
     Electrum(Box<electrum::client::Client>),
+    #[cfg(feature = "electrum")]
+    #[from]
+    /// Electrum indexer connected to a server with a pinned TLS certificate
+    ElectrumPinned(Box<super::electrum::PinnedClient>),
     #[cfg(feature = "esplora")]
     #[from]
     /// Esplora indexer
@@ -39,6 +48,10 @@ pub enum AnyIndexer {
     #[cfg(feature = "mempool")]
     /// Mempool indexer
     Mempool(Box<super::esplora::Client>),
+    #[cfg(feature = "bitcoind")]
+    #[from]
+    /// Bitcoin Core RPC indexer
+    Bitcoind(Box<super::bitcoind::Client>),
 }
 
 impl AnyIndexer {
@@ -46,10 +59,14 @@ impl AnyIndexer {
         match self {
             #[cfg(feature = "electrum")]
             AnyIndexer::Electrum(_) => "electrum",
+            #[cfg(feature = "electrum")]
+            AnyIndexer::ElectrumPinned(_) => "electrum",
             #[cfg(feature = "esplora")]
             AnyIndexer::Esplora(_) => "esplora",
             #[cfg(feature = "mempool")]
             AnyIndexer::Mempool(_) => "mempool",
+            #[cfg(feature = "bitcoind")]
+            AnyIndexer::Bitcoind(_) => "bitcoind",
         }
     }
 }
@@ -66,7 +83,18 @@ pub enum AnyIndexerError {
     #[cfg(feature = "esplora")]
     #[display(inner)]
     #[from]
-    Esplora(esplora::Error),
+    #[from(esplora::Error)]
+    Esplora(super::esplora::EsploraError),
+    #[cfg(feature = "bitcoind")]
+    #[display(inner)]
+    #[from]
+    Bitcoind(super::bitcoind::BitcoindError),
+    #[display(inner)]
+    #[from]
+    Broadcast(BroadcastError),
+    #[display(inner)]
+    #[from]
+    Unsupported(UnsupportedRequest),
 }
 
 impl Indexer for AnyIndexer {
@@ -75,14 +103,26 @@ impl Indexer for AnyIndexer {
     fn create<K, D: Descriptor<K>, L2: Layer2>(
         &self,
         descr: &WalletDescr<K, D, L2::Descr>,
-    ) -> MayError<WalletCache<L2::Cache>, Vec<Self::Error>> {
+    ) -> MayError<WalletCache<L2::Cache>, Vec<IndexerError<Self::Error>>> {
         match self {
             #[cfg(feature = "electrum")]
             AnyIndexer::Electrum(inner) => {
                 let result = inner.create::<K, D, L2>(descr);
                 MayError {
                     ok: result.ok,
-                    err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
+                    err: result
+                        .err
+                        .map(|v| v.into_iter().map(|e| e.map_source(Into::into)).collect()),
+                }
+            }
+            #[cfg(feature = "electrum")]
+            AnyIndexer::ElectrumPinned(inner) => {
+                let result = inner.create::<K, D, L2>(descr);
+                MayError {
+                    ok: result.ok,
+                    err: result
+                        .err
+                        .map(|v| v.into_iter().map(|e| e.map_source(Into::into)).collect()),
                 }
             }
             #[cfg(feature = "esplora")]
@@ -90,7 +130,9 @@ impl Indexer for AnyIndexer {
                 let result = inner.create::<K, D, L2>(descr);
                 MayError {
                     ok: result.ok,
-                    err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
+                    err: result
+                        .err
+                        .map(|v| v.into_iter().map(|e| e.map_source(Into::into)).collect()),
                 }
             }
             #[cfg(feature = "mempool")]
@@ -98,7 +140,19 @@ impl Indexer for AnyIndexer {
                 let result = inner.create::<K, D, L2>(descr);
                 MayError {
                     ok: result.ok,
-                    err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
+                    err: result
+                        .err
+                        .map(|v| v.into_iter().map(|e| e.map_source(Into::into)).collect()),
+                }
+            }
+            #[cfg(feature = "bitcoind")]
+            AnyIndexer::Bitcoind(inner) => {
+                let result = inner.create::<K, D, L2>(descr);
+                MayError {
+                    ok: result.ok,
+                    err: result
+                        .err
+                        .map(|v| v.into_iter().map(|e| e.map_source(Into::into)).collect()),
                 }
             }
         }
@@ -108,14 +162,26 @@ impl Indexer for AnyIndexer {
         &self,
         descr: &WalletDescr<K, D, L2::Descr>,
         cache: &mut WalletCache<L2::Cache>,
-    ) -> MayError<usize, Vec<Self::Error>> {
+    ) -> MayError<usize, Vec<IndexerError<Self::Error>>> {
         match self {
             #[cfg(feature = "electrum")]
             AnyIndexer::Electrum(inner) => {
                 let result = inner.update::<K, D, L2>(descr, cache);
                 MayError {
                     ok: result.ok,
-                    err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
+                    err: result
+                        .err
+                        .map(|v| v.into_iter().map(|e| e.map_source(Into::into)).collect()),
+                }
+            }
+            #[cfg(feature = "electrum")]
+            AnyIndexer::ElectrumPinned(inner) => {
+                let result = inner.update::<K, D, L2>(descr, cache);
+                MayError {
+                    ok: result.ok,
+                    err: result
+                        .err
+                        .map(|v| v.into_iter().map(|e| e.map_source(Into::into)).collect()),
                 }
             }
             #[cfg(feature = "esplora")]
@@ -123,7 +189,9 @@ impl Indexer for AnyIndexer {
                 let result = inner.update::<K, D, L2>(descr, cache);
                 MayError {
                     ok: result.ok,
-                    err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
+                    err: result
+                        .err
+                        .map(|v| v.into_iter().map(|e| e.map_source(Into::into)).collect()),
                 }
             }
             #[cfg(feature = "mempool")]
@@ -131,20 +199,156 @@ impl Indexer for AnyIndexer {
                 let result = inner.update::<K, D, L2>(descr, cache);
                 MayError {
                     ok: result.ok,
-                    err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
+                    err: result
+                        .err
+                        .map(|v| v.into_iter().map(|e| e.map_source(Into::into)).collect()),
                 }
             }
+            #[cfg(feature = "bitcoind")]
+            AnyIndexer::Bitcoind(inner) => {
+                let result = inner.update::<K, D, L2>(descr, cache);
+                MayError {
+                    ok: result.ok,
+                    err: result
+                        .err
+                        .map(|v| v.into_iter().map(|e| e.map_source(Into::into)).collect()),
+                }
+            }
+        }
+    }
+
+    fn publish(&self, tx: &Tx) -> Result<Txid, Self::Error> {
+        match self {
+            #[cfg(feature = "electrum")]
+            AnyIndexer::Electrum(inner) => inner.publish(tx).map_err(|e| match e {
+                super::electrum::ElectrumError::Broadcast(e) => e.into(),
+                e => e.into(),
+            }),
+            #[cfg(feature = "electrum")]
+            AnyIndexer::ElectrumPinned(inner) => inner.publish(tx).map_err(|e| match e {
+                super::electrum::ElectrumError::Broadcast(e) => e.into(),
+                e => e.into(),
+            }),
+            #[cfg(feature = "esplora")]
+            AnyIndexer::Esplora(inner) => inner.publish(tx).map_err(|e| match e {
+                super::esplora::EsploraError::Broadcast(e) => e.into(),
+                e => e.into(),
+            }),
+            #[cfg(feature = "mempool")]
+            AnyIndexer::Mempool(inner) => inner.publish(tx).map_err(|e| match e {
+                super::esplora::EsploraError::Broadcast(e) => e.into(),
+                e => e.into(),
+            }),
+            #[cfg(feature = "bitcoind")]
+            AnyIndexer::Bitcoind(inner) => inner.publish(tx).map_err(|e| match e {
+                super::bitcoind::BitcoindError::Broadcast(e) => e.into(),
+                e => e.into(),
+            }),
+        }
+    }
+
+    fn status(&self, txid: Txid) -> Result<TxStatus, Self::Error> {
+        match self {
+            #[cfg(feature = "electrum")]
+            AnyIndexer::Electrum(inner) => inner.status(txid).map_err(|e| e.into()),
+            #[cfg(feature = "electrum")]
+            AnyIndexer::ElectrumPinned(inner) => inner.status(txid).map_err(|e| e.into()),
+            #[cfg(feature = "esplora")]
+            AnyIndexer::Esplora(inner) => inner.status(txid).map_err(|e| e.into()),
+            #[cfg(feature = "mempool")]
+            AnyIndexer::Mempool(inner) => inner.status(txid).map_err(|e| e.into()),
+            #[cfg(feature = "bitcoind")]
+            AnyIndexer::Bitcoind(inner) => inner.status(txid).map_err(|e| e.into()),
+        }
+    }
+
+    fn fee_rate(&self, target: u16) -> Result<Option<f64>, Self::Error> {
+        match self {
+            #[cfg(feature = "electrum")]
+            AnyIndexer::Electrum(inner) => inner.fee_rate(target).map_err(|e| e.into()),
+            #[cfg(feature = "electrum")]
+            AnyIndexer::ElectrumPinned(inner) => inner.fee_rate(target).map_err(|e| e.into()),
+            #[cfg(feature = "esplora")]
+            AnyIndexer::Esplora(inner) => inner.fee_rate(target).map_err(|e| e.into()),
+            #[cfg(feature = "mempool")]
+            AnyIndexer::Mempool(inner) => inner.fee_rate(target).map_err(|e| e.into()),
+            #[cfg(feature = "bitcoind")]
+            AnyIndexer::Bitcoind(inner) => inner.fee_rate(target).map_err(|e| e.into()),
         }
     }
 
-    fn publish(&self, tx: &Tx) -> Result<(), Self::Error> {
+    fn fee_estimates(&self, targets: &[u16]) -> Result<BTreeMap<u16, f64>, Self::Error> {
         match self {
             #[cfg(feature = "electrum")]
-            AnyIndexer::Electrum(inner) => inner.publish(tx).map_err(|e| e.into()),
+            AnyIndexer::Electrum(inner) => inner.fee_estimates(targets).map_err(|e| e.into()),
+            #[cfg(feature = "electrum")]
+            AnyIndexer::ElectrumPinned(inner) => inner.fee_estimates(targets).map_err(|e| e.into()),
+            #[cfg(feature = "esplora")]
+            AnyIndexer::Esplora(inner) => inner.fee_estimates(targets).map_err(|e| e.into()),
+            #[cfg(feature = "mempool")]
+            AnyIndexer::Mempool(inner) => inner.fee_estimates(targets).map_err(|e| e.into()),
+            #[cfg(feature = "bitcoind")]
+            AnyIndexer::Bitcoind(inner) => inner.fee_estimates(targets).map_err(|e| e.into()),
+        }
+    }
+
+    fn mempool_histogram(&self) -> Result<Option<Vec<(f64, u64)>>, Self::Error> {
+        match self {
+            #[cfg(feature = "electrum")]
+            AnyIndexer::Electrum(inner) => inner.mempool_histogram().map_err(|e| e.into()),
+            #[cfg(feature = "electrum")]
+            AnyIndexer::ElectrumPinned(inner) => inner.mempool_histogram().map_err(|e| e.into()),
+            #[cfg(feature = "esplora")]
+            AnyIndexer::Esplora(inner) => inner.mempool_histogram().map_err(|e| e.into()),
+            #[cfg(feature = "mempool")]
+            AnyIndexer::Mempool(inner) => inner.mempool_histogram().map_err(|e| e.into()),
+            #[cfg(feature = "bitcoind")]
+            AnyIndexer::Bitcoind(inner) => inner.mempool_histogram().map_err(|e| e.into()),
+        }
+    }
+
+    fn tip_height(&self) -> Result<Option<u32>, Self::Error> {
+        match self {
+            #[cfg(feature = "electrum")]
+            AnyIndexer::Electrum(inner) => inner.tip_height().map_err(|e| e.into()),
+            #[cfg(feature = "electrum")]
+            AnyIndexer::ElectrumPinned(inner) => inner.tip_height().map_err(|e| e.into()),
+            #[cfg(feature = "esplora")]
+            AnyIndexer::Esplora(inner) => inner.tip_height().map_err(|e| e.into()),
+            #[cfg(feature = "mempool")]
+            AnyIndexer::Mempool(inner) => inner.tip_height().map_err(|e| e.into()),
+            #[cfg(feature = "bitcoind")]
+            AnyIndexer::Bitcoind(inner) => inner.tip_height().map_err(|e| e.into()),
+        }
+    }
+
+    fn chain_tip(&self) -> Result<Option<(u32, BlockHash)>, Self::Error> {
+        match self {
+            #[cfg(feature = "electrum")]
+            AnyIndexer::Electrum(inner) => inner.chain_tip().map_err(|e| e.into()),
+            #[cfg(feature = "electrum")]
+            AnyIndexer::ElectrumPinned(inner) => inner.chain_tip().map_err(|e| e.into()),
+            #[cfg(feature = "esplora")]
+            AnyIndexer::Esplora(inner) => inner.chain_tip().map_err(|e| e.into()),
+            #[cfg(feature = "mempool")]
+            AnyIndexer::Mempool(inner) => inner.chain_tip().map_err(|e| e.into()),
+            #[cfg(feature = "bitcoind")]
+            AnyIndexer::Bitcoind(inner) => inner.chain_tip().map_err(|e| e.into()),
+        }
+    }
+
+    fn raw_tx(&self, txid: Txid) -> Result<Option<Tx>, Self::Error> {
+        match self {
+            #[cfg(feature = "electrum")]
+            AnyIndexer::Electrum(inner) => inner.raw_tx(txid).map_err(|e| e.into()),
+            #[cfg(feature = "electrum")]
+            AnyIndexer::ElectrumPinned(inner) => inner.raw_tx(txid).map_err(|e| e.into()),
             #[cfg(feature = "esplora")]
-            AnyIndexer::Esplora(inner) => inner.publish(tx).map_err(|e| e.into()),
+            AnyIndexer::Esplora(inner) => inner.raw_tx(txid).map_err(|e| e.into()),
             #[cfg(feature = "mempool")]
-            AnyIndexer::Mempool(inner) => inner.publish(tx).map_err(|e| e.into()),
+            AnyIndexer::Mempool(inner) => inner.raw_tx(txid).map_err(|e| e.into()),
+            #[cfg(feature = "bitcoind")]
+            AnyIndexer::Bitcoind(inner) => inner.raw_tx(txid).map_err(|e| e.into()),
         }
     }
 }
@@ -19,22 +19,148 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::BTreeMap;
+use std::net::TcpStream;
 use std::num::NonZeroU32;
 use std::str::FromStr;
+use std::sync::Arc;
 
-use bpstd::{Address, BlockHash, ConsensusEncode, Outpoint, Sats, Tx, TxIn, Txid, Weight};
+use bpstd::{BlockHash, ConsensusEncode, Outpoint, Sats, Tx, TxIn, Txid, Weight};
 use descriptors::Descriptor;
+use electrum::raw_client::{ElectrumSslStream, RawClient};
+use electrum::socks::Socks5Stream;
 use electrum::{Client, ElectrumApi, GetHistoryRes, Param};
 pub use electrum::{Config, ConfigBuilder, Error, Socks5Config};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{
+    aws_lc_rs, verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms,
+};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{
+    CertificateError, ClientConfig, ClientConnection, DigitallySignedStruct, SignatureScheme,
+    StreamOwned,
+};
 use serde_json::Value;
+use sha2::{Digest, Sha256};
 
-use super::BATCH_SIZE;
+use super::{
+    index_address, mined_txids, process_inputs, process_outputs, rollback_reorgs, AddressIndex,
+    BroadcastError, Checkpoint, UnsupportedRequest,
+};
 use crate::{
-    Indexer, Layer2, MayError, MiningInfo, Party, TxCredit, TxDebit, TxStatus, WalletAddr,
+    Indexer, IndexerError, Layer2, MayError, MiningInfo, Party, TxCredit, TxDebit, TxStatus,
     WalletCache, WalletDescr, WalletTx,
 };
 
+/// Electrum client connected over TLS to a server whose certificate is pinned by its
+/// fingerprint. Unlike [`Client`], it doesn't reconnect once the connection drops.
+pub type PinnedClient = RawClient<ElectrumSslStream>;
+
+/// Electrum client types which can be used as an [`Indexer`].
+pub trait ElectrumClient: ElectrumApi {}
+
+impl ElectrumClient for Client {}
+impl ElectrumClient for PinnedClient {}
+
+/// Connection options for the Electrum client.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct ConnectionOpts {
+    /// SOCKS5 proxy (`<host>:<port>`) to route the connection through, for instance a Tor
+    /// daemon for `.onion` servers.
+    pub socks5: Option<String>,
+}
+
+/// Connects to the Electrum server at `url`, which may use either `tcp://` (the default) or
+/// `ssl://` scheme, applying the connection options.
+pub fn connect(url: &str, opts: &ConnectionOpts) -> Result<Client, Error> {
+    let config = ConfigBuilder::new().socks5(opts.socks5.as_deref().map(Socks5Config::new)).build();
+    Client::from_config(url, config)
+}
+
+/// Connects to the `ssl://` Electrum server at `url`, accepting only the server certificate
+/// with the given SHA-256 fingerprint of its DER encoding. The certificate authority and the
+/// domain name of the certificate are not checked, so this works with self-signed certificates.
+pub fn connect_pinned(
+    url: &str,
+    fingerprint: [u8; 32],
+    opts: &ConnectionOpts,
+) -> Result<PinnedClient, Error> {
+    let Some(addr) = url.strip_prefix("ssl://") else {
+        return Err(Error::Message(format!(
+            "certificate pinning requires ssl:// server, not {url}"
+        )));
+    };
+    let domain = addr.rsplit_once(':').map(|(host, _)| host).unwrap_or(addr);
+    let server_name = ServerName::try_from(domain.to_owned())
+        .map_err(|_| Error::InvalidDNSNameError(domain.to_owned()))?;
+
+    let provider = aws_lc_rs::default_provider();
+    let verifier = PinnedCert {
+        fingerprint,
+        algorithms: provider.signature_verification_algorithms,
+    };
+    let config = ClientConfig::builder_with_provider(Arc::new(provider))
+        .with_safe_default_protocol_versions()
+        .map_err(Error::CouldNotCreateConnection)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    let session = ClientConnection::new(Arc::new(config), server_name)
+        .map_err(Error::CouldNotCreateConnection)?;
+
+    let stream = match &opts.socks5 {
+        Some(proxy) => Socks5Stream::connect(proxy.as_str(), addr, None)?.into_inner(),
+        None => TcpStream::connect(addr)?,
+    };
+    Ok(RawClient::from(StreamOwned::new(session, stream)))
+}
+
+/// Server certificate verifier accepting only a certificate with a specific fingerprint.
+#[derive(Debug)]
+struct PinnedCert {
+    fingerprint: [u8; 32],
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for PinnedCert {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if Sha256::digest(end_entity).as_slice() != self.fingerprint {
+            return Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Display, Error)]
 #[display(doc_comments)]
 pub enum ElectrumApiError {
@@ -49,6 +175,8 @@ pub enum ElectrumApiError {
     /// electrum indexer returned invalid previous transaction, which doesn't have an output spent
     /// by transaction {0} input {1:?}.
     PrevOutTxMismatch(Txid, TxIn),
+    /// electrum indexer returned invalid mempool fee histogram: {0}.
+    InvalidHistogram(String),
 }
 
 #[derive(Debug, Display, Error, From)]
@@ -58,15 +186,19 @@ pub enum ElectrumError {
     Api(ElectrumApiError),
     #[from]
     Client(Error),
+    #[from]
+    Broadcast(BroadcastError),
+    #[from]
+    Unsupported(UnsupportedRequest),
 }
 
-impl Indexer for Client {
+impl<C: ElectrumClient> Indexer for C {
     type Error = ElectrumError;
 
     fn create<K, D: Descriptor<K>, L2: Layer2>(
         &self,
         descriptor: &WalletDescr<K, D, L2::Descr>,
-    ) -> MayError<WalletCache<L2::Cache>, Vec<Self::Error>> {
+    ) -> MayError<WalletCache<L2::Cache>, Vec<IndexerError<Self::Error>>> {
         let mut cache = WalletCache::new_nonsync();
         self.update::<K, D, L2>(descriptor, &mut cache).map(|_| cache)
     }
@@ -75,35 +207,34 @@ impl Indexer for Client {
         &self,
         descriptor: &WalletDescr<K, D, L2::Descr>,
         cache: &mut WalletCache<L2::Cache>,
-    ) -> MayError<usize, Vec<Self::Error>> {
-        let mut errors = Vec::<ElectrumError>::new();
+    ) -> MayError<usize, Vec<IndexerError<Self::Error>>> {
+        let mut errors = Vec::<IndexerError<ElectrumError>>::new();
+        let mined = mined_txids(cache);
+        let mut checkpoint = Checkpoint::new(cache.flush_interval);
 
-        let mut address_index = BTreeMap::new();
+        let mut address_index = AddressIndex::new();
         for keychain in descriptor.keychains() {
-            let mut empty_count = 0usize;
-            #[cfg(feature = "cli")]
-            eprint!(" keychain {keychain} ");
+            let mut gap = cache.gap_limits.tracker(keychain);
             for derive in descriptor.addresses(keychain) {
                 let script = derive.addr.script_pubkey();
 
-                #[cfg(feature = "cli")]
-                eprint!(".");
                 let mut txids = Vec::new();
-                let Ok(hres) =
-                    self.script_get_history(&script).map_err(|err| errors.push(err.into()))
-                else {
+                let Ok(hres) = self.script_get_history(&script).map_err(|err| {
+                    errors.push(IndexerError::with_derive(derive, err));
+                }) else {
                     break;
                 };
+                cache.report_progress(derive.terminal, hres.len());
+                let stop = gap.register(!hres.is_empty());
                 if hres.is_empty() {
-                    empty_count += 1;
-                    if empty_count >= BATCH_SIZE {
+                    checkpoint.register(0);
+                    checkpoint.flush(cache, descriptor.network(), &address_index);
+                    if stop {
                         break;
                     }
                     continue;
                 }
 
-                empty_count = 0;
-
                 let mut process_history_entry =
                     |hr: GetHistoryRes| -> Result<WalletTx, ElectrumError> {
                         let txid = hr.tx_hash;
@@ -150,6 +281,18 @@ impl Indexer for Client {
                         let mut input_total = Sats::ZERO;
                         let mut inputs = Vec::with_capacity(tx.inputs.len());
                         for input in tx.inputs {
+                            if input.prev_output.txid.is_coinbase() {
+                                inputs.push(TxCredit {
+                                    outpoint: input.prev_output,
+                                    payer: Party::Subsidy,
+                                    sequence: input.sequence,
+                                    coinbase: true,
+                                    script_sig: input.sig_script,
+                                    witness: input.witness,
+                                    value: Sats::ZERO,
+                                });
+                                continue;
+                            }
                             // get value from previous output tx
                             let prev_tx = self.transaction_get(&input.prev_output.txid)?;
                             let prev_out = prev_tx
@@ -190,7 +333,7 @@ impl Indexer for Client {
                             status,
                             inputs,
                             outputs,
-                            fee: input_total - output_total,
+                            fee: input_total.saturating_sub(output_total),
                             size: tx_size as u32,
                             weight,
                             version: tx.version,
@@ -204,93 +347,171 @@ impl Indexer for Client {
                         Ok(tx) => {
                             cache.tx.insert(tx.txid, tx);
                         }
-                        Err(e) => errors.push(e),
+                        Err(e) => errors.push(IndexerError::with_derive(derive, e)),
                     }
                 }
 
-                let wallet_addr = WalletAddr::<i64>::from(derive);
-                address_index.insert(script, (wallet_addr, txids));
+                checkpoint.register(txids.len());
+                index_address(&mut address_index, derive, txids);
+                checkpoint.flush(cache, descriptor.network(), &address_index);
             }
         }
 
-        // TODO: Update headers & tip
+        // TODO: Update headers
+        match self.tip_height() {
+            Ok(Some(height)) => cache.tip_height = Some(height),
+            Ok(None) => {}
+            Err(err) => errors.push(err.into()),
+        }
 
-        for (script, (wallet_addr, txids)) in &mut address_index {
-            for txid in txids {
-                let mut tx = cache.tx.remove(txid).expect("broken logic");
-                for debit in &mut tx.outputs {
-                    let Some(s) = debit.beneficiary.script_pubkey() else {
-                        continue;
-                    };
-                    if &s == script {
-                        cache.utxo.insert(debit.outpoint);
-                        debit.beneficiary = Party::from_wallet_addr(wallet_addr);
-                        wallet_addr.used = wallet_addr.used.saturating_add(1);
-                        wallet_addr.volume.saturating_add_assign(debit.value);
-                        wallet_addr.balance = wallet_addr
-                            .balance
-                            .saturating_add(debit.value.sats().try_into().expect("sats overflow"));
-                    } else if debit.beneficiary.is_unknown() {
-                        Address::with(&s, descriptor.network())
-                            .map(|addr| {
-                                debit.beneficiary = Party::Counterparty(addr);
-                            })
-                            .ok();
-                    }
-                }
-                cache.tx.insert(tx.txid, tx);
-            }
+        process_outputs(cache, descriptor.network(), &mut address_index);
+        process_inputs(cache, descriptor.network(), &mut address_index);
+        let reorged =
+            rollback_reorgs(cache, &address_index, mined, |txid| self.status(txid), &mut errors);
+
+        if errors.is_empty() {
+            MayError::ok(reorged)
+        } else {
+            MayError::err(reorged, errors)
         }
+    }
 
-        for (script, (wallet_addr, txids)) in &mut address_index {
-            for txid in txids {
-                let mut tx = cache.tx.remove(txid).expect("broken logic");
-                for credit in &mut tx.inputs {
-                    let Some(s) = credit.payer.script_pubkey() else {
-                        continue;
-                    };
-                    if &s == script {
-                        credit.payer = Party::from_wallet_addr(wallet_addr);
-                        wallet_addr.balance = wallet_addr
-                            .balance
-                            .saturating_sub(credit.value.sats().try_into().expect("sats overflow"));
-                    } else if credit.payer.is_unknown() {
-                        Address::with(&s, descriptor.network())
-                            .map(|addr| {
-                                credit.payer = Party::Counterparty(addr);
-                            })
-                            .ok();
-                    }
-                    if let Some(prev_tx) = cache.tx.get_mut(&credit.outpoint.txid) {
-                        if let Some(txout) =
-                            prev_tx.outputs.get_mut(credit.outpoint.vout_u32() as usize)
-                        {
-                            let outpoint = txout.outpoint;
-                            if tx.status.is_mined() {
-                                cache.utxo.remove(&outpoint);
-                            }
-                            txout.spent = Some(credit.outpoint.into())
-                        };
-                    }
-                }
-                cache.tx.insert(tx.txid, tx);
+    fn publish(&self, tx: &Tx) -> Result<Txid, Self::Error> {
+        self.transaction_broadcast(tx).map_err(|err| match err {
+            Error::Protocol(reason) => {
+                BroadcastError::with_reason(&protocol_message(&reason)).into()
             }
-            cache
-                .addr
-                .entry(wallet_addr.terminal.keychain)
-                .or_default()
-                .insert(wallet_addr.expect_transmute());
+            err => err.into(),
+        })
+    }
+
+    fn status(&self, txid: Txid) -> Result<TxStatus, Self::Error> {
+        let tx_details = match self.raw_call("blockchain.transaction.get", vec![
+            Param::String(txid.to_string()),
+            Param::Bool(true),
+        ]) {
+            Ok(tx_details) => tx_details,
+            Err(err) if is_unknown_tx(&err) => return Ok(TxStatus::Unknown),
+            Err(err) => return Err(err.into()),
+        };
+        let confirmations =
+            tx_details.get("confirmations").and_then(Value::as_u64).unwrap_or_default();
+        if confirmations == 0 {
+            return Ok(TxStatus::Mempool);
         }
+        let block_hash = tx_details
+            .get("blockhash")
+            .and_then(Value::as_str)
+            .and_then(|s| BlockHash::from_str(s).ok())
+            .ok_or(ElectrumApiError::InvalidBlockHash(txid))?;
+        let blocktime = tx_details
+            .get("blocktime")
+            .and_then(Value::as_u64)
+            .ok_or(ElectrumApiError::InvalidBlockTime(txid))?;
+        let tip = self.block_headers_subscribe()?.height as u64;
+        let height = u32::try_from((tip + 1).saturating_sub(confirmations))
+            .ok()
+            .and_then(|h| NonZeroU32::try_from(h).ok())
+            .ok_or(ElectrumApiError::InvalidBlockHeight(txid))?;
+        Ok(TxStatus::Mined(MiningInfo {
+            height,
+            time: blocktime,
+            block_hash,
+        }))
+    }
 
-        if errors.is_empty() {
-            MayError::ok(0)
-        } else {
-            MayError::err(0, errors)
+    fn fee_rate(&self, target: u16) -> Result<Option<f64>, Self::Error> {
+        // Electrum servers return the estimate in BTC per kilobyte, and -1 if there is none
+        let btc_per_kb = self.estimate_fee(target as usize)?;
+        Ok((btc_per_kb > 0.0).then_some(btc_per_kb * 100_000.0))
+    }
+
+    fn mempool_histogram(&self) -> Result<Option<Vec<(f64, u64)>>, Self::Error> {
+        let histogram = self.raw_call("mempool.get_fee_histogram", vec![])?;
+        let histogram = serde_json::from_value(histogram)
+            .map_err(|err| ElectrumApiError::InvalidHistogram(err.to_string()))?;
+        Ok(Some(histogram))
+    }
+
+    fn tip_height(&self) -> Result<Option<u32>, Self::Error> {
+        Ok(u32::try_from(self.block_headers_subscribe()?.height).ok())
+    }
+
+    fn chain_tip(&self) -> Result<Option<(u32, BlockHash)>, Self::Error> {
+        let tip = self.block_headers_subscribe()?;
+        Ok(u32::try_from(tip.height).ok().map(|height| (height, tip.header.block_hash())))
+    }
+
+    fn raw_tx(&self, txid: Txid) -> Result<Option<Tx>, Self::Error> {
+        match self.transaction_get(&txid) {
+            Ok(tx) => Ok(Some(tx)),
+            Err(err) if is_unknown_tx(&err) => Ok(None),
+            Err(err) => Err(err.into()),
         }
     }
+}
+
+/// Extracts the error message from the protocol error reported by the server.
+fn protocol_message(reason: &Value) -> String {
+    match reason.get("message").and_then(Value::as_str) {
+        Some(message) => message.to_owned(),
+        None => reason.as_str().map(str::to_owned).unwrap_or_else(|| reason.to_string()),
+    }
+}
+
+/// Detects whether the server has definitely reported the requested transaction as unknown.
+/// Other errors, including the ones caused by the server overload or rate limits, must not be
+/// taken as evidence that the transaction doesn't exist.
+fn is_unknown_tx(err: &Error) -> bool {
+    let Error::Protocol(reason) = err else {
+        return false;
+    };
+    let message = protocol_message(reason).to_lowercase();
+    ["no such mempool or blockchain transaction", "missing transaction", "transaction not found"]
+        .iter()
+        .any(|pattern| message.contains(pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn unknown_tx() {
+        let fulcrum = json!({
+            "code": 2,
+            "message": "daemon error: DaemonError({'code': -5, 'message': 'No such mempool or \
+                        blockchain transaction. Use gettransaction for wallet transactions.'})"
+        });
+        assert!(is_unknown_tx(&Error::Protocol(fulcrum)));
+        assert!(is_unknown_tx(&Error::Protocol(json!("missing transaction"))));
+
+        let overloaded = json!({"code": -101, "message": "excessive resource usage"});
+        assert!(!is_unknown_tx(&Error::Protocol(overloaded)));
+        let unsupported = json!({"code": -32601, "message": "unknown method"});
+        assert!(!is_unknown_tx(&Error::Protocol(unsupported)));
+        assert!(!is_unknown_tx(&Error::AllAttemptsErrored(vec![])));
+    }
+
+    #[test]
+    fn pinned_cert() {
+        let cert = CertificateDer::from(vec![0x30, 0x03, 0x02, 0x01, 0x01]);
+        let other = CertificateDer::from(vec![0x30, 0x03, 0x02, 0x01, 0x02]);
+        let verifier = PinnedCert {
+            fingerprint: Sha256::digest(&cert).into(),
+            algorithms: aws_lc_rs::default_provider().signature_verification_algorithms,
+        };
+        let name = ServerName::try_from("electrum.example.com").unwrap();
+        let now = UnixTime::now();
+        assert!(verifier.verify_server_cert(&cert, &[], &name, &[], now).is_ok());
+        assert!(verifier.verify_server_cert(&other, &[], &name, &[], now).is_err());
 
-    fn publish(&self, tx: &Tx) -> Result<(), Self::Error> {
-        self.transaction_broadcast(tx)?;
-        Ok(())
+        let opts = ConnectionOpts::default();
+        assert!(matches!(
+            connect_pinned("tcp://electrum.example.com:50001", [0; 32], &opts),
+            Err(Error::Message(_))
+        ));
     }
 }
@@ -20,26 +20,164 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::BTreeMap;
-use std::num::NonZeroU32;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hasher};
+use std::num::{NonZeroU32, NonZeroUsize};
 use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{io, thread};
 
-use bpstd::{Address, DerivedAddr, LockTime, Outpoint, SeqNo, Tx, TxVer, Witness};
+use bpstd::{Address, BlockHash, Keychain, LockTime, Outpoint, SeqNo, Tx, TxVer, Txid, Witness};
 use descriptors::Descriptor;
 use esplora::BlockingClient;
 pub use esplora::{Builder, Config, Error};
 
-use super::BATCH_SIZE;
+use super::pool::ClientPool;
+use super::{
+    index_address, mined_txids, process_inputs, process_outputs, rollback_reorgs, AddressIndex,
+    BroadcastError, Checkpoint, UnsupportedRequest,
+};
 use crate::{
-    Indexer, Layer2, MayError, MiningInfo, Party, TxCredit, TxDebit, TxStatus, WalletAddr,
+    Indexer, IndexerError, Layer2, MayError, MiningInfo, Party, TxCredit, TxDebit, TxStatus,
     WalletCache, WalletDescr, WalletTx,
 };
 
+/// Default number of addresses scanned in parallel.
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
 /// Represents a client for interacting with the Esplora indexer.
 #[derive(Debug, Clone)]
 pub struct Client {
     pub(crate) inner: BlockingClient,
     pub(crate) kind: ClientKind,
+    pub(crate) privacy: Option<ScanPrivacy>,
+    pub(crate) pool: Option<ClientPool>,
+    pub(crate) concurrency: NonZeroUsize,
+    pub(crate) retry: RetryPolicy,
+}
+
+/// Privacy settings applied to all requests made by the client.
+///
+/// Spreading the requests over several servers prevents a single server from correlating all of
+/// the wallet addresses and transactions into one wallet, and random delays make request timing
+/// less predictable. Both come at the cost of a considerably slower scan, which is also done
+/// sequentially, one address at a time.
+#[derive(Debug, Clone, Default)]
+pub struct ScanPrivacy {
+    /// Additional endpoints rotated round-robin together with the main one, one per request.
+    pub endpoints: Vec<BlockingClient>,
+    /// Maximal random delay inserted before each request.
+    pub max_jitter: Duration,
+    /// Number of requests made so far, selecting the next endpoint in the rotation.
+    next: Arc<AtomicUsize>,
+}
+
+impl ScanPrivacy {
+    fn delay(&self) -> Duration {
+        let max = self.max_jitter.as_millis() as u64;
+        if max == 0 {
+            return Duration::ZERO;
+        }
+        // Randomly keyed hasher is a sufficient source of entropy for the timing jitter
+        let rand = RandomState::new().build_hasher().finish();
+        Duration::from_millis(rand % (max + 1))
+    }
+}
+
+/// Policy for retrying requests failed with transient errors: timeouts, failures to establish or
+/// keep the connection to the server, and HTTP statuses 408, 429, 502, 503 and 504.
+///
+/// The delay before each retry grows exponentially from `base_delay` up to `max_delay`, and a
+/// random jitter of up to a half of the delay is subtracted from it to avoid retrying in lockstep
+/// with other clients. Rate-limited requests (HTTP 429) are retried only after `max_delay`, since
+/// the Esplora client doesn't expose the `Retry-After` header of the response. The policy applies
+/// on top of the retries done by the underlying HTTP client.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct RetryPolicy {
+    /// Maximal number of attempts for each request, including the first one.
+    pub max_attempts: NonZeroU32,
+    /// Delay before the first retry.
+    pub base_delay: Duration,
+    /// Upper bound for the delay between retries.
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: NonZeroU32::new(5).expect("non-zero constant"),
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Policy performing no retries.
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: NonZeroU32::MIN,
+            ..default!()
+        }
+    }
+
+    /// Checks whether the error is transient and the request may succeed if retried.
+    pub fn is_transient(err: &Error) -> bool {
+        match err {
+            Error::Minreq(minreq::Error::IoError(err)) => matches!(
+                err.kind(),
+                io::ErrorKind::TimedOut
+                    | io::ErrorKind::WouldBlock
+                    | io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::NotConnected
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::UnexpectedEof
+            ),
+            Error::Minreq(minreq::Error::ProxyConnect) => true,
+            Error::HttpResponse { status, .. } => matches!(status, 408 | 429 | 502 | 503 | 504),
+            _ => false,
+        }
+    }
+
+    /// Computes delay before the retry following the given number of failed attempts, the last of
+    /// which failed with the provided error.
+    fn delay(&self, failed: u32, err: &Error) -> Duration {
+        if matches!(err, Error::HttpResponse { status: 429, .. }) {
+            return self.max_delay;
+        }
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << failed.saturating_sub(1).min(16))
+            .min(self.max_delay);
+        let half = delay.as_millis() as u64 / 2;
+        if half == 0 {
+            return delay;
+        }
+        let rand = RandomState::new().build_hasher().finish();
+        delay - Duration::from_millis(rand % (half + 1))
+    }
+
+    /// Runs the request, retrying it on transient errors according to the policy.
+    #[allow(clippy::result_large_err)]
+    pub fn run<T>(&self, mut request: impl FnMut() -> Result<T, Error>) -> Result<T, Error> {
+        let mut failed = 0u32;
+        loop {
+            match request() {
+                Err(err) if Self::is_transient(&err) && failed + 1 < self.max_attempts.get() => {
+                    failed += 1;
+                    #[cfg(feature = "log")]
+                    log::debug!("transient indexer error: {err}; retrying (attempt {failed})");
+                    thread::sleep(self.delay(failed, &err));
+                }
+                res => return res,
+            }
+        }
+    }
 }
 
 impl Deref for Client {
@@ -73,13 +211,135 @@ impl Client {
     /// Returns an error if the client fails to connect to the Esplora server.
     #[allow(clippy::result_large_err)]
     pub fn new_esplora(url: &str) -> Result<Self, Error> {
-        let inner = esplora::Builder::new(url).build_blocking()?;
+        Self::with_kind(url, ClientKind::Esplora, None)
+    }
+
+    /// Creates a new Esplora client with the specified URL, routing all requests through the
+    /// `proxy`.
+    ///
+    /// The proxy must be an HTTP `CONNECT` proxy given as `[http://][user[:password]@]host[:port]`,
+    /// since the underlying blocking HTTP client doesn't support SOCKS5. To reach the server over
+    /// Tor, use the tunnel provided by the `HTTPTunnelPort` option of the Tor daemon.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the client fails to connect to the Esplora server.
+    #[allow(clippy::result_large_err)]
+    pub fn new_esplora_with_proxy(url: &str, proxy: &str) -> Result<Self, Error> {
+        Self::with_kind(url, ClientKind::Esplora, Some(proxy))
+    }
+
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn with_kind(
+        url: &str,
+        kind: ClientKind,
+        proxy: Option<&str>,
+    ) -> Result<Self, Error> {
+        let inner = builder(url, proxy).build_blocking()?;
         let client = Self {
             inner,
-            kind: ClientKind::Esplora,
+            kind,
+            privacy: None,
+            pool: None,
+            concurrency: NonZeroUsize::new(DEFAULT_CONCURRENCY).expect("non-zero constant"),
+            retry: RetryPolicy::default(),
         };
         Ok(client)
     }
+
+    /// Enables privacy mode, rotating all requests among the main server and additional
+    /// `endpoints` and waiting a random delay of up to `max_jitter` before each request. In the
+    /// privacy mode addresses are scanned one by one, ignoring the configured concurrency.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a client for any of the endpoints can't be constructed.
+    #[allow(clippy::result_large_err)]
+    pub fn with_privacy<'a>(
+        mut self,
+        endpoints: impl IntoIterator<Item = &'a str>,
+        max_jitter: Duration,
+    ) -> Result<Self, Error> {
+        let endpoints = endpoints
+            .into_iter()
+            .map(|url| builder(url, self.inner.proxy.as_deref()).build_blocking())
+            .collect::<Result<_, _>>()?;
+        self.privacy = Some(ScanPrivacy {
+            endpoints,
+            max_jitter,
+            next: default!(),
+        });
+        Ok(self)
+    }
+
+    /// Routes address scan requests to the pool of weighted endpoints. The main server is used
+    /// for the scan only once all of the pool endpoints are removed after failures.
+    pub fn with_pool(mut self, pool: ClientPool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Sets the number of wallet addresses scanned in parallel. Addresses past the gap limit may
+    /// get queried, but their results are discarded, so the scan result doesn't depend on the
+    /// concurrency. Ignored in the privacy mode, where addresses are scanned one by one.
+    pub fn with_concurrency(mut self, concurrency: NonZeroUsize) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    /// Sets the policy for retrying requests failed with transient errors.
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Returns the pool of endpoints used for address scanning, if any.
+    pub fn pool(&self) -> Option<&ClientPool> { self.pool.as_ref() }
+
+    /// Returns the number of addresses scanned in parallel, which is always one in the privacy
+    /// mode.
+    fn concurrency(&self) -> usize {
+        match self.privacy {
+            None => self.concurrency.get(),
+            Some(_) => 1,
+        }
+    }
+
+    /// Selects the server for the next request: the main one, or the next one in the rotation if
+    /// the privacy mode is enabled, in which case it also sleeps for a random delay first.
+    fn endpoint(&self) -> &BlockingClient {
+        let Some(privacy) = &self.privacy else {
+            return &self.inner;
+        };
+        thread::sleep(privacy.delay());
+        match privacy.next.fetch_add(1, Ordering::Relaxed) % (privacy.endpoints.len() + 1) {
+            0 => &self.inner,
+            n => &privacy.endpoints[n - 1],
+        }
+    }
+
+    /// Runs the request against the server selected by [`Self::endpoint`], retrying it on
+    /// transient errors according to the retry policy. In the privacy mode each retry goes to the
+    /// next server in the rotation.
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn request<T>(
+        &self,
+        mut request: impl FnMut(&BlockingClient) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        self.retry.run(|| request(self.endpoint()))
+    }
+
+    /// Retrieves history of a scanned address from the pool or from the endpoint selected by the
+    /// privacy mode.
+    #[allow(clippy::result_large_err)]
+    fn scan_address(&self, address: &Address) -> Result<Vec<esplora::Tx>, Error> {
+        if let Some(res) = self.pool.as_ref().and_then(|pool| {
+            pool.request(|client| get_scripthash_txs_all(client, self.kind, &self.retry, address))
+        }) {
+            return res;
+        }
+        get_scripthash_txs_all(self.endpoint(), self.kind, &self.retry, address)
+    }
 }
 
 impl From<esplora::TxStatus> for TxStatus {
@@ -146,6 +406,43 @@ impl From<esplora::Tx> for WalletTx {
     }
 }
 
+impl Client {
+    /// Retrieves the full history of transactions touching an arbitrary address, which may not
+    /// belong to the wallet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the paginated requests to the server fails.
+    #[allow(clippy::result_large_err)]
+    pub fn address_history(&self, address: &Address) -> Result<Vec<WalletTx>, Error> {
+        let txes = get_scripthash_txs_all(self.endpoint(), self.kind, &self.retry, address)?;
+        Ok(txes.into_iter().map(WalletTx::from).collect())
+    }
+
+    /// Retrieves the mempool fee-rate histogram as a list of `(fee rate, vsize)` buckets.
+    #[allow(clippy::result_large_err)]
+    pub fn fee_histogram(&self) -> Result<Vec<(f64, u64)>, Error> {
+        let info: MempoolInfo = self.request(|endpoint| {
+            let resp = endpoint.get_request("/mempool")?.send()?;
+            if resp.status_code != 200 {
+                return Err(Error::HttpResponse {
+                    status: resp.status_code as u16,
+                    message: resp.as_str().unwrap_or_default().to_owned(),
+                });
+            }
+            Ok(resp.json()?)
+        })?;
+        Ok(info.fee_histogram)
+    }
+}
+
+/// Mempool statistics returned by the `/mempool` endpoint.
+#[derive(serde::Deserialize)]
+#[serde(crate = "serde_crate")]
+struct MempoolInfo {
+    fee_histogram: Vec<(f64, u64)>,
+}
+
 /// Retrieves all transactions associated with a given script hash.
 ///
 /// # Arguments
@@ -158,22 +455,22 @@ impl From<esplora::Tx> for WalletTx {
 /// Returns an error if there was a problem retrieving the transactions.
 #[allow(clippy::result_large_err)]
 fn get_scripthash_txs_all(
-    client: &Client,
-    derive: &DerivedAddr,
+    client: &BlockingClient,
+    kind: ClientKind,
+    retry: &RetryPolicy,
+    address: &Address,
 ) -> Result<Vec<esplora::Tx>, Error> {
     const PAGE_SIZE: usize = 25;
     let mut res = Vec::new();
     let mut last_seen = None;
-    let script = derive.addr.script_pubkey();
-    #[cfg(feature = "mempool")]
-    let address = derive.addr;
+    let script = address.script_pubkey();
 
     loop {
-        let r = match client.kind {
-            ClientKind::Esplora => client.inner.scripthash_txs(&script, last_seen)?,
+        let r = retry.run(|| match kind {
+            ClientKind::Esplora => client.scripthash_txs(&script, last_seen),
             #[cfg(feature = "mempool")]
-            ClientKind::Mempool => client.inner.address_txs(&address, last_seen)?,
-        };
+            ClientKind::Mempool => client.address_txs(address, last_seen),
+        })?;
         match &r[..] {
             [a @ .., esplora::Tx { txid, .. }] if a.len() >= PAGE_SIZE - 1 => {
                 last_seen = Some(*txid);
@@ -188,13 +485,74 @@ fn get_scripthash_txs_all(
     Ok(res)
 }
 
+#[derive(Debug, Display, Error, From)]
+#[display(inner)]
+pub enum EsploraError {
+    #[from]
+    Client(Error),
+    #[from]
+    Broadcast(BroadcastError),
+    #[from]
+    Unsupported(UnsupportedRequest),
+}
+
+/// Scans addresses of a descriptor keychain until the gap limit is reached, retrieving
+/// transactions of `concurrency` addresses at once with `scan`. The results are processed in the
+/// derivation order, so the gap limit and the address index are exactly the same as with the
+/// sequential scan.
+fn scan_keychain<K, D: Descriptor<K>, L2: Layer2>(
+    descriptor: &WalletDescr<K, D, L2::Descr>,
+    keychain: Keychain,
+    cache: &mut WalletCache<L2::Cache>,
+    address_index: &mut AddressIndex,
+    checkpoint: &mut Checkpoint,
+    concurrency: usize,
+    scan: impl Fn(&Address) -> Result<Vec<WalletTx>, Error> + Sync,
+) -> Result<(), IndexerError<EsploraError>> {
+    let scan = &scan;
+    let mut gap = cache.gap_limits.tracker(keychain);
+    let mut addresses = descriptor.addresses(keychain);
+    loop {
+        let batch = addresses.by_ref().take(concurrency).collect::<Vec<_>>();
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let results = thread::scope(|scope| {
+            let handles = batch
+                .iter()
+                .map(|derive| scope.spawn(move || scan(&derive.addr)))
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("address scanning thread panicked"))
+                .collect::<Vec<_>>()
+        });
+
+        for (derive, res) in batch.into_iter().zip(results) {
+            let txes =
+                res.map_err(|err| IndexerError::with_derive(derive, EsploraError::from(err)))?;
+            cache.report_progress(derive.terminal, txes.len());
+            if gap.register(!txes.is_empty()) && txes.is_empty() {
+                checkpoint.register(0);
+                checkpoint.flush(cache, descriptor.network(), address_index);
+                return Ok(());
+            }
+            let txids = txes.iter().map(|tx| tx.txid).collect::<Vec<_>>();
+            cache.tx.extend(txes.into_iter().map(|tx| (tx.txid, tx)));
+            checkpoint.register(txids.len());
+            index_address(address_index, derive, txids);
+        }
+        checkpoint.flush(cache, descriptor.network(), address_index);
+    }
+}
+
 impl Indexer for Client {
-    type Error = Error;
+    type Error = EsploraError;
 
     fn create<K, D: Descriptor<K>, L2: Layer2>(
         &self,
         descriptor: &WalletDescr<K, D, L2::Descr>,
-    ) -> MayError<WalletCache<L2::Cache>, Vec<Self::Error>> {
+    ) -> MayError<WalletCache<L2::Cache>, Vec<IndexerError<Self::Error>>> {
         let mut cache = WalletCache::new_nonsync();
         self.update::<K, D, L2>(descriptor, &mut cache).map(|_| cache)
     }
@@ -203,120 +561,352 @@ impl Indexer for Client {
         &self,
         descriptor: &WalletDescr<K, D, L2::Descr>,
         cache: &mut WalletCache<L2::Cache>,
-    ) -> MayError<usize, Vec<Self::Error>> {
+    ) -> MayError<usize, Vec<IndexerError<Self::Error>>> {
         let mut errors = vec![];
+        let mined = mined_txids(cache);
+        let mut checkpoint = Checkpoint::new(cache.flush_interval);
 
-        let mut address_index = BTreeMap::new();
+        let mut address_index = AddressIndex::new();
         for keychain in descriptor.keychains() {
-            let mut empty_count = 0usize;
-            #[cfg(feature = "cli")]
-            eprint!(" keychain {keychain} ");
-            for derive in descriptor.addresses(keychain) {
-                let script = derive.addr.script_pubkey();
-
-                #[cfg(feature = "cli")]
-                eprint!(".");
-                let mut txids = Vec::new();
-                match get_scripthash_txs_all(self, &derive) {
-                    Err(err) => {
-                        errors.push(err);
-                        break;
-                    }
-                    Ok(txes) if txes.is_empty() => {
-                        empty_count += 1;
-                        if empty_count >= BATCH_SIZE {
-                            break;
-                        }
-                    }
-                    Ok(txes) => {
-                        empty_count = 0;
-                        txids = txes.iter().map(|tx| tx.txid).collect();
-                        cache
-                            .tx
-                            .extend(txes.into_iter().map(WalletTx::from).map(|tx| (tx.txid, tx)));
-                    }
-                }
-
-                let wallet_addr = WalletAddr::<i64>::from(derive);
-                address_index.insert(script, (wallet_addr, txids));
+            let scanned = scan_keychain::<K, D, L2>(
+                descriptor,
+                keychain,
+                cache,
+                &mut address_index,
+                &mut checkpoint,
+                self.concurrency(),
+                |addr| Ok(self.scan_address(addr)?.into_iter().map(WalletTx::from).collect()),
+            );
+            if let Err(err) = scanned {
+                errors.push(err);
             }
         }
 
-        // TODO: Update headers & tip
-
-        for (script, (wallet_addr, txids)) in &mut address_index {
-            for txid in txids {
-                let mut tx = cache.tx.remove(txid).expect("broken logic");
-                for debit in &mut tx.outputs {
-                    let Some(s) = debit.beneficiary.script_pubkey() else {
-                        continue;
-                    };
-                    if &s == script {
-                        cache.utxo.insert(debit.outpoint);
-                        debit.beneficiary = Party::from_wallet_addr(wallet_addr);
-                        wallet_addr.used = wallet_addr.used.saturating_add(1);
-                        wallet_addr.volume.saturating_add_assign(debit.value);
-                        wallet_addr.balance = wallet_addr
-                            .balance
-                            .saturating_add(debit.value.sats().try_into().expect("sats overflow"));
-                    } else if debit.beneficiary.is_unknown() {
-                        Address::with(&s, descriptor.network())
-                            .map(|addr| {
-                                debit.beneficiary = Party::Counterparty(addr);
-                            })
-                            .ok();
-                    }
-                }
-                cache.tx.insert(tx.txid, tx);
+        #[cfg(feature = "log")]
+        if let Some(pool) = &self.pool {
+            for stats in pool.stats() {
+                log::info!("esplora endpoint {stats}");
             }
         }
 
-        for (script, (wallet_addr, txids)) in &mut address_index {
-            for txid in txids {
-                let mut tx = cache.tx.remove(txid).expect("broken logic");
-                for credit in &mut tx.inputs {
-                    let Some(s) = credit.payer.script_pubkey() else {
-                        continue;
-                    };
-                    if &s == script {
-                        credit.payer = Party::from_wallet_addr(wallet_addr);
-                        wallet_addr.balance = wallet_addr
-                            .balance
-                            .saturating_sub(credit.value.sats().try_into().expect("sats overflow"));
-                    } else if credit.payer.is_unknown() {
-                        Address::with(&s, descriptor.network())
-                            .map(|addr| {
-                                credit.payer = Party::Counterparty(addr);
-                            })
-                            .ok();
-                    }
-                    if let Some(prev_tx) = cache.tx.get_mut(&credit.outpoint.txid) {
-                        if let Some(txout) =
-                            prev_tx.outputs.get_mut(credit.outpoint.vout_u32() as usize)
-                        {
-                            let outpoint = txout.outpoint;
-                            if tx.status.is_mined() {
-                                cache.utxo.remove(&outpoint);
-                            }
-                            txout.spent = Some(credit.outpoint.into())
-                        };
-                    }
-                }
-                cache.tx.insert(tx.txid, tx);
-            }
-            cache
-                .addr
-                .entry(wallet_addr.terminal.keychain)
-                .or_default()
-                .insert(wallet_addr.expect_transmute());
+        // TODO: Update headers
+        match self.tip_height() {
+            Ok(Some(height)) => cache.tip_height = Some(height),
+            Ok(None) => {}
+            Err(err) => errors.push(err.into()),
         }
 
+        process_outputs(cache, descriptor.network(), &mut address_index);
+        process_inputs(cache, descriptor.network(), &mut address_index);
+        let reorged =
+            rollback_reorgs(cache, &address_index, mined, |txid| self.status(txid), &mut errors);
+
         if errors.is_empty() {
-            MayError::ok(0)
+            MayError::ok(reorged)
         } else {
-            MayError::err(0, errors)
+            MayError::err(reorged, errors)
+        }
+    }
+
+    fn publish(&self, tx: &Tx) -> Result<Txid, Self::Error> {
+        // Esplora reports the reason of the transaction rejection given by the node in the body of
+        // a response with `400` status code
+        self.request(|endpoint| endpoint.broadcast(tx)).map_err(|err| match err {
+            Error::HttpResponse {
+                status: 400,
+                message,
+            } => BroadcastError::with_reason(&message).into(),
+            err => EsploraError::from(err),
+        })?;
+        Ok(tx.txid())
+    }
+
+    fn status(&self, txid: Txid) -> Result<TxStatus, Self::Error> {
+        Ok(self
+            .request(|endpoint| endpoint.tx_info(&txid))?
+            .map(|tx| tx.status.into())
+            .unwrap_or(TxStatus::Unknown))
+    }
+
+    fn fee_rate(&self, target: u16) -> Result<Option<f64>, Self::Error> {
+        let estimates = self.request(|endpoint| endpoint.fee_estimates())?;
+        Ok(closest_estimate(&estimates, target))
+    }
+
+    fn fee_estimates(&self, targets: &[u16]) -> Result<BTreeMap<u16, f64>, Self::Error> {
+        let estimates = self.request(|endpoint| endpoint.fee_estimates())?;
+        Ok(targets
+            .iter()
+            .filter_map(|target| Some((*target, closest_estimate(&estimates, *target)?)))
+            .collect())
+    }
+
+    fn mempool_histogram(&self) -> Result<Option<Vec<(f64, u64)>>, Self::Error> {
+        Ok(Some(self.fee_histogram()?))
+    }
+
+    fn tip_height(&self) -> Result<Option<u32>, Self::Error> {
+        Ok(Some(self.request(|endpoint| endpoint.height())?))
+    }
+
+    fn chain_tip(&self) -> Result<Option<(u32, BlockHash)>, Self::Error> {
+        let tip = self.request(|endpoint| Ok((endpoint.height()?, endpoint.tip_hash()?)))?;
+        Ok(Some(tip))
+    }
+
+    fn raw_tx(&self, txid: Txid) -> Result<Option<Tx>, Self::Error> {
+        Ok(self.request(|endpoint| endpoint.tx(&txid))?)
+    }
+}
+
+/// Creates a builder of the esplora client for the `url`, which routes requests through the
+/// `proxy`, if any.
+pub(crate) fn builder(url: &str, proxy: Option<&str>) -> esplora::Builder {
+    let builder = esplora::Builder::new(url);
+    match proxy {
+        Some(proxy) => builder.proxy(proxy),
+        None => builder,
+    }
+}
+
+/// Uses the estimate for the closest target not exceeding the requested one; if there is none,
+/// falls back to the fastest available estimate.
+fn closest_estimate(estimates: &HashMap<u16, f64>, target: u16) -> Option<f64> {
+    estimates
+        .iter()
+        .filter(|(t, _)| **t <= target)
+        .max_by_key(|(t, _)| **t)
+        .or_else(|| estimates.iter().min_by_key(|(t, _)| **t))
+        .map(|(_, rate)| *rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_policy() {
+        let policy = RetryPolicy {
+            max_attempts: NonZeroU32::new(3).unwrap(),
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+        };
+        let transient = || Error::Minreq(minreq::Error::IoError(io::ErrorKind::TimedOut.into()));
+
+        let mut attempts = 0;
+        let res = policy.run(|| {
+            attempts += 1;
+            Err::<(), _>(transient())
+        });
+        assert!(res.is_err());
+        assert_eq!(attempts, 3);
+
+        let mut attempts = 0;
+        let res = policy.run(|| {
+            attempts += 1;
+            if attempts < 2 {
+                Err(transient())
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(res.unwrap(), 2);
+
+        let mut attempts = 0;
+        let res = policy.run(|| {
+            attempts += 1;
+            Err::<(), _>(Error::HttpResponse {
+                status: 404,
+                message: s!("not found"),
+            })
+        });
+        assert!(res.is_err());
+        assert_eq!(attempts, 1);
+
+        let refused =
+            Error::Minreq(minreq::Error::IoError(io::ErrorKind::ConnectionRefused.into()));
+        assert!(RetryPolicy::is_transient(&refused));
+        assert!(RetryPolicy::is_transient(&Error::HttpResponse {
+            status: 504,
+            message: s!("gateway timeout"),
+        }));
+        for status in [429, 502, 503] {
+            assert!(RetryPolicy::is_transient(&Error::HttpResponse {
+                status,
+                message: s!("unavailable"),
+            }));
+        }
+        assert!(!RetryPolicy::is_transient(&Error::HttpResponse {
+            status: 400,
+            message: s!("bad request"),
+        }));
+
+        let policy = RetryPolicy {
+            max_attempts: NonZeroU32::new(3).unwrap(),
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_secs(10),
+        };
+        let limited = Error::HttpResponse {
+            status: 429,
+            message: s!("too many requests"),
+        };
+        assert_eq!(policy.delay(1, &limited), Duration::from_secs(10));
+        assert!(policy.delay(1, &transient()) <= Duration::from_millis(1));
+        assert!(!RetryPolicy::is_transient(&Error::Minreq(minreq::Error::AddressNotFound)));
+    }
+
+    #[test]
+    fn parallel_scan() {
+        use std::str::FromStr;
+
+        use bpstd::{Derive, Network, Sats, XpubDerivable};
+        use descriptors::{StdDescr, Wpkh};
+
+        use crate::{FlushInterval, NoLayer2};
+
+        let xpub = XpubDerivable::from_str(
+            "[73c5da0a/84h/0h/0h]xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/<0;1>/*",
+        )
+        .unwrap();
+        let descriptor = WalletDescr::new_standard(
+            StdDescr::<XpubDerivable>::from(Wpkh::from(xpub)),
+            Network::Mainnet,
+        );
+
+        let tx = |no: u8| WalletTx {
+            txid: Txid::from([no; 32]),
+            status: TxStatus::Mempool,
+            inputs: vec![],
+            outputs: vec![],
+            fee: Sats::ZERO,
+            size: 0,
+            weight: 0,
+            version: TxVer::V2,
+            locktime: LockTime::ZERO,
+        };
+        // The address at index 40 is beyond the gap limit and must not be discovered
+        let used = [(0u8, 0u16, vec![1u8]), (0, 3, vec![2, 3]), (0, 12, vec![4]), (0, 40, vec![5])];
+        let mut history = BTreeMap::new();
+        for (keychain, index, txes) in used.into_iter().chain([(1, 1, vec![1, 6])]) {
+            let addr = descriptor.addresses(keychain).nth(index as usize).unwrap().addr;
+            history.insert(addr.script_pubkey(), txes.into_iter().map(tx).collect::<Vec<_>>());
+        }
+        let failing = descriptor.addresses(0).nth(5).unwrap().addr.script_pubkey();
+
+        let scan = |concurrency: usize, fail: bool| {
+            let mut cache = WalletCache::new_nonsync();
+            let mut address_index = AddressIndex::new();
+            let mut checkpoint = Checkpoint::new(FlushInterval::default());
+            let mut errors = 0;
+            for keychain in descriptor.keychains() {
+                let res = scan_keychain::<_, _, NoLayer2>(
+                    &descriptor,
+                    keychain,
+                    &mut cache,
+                    &mut address_index,
+                    &mut checkpoint,
+                    concurrency,
+                    |addr| {
+                        let script = addr.script_pubkey();
+                        if fail && script == failing {
+                            return Err(Error::HttpResponse {
+                                status: 404,
+                                message: s!("not found"),
+                            });
+                        }
+                        Ok(history.get(&script).cloned().unwrap_or_default())
+                    },
+                );
+                errors += res.is_err() as usize;
+            }
+            (address_index, cache.tx.keys().copied().collect::<Vec<_>>(), errors)
+        };
+
+        let (index, txids, errors) = scan(1, false);
+        assert_eq!(errors, 0);
+        assert_eq!(txids, (1..=4).chain([6]).map(|no| Txid::from([no; 32])).collect::<Vec<_>>());
+        // Scan stops at the gap of 10 addresses after the outer address 12 and inner address 1
+        assert_eq!(index.len(), 22 + 11);
+        for concurrency in [2, 4, 7, 32] {
+            assert_eq!(scan(concurrency, false), (index.clone(), txids.clone(), 0));
+        }
+
+        let (index, txids, errors) = scan(1, true);
+        assert_eq!(errors, 1);
+        assert_eq!(index.len(), 5 + 11);
+        for concurrency in [2, 4, 7, 32] {
+            assert_eq!(scan(concurrency, true), (index.clone(), txids.clone(), 1));
         }
     }
 
-    fn publish(&self, tx: &Tx) -> Result<(), Self::Error> { self.inner.broadcast(tx) }
+    #[test]
+    fn gap_limit_flush() {
+        use std::str::FromStr;
+
+        use bpstd::{Network, Sats, XpubDerivable};
+        use descriptors::{StdDescr, Wpkh};
+
+        use crate::{FlushInterval, NoLayer2};
+
+        let xpub = XpubDerivable::from_str(
+            "[73c5da0a/84h/0h/0h]xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/<0;1>/*",
+        )
+        .unwrap();
+        let descriptor = WalletDescr::new_standard(
+            StdDescr::<XpubDerivable>::from(Wpkh::from(xpub)),
+            Network::Mainnet,
+        );
+        let used = descriptor.addresses(Keychain::OUTER).next().unwrap().addr;
+        let tx = WalletTx {
+            txid: Txid::from([1; 32]),
+            status: TxStatus::Mempool,
+            inputs: vec![],
+            outputs: vec![],
+            fee: Sats::ZERO,
+            size: 0,
+            weight: 0,
+            version: TxVer::V2,
+            locktime: LockTime::ZERO,
+        };
+
+        // The gap limit is reached within the first batch, so the only flush happens at the end
+        let mut cache = WalletCache::new_nonsync();
+        let mut checkpoint = Checkpoint::new(FlushInterval {
+            addresses: None,
+            transactions: NonZeroUsize::new(1),
+        });
+        scan_keychain::<_, _, NoLayer2>(
+            &descriptor,
+            Keychain::OUTER,
+            &mut cache,
+            &mut AddressIndex::new(),
+            &mut checkpoint,
+            32,
+            |addr| Ok(if *addr == used { vec![tx.clone()] } else { vec![] }),
+        )
+        .unwrap();
+        assert_eq!(cache.tx.len(), 1);
+        assert_eq!((checkpoint.addresses, checkpoint.transactions), (0, 0));
+    }
+
+    #[test]
+    fn privacy_rotation() {
+        let client = Client::new_esplora("http://127.0.0.1:1")
+            .unwrap()
+            .with_concurrency(NonZeroUsize::new(8).unwrap());
+        assert_eq!(client.concurrency(), 8);
+        assert_eq!(client.endpoint().url(), "http://127.0.0.1:1");
+
+        let client = client
+            .with_privacy(["http://127.0.0.1:2", "http://127.0.0.1:3"], Duration::ZERO)
+            .unwrap();
+        assert_eq!(client.concurrency(), 1);
+        let urls = (0..4).map(|_| client.endpoint().url().to_owned()).collect::<Vec<_>>();
+        assert_eq!(urls, [
+            "http://127.0.0.1:1",
+            "http://127.0.0.1:2",
+            "http://127.0.0.1:3",
+            "http://127.0.0.1:1"
+        ]);
+    }
 }
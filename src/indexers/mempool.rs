@@ -33,11 +33,52 @@ impl super::esplora::Client {
     /// error occurred.
     #[allow(clippy::result_large_err)]
     pub fn new_mempool(url: &str) -> Result<Self, esplora::Error> {
-        let inner = esplora::Builder::new(url).build_blocking()?;
-        let client = Self {
-            inner,
-            kind: super::esplora::ClientKind::Mempool,
-        };
-        Ok(client)
+        Self::with_kind(url, super::esplora::ClientKind::Mempool, None)
+    }
+
+    /// Creates a new mempool client with the specified URL, routing all requests through the
+    /// HTTP `CONNECT` `proxy`; see [`Self::new_esplora_with_proxy`] for the details.
+    #[allow(clippy::result_large_err)]
+    pub fn new_mempool_with_proxy(url: &str, proxy: &str) -> Result<Self, esplora::Error> {
+        Self::with_kind(url, super::esplora::ClientKind::Mempool, Some(proxy))
+    }
+
+    /// Retrieves fee rates recommended by the mempool server.
+    #[allow(clippy::result_large_err)]
+    pub fn recommended_fees(&self) -> Result<RecommendedFees, esplora::Error> {
+        self.request(|endpoint| {
+            let resp = endpoint.get_request("/v1/fees/recommended")?.send()?;
+            if resp.status_code != 200 {
+                return Err(esplora::Error::HttpResponse {
+                    status: resp.status_code as u16,
+                    message: resp.as_str().unwrap_or_default().to_owned(),
+                });
+            }
+            Ok(resp.json()?)
+        })
+    }
+}
+
+/// Fee rates (in sats per vbyte) recommended by the mempool server.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+#[derive(serde::Deserialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct RecommendedFees {
+    pub fastest_fee: f64,
+    pub half_hour_fee: f64,
+    pub hour_fee: f64,
+    pub economy_fee: f64,
+    pub minimum_fee: f64,
+}
+
+impl RecommendedFees {
+    /// Returns recommended fee rate for the transaction to be mined within `target` blocks.
+    pub fn for_target(&self, target: u16) -> f64 {
+        match target {
+            0..=1 => self.fastest_fee,
+            2..=3 => self.half_hour_fee,
+            4..=6 => self.hour_fee,
+            _ => self.economy_fee,
+        }
     }
 }
@@ -24,34 +24,1012 @@
 pub mod electrum;
 #[cfg(feature = "esplora")]
 pub mod esplora;
+#[cfg(feature = "bitcoind")]
+pub mod bitcoind;
 #[cfg(feature = "mempool")]
 pub mod mempool;
-#[cfg(any(feature = "electrum", feature = "esplora", feature = "mempool"))]
+#[cfg(feature = "esplora")]
+pub mod pool;
+#[cfg(any(feature = "electrum", feature = "esplora", feature = "mempool", feature = "bitcoind"))]
 mod any;
 
-#[cfg(any(feature = "electrum", feature = "esplora", feature = "mempool"))]
+use std::collections::BTreeMap;
+#[cfg(any(feature = "electrum", feature = "esplora", feature = "bitcoind"))]
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::num::{NonZeroUsize, ParseIntError};
+use std::str::FromStr;
+
+#[cfg(any(
+    feature = "electrum",
+    feature = "esplora",
+    feature = "mempool",
+    feature = "bitcoind"
+))]
 pub use any::{AnyIndexer, AnyIndexerError};
-use bpstd::Tx;
+#[cfg(any(feature = "electrum", feature = "esplora", feature = "bitcoind"))]
+use bpstd::{Address, Network, Outpoint, ScriptPubkey};
+use bpstd::{BlockHash, DerivedAddr, Keychain, NormalIndex, Tx, Txid};
 use descriptors::Descriptor;
 
-use crate::{Layer2, MayError, WalletCache, WalletDescr};
+#[cfg(any(feature = "electrum", feature = "esplora", feature = "bitcoind"))]
+use crate::Layer2Cache;
+use crate::{Layer2, MayError, TxStatus, WalletCache, WalletDescr};
+#[cfg(any(feature = "electrum", feature = "esplora", feature = "bitcoind"))]
+use crate::{Party, WalletAddr};
+
+/// Default number of consecutive unused addresses after which indexers stop scanning a keychain.
+pub const DEFAULT_GAP_LIMIT: usize = 10;
+
+/// Number of consecutive used addresses after which the adaptive gap limit gets doubled.
+pub const ADAPTIVE_GAP_RUN: usize = 200;
+
+/// Value up to which the adaptive gap limit may grow, unless the configured gap limit is larger.
+pub const ADAPTIVE_GAP_CAP: usize = 1000;
+
+/// Gap limit for a specific keychain, or a shared gap limit for all keychains, if no keychain is
+/// given.
+///
+/// String representation has form of `<keychain>=<limit>` or just `<limit>` for the shared value.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", try_from = "String", into = "String")
+)]
+pub enum GapLimit {
+    #[display("{0}")]
+    Shared(NonZeroUsize),
+    #[display("{0}={1}")]
+    Keychain(Keychain, NonZeroUsize),
+}
+
+impl FromStr for GapLimit {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            None => NonZeroUsize::from_str(s.trim()).map(GapLimit::Shared),
+            Some((keychain, limit)) => Ok(GapLimit::Keychain(
+                u8::from_str(keychain.trim())?.into(),
+                NonZeroUsize::from_str(limit.trim())?,
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for GapLimit {
+    type Error = ParseIntError;
+    fn try_from(s: String) -> Result<Self, Self::Error> { GapLimit::from_str(&s) }
+}
+
+impl From<GapLimit> for String {
+    fn from(limit: GapLimit) -> Self { limit.to_string() }
+}
+
+/// Gap limits used by indexers to stop scanning a keychain after a given number of consecutive
+/// unused addresses. For multi-descriptor wallets each wallet keychain is configured separately.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct GapLimits {
+    /// Gap limit applied to keychains lacking a specific value.
+    pub shared: NonZeroUsize,
+    /// Gap limits for specific keychains.
+    pub keychains: BTreeMap<Keychain, NonZeroUsize>,
+    /// Double the gap limit of a keychain each time [`ADAPTIVE_GAP_RUN`] consecutive addresses
+    /// were found used, up to [`ADAPTIVE_GAP_CAP`].
+    pub adaptive: bool,
+}
+
+impl Default for GapLimits {
+    fn default() -> Self {
+        GapLimits {
+            shared: NonZeroUsize::new(DEFAULT_GAP_LIMIT).expect("non-zero constant"),
+            keychains: none!(),
+            adaptive: false,
+        }
+    }
+}
+
+impl FromIterator<GapLimit> for GapLimits {
+    /// Constructs gap limits from a list of limits, where the later items override earlier ones.
+    fn from_iter<T: IntoIterator<Item = GapLimit>>(iter: T) -> Self {
+        let mut limits = GapLimits::default();
+        for limit in iter {
+            match limit {
+                GapLimit::Shared(n) => limits.shared = n,
+                GapLimit::Keychain(keychain, n) => {
+                    limits.keychains.insert(keychain, n);
+                }
+            }
+        }
+        limits
+    }
+}
+
+impl GapLimits {
+    pub fn for_keychain(&self, keychain: Keychain) -> usize {
+        self.keychains.get(&keychain).copied().unwrap_or(self.shared).get()
+    }
+
+    /// Constructs tracker applying the gap limit to the scan of the given keychain.
+    pub fn tracker(&self, keychain: Keychain) -> GapTracker {
+        GapTracker {
+            limit: self.for_keychain(keychain),
+            adaptive: self.adaptive,
+            empty: 0,
+            used: 0,
+        }
+    }
+}
+
+/// Tracks consecutive used and unused addresses during a keychain scan, deciding when the scan
+/// has to stop.
+///
+/// With the adaptive gap limit the effective limit only grows, and never above the cap, so the
+/// scan still stops after a bounded number of consecutive unused addresses.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct GapTracker {
+    limit: usize,
+    adaptive: bool,
+    empty: usize,
+    used: usize,
+}
+
+impl GapTracker {
+    /// Effective gap limit at the current scan position.
+    pub fn limit(&self) -> usize { self.limit }
+
+    /// Registers next scanned address, returning `true` if the keychain scan must stop.
+    pub fn register(&mut self, used: bool) -> bool {
+        if !used {
+            self.used = 0;
+            self.empty += 1;
+            return self.empty >= self.limit;
+        }
+        self.empty = 0;
+        self.used += 1;
+        if self.adaptive && self.used % ADAPTIVE_GAP_RUN == 0 {
+            self.limit = (self.limit * 2).min(self.limit.max(ADAPTIVE_GAP_CAP));
+        }
+        false
+    }
+}
+
+/// Interval at which the wallet cache is checkpointed to its persistence provider during
+/// indexer scans. The default value keeps the cache in memory until the scan completes.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct FlushInterval {
+    /// Flush the cache each time the given number of addresses was scanned.
+    pub addresses: Option<NonZeroUsize>,
+    /// Flush the cache each time the given number of transactions was retrieved.
+    pub transactions: Option<NonZeroUsize>,
+}
+
+/// Error which has happened during an indexer scan, with the derived wallet address at which it
+/// has happened, if the error is specific to a single address.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct IndexerError<E> {
+    pub derive: Option<DerivedAddr>,
+    pub source: E,
+}
+
+impl<E> From<E> for IndexerError<E> {
+    fn from(source: E) -> Self {
+        IndexerError {
+            derive: None,
+            source,
+        }
+    }
+}
+
+impl<E> IndexerError<E> {
+    pub fn with_derive(derive: DerivedAddr, source: impl Into<E>) -> Self {
+        IndexerError {
+            derive: Some(derive),
+            source: source.into(),
+        }
+    }
+
+    /// Converts the error source, keeping the address at which the error has happened.
+    pub fn map_source<E2>(self, f: impl FnOnce(E) -> E2) -> IndexerError<E2> {
+        IndexerError {
+            derive: self.derive,
+            source: f(self.source),
+        }
+    }
+}
+
+impl<E: Display> Display for IndexerError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.derive {
+            Some(derive) => {
+                write!(f, "failed scanning {} ({}): {}", derive.terminal, derive.addr, self.source)
+            }
+            None => Display::fmt(&self.source, f),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for IndexerError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> { Some(&self.source) }
+}
+
+/// Callback receiving the progress of indexer scans, invoked after each wallet address is
+/// scanned with the address keychain, its derivation index and the number of transactions found
+/// for the address.
+pub struct ScanProgress(Box<dyn FnMut(Keychain, NormalIndex, usize) + Send>);
+
+impl Debug for ScanProgress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { f.write_str("ScanProgress(..)") }
+}
+
+impl ScanProgress {
+    pub fn new(callback: impl FnMut(Keychain, NormalIndex, usize) + Send + 'static) -> Self {
+        ScanProgress(Box::new(callback))
+    }
+
+    pub fn report(&mut self, keychain: Keychain, index: NormalIndex, tx_count: usize) {
+        (self.0)(keychain, index, tx_count)
+    }
+}
 
 #[cfg(any(feature = "electrum", feature = "esplora"))]
-const BATCH_SIZE: usize = 10;
+pub(crate) struct Checkpoint {
+    interval: FlushInterval,
+    addresses: usize,
+    transactions: usize,
+}
+
+#[cfg(any(feature = "electrum", feature = "esplora"))]
+impl Checkpoint {
+    pub fn new(interval: FlushInterval) -> Self {
+        Checkpoint {
+            interval,
+            addresses: 0,
+            transactions: 0,
+        }
+    }
+
+    /// Registers a scanned address with the number of its transactions.
+    pub fn register(&mut self, tx_count: usize) {
+        self.addresses += 1;
+        self.transactions += tx_count;
+    }
+
+    /// Flushes the cache once any of the configured intervals is reached.
+    ///
+    /// Must be called only at the boundaries of scanned addresses or their batches, once all the
+    /// scanned addresses are put into the address `index`. The cache is stored with the outputs
+    /// and inputs of the indexed transactions processed, while the cache itself is left
+    /// unprocessed until the scan completes.
+    pub fn flush<L2: Layer2Cache>(
+        &mut self,
+        cache: &WalletCache<L2>,
+        network: Network,
+        index: &AddressIndex,
+    ) {
+        let due = self.interval.addresses.is_some_and(|n| self.addresses >= n.get())
+            || self.interval.transactions.is_some_and(|n| self.transactions >= n.get());
+        if !due {
+            return;
+        }
+        self.addresses = 0;
+        self.transactions = 0;
+        let res = cache.checkpoint(|snapshot| {
+            let mut index = index.clone();
+            process_outputs(snapshot, network, &mut index);
+            process_inputs(snapshot, network, &mut index);
+        });
+        if let Err(e) = res {
+            #[cfg(feature = "log")]
+            log::error!("unable to checkpoint wallet cache during the scan: {e}");
+            #[cfg(not(feature = "log"))]
+            eprintln!("unable to checkpoint wallet cache during the scan: {e}")
+        }
+    }
+}
+
+/// Wallet addresses discovered during an indexer scan together with the ids of the transactions
+/// touching them, keyed by the address script.
+#[cfg(any(feature = "electrum", feature = "esplora", feature = "bitcoind"))]
+pub(crate) type AddressIndex = BTreeMap<ScriptPubkey, (WalletAddr<i64>, Vec<Txid>)>;
+
+/// Registers a scanned wallet address in the address index.
+///
+/// A script may be derived at more than a single terminal only if the same keys are misused
+/// across several keychains. In this case the script stays attributed to the terminal scanned
+/// first, and the transactions found for the other terminals are merged into it, such that the
+/// script balance is accounted only once.
+#[cfg(any(feature = "electrum", feature = "esplora", feature = "bitcoind"))]
+pub(crate) fn index_address(index: &mut AddressIndex, derive: DerivedAddr, txids: Vec<Txid>) {
+    let script = derive.addr.script_pubkey();
+    let (_, known) = index.entry(script).or_insert_with(|| (WalletAddr::from(derive), vec![]));
+    for txid in txids {
+        if !known.contains(&txid) {
+            known.push(txid);
+        }
+    }
+}
+
+/// Assigns the outputs of the scanned transactions to the wallet addresses, registering them as
+/// unspent and adding their value to the address balances.
+///
+/// Must be called for the whole address index before [`process_inputs`]: a transaction found for
+/// a keychain-1 (change) address may spend an output of a keychain-0 address, and the outputs
+/// have to be already registered for their spending to be accounted. Each output contributes to
+/// an address balance only once, even if its transaction is listed multiple times for the same
+/// script.
+#[cfg(any(feature = "electrum", feature = "esplora", feature = "bitcoind"))]
+pub(crate) fn process_outputs<L2C: Layer2Cache>(
+    cache: &mut WalletCache<L2C>,
+    network: Network,
+    index: &mut AddressIndex,
+) {
+    let mut contributed = BTreeSet::<(Outpoint, ScriptPubkey)>::new();
+    for (script, (wallet_addr, txids)) in index {
+        for txid in txids {
+            let mut tx = cache.tx.remove(txid).expect("broken logic");
+            for debit in &mut tx.outputs {
+                let Some(s) = debit.beneficiary.script_pubkey() else {
+                    continue;
+                };
+                if &s == script {
+                    cache.utxo.insert(debit.outpoint);
+                    debit.beneficiary = Party::from_wallet_addr(wallet_addr);
+                    if !contributed.insert((debit.outpoint, s)) {
+                        continue;
+                    }
+                    wallet_addr.used = wallet_addr.used.saturating_add(1);
+                    wallet_addr.volume.saturating_add_assign(debit.value);
+                    wallet_addr.balance = wallet_addr
+                        .balance
+                        .saturating_add(debit.value.sats().try_into().expect("sats overflow"));
+                } else if debit.beneficiary.is_unknown() {
+                    Address::with(&s, network)
+                        .map(|addr| {
+                            debit.beneficiary = Party::Counterparty(addr);
+                        })
+                        .ok();
+                }
+            }
+            cache.tx.insert(tx.txid, tx);
+        }
+    }
+}
+
+/// Assigns the inputs of the scanned transactions to the wallet addresses, subtracting the spent
+/// value from the address balances and marking the spent outputs, and stores the resulting
+/// address balances in the cache. Must be called after [`process_outputs`].
+///
+/// Outputs spent by mined transactions are removed from the unspent ones, while outputs spent by
+/// unconfirmed transactions are kept unspent and registered as pending spent.
+#[cfg(any(feature = "electrum", feature = "esplora", feature = "bitcoind"))]
+pub(crate) fn process_inputs<L2C: Layer2Cache>(
+    cache: &mut WalletCache<L2C>,
+    network: Network,
+    index: &mut AddressIndex,
+) {
+    let mut contributed = BTreeSet::<(Outpoint, ScriptPubkey)>::new();
+    // Unconfirmed spendings are re-discovered with each scan, while the ones evicted from the
+    // mempool must not be kept
+    cache.pending_spent.clear();
+    for (script, (wallet_addr, txids)) in index {
+        for txid in txids {
+            let mut tx = cache.tx.remove(txid).expect("broken logic");
+            for credit in &mut tx.inputs {
+                let Some(s) = credit.payer.script_pubkey() else {
+                    continue;
+                };
+                if &s == script {
+                    credit.payer = Party::from_wallet_addr(wallet_addr);
+                    if contributed.insert((credit.outpoint, s)) {
+                        wallet_addr.balance = wallet_addr
+                            .balance
+                            .saturating_sub(credit.value.sats().try_into().expect("sats overflow"));
+                    }
+                } else if credit.payer.is_unknown() {
+                    Address::with(&s, network)
+                        .map(|addr| {
+                            credit.payer = Party::Counterparty(addr);
+                        })
+                        .ok();
+                }
+                if let Some(prev_tx) = cache.tx.get_mut(&credit.outpoint.txid) {
+                    if let Some(txout) =
+                        prev_tx.outputs.get_mut(credit.outpoint.vout_u32() as usize)
+                    {
+                        let outpoint = txout.outpoint;
+                        if tx.status.is_mined() {
+                            cache.utxo.remove(&outpoint);
+                            cache.pending_spent.remove(&outpoint);
+                        } else if cache.utxo.contains(&outpoint) {
+                            cache.pending_spent.insert(outpoint);
+                        }
+                        txout.spent = Some(credit.outpoint.into())
+                    };
+                }
+            }
+            cache.tx.insert(tx.txid, tx);
+        }
+        // Replaces address data cached by the previous scans
+        cache
+            .addr
+            .entry(wallet_addr.terminal.keychain)
+            .or_default()
+            .replace(wallet_addr.expect_transmute());
+    }
+}
+
+/// Returns ids of the cached mined transactions. Must be called before the indexer scan updates
+/// the cache, to detect reorgs with [`rollback_reorgs`] afterwards.
+#[cfg(any(feature = "electrum", feature = "esplora", feature = "bitcoind"))]
+pub(crate) fn mined_txids<L2C: Layer2Cache>(cache: &WalletCache<L2C>) -> BTreeSet<Txid> {
+    cache.tx.values().filter(|tx| tx.status.is_mined()).map(|tx| tx.txid).collect()
+}
+
+/// Detects transactions which were mined before the scan, but were reorged out of the chain,
+/// and rolls back their effects. Returns the number of the reorged transactions.
+///
+/// Transactions reported by the scan have their status and outputs already updated by
+/// [`process_outputs`] and [`process_inputs`], so they are only counted if they are not mined
+/// anymore. For the transactions missing from the scan results the indexer is queried with
+/// `status`, since they may also be missing because the scan covered fewer addresses: the
+/// transactions unknown to the indexer are dropped from the cache, restoring the outputs they
+/// have spent, and the ones returned to the mempool are downgraded to unconfirmed. Transactions
+/// touching addresses of a keychain which scan has failed with one of the `errors` are left
+/// intact, since they may be missing just because of the failure. Must be called after
+/// [`process_inputs`].
+#[cfg(any(feature = "electrum", feature = "esplora", feature = "bitcoind"))]
+pub(crate) fn rollback_reorgs<L2C: Layer2Cache, E>(
+    cache: &mut WalletCache<L2C>,
+    index: &AddressIndex,
+    mined: BTreeSet<Txid>,
+    status: impl Fn(Txid) -> Result<TxStatus, E>,
+    errors: &mut Vec<IndexerError<E>>,
+) -> usize {
+    let reported = index.values().flat_map(|(_, txids)| txids).collect::<BTreeSet<_>>();
+    let failed = errors
+        .iter()
+        .filter_map(|err| err.derive.as_ref())
+        .map(|derive| derive.terminal.keychain)
+        .collect::<BTreeSet<_>>();
+    let mut count = 0usize;
+    for txid in mined {
+        if reported.contains(&txid) {
+            if cache.tx.get(&txid).is_some_and(|tx| !tx.status.is_mined()) {
+                count += 1;
+            }
+            continue;
+        }
+        let incomplete = cache.tx.get(&txid).is_some_and(|tx| {
+            tx.inputs
+                .iter()
+                .filter_map(|credit| credit.derived_addr())
+                .chain(tx.outputs.iter().filter_map(|debit| debit.derived_addr()))
+                .any(|derive| failed.contains(&derive.terminal.keychain))
+        });
+        if incomplete {
+            continue;
+        }
+        let status = match status(txid) {
+            Ok(status @ (TxStatus::Unknown | TxStatus::Mempool)) => status,
+            Ok(_) => continue,
+            Err(err) => {
+                errors.push(err.into());
+                continue;
+            }
+        };
+        let Some(mut tx) = cache.tx.remove(&txid) else {
+            continue;
+        };
+        count += 1;
+        let dropped = status == TxStatus::Unknown;
+        if dropped {
+            for debit in &tx.outputs {
+                cache.utxo.remove(&debit.outpoint);
+                cache.pending_spent.remove(&debit.outpoint);
+            }
+        }
+        for credit in &tx.inputs {
+            let Some(prev_tx) = cache.tx.get_mut(&credit.outpoint.txid) else {
+                continue;
+            };
+            let Some(txout) = prev_tx.outputs.get_mut(credit.outpoint.vout_u32() as usize) else {
+                continue;
+            };
+            if !txout.is_ourself() {
+                continue;
+            }
+            cache.utxo.insert(txout.outpoint);
+            if dropped {
+                txout.spent = None;
+            } else {
+                cache.pending_spent.insert(txout.outpoint);
+            }
+        }
+        if !dropped {
+            tx.status = TxStatus::Mempool;
+            cache.tx.insert(txid, tx);
+        }
+    }
+    count
+}
+
+/// Request which is not supported by an indexer.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display, Error)]
+#[display("indexer doesn't support {0}.")]
+pub struct UnsupportedRequest(pub &'static str);
+
+/// Reason for which a transaction was rejected for broadcast by an indexer or by the node behind
+/// it.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum BroadcastError {
+    /// transaction is already known to the network.
+    AlreadyKnown,
+
+    /// transaction fee rate is below the minimal relay fee rate.
+    MinRelayFee,
+
+    /// transaction spends outputs which are unknown or already spent.
+    MissingInputs,
+
+    /// transaction is not final since its lock time or relative lock time is not yet satisfied.
+    NonFinal,
+
+    /// transaction was rejected: {0}
+    Other(String),
+}
+
+impl BroadcastError {
+    /// Classifies the rejection reason reported by a node or an indexer.
+    pub fn with_reason(reason: &str) -> Self {
+        let lowercase = reason.to_lowercase();
+        let has = |patterns: &[&str]| patterns.iter().any(|p| lowercase.contains(p));
+        if has(&["txn-already", "already known", "already in block chain", "already in mempool"]) {
+            BroadcastError::AlreadyKnown
+        } else if has(&["min relay fee", "min-relay-fee", "mempool min fee"]) {
+            BroadcastError::MinRelayFee
+        } else if has(&["missing-inputs", "missing inputs", "missingorspent"]) {
+            BroadcastError::MissingInputs
+        } else if has(&["non-final", "non-bip68-final"]) {
+            BroadcastError::NonFinal
+        } else {
+            BroadcastError::Other(reason.trim().to_owned())
+        }
+    }
+}
 
 pub trait Indexer {
-    type Error;
+    type Error: From<UnsupportedRequest>;
 
     fn create<K, D: Descriptor<K>, L2: Layer2>(
         &self,
         descr: &WalletDescr<K, D, L2::Descr>,
-    ) -> MayError<WalletCache<L2::Cache>, Vec<Self::Error>>;
+    ) -> MayError<WalletCache<L2::Cache>, Vec<IndexerError<Self::Error>>>;
 
     fn update<K, D: Descriptor<K>, L2: Layer2>(
         &self,
         descr: &WalletDescr<K, D, L2::Descr>,
         cache: &mut WalletCache<L2::Cache>,
-    ) -> MayError<usize, Vec<Self::Error>>;
+    ) -> MayError<usize, Vec<IndexerError<Self::Error>>>;
+
+    /// Publishes the transaction, returning its id. Rejections of the transaction by the network
+    /// are reported as [`BroadcastError`] by the indexers able to detect them.
+    fn publish(&self, tx: &Tx) -> Result<Txid, Self::Error>;
+
+    /// Queries the indexer for the status of a transaction, returning [`TxStatus::Unknown`] if
+    /// the transaction is neither mined nor present in the mempool.
+    ///
+    /// Fails with [`UnsupportedRequest`] if the indexer can't query transaction status.
+    fn status(&self, _txid: Txid) -> Result<TxStatus, Self::Error> {
+        Err(UnsupportedRequest("transaction status queries").into())
+    }
+
+    /// Queries the indexer for the fee rate (in sats per vbyte) required for the transaction to
+    /// be mined within `target` blocks. Returns `None` if the indexer has no estimate.
+    ///
+    /// Fails with [`UnsupportedRequest`] if the indexer doesn't provide fee estimates.
+    fn fee_rate(&self, _target: u16) -> Result<Option<f64>, Self::Error> {
+        Err(UnsupportedRequest("fee estimates").into())
+    }
+
+    /// Queries the indexer for fee rates (in sats per vbyte) for multiple confirmation targets.
+    /// Targets for which the indexer has no estimate are omitted from the result.
+    ///
+    /// The default implementation queries [`Indexer::fee_rate`] for each of the targets, thus
+    /// failing with [`UnsupportedRequest`] if the indexer doesn't provide fee estimates; indexers
+    /// able to provide all estimates with a single request should override it.
+    fn fee_estimates(&self, targets: &[u16]) -> Result<BTreeMap<u16, f64>, Self::Error> {
+        let mut estimates = BTreeMap::new();
+        for target in targets {
+            if let Some(rate) = self.fee_rate(*target)? {
+                estimates.insert(*target, rate);
+            }
+        }
+        Ok(estimates)
+    }
+
+    /// Retrieves the mempool fee-rate histogram as a list of `(fee rate, vsize)` buckets, where
+    /// the fee rate is in sats per vbyte and the buckets are ordered from the highest fee rate
+    /// to the lowest. Returns `None` if the indexer doesn't provide the histogram.
+    fn mempool_histogram(&self) -> Result<Option<Vec<(f64, u64)>>, Self::Error> { Ok(None) }
+
+    /// Retrieves height of the most recent block known to the indexer. Returns `None` if the
+    /// indexer doesn't provide it.
+    fn tip_height(&self) -> Result<Option<u32>, Self::Error> { Ok(None) }
+
+    /// Retrieves height and hash of the most recent block known to the indexer. Returns `None` if
+    /// the indexer doesn't provide them.
+    fn chain_tip(&self) -> Result<Option<(u32, BlockHash)>, Self::Error> { Ok(None) }
+
+    /// Retrieves the full transaction with the given id from the indexer. Returns `None` if the
+    /// transaction is not known to the indexer, for instance when it was pruned or dropped from
+    /// the mempool.
+    ///
+    /// Fails with [`UnsupportedRequest`] if the indexer can't retrieve transactions.
+    fn raw_tx(&self, _txid: Txid) -> Result<Option<Tx>, Self::Error> {
+        Err(UnsupportedRequest("transaction retrieval").into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn per_keychain_gap_limits() {
+        let limits = ["20", "1=50", "9=5", "20=1"]
+            .into_iter()
+            .map(GapLimit::from_str)
+            .collect::<Result<GapLimits, _>>()
+            .unwrap();
+        assert_eq!(limits.for_keychain(Keychain::from(0)), 20);
+        assert_eq!(limits.for_keychain(Keychain::from(1)), 50);
+        assert_eq!(limits.for_keychain(Keychain::from(9)), 5);
+        assert_eq!(limits.for_keychain(Keychain::from(20)), 1);
+        assert_eq!(GapLimits::default().for_keychain(Keychain::from(1)), DEFAULT_GAP_LIMIT);
+        assert_eq!(GapLimit::from_str("1=50").unwrap().to_string(), "1=50");
+        assert!(GapLimit::from_str("1=0").is_err());
+    }
+
+    #[test]
+    fn adaptive_gap_limit() {
+        let scan = |limits: &GapLimits, used: usize| {
+            let mut tracker = limits.tracker(Keychain::OUTER);
+            let count = (0..used)
+                .map(|_| true)
+                .chain([false; ADAPTIVE_GAP_CAP * 2])
+                .position(|is_used| tracker.register(is_used));
+            (count.map(|pos| pos - used + 1), tracker.limit())
+        };
+        let mut limits = GapLimits::default();
+        assert_eq!(
+            scan(&limits, ADAPTIVE_GAP_RUN * 4),
+            (Some(DEFAULT_GAP_LIMIT), DEFAULT_GAP_LIMIT)
+        );
+
+        limits.adaptive = true;
+        assert_eq!(
+            scan(&limits, ADAPTIVE_GAP_RUN - 1),
+            (Some(DEFAULT_GAP_LIMIT), DEFAULT_GAP_LIMIT)
+        );
+        assert_eq!(scan(&limits, ADAPTIVE_GAP_RUN), (Some(20), 20));
+        assert_eq!(scan(&limits, ADAPTIVE_GAP_RUN * 4), (Some(160), 160));
+        assert_eq!(
+            scan(&limits, ADAPTIVE_GAP_RUN * 10),
+            (Some(ADAPTIVE_GAP_CAP), ADAPTIVE_GAP_CAP)
+        );
+
+        limits.shared = NonZeroUsize::new(ADAPTIVE_GAP_CAP + 1).unwrap();
+        assert_eq!(
+            scan(&limits, ADAPTIVE_GAP_RUN),
+            (Some(ADAPTIVE_GAP_CAP + 1), ADAPTIVE_GAP_CAP + 1)
+        );
+    }
+
+    #[test]
+    fn indexer_error_display() {
+        let addr = bpstd::Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap();
+        let derive = DerivedAddr::new(addr, Keychain::from(1), NormalIndex::from(5u8));
+        let err = IndexerError::<String>::with_derive(derive, "timeout");
+        assert_eq!(
+            err.to_string(),
+            "failed scanning &1/5 (bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4): timeout"
+        );
+        assert_eq!(IndexerError::from(s!("timeout")).to_string(), "timeout");
+    }
+
+    #[test]
+    fn broadcast_error_reason() {
+        assert_eq!(BroadcastError::with_reason("txn-already-known"), BroadcastError::AlreadyKnown);
+        assert_eq!(
+            BroadcastError::with_reason("Transaction already in block chain"),
+            BroadcastError::AlreadyKnown
+        );
+        assert_eq!(
+            BroadcastError::with_reason("min relay fee not met, 100 < 141"),
+            BroadcastError::MinRelayFee
+        );
+        assert_eq!(
+            BroadcastError::with_reason("bad-txns-inputs-missingorspent"),
+            BroadcastError::MissingInputs
+        );
+        assert_eq!(BroadcastError::with_reason("non-BIP68-final"), BroadcastError::NonFinal);
+        assert_eq!(BroadcastError::with_reason(" dust \n"), BroadcastError::Other(s!("dust")));
+    }
+
+    #[test]
+    fn unsupported_requests() {
+        struct Minimal;
+        impl Indexer for Minimal {
+            type Error = UnsupportedRequest;
+
+            fn create<K, D: Descriptor<K>, L2: Layer2>(
+                &self,
+                _descr: &WalletDescr<K, D, L2::Descr>,
+            ) -> MayError<WalletCache<L2::Cache>, Vec<IndexerError<Self::Error>>> {
+                unreachable!()
+            }
+
+            fn update<K, D: Descriptor<K>, L2: Layer2>(
+                &self,
+                _descr: &WalletDescr<K, D, L2::Descr>,
+                _cache: &mut WalletCache<L2::Cache>,
+            ) -> MayError<usize, Vec<IndexerError<Self::Error>>> {
+                unreachable!()
+            }
+
+            fn publish(&self, _tx: &Tx) -> Result<Txid, Self::Error> { unreachable!() }
+        }
+
+        let txid = Txid::from([0u8; 32]);
+        let err = Minimal.status(txid).unwrap_err();
+        assert_eq!(err.to_string(), "indexer doesn't support transaction status queries.");
+        assert_eq!(Minimal.raw_tx(txid), Err(UnsupportedRequest("transaction retrieval")));
+        assert_eq!(Minimal.fee_rate(1), Err(UnsupportedRequest("fee estimates")));
+        assert_eq!(Minimal.fee_estimates(&[1, 6]), Err(UnsupportedRequest("fee estimates")));
+        assert_eq!(Minimal.mempool_histogram(), Ok(None));
+    }
+
+    /// Constructs cache with a mined transaction receiving 10000 sats to the address and a
+    /// transaction with the given status spending them, which sends 4000 sats back to the same
+    /// address as change.
+    #[cfg(any(feature = "electrum", feature = "esplora", feature = "bitcoind"))]
+    fn spending_fixture(
+        addr: bpstd::Address,
+        status: TxStatus,
+    ) -> (WalletCache<crate::Layer2Empty>, Txid, Txid) {
+        use bpstd::{Address, LockTime, Outpoint, Sats, SeqNo, TxVer, Vout};
+
+        use crate::{MiningInfo, Party, TxCredit, TxDebit, WalletTx};
+
+        let other = Address::from_str("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq").unwrap();
+        let tx = |txid: Txid, status, inputs, outputs: &[(Address, u64)]| WalletTx {
+            txid,
+            status,
+            inputs,
+            outputs: outputs
+                .iter()
+                .enumerate()
+                .map(|(vout, (addr, value))| TxDebit {
+                    outpoint: Outpoint::new(txid, Vout::from_u32(vout as u32)),
+                    beneficiary: Party::Unknown(addr.script_pubkey()),
+                    value: Sats::from(*value),
+                    spent: None,
+                })
+                .collect(),
+            fee: Sats::ZERO,
+            size: 0,
+            weight: 0,
+            version: TxVer::V2,
+            locktime: LockTime::ZERO,
+        };
+        let funding = Txid::from([1u8; 32]);
+        let spending = Txid::from([2u8; 32]);
+        let credit = TxCredit {
+            outpoint: Outpoint::new(funding, Vout::from_u32(0)),
+            payer: Party::Unknown(addr.script_pubkey()),
+            sequence: SeqNo::ZERO,
+            coinbase: false,
+            script_sig: none!(),
+            witness: none!(),
+            value: Sats::from(10_000u64),
+        };
+
+        let mut cache = WalletCache::new_nonsync();
+        let mined = TxStatus::Mined(MiningInfo::genesis());
+        cache.tx.insert(funding, tx(funding, mined, vec![], &[(addr, 10_000)]));
+        let outputs = [(other, 6_000), (addr, 4_000)];
+        cache.tx.insert(spending, tx(spending, status, vec![credit], &outputs));
+        (cache, funding, spending)
+    }
+
+    #[cfg(any(feature = "electrum", feature = "esplora"))]
+    #[test]
+    fn checkpoint_snapshot() {
+        use std::sync::{Arc, Mutex};
+
+        use bpstd::{Address, Idx, Network, Outpoint, Sats, Vout};
+        use nonasync::persistence::{
+            CloneNoPersistence, PersistenceError, PersistenceProvider, Persisting,
+        };
+
+        use crate::Layer2Empty;
+
+        #[derive(Debug, Default)]
+        struct Captured(Arc<Mutex<Option<WalletCache<Layer2Empty>>>>);
+
+        impl PersistenceProvider<WalletCache<Layer2Empty>> for Captured {
+            fn load(&self) -> Result<WalletCache<Layer2Empty>, PersistenceError> { unreachable!() }
+
+            fn store(&self, object: &WalletCache<Layer2Empty>) -> Result<(), PersistenceError> {
+                *self.0.lock().unwrap() = Some(object.clone_no_persistence());
+                Ok(())
+            }
+        }
+
+        let addr = Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap();
+        let (mut cache, funding, spending) = spending_fixture(addr, TxStatus::Mempool);
+        let captured = Captured::default();
+        let stored = captured.0.clone();
+        cache.make_persistent(captured, false).unwrap();
+
+        let mut checkpoint = Checkpoint::new(FlushInterval {
+            addresses: NonZeroUsize::new(2),
+            transactions: None,
+        });
+        let mut index = AddressIndex::new();
+        let derive = DerivedAddr::new(addr, Keychain::OUTER, NormalIndex::ZERO);
+        index_address(&mut index, derive, vec![funding, spending]);
+        *stored.lock().unwrap() = None;
+
+        checkpoint.register(2);
+        checkpoint.flush(&cache, Network::Mainnet, &index);
+        assert!(stored.lock().unwrap().is_none());
+
+        checkpoint.register(0);
+        checkpoint.flush(&cache, Network::Mainnet, &index);
+        let snapshot = stored.lock().unwrap().take().unwrap();
+        // Stored cache has the transactions processed, while the cache itself is left intact
+        let change = Outpoint::new(spending, Vout::from_u32(1));
+        assert_eq!(snapshot.utxos().map(|utxo| utxo.outpoint).collect::<Vec<_>>(), vec![change]);
+        assert_eq!(snapshot.coins().map(|row| row.amount).sum::<Sats>(), Sats::from(4_000u64));
+        assert!(cache.utxo.is_empty());
+        assert!(cache.addr.is_empty());
+    }
+
+    #[cfg(any(feature = "electrum", feature = "esplora", feature = "bitcoind"))]
+    #[test]
+    fn shared_script_across_keychains() {
+        use bpstd::{Address, Idx, Network, Outpoint, Sats, Vout};
+
+        use crate::MiningInfo;
+
+        let addr = Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap();
+        let (mut cache, funding, spending) =
+            spending_fixture(addr, TxStatus::Mined(MiningInfo::genesis()));
+
+        // The script is derived both at the external and the change keychains, and each of the
+        // terminals reports the same transactions
+        let mut index = AddressIndex::new();
+        for keychain in [Keychain::OUTER, Keychain::INNER] {
+            let derive = DerivedAddr::new(addr, keychain, NormalIndex::ZERO);
+            index_address(&mut index, derive, vec![funding, spending]);
+        }
+        process_outputs(&mut cache, Network::Mainnet, &mut index);
+        process_inputs(&mut cache, Network::Mainnet, &mut index);
+
+        let spent = Outpoint::new(funding, Vout::from_u32(0));
+        let change = Outpoint::new(spending, Vout::from_u32(1));
+        assert_eq!(cache.utxo.iter().copied().collect::<Vec<_>>(), vec![change]);
+        assert!(!cache.utxo.contains(&spent));
+        assert!(!cache.addr.contains_key(&Keychain::INNER));
+        let outer = cache.addr.get(&Keychain::OUTER).unwrap();
+        assert_eq!(outer.len(), 1);
+        let wallet_addr = outer.first().unwrap();
+        assert_eq!(wallet_addr.balance, Sats::from(4_000u64));
+        assert_eq!(wallet_addr.used, 2);
+        assert_eq!(wallet_addr.volume, Sats::from(14_000u64));
+    }
+
+    #[cfg(any(feature = "electrum", feature = "esplora", feature = "bitcoind"))]
+    #[test]
+    fn mempool_spending() {
+        use bpstd::{Address, Idx, Network, Outpoint, Sats, Vout};
+
+        let addr = Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap();
+        let (mut cache, funding, spending) = spending_fixture(addr, TxStatus::Mempool);
+
+        let mut index = AddressIndex::new();
+        let derive = DerivedAddr::new(addr, Keychain::OUTER, NormalIndex::ZERO);
+        index_address(&mut index, derive, vec![funding, spending]);
+        process_outputs(&mut cache, Network::Mainnet, &mut index);
+        process_inputs(&mut cache, Network::Mainnet, &mut index);
+
+        let spent = Outpoint::new(funding, Vout::from_u32(0));
+        let change = Outpoint::new(spending, Vout::from_u32(1));
+        assert!(cache.is_unspent(spent));
+        assert!(cache.is_pending_spent(spent));
+        assert_eq!(cache.utxos().map(|utxo| utxo.outpoint).collect::<Vec<_>>(), vec![change]);
+        assert_eq!(cache.coins().map(|row| row.outpoint).collect::<Vec<_>>(), vec![change]);
+        assert_eq!(cache.pending_coins().map(|row| row.outpoint).collect::<Vec<_>>(), vec![spent]);
+        assert_eq!(cache.coins().map(|row| row.amount).sum::<Sats>(), Sats::from(4_000u64));
+
+        // Once the spending transaction is mined, the coin is not pending anymore
+        let pending = cache.pending_spent.clone();
+        let (mut cache, ..) = spending_fixture(addr, TxStatus::Mined(crate::MiningInfo::genesis()));
+        cache.pending_spent = pending;
+        let mut index = AddressIndex::new();
+        index_address(&mut index, derive, vec![funding, spending]);
+        process_outputs(&mut cache, Network::Mainnet, &mut index);
+        process_inputs(&mut cache, Network::Mainnet, &mut index);
+        assert!(!cache.is_unspent(spent));
+        assert!(cache.pending_spent.is_empty());
+    }
+
+    #[cfg(any(feature = "electrum", feature = "esplora", feature = "bitcoind"))]
+    #[test]
+    fn reorg_rollback() {
+        use bpstd::{Address, Idx, Network, Outpoint, Sats, Vout};
+        use nonasync::persistence::CloneNoPersistence;
+
+        use crate::MiningInfo;
+
+        let addr = Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap();
+        let derive = DerivedAddr::new(addr, Keychain::OUTER, NormalIndex::ZERO);
+        let mined = TxStatus::Mined(MiningInfo::genesis());
+        let spent = Outpoint::new(Txid::from([1u8; 32]), Vout::from_u32(0));
+        let change = Outpoint::new(Txid::from([2u8; 32]), Vout::from_u32(1));
+
+        for indexer_status in [TxStatus::Unknown, TxStatus::Mempool] {
+            let (mut cache, funding, spending) = spending_fixture(addr, mined);
+            let mut index = AddressIndex::new();
+            index_address(&mut index, derive, vec![funding, spending]);
+            let before = mined_txids(&cache);
+            process_outputs(&mut cache, Network::Mainnet, &mut index);
+            process_inputs(&mut cache, Network::Mainnet, &mut index);
+            let mut errors = vec![];
+            let status = |_| Ok::<_, ()>(TxStatus::Mined(MiningInfo::genesis()));
+            assert_eq!(rollback_reorgs(&mut cache, &index, before, status, &mut errors), 0);
+            assert_eq!(cache.utxo.iter().copied().collect::<Vec<_>>(), vec![change]);
+
+            // The spending transaction is reorged out and is not reported by the next scan
+            let (fresh, ..) = spending_fixture(addr, mined);
+            let before = mined_txids(&cache);
+            cache.tx.insert(funding, fresh.tx[&funding].clone());
+            let mut index = AddressIndex::new();
+            index_address(&mut index, derive, vec![funding]);
+            process_outputs(&mut cache, Network::Mainnet, &mut index);
+            process_inputs(&mut cache, Network::Mainnet, &mut index);
+            let status = |_| Ok::<_, ()>(indexer_status);
+
+            // Nothing is rolled back if the scan of the keychain has failed
+            let mut failed = cache.clone_no_persistence();
+            let utxo = failed.utxo.clone();
+            let mut failures = vec![IndexerError::with_derive(derive, ())];
+            let count = rollback_reorgs(&mut failed, &index, before.clone(), status, &mut failures);
+            assert_eq!(count, 0);
+            assert_eq!(failed.tx[&spending].status, mined);
+            assert_eq!(failed.utxo, utxo);
+
+            assert_eq!(rollback_reorgs(&mut cache, &index, before, status, &mut errors), 1);
+            assert!(errors.is_empty());
 
-    fn publish(&self, tx: &Tx) -> Result<(), Self::Error>;
+            assert!(cache.is_unspent(spent));
+            let wallet_addr = cache.addr[&Keychain::OUTER].first().unwrap();
+            assert_eq!(wallet_addr.balance, Sats::from(10_000u64));
+            if indexer_status == TxStatus::Unknown {
+                assert!(!cache.tx.contains_key(&spending));
+                assert!(!cache.is_unspent(change));
+                assert!(!cache.is_pending_spent(spent));
+                assert_eq!(cache.tx[&funding].outputs[0].spent, None);
+            } else {
+                assert_eq!(cache.tx[&spending].status, TxStatus::Mempool);
+                assert!(cache.is_unspent(change));
+                assert!(cache.is_pending_spent(spent));
+            }
+        }
+    }
 }
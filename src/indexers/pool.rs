@@ -0,0 +1,309 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::{self, Display, Formatter};
+use std::num::{NonZeroU32, ParseIntError};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use esplora::{BlockingClient, Error};
+
+/// Number of consecutive failed requests after which an endpoint is removed from the pool.
+pub const MAX_CONSECUTIVE_ERRORS: u32 = 3;
+
+/// Endpoint URL with its weight in a [`ClientPool`].
+///
+/// String representation has form of `<url>@<weight>` or just `<url>` for the weight of 1. Since
+/// the URL itself may contain `@` separating user credentials, the weight is taken only from the
+/// digits following the last `@`.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[display("{url}@{weight}")]
+pub struct WeightedUrl {
+    pub weight: NonZeroU32,
+    pub url: String,
+}
+
+impl FromStr for WeightedUrl {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.rsplit_once('@') {
+            Some((url, weight)) if weight.bytes().all(|b| b.is_ascii_digit()) => Ok(WeightedUrl {
+                weight: weight.parse()?,
+                url: url.to_owned(),
+            }),
+            _ => Ok(WeightedUrl {
+                weight: NonZeroU32::MIN,
+                url: s.to_owned(),
+            }),
+        }
+    }
+}
+
+/// Request statistics of a [`ClientPool`] endpoint.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct EndpointStats {
+    pub url: String,
+    pub weight: u32,
+    pub requests: u32,
+    pub errors: u32,
+    /// Whether the endpoint was removed from the pool after consecutive failures.
+    pub removed: bool,
+}
+
+impl EndpointStats {
+    /// Share of the failed requests.
+    pub fn error_rate(&self) -> f64 {
+        if self.requests == 0 {
+            return 0.0;
+        }
+        self.errors as f64 / self.requests as f64
+    }
+}
+
+impl Display for EndpointStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} requests, {:.0}% failed",
+            self.url,
+            self.requests,
+            self.error_rate() * 100.0
+        )?;
+        if self.removed {
+            f.write_str(", removed")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    url: String,
+    weight: u32,
+    client: BlockingClient,
+    requests: AtomicU32,
+    errors: AtomicU32,
+    consecutive_errors: AtomicU32,
+}
+
+impl Clone for Endpoint {
+    fn clone(&self) -> Self {
+        Endpoint {
+            url: self.url.clone(),
+            weight: self.weight,
+            client: self.client.clone(),
+            requests: AtomicU32::new(self.requests.load(Ordering::Relaxed)),
+            errors: AtomicU32::new(self.errors.load(Ordering::Relaxed)),
+            consecutive_errors: AtomicU32::new(self.consecutive_errors.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl Endpoint {
+    fn is_healthy(&self) -> bool {
+        self.consecutive_errors.load(Ordering::Relaxed) < MAX_CONSECUTIVE_ERRORS
+    }
+
+    fn register<T>(&self, res: &Result<T, Error>) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if res.is_ok() {
+            self.consecutive_errors.store(0, Ordering::Relaxed);
+            return;
+        }
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        if self.consecutive_errors.fetch_add(1, Ordering::Relaxed) + 1 == MAX_CONSECUTIVE_ERRORS {
+            log::warn!(
+                "removing esplora endpoint {} from the pool after {MAX_CONSECUTIVE_ERRORS} \
+                 consecutive failures",
+                self.url
+            );
+        }
+    }
+}
+
+/// Pool of Esplora clients routing each request to one of the healthy endpoints in proportion to
+/// their weights. Endpoints failing [`MAX_CONSECUTIVE_ERRORS`] requests in a row are removed from
+/// the rotation.
+#[derive(Debug)]
+pub struct ClientPool {
+    endpoints: Vec<Endpoint>,
+    tick: AtomicU64,
+}
+
+impl Clone for ClientPool {
+    fn clone(&self) -> Self {
+        ClientPool {
+            endpoints: self.endpoints.clone(),
+            tick: AtomicU64::new(self.tick.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl ClientPool {
+    /// Constructs pool from a list of weighted endpoint URLs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a client for any of the endpoints can't be constructed.
+    #[allow(clippy::result_large_err)]
+    pub fn new(urls: impl IntoIterator<Item = WeightedUrl>) -> Result<Self, Error> {
+        Self::with_proxy(urls, None)
+    }
+
+    /// Constructs pool from a list of weighted endpoint URLs, routing requests to all of the
+    /// endpoints through the HTTP `CONNECT` `proxy`, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a client for any of the endpoints can't be constructed.
+    #[allow(clippy::result_large_err)]
+    pub fn with_proxy(
+        urls: impl IntoIterator<Item = WeightedUrl>,
+        proxy: Option<&str>,
+    ) -> Result<Self, Error> {
+        let endpoints = urls
+            .into_iter()
+            .map(|WeightedUrl { weight, url }| {
+                Ok(Endpoint {
+                    client: super::esplora::builder(&url, proxy).build_blocking()?,
+                    url,
+                    weight: weight.get(),
+                    requests: AtomicU32::new(0),
+                    errors: AtomicU32::new(0),
+                    consecutive_errors: AtomicU32::new(0),
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(ClientPool {
+            endpoints,
+            tick: AtomicU64::new(0),
+        })
+    }
+
+    /// Returns request statistics for each of the pool endpoints.
+    pub fn stats(&self) -> Vec<EndpointStats> {
+        self.endpoints
+            .iter()
+            .map(|endpoint| EndpointStats {
+                url: endpoint.url.clone(),
+                weight: endpoint.weight,
+                requests: endpoint.requests.load(Ordering::Relaxed),
+                errors: endpoint.errors.load(Ordering::Relaxed),
+                removed: !endpoint.is_healthy(),
+            })
+            .collect()
+    }
+
+    fn select(&self) -> Option<&Endpoint> {
+        // Endpoints may get removed concurrently, so their health is checked only once
+        let healthy =
+            self.endpoints.iter().filter(|endpoint| endpoint.is_healthy()).collect::<Vec<_>>();
+        let total = healthy.iter().map(|endpoint| endpoint.weight as u64).sum::<u64>();
+        if total == 0 {
+            return None;
+        }
+        let mut pos = self.tick.fetch_add(1, Ordering::Relaxed) % total;
+        for endpoint in healthy {
+            if pos < endpoint.weight as u64 {
+                return Some(endpoint);
+            }
+            pos -= endpoint.weight as u64;
+        }
+        None
+    }
+
+    /// Runs request against a healthy endpoint selected by weight, retrying with other endpoints
+    /// on failure. Returns `None` if there are no healthy endpoints left in the pool.
+    #[allow(clippy::result_large_err)]
+    pub fn request<T>(
+        &self,
+        f: impl Fn(&BlockingClient) -> Result<T, Error>,
+    ) -> Option<Result<T, Error>> {
+        let mut last = None;
+        for _ in 0..self.endpoints.len() {
+            let Some(endpoint) = self.select() else {
+                break;
+            };
+            let res = f(&endpoint.client);
+            endpoint.register(&res);
+            if res.is_ok() {
+                return Some(res);
+            }
+            last = Some(res);
+        }
+        last
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pool() -> ClientPool {
+        ClientPool::new([
+            "http://a.example@3".parse().unwrap(),
+            "http://b.example".parse().unwrap(),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn weighted_url() {
+        let url = WeightedUrl::from_str("https://example.com/api@3").unwrap();
+        assert_eq!(url.weight.get(), 3);
+        assert_eq!(url.url, "https://example.com/api");
+        assert_eq!(url.to_string(), "https://example.com/api@3");
+        assert_eq!(WeightedUrl::from_str("https://example.com").unwrap().weight, NonZeroU32::MIN);
+        assert!(WeightedUrl::from_str("https://example.com@0").is_err());
+
+        let url = WeightedUrl::from_str("https://user:pw@example.com@3").unwrap();
+        assert_eq!(url.weight.get(), 3);
+        assert_eq!(url.url, "https://user:pw@example.com");
+        let url = WeightedUrl::from_str("https://user:pw@example.com").unwrap();
+        assert_eq!(url.weight, NonZeroU32::MIN);
+        assert_eq!(url.url, "https://user:pw@example.com");
+    }
+
+    #[test]
+    fn distribution() {
+        let pool = pool();
+        let count = (0..8).filter(|_| pool.select().unwrap().url == "http://a.example").count();
+        assert_eq!(count, 6);
+
+        pool.endpoints[0].consecutive_errors.store(MAX_CONSECUTIVE_ERRORS, Ordering::Relaxed);
+        assert!((0..4).all(|_| pool.select().unwrap().url == "http://b.example"));
+        pool.endpoints[1].consecutive_errors.store(MAX_CONSECUTIVE_ERRORS, Ordering::Relaxed);
+        assert!(pool.select().is_none());
+    }
+
+    #[test]
+    fn stats_display() {
+        let pool = pool();
+        pool.endpoints[0].requests.store(8, Ordering::Relaxed);
+        pool.endpoints[0].errors.store(2, Ordering::Relaxed);
+        pool.endpoints[1].consecutive_errors.store(MAX_CONSECUTIVE_ERRORS, Ordering::Relaxed);
+        let stats = pool.stats();
+        assert_eq!(stats[0].to_string(), "http://a.example: 8 requests, 25% failed");
+        assert_eq!(stats[1].to_string(), "http://b.example: 0 requests, 0% failed, removed");
+    }
+}
@@ -38,30 +38,48 @@ mod rows;
 mod wallet;
 mod layer2;
 pub mod coinselect;
+pub mod bip322;
 #[cfg(feature = "cli")]
 pub mod cli;
 #[cfg(feature = "signers")]
 pub mod hot;
 mod bip43;
+mod multidescr;
 #[cfg(feature = "fs")]
 pub mod fs;
 
 pub use bip43::{Bip43, DerivationStandard, ParseBip43Error};
 pub use bpstd::*;
 pub use data::{
-    BlockHeight, BlockInfo, MiningInfo, Party, TxCredit, TxDebit, TxStatus, WalletAddr, WalletTx,
-    WalletUtxo,
+    BlockHeight, BlockInfo, Inpoint, LabelTarget, LabelTargetParseError, MiningInfo, Party,
+    TxCredit, TxDebit, TxStatus, WalletAddr, WalletTx, WalletUtxo, COINBASE_MATURITY,
 };
 #[cfg(feature = "hot")]
 pub use hot::{HotArgs, HotCommand};
 #[cfg(feature = "signers")]
 pub use hot::{Seed, SeedType};
-pub use indexers::Indexer;
-#[cfg(any(feature = "electrum", feature = "esplora", feature = "mempool"))]
+#[cfg(any(
+    feature = "electrum",
+    feature = "esplora",
+    feature = "mempool",
+    feature = "bitcoind"
+))]
 pub use indexers::{AnyIndexer, AnyIndexerError};
+pub use indexers::{
+    BroadcastError, FlushInterval, GapLimit, GapLimits, GapTracker, Indexer, IndexerError,
+    ScanProgress, UnsupportedRequest, ADAPTIVE_GAP_CAP, ADAPTIVE_GAP_RUN, DEFAULT_GAP_LIMIT,
+};
 pub use layer2::{
     Layer2, Layer2Cache, Layer2Coin, Layer2Data, Layer2Descriptor, Layer2Empty, Layer2Tx, NoLayer2,
 };
-pub use rows::{CoinRow, Counterparty, OpType, TxRow};
+pub use multidescr::{
+    finalize_psbt, MultiDescr, MultiDescrError, KEYCHAINS_PER_DESCR, MAX_DESCRIPTORS,
+};
+pub use rows::{
+    CoinRow, Counterparty, Instruction, Instructions, OpType, ScriptClass, TruncatedPush, TxRow,
+};
 pub use util::MayError;
-pub use wallet::{Wallet, WalletCache, WalletData, WalletDescr};
+pub use wallet::{
+    BalanceChange, BalanceMismatch, RawTxError, TxDivergence, Wallet, WalletCache, WalletData,
+    WalletDescr, WalletStore, WalletSummary,
+};
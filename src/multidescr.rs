@@ -0,0 +1,408 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{self, Display, Formatter};
+
+use amplify::Wrapper;
+use bpstd::{
+    Derive, DerivedScript, Descriptor, KeyOrigin, Keychain, LegacyKeySig, LegacyPk, NormalIndex,
+    SigScript, SpkClass, TapDerivation, TaprootKeySig, Terminal, Witness, XOnlyPk, XpubAccount,
+};
+use indexmap::IndexMap;
+use psbt::Psbt;
+
+/// Number of wallet keychains reserved for each of the descriptors inside [`MultiDescr`].
+pub const KEYCHAINS_PER_DESCR: u8 = 16;
+
+/// Maximal number of descriptors which can be combined in a [`MultiDescr`].
+pub const MAX_DESCRIPTORS: usize = (u8::MAX as usize + 1) / KEYCHAINS_PER_DESCR as usize;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum MultiDescrError {
+    /// multi-descriptor must contain at least one descriptor.
+    Empty,
+
+    /// multi-descriptor can't contain more than 16 descriptors, while {0} were provided.
+    TooMany(usize),
+
+    /// descriptor #{0} uses keychain {1}, which exceeds the maximal keychain number 15 supported
+    /// by multi-descriptors.
+    KeychainOverflow(usize, Keychain),
+}
+
+/// Set of descriptors (which may use different script types) scanned and spent as a single
+/// wallet.
+///
+/// Keychains of each descriptor are namespaced: keychain `k` of the descriptor number `n` becomes
+/// the wallet keychain `n * 16 + k`. Thus, the first descriptor keeps its keychains unchanged and
+/// receives the change unless a keychain of other descriptor is selected for it.
+///
+/// Since the descriptors may differ in their script types, the descriptor class reported by
+/// [`Descriptor::class`] is the class of the first descriptor only. Wallet uses the script type
+/// of each spent coin and of the change keychain instead (see [`crate::Wallet::keychain_class`]),
+/// and PSBTs spending from the set must be finalized with [`finalize_psbt`].
+///
+/// A set of a single descriptor is serialized as the descriptor itself, so wallets created with
+/// a single descriptor can be loaded as sets.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        crate = "serde_crate",
+        try_from = "MultiDescrRepr<D>",
+        into = "MultiDescrRepr<D>",
+        bound(
+            serialize = "D: serde::Serialize + Clone",
+            deserialize = "D: serde::Deserialize<'de> + Derive<DerivedScript>"
+        )
+    )
+)]
+pub struct MultiDescr<D> {
+    descriptors: Vec<D>,
+}
+
+impl<D: Derive<DerivedScript>> TryFrom<Vec<D>> for MultiDescr<D> {
+    type Error = MultiDescrError;
+
+    fn try_from(descriptors: Vec<D>) -> Result<Self, Self::Error> {
+        if descriptors.is_empty() {
+            return Err(MultiDescrError::Empty);
+        }
+        if descriptors.len() > MAX_DESCRIPTORS {
+            return Err(MultiDescrError::TooMany(descriptors.len()));
+        }
+        for (no, descr) in descriptors.iter().enumerate() {
+            if let Some(keychain) =
+                descr.keychains().into_iter().find(|k| k.into_inner() >= KEYCHAINS_PER_DESCR)
+            {
+                return Err(MultiDescrError::KeychainOverflow(no, keychain));
+            }
+        }
+        Ok(MultiDescr { descriptors })
+    }
+}
+
+impl<D> From<MultiDescr<D>> for Vec<D> {
+    fn from(multi: MultiDescr<D>) -> Self { multi.descriptors }
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(crate = "serde_crate", untagged)]
+enum MultiDescrRepr<D> {
+    Single(D),
+    Set(Vec<D>),
+}
+
+#[cfg(feature = "serde")]
+impl<D: Derive<DerivedScript>> TryFrom<MultiDescrRepr<D>> for MultiDescr<D> {
+    type Error = MultiDescrError;
+
+    fn try_from(repr: MultiDescrRepr<D>) -> Result<Self, Self::Error> {
+        match repr {
+            MultiDescrRepr::Single(descr) => Self::try_from(vec![descr]),
+            MultiDescrRepr::Set(descriptors) => Self::try_from(descriptors),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<D> From<MultiDescr<D>> for MultiDescrRepr<D> {
+    fn from(mut multi: MultiDescr<D>) -> Self {
+        match multi.descriptors.len() {
+            1 => MultiDescrRepr::Single(multi.descriptors.remove(0)),
+            _ => MultiDescrRepr::Set(multi.descriptors),
+        }
+    }
+}
+
+impl<D> MultiDescr<D> {
+    pub fn with(descriptors: impl IntoIterator<Item = D>) -> Result<Self, MultiDescrError>
+    where D: Derive<DerivedScript> {
+        Self::try_from(descriptors.into_iter().collect::<Vec<_>>())
+    }
+
+    pub fn descriptors(&self) -> &[D] { &self.descriptors }
+
+    /// Converts descriptor-specific keychain into the wallet keychain.
+    pub fn wallet_keychain(descr_no: usize, keychain: Keychain) -> Keychain {
+        Keychain::with(descr_no as u8 * KEYCHAINS_PER_DESCR + keychain.into_inner())
+    }
+
+    /// Resolves wallet keychain into the descriptor and its own keychain.
+    pub fn resolve_keychain(&self, keychain: Keychain) -> Option<(&D, Keychain)> {
+        let no = (keychain.into_inner() / KEYCHAINS_PER_DESCR) as usize;
+        let descr = self.descriptors.get(no)?;
+        Some((descr, Keychain::with(keychain.into_inner() % KEYCHAINS_PER_DESCR)))
+    }
+
+    fn resolve(&self, terminal: Terminal) -> (&D, Terminal) {
+        let (descr, keychain) = self.resolve_keychain(terminal.keychain).unwrap_or_else(|| {
+            panic!("keychain {} is not a part of the wallet", terminal.keychain)
+        });
+        (descr, Terminal::new(keychain, terminal.index))
+    }
+}
+
+impl<D: Display> Display for MultiDescr<D> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (no, descr) in self.descriptors.iter().enumerate() {
+            if no > 0 {
+                f.write_str(", ")?;
+            }
+            Display::fmt(descr, f)?;
+        }
+        Ok(())
+    }
+}
+
+impl<D: Derive<DerivedScript>> Derive<DerivedScript> for MultiDescr<D> {
+    fn default_keychain(&self) -> Keychain { self.descriptors[0].default_keychain() }
+
+    fn keychains(&self) -> BTreeSet<Keychain> {
+        self.descriptors
+            .iter()
+            .enumerate()
+            .flat_map(|(no, descr)| {
+                descr.keychains().into_iter().map(move |k| Self::wallet_keychain(no, k))
+            })
+            .collect()
+    }
+
+    fn derive(
+        &self,
+        keychain: impl Into<Keychain>,
+        index: impl Into<NormalIndex>,
+    ) -> DerivedScript {
+        let (descr, terminal) = self.resolve(Terminal::new(keychain.into(), index.into()));
+        descr.derive(terminal.keychain, terminal.index)
+    }
+}
+
+impl<K, V, D: Descriptor<K, V>> Descriptor<K, V> for MultiDescr<D> {
+    /// Class of the first descriptor; see [`MultiDescr`] for the details.
+    fn class(&self) -> SpkClass { self.descriptors[0].class() }
+
+    fn keys<'a>(&'a self) -> impl Iterator<Item = &'a K>
+    where K: 'a {
+        self.descriptors.iter().flat_map(D::keys)
+    }
+
+    fn vars<'a>(&'a self) -> impl Iterator<Item = &'a V>
+    where V: 'a {
+        self.descriptors.iter().flat_map(D::vars)
+    }
+
+    fn xpubs(&self) -> impl Iterator<Item = &XpubAccount> {
+        self.descriptors.iter().flat_map(D::xpubs)
+    }
+
+    fn legacy_keyset(&self, terminal: Terminal) -> IndexMap<LegacyPk, KeyOrigin> {
+        let (descr, terminal) = self.resolve(terminal);
+        descr.legacy_keyset(terminal)
+    }
+
+    fn xonly_keyset(&self, terminal: Terminal) -> IndexMap<XOnlyPk, TapDerivation> {
+        let (descr, terminal) = self.resolve(terminal);
+        descr.xonly_keyset(terminal)
+    }
+
+    fn legacy_witness(
+        &self,
+        keysigs: HashMap<&KeyOrigin, LegacyKeySig>,
+    ) -> Option<(SigScript, Witness)> {
+        // Each of the descriptors picks only signatures made with its own keys, so the first
+        // descriptor able to satisfy the input is the one which controls it
+        self.descriptors.iter().find_map(|descr| {
+            let keysigs = keysigs
+                .iter()
+                .map(|(origin, ks)| (*origin, LegacyKeySig::new(ks.key, ks.sig)))
+                .collect();
+            descr.legacy_witness(keysigs)
+        })
+    }
+
+    fn taproot_witness(&self, keysigs: HashMap<&KeyOrigin, TaprootKeySig>) -> Option<Witness> {
+        self.descriptors.iter().find_map(|descr| {
+            let keysigs = keysigs
+                .iter()
+                .map(|(origin, ks)| (*origin, TaprootKeySig::new(ks.key, ks.sig)))
+                .collect();
+            descr.taproot_witness(keysigs)
+        })
+    }
+}
+
+/// Finalizes PSBT inputs with the descriptor, choosing between taproot and pre-taproot
+/// satisfaction by the script of the output spent by each input. Unlike [`Psbt::finalize`], which
+/// relies on the class of the whole descriptor, this supports [`MultiDescr`] sets mixing taproot
+/// and pre-taproot script types. Returns number of the finalized inputs.
+pub fn finalize_psbt<K, V, D: Descriptor<K, V>>(psbt: &mut Psbt, descriptor: &D) -> usize {
+    psbt.inputs_mut()
+        .map(|input| {
+            let taproot = match &input.witness_utxo {
+                Some(prevout) => prevout.script_pubkey.is_p2tr(),
+                None => descriptor.is_taproot(),
+            };
+            input.finalize(&InputDescr {
+                descriptor,
+                taproot,
+            }) as usize
+        })
+        .sum()
+}
+
+/// Descriptor view overriding the taproot flag for the finalization of a single input.
+struct InputDescr<'descr, D> {
+    descriptor: &'descr D,
+    taproot: bool,
+}
+
+impl<D> Clone for InputDescr<'_, D> {
+    fn clone(&self) -> Self {
+        InputDescr {
+            descriptor: self.descriptor,
+            taproot: self.taproot,
+        }
+    }
+}
+
+impl<D: Display> Display for InputDescr<'_, D> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { Display::fmt(self.descriptor, f) }
+}
+
+impl<D: Derive<DerivedScript>> Derive<DerivedScript> for InputDescr<'_, D> {
+    fn default_keychain(&self) -> Keychain { self.descriptor.default_keychain() }
+
+    fn keychains(&self) -> BTreeSet<Keychain> { self.descriptor.keychains() }
+
+    fn derive(
+        &self,
+        keychain: impl Into<Keychain>,
+        index: impl Into<NormalIndex>,
+    ) -> DerivedScript {
+        self.descriptor.derive(keychain, index)
+    }
+}
+
+impl<K, V, D: Descriptor<K, V>> Descriptor<K, V> for InputDescr<'_, D> {
+    fn class(&self) -> SpkClass { self.descriptor.class() }
+
+    fn is_taproot(&self) -> bool { self.taproot }
+
+    fn keys<'a>(&'a self) -> impl Iterator<Item = &'a K>
+    where K: 'a {
+        self.descriptor.keys()
+    }
+
+    fn vars<'a>(&'a self) -> impl Iterator<Item = &'a V>
+    where V: 'a {
+        self.descriptor.vars()
+    }
+
+    fn xpubs(&self) -> impl Iterator<Item = &XpubAccount> { self.descriptor.xpubs() }
+
+    fn legacy_keyset(&self, terminal: Terminal) -> IndexMap<LegacyPk, KeyOrigin> {
+        self.descriptor.legacy_keyset(terminal)
+    }
+
+    fn xonly_keyset(&self, terminal: Terminal) -> IndexMap<XOnlyPk, TapDerivation> {
+        self.descriptor.xonly_keyset(terminal)
+    }
+
+    fn legacy_witness(
+        &self,
+        keysigs: HashMap<&KeyOrigin, LegacyKeySig>,
+    ) -> Option<(SigScript, Witness)> {
+        self.descriptor.legacy_witness(keysigs)
+    }
+
+    fn taproot_witness(&self, keysigs: HashMap<&KeyOrigin, TaprootKeySig>) -> Option<Witness> {
+        self.descriptor.taproot_witness(keysigs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bpstd::{StdDescr, TrKey, Wpkh, XpubDerivable};
+
+    use super::*;
+
+    const XPUB: &str = "[643a7adc/86h/1h/0h]tpubDCNiWHaiSkgnQjuhsg9kjwaUzaxQjUcmhagvYzqQ3TYJTgFGJstVaqnu4yhtFktBhCVFmBNLQ5sN53qKzZbMksm3XEyGJsEhQPfVZdWmTE2/<0;1>/*";
+
+    #[test]
+    fn keychain_namespacing() {
+        let xpub = XpubDerivable::from_str(XPUB).unwrap();
+        let wpkh = StdDescr::<XpubDerivable>::from(Wpkh::from(xpub.clone()));
+        let tr = StdDescr::<XpubDerivable>::from(TrKey::from(xpub));
+        let multi = MultiDescr::with([wpkh.clone(), tr.clone()]).unwrap();
+
+        assert_eq!(multi.keychains(), bset![
+            Keychain::with(0),
+            Keychain::with(1),
+            Keychain::with(16),
+            Keychain::with(17)
+        ]);
+        assert_eq!(multi.derive(1, 0u16), wpkh.derive(1, 0u16));
+        assert_eq!(multi.derive(17, 0u16), tr.derive(1, 0u16));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn single_descr_compatibility() {
+        #[derive(PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+        #[serde(crate = "serde_crate")]
+        struct Stored<D> {
+            generator: D,
+        }
+
+        let xpub = XpubDerivable::from_str(XPUB).unwrap();
+        let wpkh = StdDescr::<XpubDerivable>::from(Wpkh::from(xpub.clone()));
+        let tr = StdDescr::<XpubDerivable>::from(TrKey::from(xpub));
+
+        let single = toml::to_string(&Stored {
+            generator: wpkh.clone(),
+        })
+        .unwrap();
+        let multi = Stored {
+            generator: MultiDescr::<StdDescr>::with([wpkh.clone()]).unwrap(),
+        };
+        assert_eq!(toml::to_string(&multi).unwrap(), single);
+        assert_eq!(toml::from_str::<Stored<MultiDescr<StdDescr>>>(&single).unwrap(), multi);
+
+        let multi = Stored {
+            generator: MultiDescr::with([wpkh, tr]).unwrap(),
+        };
+        let set = toml::to_string(&multi).unwrap();
+        assert_eq!(toml::from_str::<Stored<MultiDescr<StdDescr>>>(&set).unwrap(), multi);
+    }
+
+    #[test]
+    fn invalid_sets() {
+        assert_eq!(MultiDescr::<StdDescr>::with([]), Err(MultiDescrError::Empty));
+    }
+}
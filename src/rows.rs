@@ -41,6 +41,126 @@ pub enum OpType {
     Credit,
     #[display("-")]
     Debit,
+    /// Funds moved between wallet addresses, with only the miner fee leaving the wallet.
+    #[display("=")]
+    SelfTransfer,
+}
+
+/// Single instruction of a bitcoin script.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Instruction<'script> {
+    /// Data push, together with the opcode performing it.
+    Push(u8, &'script [u8]),
+    /// Any opcode which is not a data push.
+    Op(u8),
+}
+
+/// Data push in a script is truncated by the end of the script.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display, Error)]
+#[display("data push is truncated by the end of the script")]
+pub struct TruncatedPush;
+
+/// Iterator over the instructions of a bitcoin script. After reporting a truncated data push the
+/// iterator ends.
+#[derive(Clone, Debug)]
+pub struct Instructions<'script>(&'script [u8]);
+
+impl<'script> Instructions<'script> {
+    pub fn new(script: &'script [u8]) -> Self { Instructions(script) }
+}
+
+impl<'script> Iterator for Instructions<'script> {
+    type Item = Result<Instruction<'script>, TruncatedPush>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&op, rest) = self.0.split_first()?;
+        let (len, rest) = match op {
+            0x01..=0x4b => (op as usize, rest),
+            0x4c..=0x4e => {
+                let width = 1usize << (op - 0x4c);
+                if rest.len() < width {
+                    self.0 = &[];
+                    return Some(Err(TruncatedPush));
+                }
+                let (len, rest) = rest.split_at(width);
+                (len.iter().rev().fold(0usize, |len, byte| (len << 8) | *byte as usize), rest)
+            }
+            _ => {
+                self.0 = rest;
+                return Some(Ok(Instruction::Op(op)));
+            }
+        };
+        if rest.len() < len {
+            self.0 = &[];
+            return Some(Err(TruncatedPush));
+        }
+        let (data, rest) = rest.split_at(len);
+        self.0 = rest;
+        Some(Ok(Instruction::Push(op, data)))
+    }
+}
+
+/// Classification of output scripts which don't map to any of the known address types.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+pub enum ScriptClass {
+    /// Bare public key (pay-to-pubkey) output.
+    #[display("pubkey")]
+    Pubkey,
+    /// Bare `m-of-n` multisig output.
+    #[display("multisig({0}-of-{1})")]
+    BareMultisig(u8, u8),
+    /// Segwit output of a future witness version not known to the wallet.
+    #[display("witness-v{0}")]
+    FutureWitness(u8),
+    /// Provably unspendable `OP_RETURN` output.
+    #[display("op-return")]
+    OpReturn,
+    /// Any other script.
+    #[display("non-standard")]
+    NonStandard,
+}
+
+impl ScriptClass {
+    /// Classifies a script. Scripts of known address types are classified as [`Self::NonStandard`]
+    /// as well, thus the method should be used only for the scripts not representable as an
+    /// address.
+    pub fn classify(script: &ScriptPubkey) -> Self {
+        const OP_CHECKSIG: u8 = 0xac;
+        const OP_CHECKMULTISIG: u8 = 0xae;
+        const OP_1: u8 = 0x51;
+        const OP_16: u8 = 0x60;
+
+        if script.is_op_return() {
+            return ScriptClass::OpReturn;
+        }
+        let Ok(instructions) = Instructions::new(script.as_slice()).collect::<Result<Vec<_>, _>>()
+        else {
+            return ScriptClass::NonStandard;
+        };
+        let is_key = |instr: &Instruction| matches!(instr, Instruction::Push(33 | 65, _));
+        match instructions.as_slice() {
+            [key, Instruction::Op(OP_CHECKSIG)] if is_key(key) => {
+                return ScriptClass::Pubkey;
+            }
+            [Instruction::Op(ver @ OP_1..=OP_16), Instruction::Push(2..=40, _)] => {
+                return ScriptClass::FutureWitness(ver - OP_1 + 1);
+            }
+            [Instruction::Op(m @ OP_1..=OP_16), keys @ .., Instruction::Op(n @ OP_1..=OP_16), Instruction::Op(OP_CHECKMULTISIG)] =>
+            {
+                let (m, n) = (m - OP_1 + 1, n - OP_1 + 1);
+                if keys.iter().all(is_key) && keys.len() == n as usize && m <= n {
+                    return ScriptClass::BareMultisig(m, n);
+                }
+            }
+            _ => {}
+        }
+        ScriptClass::NonStandard
+    }
 }
 
 #[derive(Clone, Eq, PartialEq, Hash, Debug, From)]
@@ -55,6 +175,8 @@ pub enum Counterparty {
     Address(Address),
     #[from]
     Unknown(ScriptPubkey),
+    /// Provably unspendable `OP_RETURN` output carrying data.
+    Data(ScriptPubkey),
 }
 
 impl From<Party> for Counterparty {
@@ -62,6 +184,7 @@ impl From<Party> for Counterparty {
         match party {
             Party::Subsidy => Counterparty::Miner,
             Party::Counterparty(addr) => Counterparty::Address(addr),
+            Party::Unknown(script) if script.is_op_return() => Counterparty::Data(script),
             Party::Unknown(script) => Counterparty::Unknown(script),
             Party::Wallet(_) => {
                 panic!("counterparty must be constructed only for external parties")
@@ -70,12 +193,45 @@ impl From<Party> for Counterparty {
     }
 }
 
+impl Counterparty {
+    /// Classifies the script of a counterparty which is not representable as an address. Returns
+    /// `None` for the miner and address counterparties.
+    pub fn script_class(&self) -> Option<ScriptClass> {
+        match self {
+            Counterparty::Miner | Counterparty::Address(_) => None,
+            Counterparty::Unknown(script) | Counterparty::Data(script) => {
+                Some(ScriptClass::classify(script))
+            }
+        }
+    }
+
+    /// Returns data carried by an `OP_RETURN` output, concatenating all data pushes following the
+    /// `OP_RETURN` opcode. Returns `None` for other counterparties and for malformed scripts
+    /// with data pushes truncated by the end of the script.
+    pub fn data(&self) -> Option<Vec<u8>> {
+        let Counterparty::Data(script) = self else {
+            return None;
+        };
+        let mut data = Vec::new();
+        for instruction in Instructions::new(script.as_slice().get(1..).unwrap_or_default()) {
+            if let Instruction::Push(_, bytes) = instruction.ok()? {
+                data.extend_from_slice(bytes);
+            }
+        }
+        Some(data)
+    }
+}
+
 impl Display for Counterparty {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             Counterparty::Miner => f.write_str("miner"),
             Counterparty::Address(addr) => Display::fmt(addr, f),
             Counterparty::Unknown(script) => LowerHex::fmt(script, f),
+            Counterparty::Data(script) => {
+                f.write_str("data:")?;
+                LowerHex::fmt(script, f)
+            }
         }
     }
 }
@@ -87,6 +243,13 @@ impl FromStr for Counterparty {
         if s == "miner" {
             return Ok(Counterparty::Miner);
         }
+        if let Some(hex) = s.strip_prefix("data:") {
+            return ScriptPubkey::from_hex(hex)
+                .ok()
+                .filter(ScriptPubkey::is_op_return)
+                .map(Counterparty::Data)
+                .ok_or_else(|| s.to_owned());
+        }
         Address::from_str(s)
             .map(Self::from)
             .or_else(|_| ScriptPubkey::from_hex(s).map(Self::from))
@@ -118,12 +281,23 @@ pub struct TxRow<L2: Layer2Tx = Layer2Empty> {
     pub fee: Sats,
     pub weight: u32,
     pub size: u32,
+    pub rbf: bool,
     pub total: Sats,
     pub amount: Sats,
     pub balance: Sats,
     pub layer2: L2,
 }
 
+impl<L2: Layer2Tx> TxRow<L2> {
+    /// Fee rate paid by the transaction, in sats per vbyte.
+    pub fn fee_rate(&self) -> f64 { self.fee.sats() as f64 * 4.0 / self.weight as f64 }
+
+    /// Detects whether the transaction is an unconfirmed incoming payment.
+    pub fn is_pending_credit(&self) -> bool {
+        self.height == TxStatus::Mempool && self.operation == OpType::Credit
+    }
+}
+
 #[cfg_attr(
     feature = "serde",
     derive(serde::Serialize, serde::Deserialize),
@@ -147,18 +321,26 @@ pub struct CoinRow<L2: Layer2Coin> {
 }
 
 impl<L2: Layer2Cache> WalletCache<L2> {
+    fn coin_row(&self, outpoint: Outpoint) -> CoinRow<L2::Coin> {
+        let tx = self.tx.get(&outpoint.txid).expect("cache data inconsistency");
+        let out = tx.outputs.get(outpoint.vout_usize()).expect("cache data inconsistency");
+        CoinRow {
+            height: tx.status.map(|info| info.height),
+            outpoint,
+            address: out.derived_addr().expect("cache data inconsistency"),
+            amount: out.value,
+            layer2: none!(), // TODO: Add support to WalletTx
+        }
+    }
+
+    /// Returns spendable coins, excluding the ones spent by unconfirmed transactions.
     pub fn coins(&self) -> impl Iterator<Item = CoinRow<L2::Coin>> + '_ {
-        self.utxo.iter().map(|outpoint| {
-            let tx = self.tx.get(&outpoint.txid).expect("cache data inconsistency");
-            let out = tx.outputs.get(outpoint.vout_usize()).expect("cache data inconsistency");
-            CoinRow {
-                height: tx.status.map(|info| info.height),
-                outpoint: *outpoint,
-                address: out.derived_addr().expect("cache data inconsistency"),
-                amount: out.value,
-                layer2: none!(), // TODO: Add support to WalletTx
-            }
-        })
+        self.utxo.difference(&self.pending_spent).map(|outpoint| self.coin_row(*outpoint))
+    }
+
+    /// Returns coins spent by unconfirmed transactions.
+    pub fn pending_coins(&self) -> impl Iterator<Item = CoinRow<L2::Coin>> + '_ {
+        self.pending_spent.iter().map(|outpoint| self.coin_row(*outpoint))
     }
 
     pub fn history(&self) -> impl Iterator<Item = TxRow<L2::Tx>> + '_ {
@@ -179,6 +361,7 @@ impl<L2: Layer2Cache> WalletCache<L2> {
                 fee: tx.fee,
                 weight: tx.weight,
                 size: tx.size,
+                rbf: tx.signals_rbf(),
                 total: tx.total_moved(),
                 amount: Sats::ZERO,
                 balance: Sats::ZERO,
@@ -216,6 +399,9 @@ impl<L2: Layer2Cache> WalletCache<L2> {
                 });
                 row.operation = OpType::Debit;
                 row.amount = debit;
+            } else if tx.is_self_transfer() {
+                row.operation = OpType::SelfTransfer;
+                row.amount = tx.total_moved() - tx.fee;
             }
             row
         })
@@ -242,5 +428,46 @@ mod tests {
         assert_from_str_to_str(Counterparty::Unknown(
             ScriptPubkey::from_hex("0014a3f8e1f1e1c7e8b4b2f4f3a1b4f7f0a1b4f7f0a1").unwrap(),
         ));
+        assert_from_str_to_str(Counterparty::Data(ScriptPubkey::op_return(b"hello")));
+    }
+
+    #[test]
+    fn test_instructions() {
+        let script = [0x00, 0x02, 0xca, 0xfe, 0x4d, 0x01, 0x00, 0xff, 0x6a, 0x4c, 0x02, 0xaa];
+        let mut instructions = Instructions::new(&script);
+        assert_eq!(instructions.next(), Some(Ok(Instruction::Op(0x00))));
+        assert_eq!(instructions.next(), Some(Ok(Instruction::Push(0x02, &[0xca, 0xfe]))));
+        assert_eq!(instructions.next(), Some(Ok(Instruction::Push(0x4d, &[0xff]))));
+        assert_eq!(instructions.next(), Some(Ok(Instruction::Op(0x6a))));
+        assert_eq!(instructions.next(), Some(Err(TruncatedPush)));
+        assert_eq!(instructions.next(), None);
+        assert_eq!(Instructions::new(&[0x4e, 0x00]).next(), Some(Err(TruncatedPush)));
+    }
+
+    #[test]
+    fn test_op_return_data() {
+        let party = Counterparty::from(Party::Unknown(ScriptPubkey::op_return(b"hello")));
+        assert_eq!(party.data().as_deref(), Some(&b"hello"[..]));
+        assert_eq!(party.to_string(), "data:6a0568656c6c6f");
+        assert_eq!(Counterparty::Miner.data(), None);
+
+        let pushdata = Counterparty::from_str("data:6a4c03cafe00").unwrap();
+        assert_eq!(pushdata.data().as_deref(), Some(&[0xca, 0xfe, 0x00][..]));
+        let truncated = Counterparty::from_str("data:6a05cafe").unwrap();
+        assert_eq!(truncated.data(), None);
+        let truncated = Counterparty::from_str("data:6a4d05").unwrap();
+        assert_eq!(truncated.data(), None);
+    }
+
+    #[test]
+    fn test_script_class() {
+        let classify = |hex: &str| ScriptClass::classify(&ScriptPubkey::from_hex(hex).unwrap());
+        let key = "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9";
+        assert_eq!(classify(&format!("21{key}ac")), ScriptClass::Pubkey);
+        assert_eq!(classify(&format!("5121{key}21{key}52ae")), ScriptClass::BareMultisig(1, 2));
+        assert_eq!(classify("52020000"), ScriptClass::FutureWitness(2));
+        assert_eq!(classify("6a0568656c6c6f"), ScriptClass::OpReturn);
+        assert_eq!(classify("51"), ScriptClass::NonStandard);
+        assert_eq!(Counterparty::Miner.script_class(), None);
     }
 }
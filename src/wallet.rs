@@ -22,21 +22,25 @@
 
 use std::cmp;
 use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
 use std::marker::PhantomData;
 use std::ops::{AddAssign, Deref};
 
 use bpstd::{
     Address, AddressNetwork, DerivedAddr, Descriptor, Idx, IdxBase, Keychain, Network, NormalIndex,
-    Outpoint, Sats, Txid, Vout,
+    Outpoint, Sats, ScriptPubkey, SpkClass, Terminal, Tx, Txid, Vout,
 };
 use nonasync::persistence::{
     CloneNoPersistence, Persistence, PersistenceError, PersistenceProvider, Persisting,
 };
-use psbt::{PsbtConstructor, Utxo};
+use psbt::{Beneficiary, ConstructionError, Psbt, PsbtConstructor, PsbtMeta, TxParams, Utxo};
 
+use crate::indexers::{FlushInterval, GapLimits, IndexerError, ScanProgress};
 use crate::{
-    BlockInfo, CoinRow, Indexer, Layer2, Layer2Cache, Layer2Data, Layer2Descriptor, Layer2Empty,
-    MayError, MiningInfo, NoLayer2, Party, TxRow, WalletAddr, WalletTx, WalletUtxo,
+    coinselect, BlockHeight, BlockInfo, CoinRow, Indexer, Inpoint, LabelTarget, Layer2,
+    Layer2Cache, Layer2Data, Layer2Descriptor, Layer2Empty, MayError, MiningInfo, NoLayer2, Party,
+    TxCredit, TxRow, TxStatus, WalletAddr, WalletTx, WalletUtxo,
 };
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Display, Error)]
@@ -50,6 +54,111 @@ pub enum NonWalletItem {
     NonWalletUtxo(Outpoint),
 }
 
+/// Error retrieving a full transaction from the indexer.
+#[derive(Clone, Eq, PartialEq, Debug, From)]
+pub enum RawTxError<E> {
+    #[from]
+    Indexer(E),
+    /// Indexer has returned a transaction with the id given as the second field instead of the
+    /// requested one.
+    Mismatch(Txid, Txid),
+}
+
+impl<E: Display> Display for RawTxError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RawTxError::Indexer(e) => Display::fmt(e, f),
+            RawTxError::Mismatch(requested, returned) => write!(
+                f,
+                "indexer has returned transaction {returned} instead of the requested {requested}"
+            ),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for RawTxError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RawTxError::Indexer(e) => Some(e),
+            RawTxError::Mismatch(..) => None,
+        }
+    }
+}
+
+/// Divergence between the balance data maintained by the wallet cache and their recomputation
+/// from the cached transactions.
+#[derive(Clone, Eq, PartialEq, Debug, Display)]
+#[display(doc_comments)]
+pub enum BalanceMismatch {
+    /// address {0} has cached balance of {1} sats, while the transactions give {2} sats.
+    Address(Address, Sats, Sats),
+    /// {0} is listed as unspent, while the transactions show it as spent or non-existing.
+    StaleUtxo(Outpoint),
+    /// {0} is unspent according to the transactions, but is missing from the cached UTXO set.
+    MissingUtxo(Outpoint),
+}
+
+/// Divergence between a cached wallet transaction and the data provided for it by the indexer.
+#[derive(Clone, Eq, PartialEq, Debug, Display)]
+#[display(doc_comments)]
+pub enum TxDivergence {
+    /// transaction {0} is not known to the indexer; it may have been replaced or dropped from the
+    /// mempool.
+    Missing(Txid),
+    /// transaction {0} has cached status {1}, while the indexer reports {2}.
+    Status(Txid, TxStatus<BlockHeight>, TxStatus<BlockHeight>),
+    /// transaction {0} is cached as mined at height {1} in a block which was reorged out.
+    Reorg(Txid, BlockHeight),
+    /// transaction {0} has {1} inputs in the cache, while the indexer reports {2}.
+    InputCount(Txid, usize, usize),
+    /// input {0} spends {1} according to the cache, while the indexer reports {2}.
+    Input(Inpoint, Outpoint, Outpoint),
+    /// transaction {0} has {1} outputs in the cache, while the indexer reports {2}.
+    OutputCount(Txid, usize, usize),
+    /// output {0} has cached value of {1} sats, while the indexer reports {2} sats.
+    OutputValue(Outpoint, Sats, Sats),
+    /// output {0} has cached script different from the one reported by the indexer.
+    OutputScript(Outpoint),
+}
+
+/// Change in the wallet balances which will happen once a transaction gets mined.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct BalanceChange {
+    pub total: i64,
+    pub keychains: BTreeMap<Keychain, i64>,
+    pub labels: BTreeMap<String, i64>,
+}
+
+impl BalanceChange {
+    fn register(&mut self, keychain: Keychain, label: Option<&str>, delta: i64) {
+        self.total += delta;
+        *self.keychains.entry(keychain).or_default() += delta;
+        if let Some(label) = label {
+            *self.labels.entry(label.to_owned()).or_default() += delta;
+        }
+    }
+}
+
+/// Lifetime statistics of the wallet.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub struct WalletSummary {
+    /// Total value received from external parties.
+    pub received: Sats,
+    /// Total value sent to external parties.
+    pub sent: Sats,
+    /// Total fees paid by the transactions initiated by the wallet.
+    pub fees: Sats,
+    /// Current wallet balance.
+    pub balance: Sats,
+    /// Number of wallet transactions.
+    pub tx_count: usize,
+}
+
 pub struct AddrIter<'descr, K, D: Descriptor<K>> {
     generator: &'descr D,
     network: AddressNetwork,
@@ -122,7 +231,7 @@ impl<K, D: Descriptor<K>, L2: Layer2Descriptor> WalletDescr<K, D, L2> {
         }
     }
 
-    pub fn addresses(&self, keychain: impl Into<Keychain>) -> AddrIter<K, D> {
+    pub fn addresses(&self, keychain: impl Into<Keychain>) -> AddrIter<'_, K, D> {
         AddrIter {
             generator: &self.generator,
             network: self.network.into(),
@@ -204,7 +313,12 @@ pub struct WalletData<L2: Layer2Data> {
     pub txout_annotations: BTreeMap<Outpoint, String>,
     pub txin_annotations: BTreeMap<Outpoint, String>,
     pub addr_annotations: BTreeMap<Address, String>,
+    /// Next derivation index to issue for each keychain. Advanced when new addresses are handed
+    /// out and reconciled upwards with the cache on each sync; kept independently of the cache.
     pub last_used: BTreeMap<Keychain, NormalIndex>,
+    /// Unspent outputs excluded from the coin selection by the user.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "BTreeSet::is_empty"))]
+    pub frozen: BTreeSet<Outpoint>,
     pub layer2: L2,
 }
 
@@ -220,6 +334,7 @@ impl<L2: Layer2Data> CloneNoPersistence for WalletData<L2> {
             addr_annotations: self.addr_annotations.clone(),
             layer2: self.layer2.clone(),
             last_used: self.last_used.clone(),
+            frozen: self.frozen.clone(),
         }
     }
 }
@@ -245,6 +360,7 @@ impl WalletData<Layer2Empty> {
             addr_annotations: empty!(),
             layer2: none!(),
             last_used: empty!(),
+            frozen: empty!(),
         }
     }
 }
@@ -262,6 +378,7 @@ impl<L2: Layer2Data> WalletData<L2> {
             addr_annotations: empty!(),
             layer2: none!(),
             last_used: empty!(),
+            frozen: empty!(),
         }
     }
 }
@@ -297,12 +414,34 @@ pub struct WalletCache<L2: Layer2Cache> {
     #[cfg_attr(feature = "serde", serde(skip))]
     pub id: Option<String>,
     pub last_block: MiningInfo,
+    /// Height of the blockchain tip as reported by the indexer during the last scan.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub tip_height: Option<u32>,
     pub last_change: NormalIndex,
     pub headers: BTreeSet<BlockInfo>,
     pub tx: BTreeMap<Txid, WalletTx>,
     pub utxo: BTreeSet<Outpoint>,
+    /// Outputs from `utxo` spent by unconfirmed transactions. They are kept unspent until the
+    /// spending transaction gets mined, but are excluded from the spendable coins.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "BTreeSet::is_empty"))]
+    pub pending_spent: BTreeSet<Outpoint>,
     pub addr: BTreeMap<Keychain, BTreeSet<WalletAddr>>,
+    /// Full transactions retrieved from the indexer on demand, see [`Wallet::raw_tx`].
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "BTreeMap::is_empty"))]
+    pub raw_tx: BTreeMap<Txid, Tx>,
     pub layer2: L2,
+
+    /// Interval for checkpointing the cache during indexer scans
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub flush_interval: FlushInterval,
+
+    /// Gap limits used by indexers during the scans
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub gap_limits: GapLimits,
+
+    /// Callback receiving the progress of indexer scans
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub progress: Option<ScanProgress>,
 }
 
 impl<L2C: Layer2Cache> WalletCache<L2C> {
@@ -311,19 +450,34 @@ impl<L2C: Layer2Cache> WalletCache<L2C> {
             persistence: None,
             id: None,
             last_block: MiningInfo::genesis(),
+            tip_height: None,
             last_change: NormalIndex::ZERO,
             headers: none!(),
             tx: none!(),
             utxo: none!(),
+            pending_spent: none!(),
             addr: none!(),
+            raw_tx: none!(),
             layer2: none!(),
+            flush_interval: none!(),
+            gap_limits: none!(),
+            progress: None,
+        }
+    }
+
+    /// Reports the progress of the indexer scan to the callback set with
+    /// [`Wallet::set_scan_progress`], if any.
+    #[cfg(any(feature = "electrum", feature = "esplora", feature = "bitcoind"))]
+    pub(crate) fn report_progress(&mut self, terminal: Terminal, tx_count: usize) {
+        if let Some(progress) = &mut self.progress {
+            progress.report(terminal.keychain, terminal.index, tx_count);
         }
     }
 
     pub fn with<I: Indexer, K, D: Descriptor<K>, L2: Layer2<Cache = L2C>>(
         descriptor: &WalletDescr<K, D, L2::Descr>,
         indexer: &I,
-    ) -> MayError<Self, Vec<I::Error>> {
+    ) -> MayError<Self, Vec<IndexerError<I::Error>>> {
         indexer.create::<K, D, L2>(descriptor)
     }
 
@@ -331,22 +485,41 @@ impl<L2C: Layer2Cache> WalletCache<L2C> {
         &mut self,
         descriptor: &WalletDescr<K, D, L2::Descr>,
         indexer: &I,
-    ) -> MayError<usize, Vec<I::Error>> {
+    ) -> MayError<usize, Vec<IndexerError<I::Error>>> {
         let res = indexer.update::<K, D, L2>(descriptor, self);
         self.mark_dirty();
         res
     }
 
+    /// Discards all the cached data and rebuilds them with a full indexer scan. Unlike
+    /// [`Indexer::create`], keeps the persistence provider and the scan settings of the cache.
     pub fn sync_from_scratch<I: Indexer, K, D: Descriptor<K>, L2: Layer2<Cache = L2C>>(
         &mut self,
         descriptor: &WalletDescr<K, D, L2::Descr>,
         indexer: &I,
-    ) -> MayError<(), Vec<I::Error>> {
-        let res = indexer.create::<K, D, L2>(descriptor);
-        let (ok, err) = res.split();
-        *self = ok;
+    ) -> MayError<(), Vec<IndexerError<I::Error>>> {
+        let mut fresh = Self::new_nonsync();
+        fresh.persistence = self.persistence.take();
+        fresh.id = self.id.take();
+        fresh.flush_interval = self.flush_interval;
+        fresh.gap_limits = self.gap_limits.clone();
+        fresh.progress = self.progress.take();
+        *self = fresh;
+        let res = indexer.update::<K, D, L2>(descriptor, self);
         self.mark_dirty();
-        MayError { ok: (), err }
+        res.map(|_| ())
+    }
+
+    /// Stores the state of the cache with its persistence provider, regardless of whether the
+    /// cache is marked dirty, after applying `update` to a copy of it, such that the cache itself
+    /// is left intact. Does nothing for non-persistent caches.
+    pub fn checkpoint(&self, update: impl FnOnce(&mut Self)) -> Result<(), PersistenceError> {
+        if let Some(p) = self.persistence() {
+            let mut snapshot = self.clone_no_persistence();
+            update(&mut snapshot);
+            p.provider.store(&snapshot)?;
+        }
+        Ok(())
     }
 
     pub fn addresses_on(&self, keychain: Keychain) -> &BTreeSet<WalletAddr> {
@@ -366,8 +539,15 @@ impl<L2C: Layer2Cache> WalletCache<L2C> {
     }
 
     #[inline]
+    /// Checks whether the output is not spent by a mined transaction. Outputs spent only by
+    /// unconfirmed transactions are reported as unspent; see [`WalletCache::is_pending_spent`].
     pub fn is_unspent(&self, outpoint: Outpoint) -> bool { self.utxo.contains(&outpoint) }
 
+    /// Checks whether the unspent output is spent by an unconfirmed transaction.
+    pub fn is_pending_spent(&self, outpoint: Outpoint) -> bool {
+        self.pending_spent.contains(&outpoint)
+    }
+
     pub fn outpoint_by(&self, outpoint: Outpoint) -> Result<WalletUtxo, NonWalletItem> {
         let tx = self.tx.get(&outpoint.txid).ok_or(NonWalletItem::NonWalletTx(outpoint.txid))?;
         let debit = tx
@@ -401,8 +581,135 @@ impl<L2C: Layer2Cache> WalletCache<L2C> {
         })
     }
 
+    /// Verifies a cached transaction against the indexer. The transaction status is always
+    /// checked; with `deep` flag the transaction itself is re-fetched and its inputs and outputs
+    /// are compared with the cached ones.
+    ///
+    /// # Panics
+    ///
+    /// If the transaction is not present in the cache.
+    pub fn verify_tx<I: Indexer>(
+        &self,
+        txid: Txid,
+        indexer: &I,
+        deep: bool,
+    ) -> Result<Vec<TxDivergence>, I::Error> {
+        let cached = self.tx.get(&txid).expect("transaction must be present in the cache");
+        let mut divergences = vec![];
+
+        let status = indexer.status(txid)?;
+        match (cached.status, status) {
+            (_, TxStatus::Unknown) => return Ok(vec![TxDivergence::Missing(txid)]),
+            (TxStatus::Mined(a), TxStatus::Mined(b))
+                if a.height == b.height && a.block_hash != b.block_hash =>
+            {
+                divergences.push(TxDivergence::Reorg(txid, a.height))
+            }
+            (a, b) if a.map(|info| info.block_hash) != b.map(|info| info.block_hash) => divergences
+                .push(TxDivergence::Status(
+                    txid,
+                    a.map(|info| info.height),
+                    b.map(|info| info.height),
+                )),
+            _ => {}
+        }
+        if !deep {
+            return Ok(divergences);
+        }
+
+        let Some(tx) = indexer.raw_tx(txid)? else {
+            divergences.push(TxDivergence::Missing(txid));
+            return Ok(divergences);
+        };
+        if cached.inputs.len() != tx.inputs.len() {
+            divergences.push(TxDivergence::InputCount(txid, cached.inputs.len(), tx.inputs.len()));
+        }
+        for (vin, (credit, txin)) in cached.inputs.iter().zip(tx.inputs()).enumerate() {
+            if credit.outpoint != txin.prev_output {
+                divergences.push(TxDivergence::Input(
+                    Inpoint::new(txid, vin as u32),
+                    credit.outpoint,
+                    txin.prev_output,
+                ));
+            }
+        }
+        if cached.outputs.len() != tx.outputs.len() {
+            divergences.push(TxDivergence::OutputCount(
+                txid,
+                cached.outputs.len(),
+                tx.outputs.len(),
+            ));
+        }
+        for (debit, txout) in cached.outputs.iter().zip(tx.outputs()) {
+            if debit.value != txout.value {
+                divergences.push(TxDivergence::OutputValue(
+                    debit.outpoint,
+                    debit.value,
+                    txout.value,
+                ));
+            }
+            let script = match &debit.beneficiary {
+                Party::Wallet(derived) => Some(derived.addr.script_pubkey()),
+                party => party.script_pubkey(),
+            };
+            if script.as_ref() != Some(&txout.script_pubkey) {
+                divergences.push(TxDivergence::OutputScript(debit.outpoint));
+            }
+        }
+        Ok(divergences)
+    }
+
+    /// Recomputes UTXO set and address balances from the cached transactions, reporting all
+    /// divergences from the cached UTXO set and [`WalletAddr::balance`] values.
+    pub fn audit_balances(&self) -> Vec<BalanceMismatch> {
+        let spent = self
+            .tx
+            .values()
+            .flat_map(|tx| tx.inputs.iter().map(|inp| inp.outpoint))
+            .collect::<BTreeSet<_>>();
+        let utxos = self
+            .tx
+            .values()
+            .flat_map(|tx| tx.outputs.iter())
+            .filter(|out| !spent.contains(&out.outpoint))
+            .filter_map(|out| out.derived_addr().map(|derived| (out.outpoint, derived, out.value)))
+            .collect::<Vec<_>>();
+
+        let mut mismatches = vec![];
+        let outpoints = utxos.iter().map(|(outpoint, ..)| *outpoint).collect::<BTreeSet<_>>();
+        // Outputs spent by unconfirmed transactions are kept unspent until the spending is mined
+        mismatches.extend(
+            self.utxo
+                .difference(&outpoints)
+                .filter(|outpoint| !self.pending_spent.contains(outpoint))
+                .copied()
+                .map(BalanceMismatch::StaleUtxo),
+        );
+        mismatches
+            .extend(outpoints.difference(&self.utxo).copied().map(BalanceMismatch::MissingUtxo));
+
+        let mut balances = BTreeMap::<Address, Sats>::new();
+        for (_, derived, value) in &utxos {
+            *balances.entry(derived.addr).or_default() += *value;
+        }
+        for wa in self.addr.values().flatten() {
+            let recomputed = balances.remove(&wa.addr).unwrap_or_default();
+            if wa.balance != recomputed {
+                mismatches.push(BalanceMismatch::Address(wa.addr, wa.balance, recomputed));
+            }
+        }
+        mismatches.extend(
+            balances
+                .into_iter()
+                .map(|(addr, recomputed)| BalanceMismatch::Address(addr, Sats::ZERO, recomputed)),
+        );
+        mismatches
+    }
+
+    /// Returns spendable wallet outputs, i.e. the unspent outputs which are not spent by
+    /// unconfirmed transactions.
     pub fn utxos(&self) -> impl Iterator<Item = WalletUtxo> + '_ {
-        self.utxo.iter().map(|outpoint| {
+        self.utxo.difference(&self.pending_spent).map(|outpoint| {
             let tx = self.tx.get(&outpoint.txid).expect("cache data inconsistency");
             let debit = tx.outputs.get(outpoint.vout_usize()).expect("cache data inconsistency");
             let terminal =
@@ -424,12 +731,18 @@ impl<L2: Layer2Cache> CloneNoPersistence for WalletCache<L2> {
             persistence: None,
             id: self.id.clone(),
             last_block: self.last_block,
+            tip_height: self.tip_height,
             last_change: self.last_change,
             headers: self.headers.clone(),
             tx: self.tx.clone(),
             utxo: self.utxo.clone(),
+            pending_spent: self.pending_spent.clone(),
             addr: self.addr.clone(),
+            raw_tx: self.raw_tx.clone(),
             layer2: self.layer2.clone(),
+            flush_interval: self.flush_interval,
+            gap_limits: self.gap_limits.clone(),
+            progress: None,
         }
     }
 }
@@ -532,6 +845,41 @@ impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
         self.data.mark_dirty();
     }
 
+    /// Ensures addresses below `next` index are not issued again for the keychain, for instance
+    /// after they were given out by other software managing the same descriptor.
+    pub fn advance_derivation_index(&mut self, keychain: Keychain, next: NormalIndex) {
+        let last_used = self.data.last_used.entry(keychain).or_default();
+        if *last_used < next {
+            *last_used = next;
+            self.data.mark_dirty();
+        }
+    }
+
+    /// Reserves the next derivation index of the keychain and stores the reservation immediately.
+    ///
+    /// Before reserving, the derivation indexes are re-read from the wallet data storage, such
+    /// that indexes reserved by other wallet instances sharing the same storage are never handed
+    /// out again. The method does not serialize access to the storage by itself: concurrent
+    /// callers must hold a storage lock (like [`crate::fs::FsTextStore::lock`]) around the call.
+    pub fn reserve_next_index(
+        &mut self,
+        keychain: impl Into<Keychain>,
+    ) -> Result<NormalIndex, PersistenceError> {
+        let keychain = keychain.into();
+        if let Some(persistence) = self.data.persistence() {
+            let stored = persistence.provider.load()?;
+            for (keychain, next) in &stored.last_used {
+                let last_used = self.data.last_used.entry(*keychain).or_default();
+                *last_used = cmp::max(*last_used, *next);
+            }
+        }
+        let index = self.next_derivation_index(keychain, true);
+        if let Some(persistence) = self.data.persistence() {
+            persistence.provider.store(&self.data)?;
+        }
+        Ok(index)
+    }
+
     pub fn descriptor_mut<R>(
         &mut self,
         f: impl FnOnce(&mut WalletDescr<K, D, L2::Descr>) -> R,
@@ -555,12 +903,51 @@ impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
         res
     }
 
-    pub fn update<I: Indexer>(&mut self, indexer: &I) -> MayError<(), Vec<I::Error>> {
-        self.cache.update::<I, K, D, L2>(&self.descr, indexer).map(|_| ())
+    #[cfg(all(test, feature = "cli"))]
+    pub(crate) fn with_cache<R>(&mut self, f: impl FnOnce(&mut WalletCache<L2::Cache>) -> R) -> R {
+        f(&mut self.cache)
+    }
+
+    pub fn set_flush_interval(&mut self, interval: FlushInterval) {
+        self.cache.flush_interval = interval;
+    }
+
+    pub fn set_gap_limits(&mut self, limits: GapLimits) { self.cache.gap_limits = limits; }
+
+    /// Sets the callback receiving the progress of the subsequent indexer scans.
+    pub fn set_scan_progress(&mut self, progress: ScanProgress) {
+        self.cache.progress = Some(progress);
+    }
+
+    /// Updates the wallet cache with the indexer, returning the number of previously mined
+    /// transactions which were reorged out of the chain.
+    pub fn update<I: Indexer>(
+        &mut self,
+        indexer: &I,
+    ) -> MayError<usize, Vec<IndexerError<I::Error>>> {
+        let res = self.cache.update::<I, K, D, L2>(&self.descr, indexer);
+        self.reconcile_derivation_indexes();
+        res
+    }
+
+    pub fn sync_from_scratch<I: Indexer>(
+        &mut self,
+        indexer: &I,
+    ) -> MayError<(), Vec<IndexerError<I::Error>>> {
+        let res = self.cache.sync_from_scratch::<I, K, D, L2>(&self.descr, indexer).map(|_| ());
+        self.reconcile_derivation_indexes();
+        res
     }
 
-    pub fn sync_from_scratch<I: Indexer>(&mut self, indexer: &I) -> MayError<(), Vec<I::Error>> {
-        self.cache.sync_from_scratch::<I, K, D, L2>(&self.descr, indexer).map(|_| ())
+    /// Raises the persisted next derivation index of each keychain to the usage observed in the
+    /// cache, so that addresses used outside of this wallet instance are never re-issued. The
+    /// persisted indexes never go backwards, even if the cache is wiped.
+    fn reconcile_derivation_indexes(&mut self) {
+        let keychains = self.descr.keychains().into_iter().collect::<Vec<_>>();
+        for keychain in keychains {
+            let published = self.last_published_derivation_index(keychain);
+            self.advance_derivation_index(keychain, published);
+        }
     }
 
     pub fn to_deriver(&self) -> D
@@ -589,6 +976,55 @@ impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
         cmp::max(last_index, self.last_published_derivation_index(keychain))
     }
 
+    /// Scans the scripts derived from the wallet descriptor for the provided script pubkey. Each
+    /// keychain is scanned up to its gap limit past the last derivation index.
+    pub fn terminal_for(&self, script: &ScriptPubkey) -> Option<Terminal> {
+        self.descr.keychains().into_iter().find_map(|keychain| {
+            let depth = self.last_derivation_index(keychain).index() as usize
+                + self.cache.gap_limits.for_keychain(keychain);
+            self.addresses(keychain)
+                .take(depth)
+                .find(|derived| derived.addr.script_pubkey() == *script)
+                .map(|derived| derived.terminal)
+        })
+    }
+
+    /// Detects class of the scripts derived for the keychain. For the descriptor sets spanning
+    /// several script types (see [`crate::MultiDescr`]) it may differ from the descriptor class.
+    pub fn keychain_class(&self, keychain: impl Into<Keychain>) -> SpkClass {
+        let script = self.descr.derive(keychain, NormalIndex::ZERO).to_script_pubkey();
+        coinselect::spk_class(&script).unwrap_or_else(|| self.descr.class())
+    }
+
+    /// Constructs PSBT with [`PsbtConstructor::construct_psbt`], checking the change output
+    /// against the dust limit of the change keychain script type instead of the descriptor class
+    /// (see [`Self::keychain_class`]). Change not exceeding the dust limit is added to the fee.
+    pub fn construct_psbt<'b>(
+        &mut self,
+        coins: impl IntoIterator<Item = Outpoint>,
+        beneficiaries: impl IntoIterator<Item = &'b Beneficiary>,
+        mut params: TxParams,
+    ) -> Result<(Psbt, PsbtMeta), ConstructionError> {
+        let coins = coins.into_iter().collect::<Vec<_>>();
+        let beneficiaries = beneficiaries.into_iter().collect::<Vec<_>>();
+        let shift = params.change_shift;
+        params.change_shift = false;
+        let (psbt, meta) =
+            PsbtConstructor::construct_psbt(self, coins.clone(), beneficiaries.clone(), params)?;
+        let change = meta.change_vout.and_then(|vout| psbt.output(vout.into_usize()));
+        match change.map(|output| output.amount) {
+            Some(amount) if amount <= self.keychain_class(params.change_keychain).dust_limit() => {
+                params.fee = params.fee.checked_add(amount).expect("change is part of inputs");
+                PsbtConstructor::construct_psbt(self, coins, beneficiaries, params)
+            }
+            Some(_) if shift => {
+                self.next_derivation_index(params.change_keychain, true);
+                Ok((psbt, meta))
+            }
+            _ => Ok((psbt, meta)),
+        }
+    }
+
     pub fn next_address(&mut self, keychain: impl Into<Keychain>, shift: bool) -> Address {
         let keychain = keychain.into();
         let index = self.next_derivation_index(keychain, shift);
@@ -600,14 +1036,75 @@ impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
 
     pub fn balance(&self) -> Sats { self.cache.coins().map(|utxo| utxo.amount).sum::<Sats>() }
 
+    /// Computes balance which can be spent now: excludes frozen coins, immature coinbase outputs
+    /// and, unless `unconfirmed` is set, outputs of unconfirmed transactions not initiated by
+    /// the wallet.
+    pub fn spendable_balance(&self, unconfirmed: bool) -> Sats {
+        self.cache
+            .coins()
+            .filter(|coin| !self.is_frozen(coin.outpoint) && !self.is_immature(coin.outpoint))
+            .filter(|coin| {
+                unconfirmed
+                    || coin.height.is_mined()
+                    || self
+                        .cache
+                        .tx
+                        .get(&coin.outpoint.txid)
+                        .is_some_and(|tx| tx.inputs.iter().any(TxCredit::is_ourself))
+            })
+            .map(|coin| coin.amount)
+            .sum::<Sats>()
+    }
+
+    /// Detects whether the output belongs to a coinbase transaction which is not mature yet.
+    pub fn is_immature(&self, outpoint: Outpoint) -> bool {
+        self.cache.tx.get(&outpoint.txid).is_some_and(|tx| tx.is_immature(self.cache.tip_height))
+    }
+
+    /// Computes lifetime statistics of the wallet from the cached transactions.
+    ///
+    /// Transactions spending wallet coins are considered initiated by the wallet: their outputs
+    /// to external parties count as sent and their fees as paid by the wallet, while inputs from
+    /// external parties (as in collaborative transactions) count as received. For all other
+    /// transactions the value of the outputs belonging to the wallet counts as received.
+    pub fn summary(&self) -> WalletSummary {
+        let mut summary = WalletSummary {
+            balance: self.balance(),
+            tx_count: self.cache.tx.len(),
+            ..default!()
+        };
+        for tx in self.cache.tx.values() {
+            if tx.inputs.iter().any(TxCredit::is_ourself) {
+                summary.received += tx.credit_sum();
+                summary.sent += tx.debit_sum();
+                summary.fees += tx.fee;
+            } else {
+                summary.received +=
+                    tx.outputs.iter().filter(|out| out.is_ourself()).map(|out| out.value).sum();
+            }
+        }
+        summary
+    }
+
     #[inline]
     pub fn transactions(&self) -> &BTreeMap<Txid, WalletTx> { &self.cache.tx }
 
+    /// Returns height of the blockchain tip known from the last indexer scan, if any.
+    #[inline]
+    pub fn tip_height(&self) -> Option<u32> { self.cache.tip_height }
+
     #[inline]
     pub fn coins(&self) -> impl Iterator<Item = CoinRow<<L2::Cache as Layer2Cache>::Coin>> + '_ {
         self.cache.coins()
     }
 
+    /// Returns unspent outputs which are spent by unconfirmed transactions.
+    pub fn pending_coins(
+        &self,
+    ) -> impl Iterator<Item = CoinRow<<L2::Cache as Layer2Cache>::Coin>> + '_ {
+        self.cache.pending_coins()
+    }
+
     pub fn address_coins(
         &self,
     ) -> HashMap<DerivedAddr, Vec<CoinRow<<L2::Cache as Layer2Cache>::Coin>>> {
@@ -622,6 +1119,17 @@ impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
         self.cache.addr.values().flat_map(|set| set.iter()).copied()
     }
 
+    /// Returns the highest derivation index of an address with on-chain history for each of the
+    /// keychains, as known from the cache.
+    pub fn last_used_indexes(&self) -> BTreeMap<Keychain, NormalIndex> {
+        let mut map = BTreeMap::new();
+        for addr in self.address_balance().filter(|addr| addr.used > 0) {
+            let index = map.entry(addr.terminal.keychain).or_insert(addr.terminal.index);
+            *index = cmp::max(*index, addr.terminal.index);
+        }
+        map
+    }
+
     #[inline]
     pub fn history(&self) -> impl Iterator<Item = TxRow<<L2::Cache as Layer2Cache>::Tx>> + '_ {
         self.cache.history()
@@ -637,13 +1145,218 @@ impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
     pub fn txos(&self) -> impl Iterator<Item = WalletUtxo> + '_ { self.cache.txos() }
     pub fn utxos(&self) -> impl Iterator<Item = WalletUtxo> + '_ { self.cache.utxos() }
 
+    /// Returns unspent outputs available for the coin selection, i.e. the ones which are neither
+    /// frozen nor immature coinbase outputs.
+    pub fn spendable_utxos(&self) -> impl Iterator<Item = WalletUtxo> + '_ {
+        self.utxos()
+            .filter(|utxo| !self.is_frozen(utxo.outpoint) && !self.is_immature(utxo.outpoint))
+    }
+
+    /// Returns the full transaction, retrieving it from the indexer and caching if it was not
+    /// retrieved before. Returns `None` if the transaction is not available from the indexer.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the indexer fails or returns a transaction with a different id.
+    pub fn raw_tx<I: Indexer>(
+        &mut self,
+        txid: Txid,
+        indexer: &I,
+    ) -> Result<Option<Tx>, RawTxError<I::Error>> {
+        if let Some(tx) = self.cache.raw_tx.get(&txid) {
+            return Ok(Some(tx.clone()));
+        }
+        let Some(tx) = indexer.raw_tx(txid)? else {
+            return Ok(None);
+        };
+        if tx.txid() != txid {
+            return Err(RawTxError::Mismatch(txid, tx.txid()));
+        }
+        self.cache.raw_tx.insert(txid, tx.clone());
+        self.cache.mark_dirty();
+        Ok(Some(tx))
+    }
+
+    /// Verifies cached balances against their recomputation from the cached transactions. See
+    /// [`WalletCache::audit_balances`].
+    pub fn audit_balances(&self) -> Vec<BalanceMismatch> { self.cache.audit_balances() }
+
+    /// Verifies a cached transaction against the indexer. See [`WalletCache::verify_tx`].
+    pub fn verify_tx<I: Indexer>(
+        &self,
+        txid: Txid,
+        indexer: &I,
+        deep: bool,
+    ) -> Result<Vec<TxDivergence>, I::Error> {
+        self.cache.verify_tx(txid, indexer, deep)
+    }
+
+    /// Returns label assigned to the wallet object. Unlike [`Wallet::coin_label`], labels of
+    /// transaction outputs don't fall back to the labels of their addresses.
+    pub fn label(&self, target: LabelTarget) -> Option<&str> {
+        match target {
+            LabelTarget::Tx(txid) => self.data.tx_annotations.get(&txid),
+            LabelTarget::Output(outpoint) => self.data.txout_annotations.get(&outpoint),
+            LabelTarget::Address(addr) => self.data.addr_annotations.get(&addr),
+        }
+        .map(String::as_str)
+    }
+
+    /// Iterates over labels of wallet transactions, transaction outputs and addresses.
+    pub fn labels(&self) -> impl Iterator<Item = (LabelTarget, &str)> + '_ {
+        let data = &self.data;
+        data.tx_annotations
+            .iter()
+            .map(|(txid, label)| (LabelTarget::Tx(*txid), label.as_str()))
+            .chain(
+                data.txout_annotations
+                    .iter()
+                    .map(|(outpoint, label)| (LabelTarget::Output(*outpoint), label.as_str())),
+            )
+            .chain(
+                data.addr_annotations
+                    .iter()
+                    .map(|(addr, label)| (LabelTarget::Address(*addr), label.as_str())),
+            )
+    }
+
+    /// Checks whether the labelled object is known to the wallet: a wallet transaction, an output
+    /// of a wallet transaction or an address derived by the wallet within the gap limit, even if
+    /// it was never used.
+    pub fn contains(&self, target: LabelTarget) -> bool {
+        match target {
+            LabelTarget::Tx(txid) => self.cache.tx.contains_key(&txid),
+            LabelTarget::Output(outpoint) => self
+                .cache
+                .tx
+                .get(&outpoint.txid)
+                .is_some_and(|tx| tx.outputs.len() > outpoint.vout_usize()),
+            LabelTarget::Address(addr) => self.terminal_for(&addr.script_pubkey()).is_some(),
+        }
+    }
+
+    /// Assigns label to the wallet object, or removes its label if `label` is `None`.
+    pub fn set_label(&mut self, target: LabelTarget, label: Option<String>) {
+        fn set<K: Ord>(map: &mut BTreeMap<K, String>, key: K, label: Option<String>) {
+            match label {
+                Some(label) => map.insert(key, label),
+                None => map.remove(&key),
+            };
+        }
+        match target {
+            LabelTarget::Tx(txid) => set(&mut self.data.tx_annotations, txid, label),
+            LabelTarget::Output(outpoint) => set(&mut self.data.txout_annotations, outpoint, label),
+            LabelTarget::Address(addr) => set(&mut self.data.addr_annotations, addr, label),
+        }
+        self.data.mark_dirty();
+    }
+
+    pub fn address_label(&self, addr: &Address) -> Option<&str> {
+        self.data.addr_annotations.get(addr).map(String::as_str)
+    }
+
+    /// Returns label assigned to a wallet transaction output, falling back to the label of the
+    /// address holding the output.
+    pub fn coin_label(&self, outpoint: Outpoint) -> Option<&str> {
+        if let Some(label) = self.data.txout_annotations.get(&outpoint) {
+            return Some(label.as_str());
+        }
+        let tx = self.cache.tx.get(&outpoint.txid)?;
+        let addr = tx.outputs.get(outpoint.vout_usize())?.derived_addr()?.addr;
+        self.address_label(&addr)
+    }
+
+    /// Detects whether the output is frozen, i.e. excluded from the coin selection.
+    pub fn is_frozen(&self, outpoint: Outpoint) -> bool { self.data.frozen.contains(&outpoint) }
+
+    /// Freezes the output, excluding it from the coin selection. Returns `false` if the output
+    /// was already frozen.
+    pub fn freeze(&mut self, outpoint: Outpoint) -> bool {
+        let changed = self.data.frozen.insert(outpoint);
+        if changed {
+            self.data.mark_dirty();
+        }
+        changed
+    }
+
+    /// Unfreezes the output, returning it to the coin selection. Returns `false` if the output
+    /// was not frozen.
+    pub fn unfreeze(&mut self, outpoint: Outpoint) -> bool {
+        let changed = self.data.frozen.remove(&outpoint);
+        if changed {
+            self.data.mark_dirty();
+        }
+        changed
+    }
+
+    pub fn keychain_balance(&self) -> BTreeMap<Keychain, Sats> {
+        self.coins().fold(BTreeMap::new(), |mut acc, coin| {
+            acc.entry(coin.address.terminal.keychain).or_default().add_assign(coin.amount);
+            acc
+        })
+    }
+
+    pub fn label_balance(&self) -> BTreeMap<String, Sats> {
+        self.coins().fold(BTreeMap::new(), |mut acc, coin| {
+            if let Some(label) = self.coin_label(coin.outpoint) {
+                acc.entry(label.to_owned()).or_default().add_assign(coin.amount);
+            }
+            acc
+        })
+    }
+
+    /// Finds wallet address matching the provided script pubkey, checking the derivation hint
+    /// first and then the addresses known to the wallet cache.
+    pub fn derived_addr_for(
+        &self,
+        script: &ScriptPubkey,
+        hint: Option<Terminal>,
+    ) -> Option<DerivedAddr> {
+        if let Some(terminal) = hint {
+            let network = self.descr.network.into();
+            if let Ok(addr) =
+                self.descr.generator.derive_address(network, terminal.keychain, terminal.index)
+            {
+                if addr.script_pubkey() == *script {
+                    return Some(DerivedAddr::new(addr, terminal.keychain, terminal.index));
+                }
+            }
+        }
+        self.address_balance()
+            .find(|wa| wa.addr.script_pubkey() == *script)
+            .map(|wa| DerivedAddr::new(wa.addr, wa.terminal.keychain, wa.terminal.index))
+    }
+
+    /// Computes how wallet balances change once the transaction from the PSBT gets mined. The
+    /// wallet itself is not modified.
+    pub fn simulate(&self, psbt: &Psbt) -> BalanceChange {
+        let mut change = BalanceChange::default();
+        for input in psbt.inputs() {
+            let outpoint = input.previous_outpoint;
+            let Ok(utxo) = self.outpoint_by(outpoint) else {
+                continue;
+            };
+            let label = self.coin_label(outpoint);
+            change.register(utxo.terminal.keychain, label, -utxo.value.sats_i64());
+        }
+        for output in psbt.outputs() {
+            let Some(derived) = self.derived_addr_for(&output.script, output.terminal_derivation())
+            else {
+                continue;
+            };
+            let label = self.address_label(&derived.addr);
+            change.register(derived.terminal.keychain, label, output.value().sats_i64());
+        }
+        change
+    }
+
     pub fn coinselect<'a>(
         &'a self,
         up_to: Sats,
         selector: impl Fn(&WalletUtxo) -> bool + 'a,
     ) -> impl Iterator<Item = Outpoint> + 'a {
         let mut selected = Sats::ZERO;
-        self.utxos()
+        self.spendable_utxos()
             .filter(selector)
             .take_while(move |utxo| {
                 if selected <= up_to {
@@ -655,16 +1368,63 @@ impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
             })
             .map(|utxo| utxo.outpoint)
     }
+
+    /// Selects coins matching the `selector` in the `order` defined by one of [`coinselect`]
+    /// functions, like [`coinselect::largest_first`], until they cover `up_to` amount.
+    pub fn coinselect_by<'a>(
+        &'a self,
+        up_to: Sats,
+        selector: impl Fn(&WalletUtxo) -> bool + 'a,
+        order: impl Fn(&WalletUtxo, &WalletUtxo) -> cmp::Ordering,
+    ) -> impl Iterator<Item = Outpoint> + 'a {
+        let mut coins = self.spendable_utxos().filter(selector).collect::<Vec<_>>();
+        coins.sort_by(order);
+        let mut selected = Sats::ZERO;
+        coins
+            .into_iter()
+            .take_while(move |utxo| {
+                if selected <= up_to {
+                    selected.add_assign(utxo.value);
+                    true
+                } else {
+                    false
+                }
+            })
+            .map(|utxo| utxo.outpoint)
+    }
+}
+
+/// Storage backend persisting all wallet components: the descriptor, wallet data (including
+/// labels), the cache and layer 2 state.
+///
+/// The trait is implemented for any persistence provider supporting each of the components, so
+/// custom backends (databases, key-value stores, encrypted blobs) need to implement
+/// [`PersistenceProvider`] for them. File system backend is provided by `FsTextStore` under
+/// `fs` feature.
+pub trait WalletStore<K, D: Descriptor<K>, L2: Layer2>:
+    Clone
+    + PersistenceProvider<WalletDescr<K, D, L2::Descr>>
+    + PersistenceProvider<WalletData<L2::Data>>
+    + PersistenceProvider<WalletCache<L2::Cache>>
+    + PersistenceProvider<L2>
+    + 'static
+{
+}
+
+impl<K, D: Descriptor<K>, L2: Layer2, P> WalletStore<K, D, L2> for P where P: Clone
+        + PersistenceProvider<WalletDescr<K, D, L2::Descr>>
+        + PersistenceProvider<WalletData<L2::Data>>
+        + PersistenceProvider<WalletCache<L2::Cache>>
+        + PersistenceProvider<L2>
+        + 'static
+{
 }
 
 impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
-    pub fn load<P>(provider: P, autosave: bool) -> Result<Wallet<K, D, L2>, PersistenceError>
-    where P: Clone
-            + PersistenceProvider<WalletDescr<K, D, L2::Descr>>
-            + PersistenceProvider<WalletData<L2::Data>>
-            + PersistenceProvider<WalletCache<L2::Cache>>
-            + PersistenceProvider<L2>
-            + 'static {
+    pub fn load<P: WalletStore<K, D, L2>>(
+        provider: P,
+        autosave: bool,
+    ) -> Result<Wallet<K, D, L2>, PersistenceError> {
         let descr = WalletDescr::<K, D, L2::Descr>::load(provider.clone(), autosave)?;
         let data = WalletData::<L2::Data>::load(provider.clone(), autosave)?;
         let cache = WalletCache::<L2::Cache>::load(provider.clone(), autosave)?;
@@ -683,19 +1443,11 @@ impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
         self.cache.id = Some(id.to_string());
     }
 
-    pub fn make_persistent<P>(
+    pub fn make_persistent<P: WalletStore<K, D, L2>>(
         &mut self,
         provider: P,
         autosave: bool,
-    ) -> Result<bool, PersistenceError>
-    where
-        P: Clone
-            + PersistenceProvider<WalletDescr<K, D, L2::Descr>>
-            + PersistenceProvider<WalletData<L2::Data>>
-            + PersistenceProvider<WalletCache<L2::Cache>>
-            + PersistenceProvider<L2>
-            + 'static,
-    {
+    ) -> Result<bool, PersistenceError> {
         let a = self.descr.make_persistent(provider.clone(), autosave)?;
         let b = self.data.make_persistent(provider.clone(), autosave)?;
         let c = self.cache.make_persistent(provider.clone(), autosave)?;
@@ -714,3 +1466,168 @@ impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bpstd::secp256k1::{Message, SecretKey, SECP256K1};
+    use bpstd::{
+        Bip340Sig, LegacySig, LockTime, SeqNo, StdDescr, TrKey, TxVer, Wpkh, XpubDerivable,
+    };
+
+    use super::*;
+    use crate::{finalize_psbt, MultiDescr, TxDebit, COINBASE_MATURITY};
+
+    const XPUB: &str = "[643a7adc/84h/1h/0h]tpubDCNiWHaiSkgnQjuhsg9kjwaUzaxQjUcmhagvYzqQ3TYJTgFGJstVaqnu4yhtFktBhCVFmBNLQ5sN53qKzZbMksm3XEyGJsEhQPfVZdWmTE2/<0;1>/*";
+
+    #[test]
+    fn immature_coinbase() {
+        let xpub = XpubDerivable::from_str(XPUB).unwrap();
+        let descr = StdDescr::<XpubDerivable>::from(Wpkh::from(xpub));
+        let mut wallet = Wallet::<_, StdDescr>::new_layer1(descr, Network::Testnet3);
+        let addr = wallet.addresses(Keychain::OUTER).next().unwrap();
+
+        let txid = Txid::from([1u8; 32]);
+        let outpoint = Outpoint::new(txid, Vout::from_u32(0));
+        let value = Sats::from(50_000_000u64);
+        let height = BlockHeight::new(1000).unwrap();
+        wallet.cache.tx.insert(txid, WalletTx {
+            txid,
+            status: TxStatus::Mined(MiningInfo {
+                height,
+                ..MiningInfo::genesis()
+            }),
+            inputs: vec![TxCredit {
+                outpoint: Outpoint::new(Txid::coinbase(), Vout::from_u32(0xFFFF_FFFF)),
+                payer: Party::Subsidy,
+                sequence: SeqNo::from_consensus_u32(0xFFFF_FFFF),
+                coinbase: true,
+                script_sig: none!(),
+                witness: none!(),
+                value: Sats::ZERO,
+            }],
+            outputs: vec![TxDebit {
+                outpoint,
+                beneficiary: Party::Wallet(addr),
+                value,
+                spent: None,
+            }],
+            fee: Sats::ZERO,
+            size: 0,
+            weight: 0,
+            version: TxVer::V1,
+            locktime: LockTime::ZERO,
+        });
+        wallet.cache.utxo.insert(outpoint);
+
+        for tip in [None, Some(height.get()), Some(height.get() + COINBASE_MATURITY - 2)] {
+            wallet.cache.tip_height = tip;
+            assert!(wallet.is_immature(outpoint));
+            assert_eq!(wallet.balance(), value);
+            assert_eq!(wallet.spendable_balance(true), Sats::ZERO);
+            assert_eq!(wallet.spendable_utxos().count(), 0);
+            assert_eq!(wallet.coinselect(Sats::from(1000u64), |_| true).count(), 0);
+        }
+
+        wallet.cache.tip_height = Some(height.get() + COINBASE_MATURITY - 1);
+        assert!(!wallet.is_immature(outpoint));
+        assert_eq!(wallet.spendable_balance(false), value);
+        assert_eq!(wallet.coinselect(Sats::from(1000u64), |_| true).collect::<Vec<_>>(), [
+            outpoint
+        ]);
+    }
+
+    fn fund<D: Descriptor>(wallet: &mut Wallet<XpubDerivable, D>, no: u8, terminal: Terminal) {
+        let addr = wallet.addresses(terminal.keychain).nth(terminal.index.index() as usize);
+        let txid = Txid::from([no; 32]);
+        let outpoint = Outpoint::new(txid, Vout::from_u32(0));
+        wallet.cache.tx.insert(txid, WalletTx {
+            txid,
+            status: TxStatus::Mined(MiningInfo::genesis()),
+            inputs: vec![],
+            outputs: vec![TxDebit {
+                outpoint,
+                beneficiary: Party::Wallet(addr.unwrap()),
+                value: Sats::from(10_000u64),
+                spent: None,
+            }],
+            fee: Sats::ZERO,
+            size: 0,
+            weight: 0,
+            version: TxVer::V2,
+            locktime: LockTime::ZERO,
+        });
+        wallet.cache.utxo.insert(outpoint);
+    }
+
+    #[test]
+    fn mixed_descriptor_spending() {
+        let xpub = XpubDerivable::from_str(XPUB).unwrap();
+        let wpkh = StdDescr::<XpubDerivable>::from(Wpkh::from(xpub.clone()));
+        let tr = StdDescr::<XpubDerivable>::from(TrKey::from(xpub));
+        let descr = MultiDescr::with([wpkh, tr]).unwrap();
+        let mut wallet = Wallet::<_, MultiDescr<StdDescr>>::new_layer1(descr, Network::Testnet3);
+        let tr_change = MultiDescr::<StdDescr>::wallet_keychain(1, Keychain::INNER);
+        assert_eq!(wallet.keychain_class(Keychain::OUTER), SpkClass::P2wpkh);
+        assert_eq!(wallet.keychain_class(tr_change), SpkClass::P2tr);
+
+        fund(&mut wallet, 1, Terminal::new(Keychain::OUTER, NormalIndex::ZERO));
+        fund(&mut wallet, 2, Terminal::new(Keychain::with(16), NormalIndex::ZERO));
+        let coins = wallet.spendable_utxos().map(WalletUtxo::into_outpoint).collect::<Vec<_>>();
+        let payee = wallet.addresses(Keychain::OUTER).nth(5).unwrap().addr;
+        let beneficiaries = [Beneficiary::new(payee, Sats::from(15_000u64))];
+
+        // Change goes to the taproot descriptor
+        let mut params = TxParams::with(Sats::from(1_000u64));
+        params.change_keychain = tr_change;
+        let (mut psbt, meta) =
+            wallet.construct_psbt(coins.clone(), &beneficiaries, params).unwrap();
+        let change = psbt.output(meta.change_vout.unwrap().into_usize()).unwrap();
+        assert!(change.script.is_p2tr());
+        assert_eq!(change.amount, Sats::from(4_000u64));
+        assert_eq!(wallet.last_derivation_index(tr_change), NormalIndex::ONE);
+
+        // Change above the P2WPKH dust limit but below the P2TR one goes to the fee
+        params.fee = Sats::from(4_700u64);
+        let (dust, meta) = wallet.construct_psbt(coins, &beneficiaries, params).unwrap();
+        assert_eq!(meta.change_vout, None);
+        assert_eq!(dust.fee(), Some(Sats::from(5_000u64)));
+
+        let sk = SecretKey::from_slice(&[1u8; 32]).unwrap();
+        let sig = SECP256K1.sign_ecdsa(&Message::from_digest([2u8; 32]), &sk);
+        {
+            let mut inputs = psbt.inputs_mut();
+            let wpkh_input = inputs.next().unwrap();
+            let pk = *wpkh_input.bip32_derivation.keys().next().unwrap();
+            wpkh_input.partial_sigs.insert(pk, LegacySig::sighash_all(sig));
+            let tr_input = inputs.next().unwrap();
+            tr_input.tap_key_sig = Some(Bip340Sig::from_bytes(&[1u8; 64]).unwrap());
+        }
+
+        // Finalization by the descriptor class handles only the inputs of the first descriptor
+        assert_eq!(psbt.clone().finalize(wallet.descriptor()), 1);
+        assert_eq!(finalize_psbt(&mut psbt, wallet.descriptor()), 2);
+        assert!(psbt.is_finalized());
+    }
+
+    #[test]
+    fn label_targets() {
+        let xpub = XpubDerivable::from_str(XPUB).unwrap();
+        let descr = StdDescr::<XpubDerivable>::from(Wpkh::from(xpub));
+        let mut wallet = Wallet::<_, StdDescr>::new_layer1(descr, Network::Testnet3);
+        fund(&mut wallet, 1, Terminal::new(Keychain::OUTER, NormalIndex::ZERO));
+
+        let txid = Txid::from([1u8; 32]);
+        assert!(wallet.contains(LabelTarget::Tx(txid)));
+        assert!(!wallet.contains(LabelTarget::Tx(Txid::from([2u8; 32]))));
+        assert!(wallet.contains(LabelTarget::Output(Outpoint::new(txid, Vout::from_u32(0)))));
+        assert!(!wallet.contains(LabelTarget::Output(Outpoint::new(txid, Vout::from_u32(1)))));
+
+        // Unused addresses within the gap limit are known by derivation
+        let unused = wallet.addresses(Keychain::INNER).nth(5).unwrap().addr;
+        assert!(wallet.contains(LabelTarget::Address(unused)));
+        let beyond = wallet.addresses(Keychain::OUTER).nth(1_000).unwrap().addr;
+        assert!(!wallet.contains(LabelTarget::Address(beyond)));
+    }
+}